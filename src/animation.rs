@@ -0,0 +1,669 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::camera::{Camera, Projection};
+use crate::color::Color;
+use crate::matrix::Matrix4;
+use crate::ray::Hittable;
+use crate::scene::{Object, Scene};
+use crate::toml::{self, Value};
+use crate::transform::Transformed;
+use crate::vec::Vector;
+
+// Properties that tracks can animate, with how many values each takes. A single
+// value given for a vector or colour is used for every component.
+const CAMERA_PROPERTIES: &[(&str, usize)] = &[("position", 3), ("target", 3), ("up", 3), ("fov", 1)];
+const OBJECT_PROPERTIES: &[(&str, usize)] = &[("translation", 3), ("rotation", 3), ("scale", 3)];
+const MATERIAL_PROPERTIES: &[(&str, usize)] = &[
+    ("base_color", 3),
+    ("alpha", 1),
+    ("metallic", 1),
+    ("roughness", 1),
+    ("specular", 1),
+    ("sheen", 1),
+    ("clearcoat", 1),
+    ("clearcoat_roughness", 1),
+    ("transmission", 1),
+    ("ior", 1),
+    ("emissive", 3),
+    ("normal_scale", 1),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    // Hold each key's value until the next.
+    Step,
+    Linear,
+    // Smooth curves through the keys, with handles following the neighbouring keys
+    // and flat at the first and last, so that motion eases in and out.
+    Bezier,
+}
+
+impl FromStr for Interpolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "step" => Ok(Interpolation::Step),
+            "linear" => Ok(Interpolation::Linear),
+            "bezier" => Ok(Interpolation::Bezier),
+            _ => Err(format!("unknown interpolation `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interpolation::Step => write!(f, "step"),
+            Interpolation::Linear => write!(f, "linear"),
+            Interpolation::Bezier => write!(f, "bezier"),
+        }
+    }
+}
+
+// What a track animates: the scene camera, or the objects or materials with a name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Camera,
+    Object(String),
+    Material(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Key {
+    pub frame: f64,
+    pub value: [f64; 3],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    pub target: Target,
+    pub property: &'static str,
+    // Keys in order of frame.
+    pub keys: Vec<Key>,
+    pub interpolation: Interpolation,
+}
+
+impl Track {
+    // The value at a frame, holding the first and last keys' values beyond them.
+    pub fn value_at(&self, frame: f64) -> [f64; 3] {
+        let keys = &self.keys;
+        let i = keys.partition_point(|k| k.frame <= frame);
+        if i == 0 {
+            return keys[0].value;
+        }
+        if i == keys.len() {
+            return keys[i - 1].value;
+        }
+        let (k0, k1) = (&keys[i - 1], &keys[i]);
+        let dt = k1.frame - k0.frame;
+        let t = (frame - k0.frame) / dt;
+        let mut value = [0.0; 3];
+        for (c, v) in value.iter_mut().enumerate() {
+            let (p0, p1) = (k0.value[c], k1.value[c]);
+            *v = match self.interpolation {
+                Interpolation::Step => p0,
+                Interpolation::Linear => p0 + t * (p1 - p0),
+                Interpolation::Bezier => {
+                    let (m0, m1) = (self.slope(i - 1, c), self.slope(i, c));
+                    // Bezier handles a third of the way along the tangents.
+                    let (h0, h1) = (p0 + m0 * dt / 3.0, p1 - m1 * dt / 3.0);
+                    let s = 1.0 - t;
+                    s * s * s * p0 + 3.0 * s * s * t * h0 + 3.0 * s * t * t * h1 + t * t * t * p1
+                },
+            };
+        }
+        value
+    }
+
+    // Slope of a component at a key, from its neighbours, or flat at either end.
+    fn slope(&self, i: usize, c: usize) -> f64 {
+        if i == 0 || i + 1 == self.keys.len() {
+            return 0.0;
+        }
+        let (prev, next) = (&self.keys[i - 1], &self.keys[i + 1]);
+        (next.value[c] - prev.value[c]) / (next.frame - prev.frame)
+    }
+}
+
+// A camera moving by itself over a range of frames, holding still outside it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Motion {
+    // Circling a target, by default the centre of the scene at a distance that keeps
+    // it all in view. Angles are in degrees, with azimuth 0 on the +z side and turns
+    // going anticlockwise seen from above. The turns end a frame after the last, so
+    // that a loop of them repeats without a doubled frame.
+    Orbit {
+        target: Option<Vector>,
+        radius: Option<f64>,
+        elevation: f64,
+        azimuth: f64,
+        turns: f64,
+        frames: (f64, f64),
+    },
+    // Along a Catmull-Rom spline through the points, looking at the target or else
+    // along the path.
+    Dolly {
+        points: Vec<Vector>,
+        target: Option<Vector>,
+        frames: (f64, f64),
+    },
+}
+
+impl Motion {
+    fn frames(&self) -> (f64, f64) {
+        match self {
+            Motion::Orbit { frames, .. } | Motion::Dolly { frames, .. } => *frames,
+        }
+    }
+
+    // Where the camera is at a frame, and what it looks at if the motion decides.
+    fn pose(&self, scene: &Scene, fov: f64, frame: f64) -> (Vector, Option<Vector>) {
+        let (first, last) = self.frames();
+        match self {
+            Motion::Orbit { target, radius, elevation, azimuth, turns, .. } => {
+                let bounds = scene.bounding_box();
+                let target = target.unwrap_or_else(|| bounds.centroid());
+                let radius = radius.unwrap_or_else(|| {
+                    (bounds.max - bounds.min).length() / 2.0 / (fov.to_radians() / 2.0).sin()
+                });
+                let t = ((frame - first) / (last - first + 1.0)).clamp(0.0, 1.0);
+                let (elevation, azimuth) = (elevation.to_radians(), (azimuth + 360.0 * turns * t).to_radians());
+                let offset = Vector::new(elevation.cos() * azimuth.sin(), elevation.sin(), elevation.cos() * azimuth.cos());
+                (target + radius * offset, Some(target))
+            },
+            Motion::Dolly { points, target, .. } => {
+                let t = if last > first { ((frame - first) / (last - first)).clamp(0.0, 1.0) } else { 0.0 };
+                let (position, tangent) = catmull_rom(points, t);
+                let target = target.or_else(|| (!tangent.near_zero()).then(|| position + tangent.normalize()));
+                (position, target)
+            },
+        }
+    }
+}
+
+// Keyframed tracks for the camera, object transforms and material values, read from
+// a TOML file with a table for each track, such as
+//
+//     [camera.position]
+//     keys = [[1, [0, 1, 5]], [120, [5, 1, 0]]]
+//     interpolation = "bezier"
+//
+//     [object.Cube.rotation]
+//     keys = [[1, 0], [240, [0, 360, 0]]]
+//
+// Frames may be fractional. Object transforms apply about the centre of the objects'
+// bounds, with rotations as angles in degrees about x, then y, then z.
+//
+// The camera may instead move by itself, with a `[camera.orbit]` or `[camera.dolly]`
+// table giving the `frames = [FIRST, LAST]` to move over and the motion's settings,
+// and may follow an object with `[camera.track]` and `object = NAME`. These override
+// the keyframed position and target.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Animation {
+    pub tracks: Vec<Track>,
+    pub motion: Option<Motion>,
+    // The object the camera keeps looking at.
+    pub tracking: Option<String>,
+}
+
+impl Animation {
+    pub fn load(filename: &str) -> Result<Animation, String> {
+        let text = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        Animation::parse(&text).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Animation, String> {
+        let mut animation = Animation::default();
+        for table in toml::parse(text).map_err(|e| e.to_string())? {
+            match table.name.as_str() {
+                "" => {
+                    if let Some(entry) = table.entries.first() {
+                        return Err(format!("line {}: `{}` is outside any track", entry.line, entry.key));
+                    }
+                },
+                "camera.orbit" | "camera.dolly" => {
+                    if animation.motion.is_some() {
+                        return Err(format!("[{}]: the camera already has a motion", table.name));
+                    }
+                    animation.motion = Some(parse_motion(&table)?);
+                },
+                "camera.track" => animation.tracking = Some(parse_tracking(&table)?),
+                _ => animation.tracks.push(parse_track(&table)?),
+            }
+        }
+        Ok(animation)
+    }
+
+    // The frames from the first key to the last, and over any camera motion.
+    pub fn frames(&self) -> Option<(u32, u32)> {
+        let ranges = self.tracks.iter()
+            .map(|t| (t.keys[0].frame, t.keys[t.keys.len() - 1].frame))
+            .chain(self.motion.as_ref().map(Motion::frames));
+        let (first, last) = ranges.reduce(|(a0, a1), (b0, b1)| (a0.min(b0), a1.max(b1)))?;
+        Some((first.floor().max(0.0) as u32, last.ceil().max(0.0) as u32))
+    }
+
+    fn tracks_for<'a>(&'a self, target: &'a Target) -> impl Iterator<Item = &'a Track> {
+        self.tracks.iter().filter(move |t| t.target == *target)
+    }
+
+    // Poses the scene for a frame. Every object and material named by a track must
+    // be in the scene.
+    pub fn apply(&self, scene: Scene, frame: f64) -> Result<Scene, String> {
+        let mut scene = self.pose_objects(scene, frame)?;
+        // The camera comes last, to look at objects where they are now.
+        if self.tracks.iter().any(|t| t.target == Target::Camera) || self.motion.is_some() || self.tracking.is_some() {
+            let base = scene.camera.unwrap_or_default();
+            let (mut position, mut target, mut up, mut fov) = (base.origin, base.target(), base.vertical, base.vfov());
+            for track in self.tracks_for(&Target::Camera) {
+                let v = track.value_at(frame);
+                match track.property {
+                    "position" => position = vector(v),
+                    "target" => target = vector(v),
+                    "up" => up = vector(v),
+                    _ => fov = v[0],
+                }
+            }
+            if let Some(motion) = &self.motion {
+                let (p, t) = motion.pose(&scene, fov, frame);
+                position = p;
+                target = t.unwrap_or(target);
+            }
+            if let Some(name) = &self.tracking {
+                target = scene.objects().iter()
+                    .filter(|o| o.name == *name)
+                    .map(|o| o.shape.bounding_box())
+                    .reduce(|a, b| a.union(&b))
+                    .ok_or_else(|| format!("the scene has no object named `{}`", name))?
+                    .centroid();
+            }
+            let camera = Camera::look_at(position, target, up, fov, base.aspect_ratio()).with_projection(base.projection);
+            // Orthographic views keep their size, having no field of view.
+            scene.camera = Some(match base.projection {
+                Projection::Orthographic => camera.with_view_height(base.vertical.length()),
+                _ => camera,
+            });
+        }
+        Ok(scene)
+    }
+
+    fn pose_objects(&self, mut scene: Scene, frame: f64) -> Result<Scene, String> {
+        for track in &self.tracks {
+            let Target::Material(name) = &track.target else { continue };
+            let v = track.value_at(frame);
+            let mut found = false;
+            for m in scene.materials.iter_mut().filter(|m| m.name == *name) {
+                found = true;
+                match track.property {
+                    "base_color" => m.base_color = Color::new(v[0], v[1], v[2]),
+                    "alpha" => m.alpha = v[0],
+                    "metallic" => m.metallic = v[0],
+                    "roughness" => m.roughness = v[0],
+                    "specular" => m.specular = v[0],
+                    "sheen" => m.sheen = v[0],
+                    "clearcoat" => m.clearcoat = v[0],
+                    "clearcoat_roughness" => m.clearcoat_roughness = v[0],
+                    "transmission" => m.transmission = v[0],
+                    "ior" => m.ior = v[0],
+                    "emissive" => m.emissive = Color::new(v[0], v[1], v[2]),
+                    _ => m.normal_scale = v[0],
+                }
+            }
+            if !found {
+                return Err(format!("the scene has no material named `{}`", name));
+            }
+        }
+
+        let mut transforms: Vec<(&str, Matrix4)> = Vec::new();
+        for track in &self.tracks {
+            let Target::Object(name) = &track.target else { continue };
+            if transforms.iter().any(|(n, _)| n == name) {
+                continue;
+            }
+            let bounds = scene.objects().iter()
+                .filter(|o| o.name == *name)
+                .map(|o| o.shape.bounding_box())
+                .reduce(|a, b| a.union(&b))
+                .ok_or_else(|| format!("the scene has no object named `{}`", name))?;
+            let (mut translation, mut rotation, mut scale) = ([0.0; 3], [0.0; 3], [1.0; 3]);
+            for track in self.tracks_for(&track.target) {
+                match track.property {
+                    "translation" => translation = track.value_at(frame),
+                    "rotation" => rotation = track.value_at(frame),
+                    _ => scale = track.value_at(frame),
+                }
+            }
+            let pivot = bounds.centroid();
+            let matrix = Matrix4::translation(pivot + vector(translation))
+                * euler_rotation(rotation)
+                * Matrix4::scale(vector(scale))
+                * Matrix4::translation(-pivot);
+            transforms.push((name, matrix));
+        }
+        if transforms.is_empty() {
+            return Ok(scene);
+        }
+        // Objects scaled to nothing disappear.
+        Ok(scene.map_objects(|object| {
+            match transforms.iter().find(|(name, _)| *name == object.name) {
+                Some((_, matrix)) => {
+                    let shape = Transformed::new(object.shape, *matrix)?;
+                    Some(Object { shape: Box::new(shape), ..object })
+                },
+                None => Some(object),
+            }
+        }))
+    }
+}
+
+fn parse_track(table: &toml::Table) -> Result<Track, String> {
+    let error = |reason: String| format!("[{}]: {}", table.name, reason);
+    let (kind, rest) = table.name.split_once('.').ok_or_else(|| error(String::from("unknown track")))?;
+    let (target, property, properties) = match kind {
+        "camera" => (Target::Camera, rest, CAMERA_PROPERTIES),
+        "object" | "material" => {
+            let (name, property) = rest.rsplit_once('.').ok_or_else(|| error(format!("no {} property", kind)))?;
+            match kind {
+                "object" => (Target::Object(name.to_string()), property, OBJECT_PROPERTIES),
+                _ => (Target::Material(name.to_string()), property, MATERIAL_PROPERTIES),
+            }
+        },
+        _ => return Err(error(String::from("tracks animate `camera`, `object.NAME` or `material.NAME`"))),
+    };
+    let &(property, size) = properties.iter().find(|(p, _)| *p == property)
+        .ok_or_else(|| error(format!("unknown property `{}`", property)))?;
+
+    let mut track = Track { target, property, keys: Vec::new(), interpolation: Interpolation::Linear };
+    for entry in &table.entries {
+        let error = |reason: &str| format!("line {}: {}", entry.line, reason);
+        match (entry.key.as_str(), &entry.value) {
+            ("interpolation", Value::String(s)) => track.interpolation = s.parse().map_err(|e: String| error(&e))?,
+            ("keys", Value::Array(keys)) => {
+                for key in keys {
+                    let key = parse_key(key, size).ok_or_else(|| error("keys must be [FRAME, VALUE] pairs"))?;
+                    if track.keys.last().is_some_and(|k| k.frame >= key.frame) {
+                        return Err(error("keys must be in order of frame"));
+                    }
+                    track.keys.push(key);
+                }
+            },
+            _ => return Err(error(&format!("unexpected `{}`", entry.key))),
+        }
+    }
+    if track.keys.is_empty() {
+        return Err(error(String::from("no keys")));
+    }
+    Ok(track)
+}
+
+fn parse_motion(table: &toml::Table) -> Result<Motion, String> {
+    let mut frames = None;
+    let (mut target, mut radius, mut elevation, mut azimuth, mut turns) = (None, None, 20.0, 0.0, 1.0);
+    let mut points = Vec::new();
+    let orbit = table.name == "camera.orbit";
+    for entry in &table.entries {
+        let error = |reason: &str| format!("line {}: {}", entry.line, reason);
+        let value = &entry.value;
+        match entry.key.as_str() {
+            "frames" => {
+                let range = match value {
+                    Value::Array(range) if range.len() == 2 => number(&range[0]).zip(number(&range[1])),
+                    _ => None,
+                };
+                frames = Some(range.filter(|(a, b)| a <= b).ok_or_else(|| error("frames must be [FIRST, LAST]"))?);
+            },
+            "target" => target = Some(point(value).ok_or_else(|| error("the target must be [X, Y, Z]"))?),
+            "radius" if orbit => radius = Some(number(value).filter(|r| *r > 0.0)
+                .ok_or_else(|| error("the radius must be a positive number"))?),
+            "elevation" if orbit => elevation = number(value).filter(|e| e.abs() < 90.0)
+                .ok_or_else(|| error("the elevation must be between -90 and 90 degrees"))?,
+            "azimuth" if orbit => azimuth = number(value).ok_or_else(|| error("the azimuth must be a number"))?,
+            "turns" if orbit => turns = number(value).ok_or_else(|| error("turns must be a number"))?,
+            "points" if !orbit => {
+                let Value::Array(values) = value else { return Err(error("points must be a list of [X, Y, Z]")) };
+                points = values.iter().map(point).collect::<Option<_>>()
+                    .ok_or_else(|| error("points must be a list of [X, Y, Z]"))?;
+            },
+            _ => return Err(error(&format!("unexpected `{}`", entry.key))),
+        }
+    }
+    let frames = frames.ok_or_else(|| format!("[{}]: no frames", table.name))?;
+    if orbit {
+        Ok(Motion::Orbit { target, radius, elevation, azimuth, turns, frames })
+    } else if points.len() < 2 {
+        Err(format!("[{}]: a dolly needs at least two points", table.name))
+    } else {
+        Ok(Motion::Dolly { points, target, frames })
+    }
+}
+
+fn parse_tracking(table: &toml::Table) -> Result<String, String> {
+    match &table.entries[..] {
+        [toml::Entry { key, value: Value::String(name), .. }] if key == "object" => Ok(name.clone()),
+        _ => Err(format!("[{}]: expected only `object = NAME`", table.name)),
+    }
+}
+
+fn parse_key(key: &Value, size: usize) -> Option<Key> {
+    let Value::Array(pair) = key else { return None };
+    let [frame, value] = &pair[..] else { return None };
+    let value = match value {
+        Value::Array(values) if values.len() == size => {
+            let mut v = [0.0; 3];
+            for (i, x) in values.iter().enumerate() {
+                v[i] = number(x)?;
+            }
+            v
+        },
+        value => [number(value)?; 3],
+    };
+    Some(Key { frame: number(frame)?, value })
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Float(x) if x.is_finite() => Some(*x),
+        _ => None,
+    }
+}
+
+fn point(value: &Value) -> Option<Vector> {
+    match value {
+        Value::Array(v) if v.len() == 3 => Some(Vector::new(number(&v[0])?, number(&v[1])?, number(&v[2])?)),
+        _ => None,
+    }
+}
+
+fn vector(v: [f64; 3]) -> Vector {
+    Vector::new(v[0], v[1], v[2])
+}
+
+// Rotation by angles in degrees about x, then y, then z.
+fn euler_rotation(degrees: [f64; 3]) -> Matrix4 {
+    let axis = |i: usize| {
+        let half = degrees[i].to_radians() / 2.0;
+        let mut q = [0.0, 0.0, 0.0, half.cos()];
+        q[i] = half.sin();
+        Matrix4::rotation(q)
+    };
+    axis(2) * axis(1) * axis(0)
+}
+
+// The point a fraction t of the way along a uniform Catmull-Rom spline through the
+// points, and the direction of travel there. The ends continue straight on.
+fn catmull_rom(points: &[Vector], t: f64) -> (Vector, Vector) {
+    let segments = points.len() - 1;
+    let s = t * segments as f64;
+    let i = (s.floor() as usize).min(segments - 1);
+    let u = s - i as f64;
+    let (p1, p2) = (points[i], points[i + 1]);
+    let p0 = if i > 0 { points[i - 1] } else { 2.0 * p1 - p2 };
+    let p3 = if i + 2 < points.len() { points[i + 2] } else { 2.0 * p2 - p1 };
+    let (a, b, c) = (p2 - p0, 2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3, 3.0 * (p1 - p2) + p3 - p0);
+    let position = p1 + 0.5 * (u * a + u * u * b + u * u * u * c);
+    let tangent = 0.5 * (a + 2.0 * u * b + 3.0 * u * u * c);
+    (position, tangent)
+}
+
+// The output file of a frame. The last run of `#` in the pattern becomes the frame
+// number padded to its length; without one, the number goes before the extension,
+// as in `out.0001.png`.
+pub fn frame_filename(pattern: &str, frame: u32) -> String {
+    if let Some(end) = pattern.rfind('#') {
+        let start = pattern[..end].trim_end_matches('#').len();
+        let width = end + 1 - start;
+        return format!("{}{:0width$}{}", &pattern[..start], frame, &pattern[end + 1..], width = width);
+    }
+    let path = Path::new(pattern);
+    let numbered = match (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|e| e.to_str())) {
+        (Some(stem), Some(extension)) => format!("{}.{:04}.{}", stem, frame, extension),
+        _ => format!("{}.{:04}", path.file_name().and_then(|n| n.to_str()).unwrap_or(pattern), frame),
+    };
+    path.with_file_name(numbered).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::sphere::Sphere;
+
+    fn track(interpolation: Interpolation, keys: &[(f64, f64)]) -> Track {
+        Track {
+            target: Target::Camera,
+            property: "fov",
+            keys: keys.iter().map(|&(frame, v)| Key { frame, value: [v; 3] }).collect(),
+            interpolation,
+        }
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let keys = [(1.0, 0.0), (11.0, 10.0), (21.0, 0.0)];
+        let linear = track(Interpolation::Linear, &keys);
+        assert_eq!(linear.value_at(0.0)[0], 0.0);
+        assert_eq!(linear.value_at(6.0)[0], 5.0);
+        assert_eq!(linear.value_at(16.0)[0], 5.0);
+        assert_eq!(linear.value_at(30.0)[0], 0.0);
+        assert_eq!(track(Interpolation::Step, &keys).value_at(10.9)[0], 0.0);
+
+        // Bezier curves pass through the keys, easing out of the first.
+        let bezier = track(Interpolation::Bezier, &keys);
+        assert_eq!(bezier.value_at(11.0)[0], 10.0);
+        assert!(bezier.value_at(2.0)[0] < linear.value_at(2.0)[0]);
+        assert!((bezier.value_at(6.0)[0] - 5.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = "\
+[camera.fov]
+keys = [[1, 40], [48, 25.5]]
+interpolation = \"bezier\"
+
+[object.Ball.translation]
+keys = [[1, [0, 0, 0]], [24, [0, 2, 0]]]
+
+[material.Red.base_color]
+keys = [[1, 0.5]]
+";
+        let animation = Animation::parse(text).expect("valid animation rejected");
+        assert_eq!(animation.tracks.len(), 3);
+        assert_eq!(animation.tracks[0].interpolation, Interpolation::Bezier);
+        assert_eq!(animation.tracks[1].target, Target::Object(String::from("Ball")));
+        assert_eq!(animation.tracks[2].keys[0].value, [0.5; 3]);
+        assert_eq!(animation.frames(), Some((1, 48)));
+
+        assert!(Animation::parse("[camera.zoom]\nkeys = [[1, 2]]\n").is_err());
+        assert!(Animation::parse("[camera.position]\nkeys = [[1, [1, 2]]]\n").is_err());
+        assert!(Animation::parse("[camera.fov]\nkeys = [[2, 30], [1, 40]]\n").is_err());
+        assert!(Animation::parse("[light.Sun.power]\nkeys = [[1, 2]]\n").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let animation = Animation::parse("\
+[object.Ball.translation]
+keys = [[0, 0], [10, [0, 0, -2]]]
+
+[material.Red.roughness]
+keys = [[0, 1], [10, 0]]
+
+[camera.fov]
+keys = [[0, 90]]
+").unwrap();
+        let scene = || {
+            let objects = vec![Object::new("Ball", Box::new(Sphere::new(Vector::new(0.0, 0.0, -3.0), 0.5)), 0)];
+            let materials = vec![Material { name: String::from("Red"), ..Material::default() }];
+            Scene::new(objects, materials, vec![], None)
+        };
+
+        let posed = animation.apply(scene(), 5.0).expect("animation failed");
+        assert_eq!(posed.materials[0].roughness, 0.5);
+        assert!((posed.camera.unwrap().vfov() - 90.0).abs() < 1e-9);
+        let ray = posed.camera.unwrap().get_ray(0.5, 0.5);
+        let hit = posed.hit(&ray, 0.0, f64::INFINITY).expect("ball not hit");
+        assert!((hit.p.z + 3.5).abs() < 1e-9);
+
+        let missing = Animation::parse("[object.Box.scale]\nkeys = [[0, 2]]\n").unwrap();
+        assert!(missing.apply(scene(), 0.0).is_err());
+    }
+
+    #[test]
+    fn test_camera_motion() {
+        let scene = || {
+            let objects = vec![Object::new("Ball", Box::new(Sphere::new(Vector::new(0.0, 1.0, 0.0), 1.0)), 0)];
+            Scene::new(objects, vec![Material::default()], vec![], None)
+        };
+        let camera = |animation: &Animation, frame: f64| animation.apply(scene(), frame).unwrap().camera.unwrap();
+
+        let orbit = Animation::parse("[camera.orbit]\nframes = [1, 4]\nradius = 5\nelevation = 0\n").unwrap();
+        assert_eq!(orbit.frames(), Some((1, 4)));
+        assert!((camera(&orbit, 1.0).origin - Vector::new(0.0, 1.0, 5.0)).length() < 1e-9);
+        assert!((camera(&orbit, 2.0).origin - Vector::new(5.0, 1.0, 0.0)).length() < 1e-9);
+        assert!((camera(&orbit, 2.0).target() - Vector::new(4.0, 1.0, 0.0)).length() < 1e-9);
+
+        // A dolly passes through its points and looks along the way.
+        let dolly = Animation::parse("\
+[camera.dolly]
+frames = [0, 10]
+points = [[0, 0, 10], [0, 0, 5], [5, 0, 5]]
+").unwrap();
+        assert!((camera(&dolly, 5.0).origin - Vector::new(0.0, 0.0, 5.0)).length() < 1e-9);
+        assert!((camera(&dolly, 10.0).origin - Vector::new(5.0, 0.0, 5.0)).length() < 1e-9);
+        assert!((camera(&dolly, 0.0).target() - Vector::new(0.0, 0.0, 9.0)).length() < 1e-9);
+
+        // Tracking looks at the object wherever it has moved to.
+        let tracking = Animation::parse("\
+[camera.position]
+keys = [[0, [10, 0, 0]]]
+
+[camera.track]
+object = \"Ball\"
+
+[object.Ball.translation]
+keys = [[0, 0], [10, [0, 0, -4]]]
+").unwrap();
+        let camera = camera(&tracking, 10.0);
+        let direction = (camera.target() - camera.origin).normalize();
+        assert!((direction - Vector::new(-10.0, 1.0, -4.0).normalize()).length() < 1e-9);
+
+        assert!(Animation::parse("[camera.orbit]\nradius = 5\n").is_err());
+        assert!(Animation::parse("[camera.dolly]\nframes = [1, 2]\npoints = [[0, 0, 0]]\n").is_err());
+        assert!(Animation::parse("[camera.orbit]\nframes = [1, 2]\n[camera.dolly]\nframes = [1, 2]\n").is_err());
+        assert!(Animation::parse("[camera.track]\nname = \"Ball\"\n").is_err());
+    }
+
+    #[test]
+    fn test_frame_filename() {
+        assert_eq!(frame_filename("out.####.png", 7), "out.0007.png");
+        assert_eq!(frame_filename("render_##.exr", 123), "render_123.exr");
+        assert_eq!(frame_filename("shots/out.png", 42), "shots/out.0042.png");
+        assert_eq!(frame_filename("out", 1), "out.0001");
+    }
+}
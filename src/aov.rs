@@ -0,0 +1,500 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use crate::camera::StereoLayout;
+use crate::checkpoint::{invalid, Decoder, Encoder};
+use crate::color::Color;
+use crate::denoise::Features;
+use crate::exr::Channel;
+use crate::image::ImagePpm;
+use crate::render::Region;
+use crate::vec::Vector;
+
+// Auxiliary per-pixel passes written next to the beauty image for compositing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    Depth,
+    Position,
+    Normal,
+    Albedo,
+    ObjectId,
+    MaterialId,
+    DirectDiffuse,
+    DirectSpecular,
+    IndirectDiffuse,
+    IndirectSpecular,
+    Emission,
+    SampleCount,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 12] = [
+        Aov::Depth, Aov::Position, Aov::Normal, Aov::Albedo, Aov::ObjectId, Aov::MaterialId,
+        Aov::DirectDiffuse, Aov::DirectSpecular, Aov::IndirectDiffuse, Aov::IndirectSpecular,
+        Aov::Emission, Aov::SampleCount,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::DirectDiffuse => "direct_diffuse",
+            Aov::DirectSpecular => "direct_specular",
+            Aov::IndirectDiffuse => "indirect_diffuse",
+            Aov::IndirectSpecular => "indirect_specular",
+            Aov::Emission => "emission",
+            Aov::SampleCount => "sample_count",
+        }
+    }
+
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Position | Aov::Normal => &["X", "Y", "Z"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            Aov::SampleCount => &["count"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    // Whether the pass holds radiance, which is displayed like the beauty image.
+    pub fn is_lighting(&self) -> bool {
+        matches!(self, Aov::DirectDiffuse | Aov::DirectSpecular | Aov::IndirectDiffuse
+                 | Aov::IndirectSpecular | Aov::Emission)
+    }
+
+    // A comma-separated list of pass names, or `all`.
+    pub fn parse_list(s: &str) -> Result<Vec<Aov>, String> {
+        if s.eq_ignore_ascii_case("all") {
+            return Ok(Aov::ALL.to_vec());
+        }
+        let mut aovs = Vec::new();
+        for name in s.split(',') {
+            let aov = name.trim().parse::<Aov>()?;
+            if !aovs.contains(&aov) {
+                aovs.push(aov);
+            }
+        }
+        Ok(aovs)
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Aov, String> {
+        let s = s.to_ascii_lowercase().replace('-', "_");
+        Aov::ALL.iter()
+            .find(|aov| aov.name() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown render pass `{}`", s))
+    }
+}
+
+impl fmt::Display for Aov {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// The surface a camera path hit first.
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceRecord {
+    pub depth: f64,
+    pub position: Vector,
+    pub normal: Vector,
+    pub albedo: Color,
+    pub object: usize,
+    pub material: usize,
+}
+
+// What one camera path saw at its first hit, and how its radiance splits into light
+// that arrived straight from the lights and light that bounced, by the lobe that
+// scattered it at the first hit. Emission includes the background.
+#[derive(Debug, Clone, Copy)]
+pub struct PathRecord {
+    pub surface: Option<SurfaceRecord>,
+    pub direct_diffuse: Color,
+    pub direct_specular: Color,
+    pub indirect_diffuse: Color,
+    pub indirect_specular: Color,
+    pub emission: Color,
+}
+
+impl PathRecord {
+    pub fn map_lighting<F: Fn(Color) -> Color>(&self, f: F) -> PathRecord {
+        PathRecord {
+            surface: self.surface,
+            direct_diffuse: f(self.direct_diffuse),
+            direct_specular: f(self.direct_specular),
+            indirect_diffuse: f(self.indirect_diffuse),
+            indirect_specular: f(self.indirect_specular),
+            emission: f(self.emission),
+        }
+    }
+}
+
+impl Default for PathRecord {
+    fn default() -> Self {
+        PathRecord {
+            surface: None,
+            direct_diffuse: Color::BLACK,
+            direct_specular: Color::BLACK,
+            indirect_diffuse: Color::BLACK,
+            indirect_specular: Color::BLACK,
+            emission: Color::BLACK,
+        }
+    }
+}
+
+// Path records of a pixel's samples. Geometric passes average over the samples that
+// hit something, IDs come from the first of them, and lighting averages over all.
+#[derive(Debug, Clone, Copy)]
+pub struct AovPixel {
+    samples: u32,
+    hits: u32,
+    depth: f64,
+    position: Vector,
+    normal: Vector,
+    albedo: Color,
+    ids: Option<(usize, usize)>,
+    lighting: PathRecord,
+}
+
+impl AovPixel {
+    pub fn add(&mut self, record: &PathRecord) {
+        self.samples += 1;
+        if let Some(s) = &record.surface {
+            self.hits += 1;
+            self.depth += s.depth;
+            self.position = self.position + s.position;
+            self.normal = self.normal + s.normal;
+            self.albedo += s.albedo;
+            self.ids.get_or_insert((s.object, s.material));
+        }
+        let l = &mut self.lighting;
+        l.direct_diffuse += record.direct_diffuse;
+        l.direct_specular += record.direct_specular;
+        l.indirect_diffuse += record.indirect_diffuse;
+        l.indirect_specular += record.indirect_specular;
+        l.emission += record.emission;
+    }
+
+    pub fn merge(&mut self, other: &AovPixel) {
+        self.samples += other.samples;
+        self.hits += other.hits;
+        self.depth += other.depth;
+        self.position = self.position + other.position;
+        self.normal = self.normal + other.normal;
+        self.albedo += other.albedo;
+        if self.ids.is_none() {
+            self.ids = other.ids;
+        }
+        let l = &mut self.lighting;
+        l.direct_diffuse += other.lighting.direct_diffuse;
+        l.direct_specular += other.lighting.direct_specular;
+        l.indirect_diffuse += other.lighting.indirect_diffuse;
+        l.indirect_specular += other.lighting.indirect_specular;
+        l.emission += other.lighting.emission;
+    }
+
+    pub fn features(&self) -> Features {
+        if self.hits == 0 {
+            return Features::MISS;
+        }
+        let n = self.hits as f64;
+        Features { albedo: self.albedo / n, normal: self.normal / n, depth: self.depth / n }
+    }
+
+    // Channel values of a pass. Pixels that saw only background have infinite depth,
+    // a zero position and normal, and ID 0; IDs otherwise count from 1.
+    pub fn value(&self, aov: Aov) -> [f64; 3] {
+        let hits = self.hits.max(1) as f64;
+        let samples = self.samples.max(1) as f64;
+        let color = |c: Color| [c.r(), c.g(), c.b()];
+        let vector = |v: Vector| [v.x / hits, v.y / hits, v.z / hits];
+        let id = |i: Option<usize>| [i.map_or(0.0, |i| (i + 1) as f64), 0.0, 0.0];
+        match aov {
+            Aov::Depth if self.hits == 0 => [f64::INFINITY, 0.0, 0.0],
+            Aov::Depth => [self.depth / hits, 0.0, 0.0],
+            Aov::Position => vector(self.position),
+            Aov::Normal => vector(self.normal),
+            Aov::Albedo => color(self.albedo / hits),
+            Aov::ObjectId => id(self.ids.map(|(object, _)| object)),
+            Aov::MaterialId => id(self.ids.map(|(_, material)| material)),
+            Aov::DirectDiffuse => color(self.lighting.direct_diffuse / samples),
+            Aov::DirectSpecular => color(self.lighting.direct_specular / samples),
+            Aov::IndirectDiffuse => color(self.lighting.indirect_diffuse / samples),
+            Aov::IndirectSpecular => color(self.lighting.indirect_specular / samples),
+            Aov::Emission => color(self.lighting.emission / samples),
+            Aov::SampleCount => [self.samples as f64, 0.0, 0.0],
+        }
+    }
+}
+
+impl Default for AovPixel {
+    fn default() -> Self {
+        AovPixel {
+            samples: 0,
+            hits: 0,
+            depth: 0.0,
+            position: Vector::ORIGIN,
+            normal: Vector::ORIGIN,
+            albedo: Color::BLACK,
+            ids: None,
+            lighting: PathRecord::default(),
+        }
+    }
+}
+
+// Passes for a whole image, with rows from the bottom up like `ImagePpm`.
+#[derive(Debug, Clone)]
+pub struct AovBuffer {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<AovPixel>,
+}
+
+impl AovBuffer {
+    pub fn new(width: u32, height: u32) -> AovBuffer {
+        AovBuffer { width, height, pixels: vec![AovPixel::default(); width as usize * height as usize] }
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, pixel: AovPixel) {
+        self.pixels[(y * self.width + x) as usize] = pixel;
+    }
+
+    // Adds more samples to a pixel.
+    pub fn add(&mut self, x: u32, y: u32, pixel: &AovPixel) {
+        self.pixels[(y * self.width + x) as usize].merge(pixel);
+    }
+
+    pub fn features(&self) -> Vec<Features> {
+        self.pixels.iter().map(AovPixel::features).collect()
+    }
+
+    // The passes of part of the image.
+    pub fn crop(&self, region: &Region) -> AovBuffer {
+        AovBuffer { width: region.width(), height: region.height(), pixels: region.crop(&self.pixels, self.width) }
+    }
+
+    // The passes of a stereo pair of images, laid out like the images.
+    pub fn stereo(left: &AovBuffer, right: &AovBuffer, layout: StereoLayout) -> AovBuffer {
+        let (width, height) = layout.size(left.width, left.height);
+        AovBuffer { width, height, pixels: layout.combine(&left.pixels, &right.pixels, left.width) }
+    }
+
+    pub fn encode(&self, out: &mut Encoder) {
+        out.u32(self.width);
+        out.u32(self.height);
+        for p in &self.pixels {
+            out.u32(p.samples);
+            out.u32(p.hits);
+            out.f64(p.depth);
+            for v in [p.position, p.normal] {
+                [v.x, v.y, v.z].into_iter().for_each(|x| out.f64(x));
+            }
+            let l = &p.lighting;
+            for c in [p.albedo, l.direct_diffuse, l.direct_specular, l.indirect_diffuse, l.indirect_specular, l.emission] {
+                [c.r(), c.g(), c.b()].into_iter().for_each(|x| out.f64(x));
+            }
+            match p.ids {
+                Some((object, material)) => {
+                    out.u8(1);
+                    out.u64(object as u64);
+                    out.u64(material as u64);
+                },
+                None => out.u8(0),
+            }
+        }
+    }
+
+    pub fn decode(input: &mut Decoder) -> io::Result<AovBuffer> {
+        let width = input.u32()?;
+        let height = input.u32()?;
+        // Pixels take at least this many bytes, so the size can be checked before
+        // allocating for it.
+        const MIN_ENCODED_SIZE: usize = 2 * 4 + 7 * 8 + 6 * 3 * 8 + 1;
+        let size = (width as usize).checked_mul(height as usize).and_then(|n| n.checked_mul(MIN_ENCODED_SIZE));
+        if size.is_none_or(|size| size > input.remaining()) {
+            return Err(invalid("render passes are truncated"));
+        }
+        let mut buffer = AovBuffer::new(width, height);
+        for p in buffer.pixels.iter_mut() {
+            p.samples = input.u32()?;
+            p.hits = input.u32()?;
+            p.depth = input.f64()?;
+            p.position = Vector::new(input.f64()?, input.f64()?, input.f64()?);
+            p.normal = Vector::new(input.f64()?, input.f64()?, input.f64()?);
+            let mut colors = [Color::BLACK; 6];
+            for c in colors.iter_mut() {
+                *c = Color::new(input.f64()?, input.f64()?, input.f64()?);
+            }
+            let [albedo, direct_diffuse, direct_specular, indirect_diffuse, indirect_specular, emission] = colors;
+            p.albedo = albedo;
+            p.lighting = PathRecord { surface: None, direct_diffuse, direct_specular, indirect_diffuse, indirect_specular, emission };
+            p.ids = match input.u8()? {
+                0 => None,
+                _ => Some((input.u64()? as usize, input.u64()? as usize)),
+            };
+        }
+        Ok(buffer)
+    }
+
+
+    // EXR channels of a pass, named `pass.channel`.
+    pub fn channels(&self, aov: Aov) -> Vec<Channel> {
+        aov.channels().iter().enumerate()
+            .map(|(i, channel)| {
+                let data = self.pixels.iter().map(|p| p.value(aov)[i] as f32).collect();
+                Channel::new(&format!("{}.{}", aov, channel), data)
+            })
+            .collect()
+    }
+
+    // A viewable image of a pass. Lighting goes through `display` like the beauty
+    // image; other passes are scaled into [0, 1]: depth, position and sample count by
+    // their range over the image, normals as (n + 1) / 2, and IDs as distinct colours.
+    pub fn image<F: Fn(Color) -> Color>(&self, aov: Aov, display: F) -> ImagePpm {
+        let values: Vec<[f64; 3]> = self.pixels.iter().map(|p| p.value(aov)).collect();
+        let (lo, hi) = range(&values, aov.channels().len());
+        let mut img = ImagePpm::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = (y * self.width + x) as usize;
+                let v = values[index];
+                let c = match aov {
+                    _ if aov.is_lighting() => display(Color::new(v[0], v[1], v[2])),
+                    Aov::Albedo => Color::new(v[0], v[1], v[2]),
+                    Aov::Normal => Color::from_normal(&Vector::new(v[0], v[1], v[2])),
+                    Aov::ObjectId | Aov::MaterialId => id_color(v[0] as usize),
+                    Aov::Depth if v[0].is_infinite() => Color::WHITE,
+                    Aov::Position if self.pixels[index].hits == 0 => Color::BLACK,
+                    _ => {
+                        let scale = |i: usize| if hi[i] > lo[i] { (v[i] - lo[i]) / (hi[i] - lo[i]) } else { 0.0 };
+                        match aov.channels().len() {
+                            1 => Color::new(scale(0), scale(0), scale(0)),
+                            _ => Color::new(scale(0), scale(1), scale(2)),
+                        }
+                    },
+                };
+                img.set_pixel(x, y, c);
+            }
+        }
+        img
+    }
+}
+
+// Per-channel range of the finite values.
+fn range(values: &[[f64; 3]], channels: usize) -> ([f64; 3], [f64; 3]) {
+    let mut lo = [f64::INFINITY; 3];
+    let mut hi = [f64::NEG_INFINITY; 3];
+    for v in values {
+        for i in 0..channels {
+            if v[i].is_finite() {
+                lo[i] = lo[i].min(v[i]);
+                hi[i] = hi[i].max(v[i]);
+            }
+        }
+    }
+    (lo, hi)
+}
+
+// Well separated colours for small integers, by stepping the hue by the golden angle.
+fn id_color(id: usize) -> Color {
+    if id == 0 {
+        return Color::BLACK;
+    }
+    let hue = (id as f64 * 0.618_033_988_75).fract() * 6.0;
+    let channel = |offset: f64| (((hue + offset) % 6.0 - 3.0).abs() - 1.0).clamp(0.0, 1.0);
+    Color::new(channel(0.0), channel(4.0), channel(2.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(object: usize, depth: f64) -> PathRecord {
+        PathRecord {
+            surface: Some(SurfaceRecord {
+                depth,
+                position: Vector::new(1.0, 2.0, 3.0),
+                normal: Vector::new(0.0, 1.0, 0.0),
+                albedo: Color::new(0.5, 0.5, 0.5),
+                object,
+                material: 0,
+            }),
+            direct_diffuse: Color::new(1.0, 0.0, 0.0),
+            ..PathRecord::default()
+        }
+    }
+
+    #[test]
+    fn test_aov_parse() {
+        assert_eq!(Aov::parse_list("all").unwrap().len(), Aov::ALL.len());
+        assert_eq!(Aov::parse_list("depth, object-id,depth").unwrap(), vec![Aov::Depth, Aov::ObjectId]);
+        assert!(Aov::parse_list("depth,motion").is_err());
+        for aov in Aov::ALL {
+            assert_eq!(aov.to_string().parse::<Aov>(), Ok(aov));
+        }
+    }
+
+    #[test]
+    fn test_aov_pixel() {
+        let mut pixel = AovPixel::default();
+        pixel.add(&record(3, 2.0));
+        pixel.add(&PathRecord { emission: Color::new(0.0, 0.0, 2.0), ..PathRecord::default() });
+        pixel.add(&record(5, 4.0));
+
+        assert_eq!(pixel.value(Aov::Depth)[0], 3.0);
+        assert_eq!(pixel.value(Aov::ObjectId)[0], 4.0);
+        assert_eq!(pixel.value(Aov::SampleCount)[0], 3.0);
+        assert_eq!(pixel.value(Aov::Normal), [0.0, 1.0, 0.0]);
+        let direct = pixel.value(Aov::DirectDiffuse);
+        assert!((direct[0] - 2.0 / 3.0).abs() < 1e-12);
+        assert!((pixel.value(Aov::Emission)[2] - 2.0 / 3.0).abs() < 1e-12);
+        assert_eq!(pixel.features().depth, 3.0);
+
+        let background = AovPixel::default();
+        assert!(background.value(Aov::Depth)[0].is_infinite());
+        assert_eq!(background.value(Aov::ObjectId)[0], 0.0);
+        assert_eq!(background.features(), Features::MISS);
+
+        // Merging pixels is the same as adding their samples to one.
+        let mut first = AovPixel::default();
+        first.add(&record(3, 2.0));
+        let mut rest = AovPixel::default();
+        rest.add(&PathRecord { emission: Color::new(0.0, 0.0, 2.0), ..PathRecord::default() });
+        rest.add(&record(5, 4.0));
+        first.merge(&rest);
+        for aov in Aov::ALL {
+            assert_eq!(first.value(aov), pixel.value(aov));
+        }
+    }
+
+    #[test]
+    fn test_aov_buffer() {
+        let mut buffer = AovBuffer::new(2, 1);
+        let mut near = AovPixel::default();
+        near.add(&record(0, 1.0));
+        let mut far = AovPixel::default();
+        far.add(&record(1, 5.0));
+        buffer.set(0, 0, near);
+        buffer.set(1, 0, far);
+
+        let channels = buffer.channels(Aov::Position);
+        let names: Vec<&str> = channels.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["position.X", "position.Y", "position.Z"]);
+
+        let depth = buffer.image(Aov::Depth, |c| c);
+        assert_eq!(depth.get_pixel(0, 0), Color::BLACK);
+        assert_eq!(depth.get_pixel(1, 0), Color::WHITE);
+        let ids = buffer.image(Aov::ObjectId, |c| c);
+        assert!(ids.get_pixel(0, 0) != ids.get_pixel(1, 0));
+    }
+}
@@ -0,0 +1,468 @@
+use std::f64::consts::PI;
+use rand::Rng;
+
+use crate::color::Color;
+use crate::vec::Vector;
+
+const MIN_ALPHA: f64 = 1e-3;
+const CLEARCOAT_F0: f64 = 0.04;
+
+// Orthonormal shading frame; local coordinates have the normal along +z.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub s: Vector,
+    pub t: Vector,
+    pub n: Vector,
+}
+
+impl Frame {
+    pub fn new(s: Vector, t: Vector, n: Vector) -> Frame {
+        Frame { s, t, n, }
+    }
+
+    // Branchless construction from Duff et al., "Building an Orthonormal Basis, Revisited".
+    pub fn from_normal(n: &Vector) -> Frame {
+        let sign = 1.0f64.copysign(n.z);
+        let a = -1.0 / (sign + n.z);
+        let b = n.x * n.y * a;
+        let s = Vector::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x);
+        let t = Vector::new(b, sign + n.y * n.y * a, -n.y);
+        Frame { s, t, n: *n, }
+    }
+
+    pub fn to_local(&self, v: &Vector) -> Vector {
+        Vector::new(*v * self.s, *v * self.t, *v * self.n)
+    }
+
+    pub fn to_world(&self, v: &Vector) -> Vector {
+        v.x * self.s + v.y * self.t + v.z * self.n
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    pub wi: Vector,
+    // BSDF value times cosine divided by the sampling density.
+    pub weight: Color,
+    // The part of `weight` due to the diffuse and sheen lobes.
+    pub diffuse_weight: Color,
+    pub pdf: f64,
+}
+
+// Principled BSDF at a single shading point, in the spirit of the Disney model and the
+// glTF PBR reference: a Lambertian base with sheen under a dielectric GGX specular
+// layer, a conductor lobe tinted by the base colour, rough dielectric transmission and
+// a clearcoat. Both directions point away from the surface.
+#[derive(Debug, Clone, Copy)]
+pub struct Bsdf {
+    pub frame: Frame,
+    pub base_color: Color,
+    pub metallic: f64,
+    pub alpha: f64,
+    pub specular_f0: f64,
+    pub sheen: Color,
+    pub clearcoat: f64,
+    pub clearcoat_alpha: f64,
+    pub transmission: f64,
+    // Ratio of the refractive index below the surface to the one above it.
+    pub eta: f64,
+}
+
+impl Bsdf {
+    pub fn eval(&self, wo: &Vector, wi: &Vector) -> Color {
+        self.eval_local(&self.frame.to_local(wo), &self.frame.to_local(wi))
+    }
+
+    // `eval` along with its diffuse and sheen part, as (diffuse, total).
+    pub fn eval_split(&self, wo: &Vector, wi: &Vector) -> (Color, Color) {
+        self.eval_split_local(&self.frame.to_local(wo), &self.frame.to_local(wi))
+    }
+
+    pub fn pdf(&self, wo: &Vector, wi: &Vector) -> f64 {
+        self.pdf_local(&self.frame.to_local(wo), &self.frame.to_local(wi))
+    }
+
+    pub fn sample(&self, wo: &Vector) -> Option<BsdfSample> {
+        let wo = self.frame.to_local(wo);
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        let u: [f64; 3] = [rng.gen(), rng.gen(), rng.gen()];
+        let [p_diffuse, p_specular, p_glass, _] = self.lobe_probabilities(wo.z)?;
+        let wi = if u[0] < p_diffuse {
+            sample_cosine_hemisphere(u[1], u[2])
+        } else if u[0] < p_diffuse + p_specular {
+            reflect(&wo, &sample_ggx_vndf(self.alpha, &wo, u[1], u[2]))
+        } else if u[0] < p_diffuse + p_specular + p_glass {
+            let h = sample_ggx_vndf(self.alpha, &wo, u[1], u[2]);
+            let f = fresnel_dielectric(wo * h, self.eta);
+            // Reuse the lobe-selection variable so the sample needs no extra random number.
+            let u_refract = (u[0] - p_diffuse - p_specular) / p_glass;
+            if u_refract < f {
+                reflect(&wo, &h)
+            } else {
+                refract(&wo, &h, self.eta)?
+            }
+        } else {
+            reflect(&wo, &sample_ggx_vndf(self.clearcoat_alpha, &wo, u[1], u[2]))
+        };
+
+        let pdf = self.pdf_local(&wo, &wi);
+        if pdf <= 0.0 || !pdf.is_finite() {
+            return None;
+        }
+        let (diffuse, f) = self.eval_split_local(&wo, &wi);
+        Some(BsdfSample { wi: self.frame.to_world(&wi), weight: f / pdf, diffuse_weight: diffuse / pdf, pdf })
+    }
+
+    // Probabilities of sampling the diffuse, specular, glass and clearcoat lobes,
+    // roughly in proportion to their contribution at this viewing angle.
+    fn lobe_probabilities(&self, cos_o: f64) -> Option<[f64; 4]> {
+        let dielectric = 1.0 - self.metallic;
+        let fd = schlick(self.specular_f0, cos_o);
+        let fm = schlick_color(self.base_color, cos_o).luminance();
+        let fc = schlick(CLEARCOAT_F0, cos_o);
+        let weights = [
+            dielectric * (1.0 - self.transmission) * (1.0 - fd)
+                * (self.base_color.luminance() + self.sheen.luminance()),
+            dielectric * (1.0 - self.transmission) * fd + self.metallic * fm,
+            dielectric * self.transmission,
+            self.clearcoat * fc,
+        ];
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+        Some(weights.map(|w| w / total))
+    }
+
+    fn eval_local(&self, wo: &Vector, wi: &Vector) -> Color {
+        self.eval_split_local(wo, wi).1
+    }
+
+    // The diffuse part of the BSDF times cosine, and the whole of it.
+    fn eval_split_local(&self, wo: &Vector, wi: &Vector) -> (Color, Color) {
+        let cos_o = wo.z;
+        let cos_i = wi.z;
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return (Color::BLACK, Color::BLACK);
+        }
+        let dielectric = 1.0 - self.metallic;
+
+        if cos_i > 0.0 {
+            let h = (*wo + *wi).normalize();
+            let cos_d = (*wo * h).max(0.0);
+            let specular = ggx_reflection(self.alpha, wo, wi, &h);
+            let fd = schlick(self.specular_f0, cos_d);
+            let diffuse = self.base_color / PI + self.sheen * (1.0 - cos_d).powi(5);
+
+            let mut d = (dielectric * (1.0 - self.transmission) * (1.0 - fd)) * diffuse;
+            let opaque = fd * specular * Color::WHITE;
+            let glass = fresnel_dielectric(cos_d, self.eta) * specular * Color::WHITE;
+            let metal = specular * schlick_color(self.base_color, cos_d);
+            let mut f = d + dielectric * ((1.0 - self.transmission) * opaque + self.transmission * glass)
+                + self.metallic * metal;
+
+            if self.clearcoat > 0.0 {
+                let fc = self.clearcoat * schlick(CLEARCOAT_F0, cos_d);
+                let coat = ggx_reflection(self.clearcoat_alpha, wo, wi, &h);
+                d = (1.0 - fc) * d;
+                f = (1.0 - fc) * f + fc * coat * Color::WHITE;
+            }
+            return (cos_i * d, cos_i * f);
+        }
+
+        if self.transmission <= 0.0 || dielectric <= 0.0 {
+            return (Color::BLACK, Color::BLACK);
+        }
+        let Some((wm, denom)) = refraction_half_vector(wo, wi, self.eta) else {
+            return (Color::BLACK, Color::BLACK);
+        };
+        let f = fresnel_dielectric(*wo * wm, self.eta);
+        let g = smith_g2(self.alpha, wo, wi);
+        // Radiance is compressed by eta^2 when entering a denser medium.
+        let ft = ggx_d(self.alpha, &wm) * g * (1.0 - f) * ((*wi * wm) * (*wo * wm)).abs()
+            / (cos_i.abs() * cos_o * denom * self.eta * self.eta);
+        (Color::BLACK, (dielectric * self.transmission * ft * cos_i.abs()) * self.base_color)
+    }
+
+    fn pdf_local(&self, wo: &Vector, wi: &Vector) -> f64 {
+        let Some([p_diffuse, p_specular, p_glass, p_clearcoat]) = self.lobe_probabilities(wo.z) else {
+            return 0.0;
+        };
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+
+        if wi.z > 0.0 {
+            let h = (*wo + *wi).normalize();
+            let cos_d = *wo * h;
+            if cos_d <= 0.0 {
+                return 0.0;
+            }
+            let jacobian = 1.0 / (4.0 * cos_d);
+            let mut pdf = p_diffuse * wi.z / PI;
+            pdf += p_specular * ggx_vndf_pdf(self.alpha, wo, &h) * jacobian;
+            if p_glass > 0.0 {
+                pdf += p_glass * fresnel_dielectric(cos_d, self.eta) * ggx_vndf_pdf(self.alpha, wo, &h) * jacobian;
+            }
+            if p_clearcoat > 0.0 {
+                pdf += p_clearcoat * ggx_vndf_pdf(self.clearcoat_alpha, wo, &h) * jacobian;
+            }
+            return pdf;
+        }
+
+        if p_glass <= 0.0 {
+            return 0.0;
+        }
+        match refraction_half_vector(wo, wi, self.eta) {
+            Some((wm, denom)) => {
+                let f = fresnel_dielectric(*wo * wm, self.eta);
+                let dwm_dwi = (*wi * wm).abs() / denom;
+                p_glass * (1.0 - f) * ggx_vndf_pdf(self.alpha, wo, &wm) * dwm_dwi
+            },
+            None => 0.0,
+        }
+    }
+}
+
+pub fn roughness_to_alpha(roughness: f64) -> f64 {
+    (roughness * roughness).max(MIN_ALPHA)
+}
+
+// GGX / Trowbridge-Reitz normal distribution.
+pub fn ggx_d(alpha: f64, h: &Vector) -> f64 {
+    if h.z <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let d = h.z * h.z * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+fn smith_lambda(alpha: f64, w: &Vector) -> f64 {
+    let cos2 = w.z * w.z;
+    if cos2 <= 0.0 {
+        return f64::INFINITY;
+    }
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    0.5 * (-1.0 + (1.0 + alpha * alpha * tan2).sqrt())
+}
+
+pub fn smith_g1(alpha: f64, w: &Vector) -> f64 {
+    1.0 / (1.0 + smith_lambda(alpha, w))
+}
+
+// Height-correlated masking-shadowing.
+pub fn smith_g2(alpha: f64, wo: &Vector, wi: &Vector) -> f64 {
+    1.0 / (1.0 + smith_lambda(alpha, wo) + smith_lambda(alpha, wi))
+}
+
+fn ggx_reflection(alpha: f64, wo: &Vector, wi: &Vector, h: &Vector) -> f64 {
+    ggx_d(alpha, h) * smith_g2(alpha, wo, wi) / (4.0 * wo.z * wi.z)
+}
+
+// Density of visible normals, D_wo(h) = G1(wo) max(0, wo.h) D(h) / cos(wo).
+pub fn ggx_vndf_pdf(alpha: f64, wo: &Vector, h: &Vector) -> f64 {
+    smith_g1(alpha, wo) * (*wo * *h).max(0.0) * ggx_d(alpha, h) / wo.z
+}
+
+// Samples a visible microfacet normal (Heitz, "Sampling the GGX Distribution of
+// Visible Normals", 2018).
+pub fn sample_ggx_vndf(alpha: f64, wo: &Vector, u1: f64, u2: f64) -> Vector {
+    let vh = Vector::new(alpha * wo.x, alpha * wo.y, wo.z).normalize();
+    let len2 = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len2 > 0.0 {
+        Vector::new(-vh.y, vh.x, 0.0) / len2.sqrt()
+    } else {
+        Vector::new(1.0, 0.0, 0.0)
+    };
+    let t2 = vh.cross(&t1);
+
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+    Vector::new(alpha * nh.x, alpha * nh.y, nh.z.max(0.0)).normalize()
+}
+
+pub fn sample_cosine_hemisphere(u1: f64, u2: f64) -> Vector {
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    Vector::new(r * phi.cos(), r * phi.sin(), (1.0 - u1).max(0.0).sqrt())
+}
+
+fn schlick(f0: f64, cos_theta: f64) -> f64 {
+    f0 + (1.0 - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+fn schlick_color(f0: Color, cos_theta: f64) -> Color {
+    let w = (1.0 - cos_theta).clamp(0.0, 1.0).powi(5);
+    (1.0 - w) * f0 + w * Color::WHITE
+}
+
+// Unpolarised Fresnel reflectance of a dielectric interface, with `cos_i` measured on
+// the side the light arrives from and `eta` the relative index across the interface.
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(-1.0, 1.0);
+    let (cos_i, eta) = if cos_i < 0.0 { (-cos_i, 1.0 / eta) } else { (cos_i, eta) };
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parl * r_parl + r_perp * r_perp)
+}
+
+fn reflect(wo: &Vector, h: &Vector) -> Vector {
+    2.0 * (*wo * *h) * *h - *wo
+}
+
+fn refract(wo: &Vector, h: &Vector, eta: f64) -> Option<Vector> {
+    let cos_i = *wo * *h;
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-*wo / eta + (cos_i / eta - cos_t) * *h)
+}
+
+// Generalised half vector for refraction and the squared denominator of the
+// half-vector Jacobian, or None if the pair cannot be connected by refraction.
+fn refraction_half_vector(wo: &Vector, wi: &Vector, eta: f64) -> Option<(Vector, f64)> {
+    let mut wm = eta * *wi + *wo;
+    if wm.near_zero() {
+        return None;
+    }
+    wm = wm.normalize();
+    if wm.z < 0.0 {
+        wm = -wm;
+    }
+    if *wi * wm >= 0.0 || *wo * wm <= 0.0 {
+        return None;
+    }
+    let denom = (*wi * wm + (*wo * wm) / eta).powi(2);
+    Some((wm, denom))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_cmp::assert_approx_eq;
+
+    fn bsdf(metallic: f64, roughness: f64, transmission: f64) -> Bsdf {
+        Bsdf {
+            frame: Frame::from_normal(&Vector::new(0.0, 0.0, 1.0)),
+            base_color: Color::new(0.8, 0.8, 0.8),
+            metallic,
+            alpha: roughness_to_alpha(roughness),
+            specular_f0: 0.04,
+            sheen: Color::BLACK,
+            clearcoat: 0.0,
+            clearcoat_alpha: roughness_to_alpha(0.1),
+            transmission,
+            eta: 1.5,
+        }
+    }
+
+    #[test]
+    fn test_frame_orthonormal() {
+        for n in [Vector::new(0.0, 0.0, 1.0), Vector::new(0.0, 0.0, -1.0), Vector::new(1.0, 2.0, -3.0).normalize()] {
+            let f = Frame::from_normal(&n);
+            assert_approx_eq!(f64, f.s * f.t, 0.0, epsilon = 1e-12);
+            assert_approx_eq!(f64, f.s * f.n, 0.0, epsilon = 1e-12);
+            assert_approx_eq!(f64, f.s.length(), 1.0, epsilon = 1e-12);
+            let v = Vector::new(0.3, -0.2, 0.5);
+            assert!((f.to_world(&f.to_local(&v)) - v).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_ggx_normalized() {
+        // The projected area of the microfacets must equal the macro surface:
+        // the integral of D(h) cos(h) over the hemisphere is one.
+        let alpha = roughness_to_alpha(0.5);
+        let n = 512;
+        let mut sum = 0.0;
+        for i in 0..n {
+            let cos_theta = (i as f64 + 0.5) / n as f64;
+            let h = Vector::new((1.0 - cos_theta * cos_theta).sqrt(), 0.0, cos_theta);
+            sum += ggx_d(alpha, &h) * cos_theta * 2.0 * PI / n as f64;
+        }
+        assert_approx_eq!(f64, sum, 1.0, epsilon = 1e-3);
+    }
+
+    #[test]
+    fn test_fresnel_dielectric() {
+        assert_approx_eq!(f64, fresnel_dielectric(1.0, 1.5), 0.04, epsilon = 1e-12);
+        assert_eq!(fresnel_dielectric(-0.2, 1.5), 1.0);
+        assert_eq!(fresnel_dielectric(0.0, 1.5), 1.0);
+    }
+
+    // Monte Carlo estimate of the directional albedo for a fixed outgoing direction.
+    fn albedo(b: &Bsdf, wo: &Vector, n: usize) -> Color {
+        let mut sum = Color::BLACK;
+        for _ in 0..n {
+            if let Some(s) = b.sample(wo) {
+                sum += s.weight;
+            }
+        }
+        sum / n as f64
+    }
+
+    #[test]
+    fn test_energy_conservation() {
+        let wo = Vector::new(0.6, 0.0, 0.8);
+        for (metallic, roughness, transmission) in [(0.0, 0.5, 0.0), (1.0, 0.3, 0.0), (0.0, 0.2, 1.0), (0.5, 0.8, 0.0)] {
+            let a = albedo(&bsdf(metallic, roughness, transmission), &wo, 20000);
+            assert!(a.r() <= 1.02, "albedo {:?} for {:?}", a, (metallic, roughness, transmission));
+        }
+
+        // A white rough metal should reflect most of the light.
+        let mut white_metal = bsdf(1.0, 0.3, 0.0);
+        white_metal.base_color = Color::WHITE;
+        let a = albedo(&white_metal, &Vector::new(0.0, 0.0, 1.0), 20000);
+        assert!(a.r() > 0.85, "white metal albedo {:?}", a);
+    }
+
+    #[test]
+    fn test_sample_matches_pdf() {
+        let b = Bsdf { clearcoat: 0.5, sheen: Color::new(0.1, 0.1, 0.1), ..bsdf(0.3, 0.4, 0.5) };
+        let wo = Vector::new(0.0, 0.6, 0.8);
+        for _ in 0..1000 {
+            if let Some(s) = b.sample(&wo) {
+                assert_approx_eq!(f64, s.pdf, b.pdf(&wo, &s.wi), epsilon = 1e-9 * s.pdf.max(1.0));
+                let expected = b.eval(&wo, &s.wi) / s.pdf;
+                assert_eq!(s.weight, expected);
+                assert_eq!(s.diffuse_weight, b.eval_split(&wo, &s.wi).0 / s.pdf);
+            }
+        }
+    }
+
+    #[test]
+    fn test_eval_split() {
+        let wo = Vector::new(0.0, 0.6, 0.8);
+        let wi = Vector::new(0.3, -0.4, 0.866).normalize();
+        let plastic = bsdf(0.0, 0.5, 0.0);
+        let (d, f) = plastic.eval_split(&wo, &wi);
+        assert_eq!(f, plastic.eval(&wo, &wi));
+        assert!(d.r() > 0.0 && d.r() < f.r());
+        assert!(bsdf(1.0, 0.5, 0.0).eval_split(&wo, &wi).0.is_black());
+        assert!(bsdf(0.0, 0.5, 1.0).eval_split(&wo, &wi).0.is_black());
+    }
+
+    #[test]
+    fn test_transmission_refracts() {
+        let b = bsdf(0.0, 0.0, 1.0);
+        let wo = Vector::new(0.0, 0.0, 1.0);
+        let transmitted = (0..100).filter_map(|_| b.sample(&wo)).filter(|s| s.wi.z < 0.0).count();
+        assert!(transmitted > 80);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::ray::{Ray, HitRecord};
+use crate::vec::Vector;
+
+const MAX_LEAF_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vector,
+    pub max: Vector,
+}
+
+impl Aabb {
+    pub const EMPTY: Aabb = Aabb {
+        min: Vector { x: f64::INFINITY, y: f64::INFINITY, z: f64::INFINITY, },
+        max: Vector { x: f64::NEG_INFINITY, y: f64::NEG_INFINITY, z: f64::NEG_INFINITY, },
+    };
+
+    pub fn new(min: Vector, max: Vector) -> Aabb {
+        Aabb { min, max, }
+    }
+
+    pub fn from_points(points: &[Vector]) -> Aabb {
+        points.iter().fold(Aabb::EMPTY, |b, p| b.grow(p))
+    }
+
+    pub fn grow(&self, p: &Vector) -> Aabb {
+        Aabb {
+            min: Vector::new(self.min.x.min(p.x), self.min.y.min(p.y), self.min.z.min(p.z)),
+            max: Vector::new(self.max.x.max(p.x), self.max.y.max(p.y), self.max.z.max(p.z)),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        self.grow(&other.min).grow(&other.max)
+    }
+
+    pub fn centroid(&self) -> Vector {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn extent(&self) -> Vector {
+        self.max - self.min
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for axis in 0..3 {
+            let inv_d = 1.0 / component(&r.direction, axis);
+            let mut near = (component(&self.min, axis) - component(&r.origin, axis)) * inv_d;
+            let mut far = (component(&self.max, axis) - component(&r.origin, axis)) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut near, &mut far);
+            }
+            // NaN from 0 * inf leaves the bounds untouched rather than rejecting the ray.
+            if near > t0 {
+                t0 = near;
+            }
+            if far < t1 {
+                t1 = far;
+            }
+            if t1 < t0 {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BvhNode {
+    Leaf { bounds: Aabb, first: usize, count: usize },
+    Interior { bounds: Aabb, right: usize },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            BvhNode::Leaf { bounds, .. } => bounds,
+            BvhNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+// Bounding volume hierarchy over an arbitrary set of primitives. Nodes are stored
+// depth-first, so the left child of an interior node immediately follows it.
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * bounds.len() / MAX_LEAF_SIZE + 1),
+            indices: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            bvh.build(bounds, 0, bounds.len());
+        }
+        bvh
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        match self.nodes.first() {
+            Some(node) => *node.bounds(),
+            None => Aabb::EMPTY,
+        }
+    }
+
+    fn build(&mut self, bounds: &[Aabb], first: usize, end: usize) -> usize {
+        let node_bounds = self.indices[first..end].iter()
+            .fold(Aabb::EMPTY, |b, &i| b.union(&bounds[i]));
+        let node = self.nodes.len();
+        let count = end - first;
+        if count <= MAX_LEAF_SIZE {
+            self.nodes.push(BvhNode::Leaf { bounds: node_bounds, first, count });
+            return node;
+        }
+
+        let centroids = self.indices[first..end].iter()
+            .fold(Aabb::EMPTY, |b, &i| b.grow(&bounds[i].centroid()));
+        let extent = centroids.extent();
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        let mid = first + count / 2;
+        self.indices[first..end].select_nth_unstable_by(count / 2, |&a, &b| {
+            let ca = component(&bounds[a].centroid(), axis);
+            let cb = component(&bounds[b].centroid(), axis);
+            ca.total_cmp(&cb)
+        });
+
+        self.nodes.push(BvhNode::Interior { bounds: node_bounds, right: 0 });
+        self.build(bounds, first, mid);
+        let right_child = self.build(bounds, mid, end);
+        self.nodes[node] = BvhNode::Interior { bounds: node_bounds, right: right_child };
+        node
+    }
+
+    // Finds the closest primitive hit. `hit_primitive` is called with a primitive index
+    // and the closest distance found so far.
+    pub fn hit<F>(&self, r: &Ray, t_min: f64, t_max: f64, mut hit_primitive: F) -> Option<HitRecord>
+    where F: FnMut(usize, f64) -> Option<HitRecord>
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut closest = t_max;
+        let mut hit_record = None;
+        let mut stack = vec![0usize];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bounds().hit(r, t_min, closest) {
+                continue;
+            }
+            match *node {
+                BvhNode::Leaf { first, count, .. } => {
+                    for &prim in &self.indices[first..first + count] {
+                        if let Some(hit) = hit_primitive(prim, closest) {
+                            closest = hit.t;
+                            hit_record = Some(hit);
+                        }
+                    }
+                },
+                BvhNode::Interior { right, .. } => {
+                    stack.push(right);
+                    stack.push(index + 1);
+                },
+            }
+        }
+        hit_record
+    }
+}
+
+fn component(v: &Vector, axis: usize) -> f64 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aabb_hit() {
+        let aabb = Aabb::new(Vector::new(-1.0, -1.0, -1.0), Vector::new(1.0, 1.0, 1.0));
+        let ray = Ray::new(Vector::new(0.0, 0.0, -5.0), Vector::new(0.0, 0.0, 1.0));
+        assert!(aabb.hit(&ray, 0.0, f64::INFINITY));
+        assert!(!aabb.hit(&ray, 0.0, 3.0));
+
+        let ray = Ray::new(Vector::new(2.0, 0.0, -5.0), Vector::new(0.0, 0.0, 1.0));
+        assert!(!aabb.hit(&ray, 0.0, f64::INFINITY));
+    }
+
+    #[test]
+    fn test_bvh_closest_hit() {
+        // A row of unit boxes along -z; the ray must report the nearest one.
+        let bounds: Vec<Aabb> = (0..20)
+            .map(|i| {
+                let z = -2.0 * i as f64 - 2.0;
+                Aabb::new(Vector::new(-0.5, -0.5, z - 0.5), Vector::new(0.5, 0.5, z + 0.5))
+            })
+            .collect();
+        let bvh = Bvh::new(&bounds);
+        let ray = Ray::new(Vector::ORIGIN, Vector::new(0.0, 0.0, -1.0));
+        let hit = bvh.hit(&ray, 0.0, f64::INFINITY, |i, t_max| {
+            let t = -bounds[i].max.z;
+            if t < t_max {
+                Some(HitRecord::new(ray.at(t), Vector::new(0.0, 0.0, 1.0), t, true))
+            } else {
+                None
+            }
+        });
+        assert_eq!(hit.expect("no hit").t, 1.5);
+    }
+}
@@ -1,12 +1,165 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
 use crate::vec::Vector;
 use crate::ray::Ray;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FisheyeMapping {
+    // Distance from the centre proportional to the angle from the view axis.
+    Equidistant,
+    // Equal areas of the image for equal solid angles.
+    Equisolid,
+}
+
+// How a camera maps the image to directions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    // Parallel rays across a viewport the size of the camera's, so that objects keep
+    // their size with distance.
+    Orthographic,
+    // A circle of `fov` degrees across the height of the image.
+    Fisheye { mapping: FisheyeMapping, fov: f64 },
+    // Longitude across the image and latitude up it, all the way around.
+    Equirectangular,
+    // Six square faces of 90 degrees, in a 3 x 2 grid of right, left and up above
+    // down, front and back.
+    Cubemap,
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    // Accepts `perspective`, `orthographic`, `equirectangular`, `cubemap` and
+    // `fisheye`, optionally followed by `:equidistant` or `:equisolid` and `:FOV`.
+    fn from_str(s: &str) -> Result<Projection, String> {
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let projection = match name.as_str() {
+            "perspective" => Projection::Perspective,
+            "orthographic" => Projection::Orthographic,
+            "equirectangular" => Projection::Equirectangular,
+            "cubemap" => Projection::Cubemap,
+            "fisheye" => {
+                let mut mapping = FisheyeMapping::Equidistant;
+                let mut fov = 180.0;
+                let mut rest = parts.by_ref().peekable();
+                match rest.peek().map(|m| m.to_ascii_lowercase()).as_deref() {
+                    Some("equidistant") => { rest.next(); },
+                    Some("equisolid") => {
+                        mapping = FisheyeMapping::Equisolid;
+                        rest.next();
+                    },
+                    _ => (),
+                }
+                if let Some(f) = rest.next() {
+                    fov = f.parse::<f64>().ok().filter(|f| *f > 0.0 && *f <= 360.0)
+                        .ok_or_else(|| format!("invalid fisheye field of view `{}`", f))?;
+                }
+                Projection::Fisheye { mapping, fov }
+            },
+            _ => return Err(format!("unknown projection `{}`", s)),
+        };
+        match parts.next() {
+            Some(_) => Err(format!("unknown projection `{}`", s)),
+            None => Ok(projection),
+        }
+    }
+}
+
+impl fmt::Display for Projection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Projection::Perspective => write!(f, "perspective"),
+            Projection::Orthographic => write!(f, "orthographic"),
+            Projection::Fisheye { mapping: FisheyeMapping::Equidistant, fov } => write!(f, "fisheye:equidistant:{}", fov),
+            Projection::Fisheye { mapping: FisheyeMapping::Equisolid, fov } => write!(f, "fisheye:equisolid:{}", fov),
+            Projection::Equirectangular => write!(f, "equirectangular"),
+            Projection::Cubemap => write!(f, "cubemap"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl fmt::Display for Eye {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Eye::Left => write!(f, "left"),
+            Eye::Right => write!(f, "right"),
+        }
+    }
+}
+
+// How the two images of a stereo pair share one, with the left eye on the left or
+// at the top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StereoLayout {
+    SideBySide,
+    TopBottom,
+}
+
+impl StereoLayout {
+    // The size of the pair of images of a size.
+    pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            StereoLayout::SideBySide => (2 * width, height),
+            StereoLayout::TopBottom => (width, 2 * height),
+        }
+    }
+
+    // Lays out the pixels of two images `width` across, with rows from the bottom up.
+    pub fn combine<T: Copy>(&self, left: &[T], right: &[T], width: u32) -> Vec<T> {
+        let width = width as usize;
+        match self {
+            StereoLayout::SideBySide => left.chunks(width).zip(right.chunks(width))
+                .flat_map(|(l, r)| l.iter().chain(r))
+                .copied()
+                .collect(),
+            StereoLayout::TopBottom => right.iter().chain(left).copied().collect(),
+        }
+    }
+}
+
+impl FromStr for StereoLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StereoLayout, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "side-by-side" | "sbs" => Ok(StereoLayout::SideBySide),
+            "top-bottom" | "over-under" => Ok(StereoLayout::TopBottom),
+            _ => Err(format!("unknown stereo layout `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for StereoLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StereoLayout::SideBySide => write!(f, "side-by-side"),
+            StereoLayout::TopBottom => write!(f, "top-bottom"),
+        }
+    }
+}
+
+// The viewport is one unit in front of the origin, except for orthographic cameras,
+// whose viewport is the size of the view and rays start level with the origin.
 #[derive(Debug, Clone, Copy)]
 pub struct Camera {
     pub origin: Vector,
     pub lower_left: Vector,
     pub horizontal: Vector,
     pub vertical: Vector,
+    pub projection: Projection,
+    // For omnidirectional stereo panoramas, how far right of the origin an eye is.
+    // Each ray starts on the circle of eyes looking its way.
+    pub eye_offset: f64,
 }
 
 impl Camera {
@@ -22,15 +175,151 @@ impl Camera {
         let horizontal = Vector::new(viewport_width, 0.0, 0.0);
         let vertical = Vector::new(0.0, viewport_height, 0.0);
         let lower_left = origin - horizontal / 2.0 - vertical / 2.0 - Vector::new(0.0, 0.0, focal_length);
-        Camera { origin, lower_left, horizontal, vertical, }
+        Camera { origin, lower_left, horizontal, vertical, projection: Projection::Perspective, eye_offset: 0.0 }
     }
 
-    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
-        Ray {
-            origin: self.origin,
-            direction: self.lower_left + u * self.horizontal + v * self.vertical - self.origin,
+    // Pinhole camera at `from` looking towards `at`. `vfov` is the vertical field of
+    // view in degrees.
+    pub fn look_at(from: Vector, at: Vector, vup: Vector, vfov: f64, aspect_ratio: f64) -> Camera {
+        Camera::from_frame(from, at - from, vup, vfov, aspect_ratio)
+    }
+
+    pub fn from_frame(origin: Vector, forward: Vector, vup: Vector, vfov: f64, aspect_ratio: f64) -> Camera {
+        let viewport_height = 2.0 * (vfov.to_radians() / 2.0).tan();
+        let viewport_width = aspect_ratio * viewport_height;
+
+        let w = -forward.normalize();
+        let u = vup.cross(&w).normalize();
+        let v = w.cross(&u);
+
+        let horizontal = viewport_width * u;
+        let vertical = viewport_height * v;
+        let lower_left = origin - horizontal / 2.0 - vertical / 2.0 - w;
+        Camera { origin, lower_left, horizontal, vertical, projection: Projection::Perspective, eye_offset: 0.0 }
+    }
+
+    // Orthographic camera at `origin` showing a view `height` across.
+    pub fn orthographic(origin: Vector, forward: Vector, vup: Vector, height: f64, aspect_ratio: f64) -> Camera {
+        Camera { projection: Projection::Orthographic, ..Camera::from_frame(origin, forward, vup, 90.0, aspect_ratio) }
+            .with_view_height(height)
+    }
+
+    pub fn with_projection(&self, projection: Projection) -> Camera {
+        Camera { projection, ..*self }
+    }
+
+    // Same view with the viewport scaled about its centre to a new height.
+    pub fn with_view_height(&self, height: f64) -> Camera {
+        let scale = height / self.vertical.length();
+        let (horizontal, vertical) = (scale * self.horizontal, scale * self.vertical);
+        let lower_left = self.target() - horizontal / 2.0 - vertical / 2.0;
+        Camera { lower_left, horizontal, vertical, ..*self }
+    }
+
+    // The view of one eye of a pair `ipd` apart. Perspective eyes share the window at
+    // the convergence distance, so that things there appear level with the screen, or
+    // look in parallel without one. Panoramas are omnidirectional stereo.
+    pub fn eye(&self, eye: Eye, ipd: f64, convergence: Option<f64>) -> Camera {
+        let offset = match eye {
+            Eye::Left => -ipd / 2.0,
+            Eye::Right => ipd / 2.0,
+        };
+        let shift = offset * self.horizontal.normalize();
+        match self.projection {
+            Projection::Equirectangular => Camera { eye_offset: offset, ..*self },
+            Projection::Perspective => {
+                let skew = convergence.map_or(0.0, |distance| 1.0 / distance);
+                Camera { origin: self.origin + shift, lower_left: self.lower_left + (1.0 - skew) * shift, ..*self }
+            },
+            _ => Camera { origin: self.origin + shift, lower_left: self.lower_left + shift, ..*self },
+        }
+    }
+
+    // Same view with the viewport width adjusted to a new image aspect ratio.
+    pub fn with_aspect_ratio(&self, aspect_ratio: f64) -> Camera {
+        let center = self.target();
+        let horizontal = self.horizontal.normalize() * (aspect_ratio * self.vertical.length());
+        let lower_left = center - horizontal / 2.0 - self.vertical / 2.0;
+        Camera { lower_left, horizontal, ..*self }
+    }
+
+    // The centre of the view, one focal length in front of the camera.
+    pub fn target(&self) -> Vector {
+        self.lower_left + self.horizontal / 2.0 + self.vertical / 2.0
+    }
+
+    // Vertical field of view in degrees.
+    pub fn vfov(&self) -> f64 {
+        2.0 * (self.vertical.length() / 2.0).atan2((self.target() - self.origin).length()).to_degrees()
+    }
+
+    // Unit vectors to the right, up and ahead.
+    pub fn basis(&self) -> (Vector, Vector, Vector) {
+        (self.horizontal.normalize(), self.vertical.normalize(), (self.target() - self.origin).normalize())
+    }
+
+    pub fn aspect_ratio(&self) -> f64 {
+        self.horizontal.length() / self.vertical.length()
+    }
+
+    // Whether a point of the image sees anything. Fisheye images are circles.
+    pub fn covers(&self, u: f64, v: f64) -> bool {
+        match self.projection {
+            Projection::Fisheye { .. } => self.fisheye_radius(u, v) <= 1.0,
+            _ => true,
         }
     }
+
+    // The ray through a point of the image, from (0, 0) at the lower left to (1, 1)
+    // at the upper right.
+    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
+        let point = self.lower_left + u * self.horizontal + v * self.vertical;
+        let (right, up, forward) = self.basis();
+        let direction = match self.projection {
+            Projection::Perspective => point - self.origin,
+            Projection::Orthographic => {
+                return Ray { origin: self.origin + point - self.target(), direction: self.target() - self.origin };
+            },
+            Projection::Fisheye { mapping, fov } => {
+                let r = self.fisheye_radius(u, v);
+                let half = fov.to_radians() / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * half,
+                    FisheyeMapping::Equisolid => 2.0 * (r * (half / 2.0).sin()).min(1.0).asin(),
+                };
+                let phi = (v - 0.5).atan2((u - 0.5) * self.aspect_ratio());
+                theta.cos() * forward + theta.sin() * (phi.cos() * right + phi.sin() * up)
+            },
+            Projection::Equirectangular => {
+                let (longitude, latitude) = ((u - 0.5) * 2.0 * PI, (v - 0.5) * PI);
+                let direction = latitude.cos() * (longitude.sin() * right + longitude.cos() * forward)
+                    + latitude.sin() * up;
+                let eye = self.eye_offset * (longitude.cos() * right - longitude.sin() * forward);
+                return Ray { origin: self.origin + eye, direction };
+            },
+            Projection::Cubemap => {
+                // Faces as (ahead, right, up), in image order from the top left.
+                let faces = [
+                    (right, -forward, up),
+                    (-right, forward, up),
+                    (up, right, -forward),
+                    (-up, right, forward),
+                    (forward, right, up),
+                    (-forward, -right, up),
+                ];
+                let (column, row) = ((u * 3.0).floor().clamp(0.0, 2.0), ((1.0 - v) * 2.0).floor().clamp(0.0, 1.0));
+                let (ahead, across, above) = faces[(row * 3.0 + column) as usize];
+                let (a, b) = (2.0 * (u * 3.0 - column) - 1.0, 2.0 * (1.0 - ((1.0 - v) * 2.0 - row)) - 1.0);
+                ahead + a * across + b * above
+            },
+        };
+        Ray { origin: self.origin, direction }
+    }
+
+    // Distance from the centre of the image, as a fraction of half its height.
+    fn fisheye_radius(&self, u: f64, v: f64) -> f64 {
+        2.0 * ((u - 0.5) * self.aspect_ratio()).hypot(v - 0.5)
+    }
 }
 
 impl Default for Camera {
@@ -60,4 +349,100 @@ mod tests {
         assert!(ray.origin == Vector::ORIGIN);
         assert!(ray.direction == Vector::new(0.0, 0.0, -1.0));
     }
+
+    #[test]
+    fn test_camera_look_at() {
+        let expected = Camera::new();
+        let camera = Camera::look_at(Vector::ORIGIN, Vector::new(0.0, 0.0, -1.0),
+                                     Vector::new(0.0, 1.0, 0.0), 90.0, 16.0 / 9.0);
+        assert!((camera.horizontal - expected.horizontal).length() < 1e-12);
+        assert!((camera.vertical - expected.vertical).length() < 1e-12);
+        assert!((camera.lower_left - expected.lower_left).length() < 1e-12);
+
+        let camera = Camera::look_at(Vector::new(1.0, 2.0, 3.0), Vector::ORIGIN, Vector::new(0.0, 1.0, 0.0), 30.0, 1.5);
+        assert!((camera.vfov() - 30.0).abs() < 1e-9);
+        assert!((camera.aspect_ratio() - 1.5).abs() < 1e-12);
+        assert!((camera.target() - camera.origin).normalize() * Vector::new(-1.0, -2.0, -3.0).normalize() > 1.0 - 1e-12);
+    }
+
+    #[test]
+    fn test_projections() {
+        let close = |a: Vector, b: Vector| (a.normalize() - b.normalize()).length() < 1e-9;
+        let camera = Camera::new();
+        let (right, up, forward) = (Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0), Vector::new(0.0, 0.0, -1.0));
+
+        let orthographic = Camera::orthographic(Vector::ORIGIN, forward, up, 4.0, 2.0);
+        let ray = orthographic.get_ray(1.0, 0.0);
+        assert!((ray.origin - Vector::new(4.0, -2.0, 0.0)).length() < 1e-12);
+        assert!(close(ray.direction, forward));
+
+        let fisheye = camera.with_aspect_ratio(1.0).with_projection("fisheye".parse().unwrap());
+        assert!(close(fisheye.get_ray(0.5, 0.5).direction, forward));
+        assert!(close(fisheye.get_ray(1.0, 0.5).direction, right));
+        assert!(close(fisheye.get_ray(0.5, 0.75).direction, up + forward));
+        assert!(!fisheye.covers(1.0, 1.0));
+        let equisolid = fisheye.with_projection("fisheye:equisolid:180".parse().unwrap());
+        assert!(close(equisolid.get_ray(1.0, 0.5).direction, right));
+        assert!(equisolid.get_ray(0.75, 0.5).direction.x < fisheye.get_ray(0.75, 0.5).direction.x);
+
+        let panorama = camera.with_projection(Projection::Equirectangular);
+        assert!(close(panorama.get_ray(0.5, 0.5).direction, forward));
+        assert!(close(panorama.get_ray(0.75, 0.5).direction, right));
+        assert!(close(panorama.get_ray(0.0, 0.5).direction, -forward));
+        assert!(close(panorama.get_ray(0.3, 1.0).direction, up));
+
+        // Face centres look along the axes.
+        let cube = camera.with_projection(Projection::Cubemap);
+        let centre = |column: f64, row: f64| cube.get_ray((column + 0.5) / 3.0, 1.0 - (row + 0.5) / 2.0).direction;
+        assert!(close(centre(0.0, 0.0), right));
+        assert!(close(centre(1.0, 0.0), -right));
+        assert!(close(centre(2.0, 0.0), up));
+        assert!(close(centre(0.0, 1.0), -up));
+        assert!(close(centre(1.0, 1.0), forward));
+        assert!(close(centre(2.0, 1.0), -forward));
+        // The top right of the front face is up and to the right.
+        assert!(close(cube.get_ray((2.0 - 1e-12) / 3.0, 0.5 - 1e-12).direction, forward + right + up));
+
+        for name in ["perspective", "orthographic", "fisheye:equisolid:220", "equirectangular", "cubemap"] {
+            assert_eq!(name.parse::<Projection>().unwrap().to_string(), name);
+        }
+        assert!("fisheye:400".parse::<Projection>().is_err());
+        assert!("cylindrical".parse::<Projection>().is_err());
+        assert!("cubemap:2".parse::<Projection>().is_err());
+    }
+
+    #[test]
+    fn test_stereo() {
+        let camera = Camera::new();
+        let (left, right) = (camera.eye(Eye::Left, 0.2, None), camera.eye(Eye::Right, 0.2, None));
+        assert!((right.origin - left.origin - Vector::new(0.2, 0.0, 0.0)).length() < 1e-12);
+        assert!((left.get_ray(0.5, 0.5).direction - Vector::new(0.0, 0.0, -1.0)).length() < 1e-12);
+
+        // Converged eyes see the same point at the centre of the image.
+        let (left, right) = (camera.eye(Eye::Left, 0.2, Some(4.0)), camera.eye(Eye::Right, 0.2, Some(4.0)));
+        let point = Vector::new(0.0, 0.0, -4.0);
+        for eye in [left, right] {
+            let ray = eye.get_ray(0.5, 0.5);
+            assert!((ray.at(4.0) - point).length() < 1e-12);
+        }
+
+        // Panorama eyes sit to the right of whichever way they look.
+        let panorama = camera.with_projection(Projection::Equirectangular).eye(Eye::Right, 0.2, None);
+        assert!((panorama.get_ray(0.5, 0.5).origin - Vector::new(0.1, 0.0, 0.0)).length() < 1e-12);
+        assert!((panorama.get_ray(0.75, 0.5).origin - Vector::new(0.0, 0.0, 0.1)).length() < 1e-12);
+
+        let (l, r) = ([1, 2, 3, 4], [5, 6, 7, 8]);
+        assert_eq!(StereoLayout::SideBySide.combine(&l, &r, 2), vec![1, 2, 5, 6, 3, 4, 7, 8]);
+        assert_eq!(StereoLayout::TopBottom.combine(&l, &r, 2), vec![5, 6, 7, 8, 1, 2, 3, 4]);
+        assert_eq!(StereoLayout::TopBottom.size(4, 3), (4, 6));
+        assert_eq!("sbs".parse(), Ok(StereoLayout::SideBySide));
+    }
+
+    #[test]
+    fn test_camera_with_aspect_ratio() {
+        let camera = Camera::new().with_aspect_ratio(1.0);
+        assert!((camera.horizontal - Vector::new(2.0, 0.0, 0.0)).length() < 1e-12);
+        assert!((camera.lower_left - Vector::new(-1.0, -1.0, -1.0)).length() < 1e-12);
+        assert!(camera.get_ray(0.5, 0.5).direction == Vector::new(0.0, 0.0, -1.0));
+    }
 }
@@ -0,0 +1,202 @@
+use std::fs;
+use std::io;
+
+use crate::aov::AovBuffer;
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::render::Frame;
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 2;
+
+// Progress of a render saved to disk so that it can be resumed, or continued with more
+// samples. `fingerprint` identifies the scene and settings the samples belong to, and
+// `seed` with the frame's sample count picks up the sequence of camera samples where
+// it stopped.
+pub struct Checkpoint {
+    pub fingerprint: u64,
+    pub seed: u64,
+    pub frame: Frame,
+}
+
+impl Checkpoint {
+    pub fn save(&self, filename: &str) -> io::Result<()> {
+        save(filename, self.fingerprint, self.seed, &self.frame)
+    }
+
+    pub fn load(filename: &str, filter: Filter) -> io::Result<Checkpoint> {
+        let data = fs::read(filename)?;
+        let mut input = Decoder::new(&data);
+        if input.bytes(4)? != MAGIC {
+            return Err(invalid("not a raytracer checkpoint"));
+        }
+        if input.u32()? != VERSION {
+            return Err(invalid("unsupported checkpoint version"));
+        }
+        let fingerprint = input.u64()?;
+        let seed = input.u64()?;
+        let samples = input.u32()?;
+        let film = Film::decode(&mut input, filter)?;
+        let aovs = match input.u8()? {
+            0 => None,
+            _ => Some(AovBuffer::decode(&mut input)?),
+        };
+        Ok(Checkpoint { fingerprint, seed, frame: Frame { film, aovs, samples } })
+    }
+}
+
+// Writes to a temporary file first, so that an interrupted save leaves the last
+// checkpoint intact.
+pub fn save(filename: &str, fingerprint: u64, seed: u64, frame: &Frame) -> io::Result<()> {
+    let mut out = Encoder::default();
+    out.bytes(MAGIC);
+    out.u32(VERSION);
+    out.u64(fingerprint);
+    out.u64(seed);
+    out.u32(frame.samples);
+    frame.film.encode(&mut out);
+    match &frame.aovs {
+        Some(aovs) => {
+            out.u8(1);
+            aovs.encode(&mut out);
+        },
+        None => out.u8(0),
+    }
+
+    let temporary = format!("{}.tmp", filename);
+    fs::write(&temporary, &out.data)?;
+    fs::rename(&temporary, filename)
+}
+
+// FNV-1a over everything that decides what a sample is worth, so that a checkpoint is
+// only resumed with the render it came from.
+pub fn fingerprint(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(part.iter()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+pub fn invalid(why: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, why.to_string())
+}
+
+// Little-endian serialisation of checkpoint fields.
+#[derive(Default)]
+pub struct Encoder {
+    pub data: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn bytes(&mut self, b: &[u8]) {
+        self.data.extend_from_slice(b);
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    pub fn f64(&mut self, v: f64) {
+        self.bytes(&v.to_le_bytes());
+    }
+}
+
+pub struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Decoder<'a> {
+        Decoder { data }
+    }
+
+    pub fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(invalid("checkpoint is truncated"));
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::{AovPixel, PathRecord};
+    use crate::color::Color;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let filter = Filter::Tent { radius: 1.0 };
+        let mut film = Film::new(3, 2, filter);
+        film.add_sample(1.2, 0.7, Color::new(0.25, 0.5, 1.0));
+        let mut aovs = AovBuffer::new(3, 2);
+        let mut pixel = AovPixel::default();
+        pixel.add(&PathRecord { emission: Color::WHITE, ..PathRecord::default() });
+        aovs.set(2, 1, pixel);
+
+        let frame = Frame { film, aovs: Some(aovs), samples: 16 };
+        let checkpoint = Checkpoint { fingerprint: 42, seed: 7, frame };
+        let filename = std::env::temp_dir().join(format!("raytracer-test-{}.checkpoint", std::process::id()));
+        let filename = filename.to_str().unwrap();
+        checkpoint.save(filename).expect("could not save checkpoint");
+        let loaded = Checkpoint::load(filename, filter);
+        std::fs::remove_file(filename).unwrap();
+
+        let loaded = loaded.expect("could not load checkpoint");
+        assert_eq!((loaded.fingerprint, loaded.seed, loaded.frame.samples), (42, 7, 16));
+        assert_eq!(loaded.frame.film.pixels(), checkpoint.frame.film.pixels());
+        assert_eq!(loaded.frame.film.sample_counts(), checkpoint.frame.film.sample_counts());
+        let aovs = loaded.frame.aovs.expect("AOVs were not restored");
+        assert_eq!(aovs.features(), checkpoint.frame.aovs.unwrap().features());
+    }
+
+    #[test]
+    fn test_checkpoint_invalid() {
+        let mut input = Decoder::new(b"RTCK\x01");
+        input.bytes(4).unwrap();
+        assert!(input.u32().is_err());
+        assert_ne!(fingerprint(&[b"ab", b"c"]), fingerprint(&[b"a", b"bc"]));
+
+        // A film claiming more pixels than the data holds.
+        let mut out = Encoder::default();
+        [u32::MAX, u32::MAX, 0, 1].into_iter().for_each(|v| out.u32(v));
+        assert!(Film::decode(&mut Decoder::new(&out.data), Filter::default()).is_err());
+        let mut out = Encoder::default();
+        [1 << 16, 1 << 16].into_iter().for_each(|v| out.u32(v));
+        assert!(AovBuffer::decode(&mut Decoder::new(&out.data)).is_err());
+    }
+}
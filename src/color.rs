@@ -1,6 +1,6 @@
 use std::cmp::PartialEq;
 use std::fmt;
-use std::ops::{Add, AddAssign, Mul, Div, DivAssign};
+use std::ops::{Add, AddAssign, Sub, Mul, Div, DivAssign};
 use float_cmp::approx_eq;
 
 use crate::vec::Vector;
@@ -21,6 +21,26 @@ impl Color {
         Color { r, g, b, }
     }
 
+    pub fn r(&self) -> f64 {
+        self.r
+    }
+
+    pub fn g(&self) -> f64 {
+        self.g
+    }
+
+    pub fn b(&self) -> f64 {
+        self.b
+    }
+
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.r <= 0.0 && self.g <= 0.0 && self.b <= 0.0
+    }
+
     pub fn from_normal(n: &Vector) -> Color {
         let r = 0.5 * (n.x + 1.0);
         let g = 0.5 * (n.y + 1.0);
@@ -36,14 +56,6 @@ impl Color {
         (1.0 - t) * start_color + t * end_color
     }
 
-    pub fn gamma_correct(&self) -> Color {
-        Color {
-            r: self.r.sqrt(),
-            g: self.g.sqrt(),
-            b: self.b.sqrt(),
-        }
-    }
-
     pub fn as_bytes(&self) -> [u8; 3] {
         let r = (clamp(self.r, 0.0, 1.0) * 255.0) as u8;
         let g = (clamp(self.g, 0.0, 1.0) * 255.0) as u8;
@@ -82,6 +94,17 @@ impl AddAssign for Color {
     }
 }
 
+impl Sub for Color {
+    type Output = Color;
+
+    fn sub(self, other: Color) -> Color {
+        let r = self.r - other.r;
+        let g = self.g - other.g;
+        let b = self.b - other.b;
+        Color { r, g, b, }
+    }
+}
+
 impl Mul<f64> for Color {
     type Output = Color;
 
@@ -104,6 +127,17 @@ impl Mul<Color> for f64 {
     }
 }
 
+impl Mul<Color> for Color {
+    type Output = Color;
+
+    fn mul(self, other: Color) -> Color {
+        let r = self.r * other.r;
+        let g = self.g * other.g;
+        let b = self.b * other.b;
+        Color { r, g, b, }
+    }
+}
+
 impl Div<f64> for Color {
     type Output = Color;
 
@@ -135,6 +169,24 @@ impl fmt::Display for Color {
     }
 }
 
+// Decodes an sRGB-encoded channel value in [0, 1] to linear light.
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Encodes a linear channel value with the sRGB transfer function.
+pub fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
 fn clamp<T>(val: T, min: T, max: T) -> T
 where T: PartialOrd
 {
@@ -213,6 +265,14 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_color_sub() {
+        let c1 = Color::new(0.5, 0.5, 0.5);
+        let c2 = Color::new(0.0625, 0.125, 0.25);
+
+        assert_eq!(c1 - c2, Color::new(0.4375, 0.375, 0.25));
+    }
+
     #[test]
     fn test_color_mul_scalar() {
         let c = Color::new(0.2, 0.4, 0.5);
@@ -225,6 +285,19 @@ mod tests {
         assert_eq!(d * c, Color::new(0.1, 0.2, 0.25));
     }
 
+    #[test]
+    fn test_color_mul_color() {
+        let c = Color::new(0.2, 0.4, 0.5) * Color::new(0.5, 0.5, 2.0);
+        assert_eq!(c, Color::new(0.1, 0.2, 1.0));
+    }
+
+    #[test]
+    fn test_srgb_to_linear() {
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert_eq!(srgb_to_linear(1.0), 1.0);
+        assert!((srgb_to_linear(0.5) - 0.214041).abs() < 1e-6);
+    }
+
     #[test]
     fn test_color_div() {
         let c = Color::new(0.2, 0.5, 0.8) / 2.0;
@@ -241,10 +314,12 @@ mod tests {
     }
 
     #[test]
-    fn test_color_gamma_correct() {
-        let c = Color::new(0.1, 0.5, 1.0);
-        let cc = c.gamma_correct();
-        let expected = Color::new((0.1f64).sqrt(), (0.5f64).sqrt(), 1.0);
-        assert_eq!(expected, cc);
+    fn test_linear_to_srgb() {
+        assert_eq!(linear_to_srgb(0.0), 0.0);
+        assert!((linear_to_srgb(1.0) - 1.0).abs() < 1e-12);
+        assert!((linear_to_srgb(0.214041) - 0.5).abs() < 1e-6);
+        for c in [0.001, 0.01, 0.2, 0.9] {
+            assert!((srgb_to_linear(linear_to_srgb(c)) - c).abs() < 1e-12);
+        }
     }
 }
@@ -0,0 +1,310 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::color::{linear_to_srgb, Color};
+use crate::matrix::Matrix3;
+
+// Colours, textures and lights are all given in linear Rec.709 / sRGB primaries, so
+// that is what the renderer works in.
+pub const WORKING_SPACE: ColorSpace = ColorSpace::LinearSrgb;
+
+// Luminance in cd/m² of diffuse white when encoding absolute (PQ) output, per ITU-R BT.2408.
+pub const REFERENCE_WHITE_NITS: f64 = 203.0;
+
+// Bradford cone response matrix used for white point adaptation.
+const BRADFORD: Matrix3 = Matrix3 {
+    m: [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ],
+};
+
+// Linear RGB colour spaces, defined by the xy chromaticities of their primaries and
+// white point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    LinearSrgb,
+    DisplayP3,
+    Rec2020,
+    AcesCg,
+}
+
+impl ColorSpace {
+    // Red, green, blue and white chromaticities.
+    pub fn chromaticities(&self) -> [(f64, f64); 4] {
+        const D65: (f64, f64) = (0.3127, 0.3290);
+        match self {
+            ColorSpace::LinearSrgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), D65],
+            ColorSpace::DisplayP3 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060), D65],
+            ColorSpace::Rec2020 => [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046), D65],
+            ColorSpace::AcesCg => [(0.713, 0.293), (0.165, 0.830), (0.128, 0.044), (0.32168, 0.33767)],
+        }
+    }
+
+    pub fn white_point(&self) -> [f64; 3] {
+        xy_to_xyz(self.chromaticities()[3])
+    }
+
+    pub fn to_xyz(&self) -> Matrix3 {
+        let [r, g, b, _] = self.chromaticities().map(xy_to_xyz);
+        let primaries = Matrix3::new(std::array::from_fn(|i| [r[i], g[i], b[i]]));
+        let inverse = primaries.inverse().expect("colour space primaries are collinear");
+        // Scale the primaries so that RGB (1, 1, 1) lands on the white point.
+        let scale = inverse.apply(self.white_point());
+        primaries * Matrix3::diagonal(scale)
+    }
+
+    pub fn from_xyz(&self) -> Matrix3 {
+        self.to_xyz().inverse().expect("colour space primaries are collinear")
+    }
+
+    // Matrix taking linear RGB in this space to `target`, with Bradford chromatic
+    // adaptation when the white points differ.
+    pub fn conversion_to(&self, target: ColorSpace) -> Matrix3 {
+        if *self == target {
+            return Matrix3::IDENTITY;
+        }
+        let adapt = chromatic_adaptation(self.white_point(), target.white_point());
+        target.from_xyz() * adapt * self.to_xyz()
+    }
+
+    pub fn convert(&self, c: Color, target: ColorSpace) -> Color {
+        self.conversion_to(target).transform_color(c)
+    }
+}
+
+impl fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorSpace::LinearSrgb => write!(f, "linear sRGB"),
+            ColorSpace::DisplayP3 => write!(f, "Display P3"),
+            ColorSpace::Rec2020 => write!(f, "Rec.2020"),
+            ColorSpace::AcesCg => write!(f, "ACEScg"),
+        }
+    }
+}
+
+// Bradford transform adapting XYZ colours seen under one white to another.
+pub fn chromatic_adaptation(from_white: [f64; 3], to_white: [f64; 3]) -> Matrix3 {
+    let inverse = BRADFORD.inverse().expect("Bradford matrix is invertible");
+    let from = BRADFORD.apply(from_white);
+    let to = BRADFORD.apply(to_white);
+    inverse * Matrix3::diagonal(std::array::from_fn(|i| to[i] / from[i])) * BRADFORD
+}
+
+pub fn xy_to_xyz((x, y): (f64, f64)) -> [f64; 3] {
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+// The range of colour temperatures in kelvin that `temperature_to_xy` covers.
+pub const TEMPERATURE_RANGE: (f64, f64) = (1667.0, 25000.0);
+
+// Chromaticity of white light at a colour temperature: the CIE daylight locus from
+// 4000 K, and the Planckian locus below that, by Kim et al.'s cubic fit.
+pub fn temperature_to_xy(kelvin: f64) -> (f64, f64) {
+    let t = kelvin.clamp(TEMPERATURE_RANGE.0, TEMPERATURE_RANGE.1);
+    let (t2, t3) = (t * t, t * t * t);
+    if t >= 4000.0 {
+        let x = if t <= 7000.0 {
+            -4.6070e9 / t3 + 2.9678e6 / t2 + 0.09911e3 / t + 0.244063
+        } else {
+            -2.0064e9 / t3 + 1.9018e6 / t2 + 0.24748e3 / t + 0.237040
+        };
+        (x, -3.0 * x * x + 2.870 * x - 0.275)
+    } else {
+        let x = -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910;
+        let y = if t <= 2222.0 {
+            -1.1063814 * x * x * x - 1.34811020 * x * x + 2.18555832 * x - 0.20219683
+        } else {
+            -0.9549476 * x * x * x - 1.37418593 * x * x + 2.09137015 * x - 0.16748867
+        };
+        (x, y)
+    }
+}
+
+// Matrix on working space colours that makes white light of the colour temperature
+// `kelvin` appear white, as a camera's white balance does.
+pub fn white_balance(kelvin: f64) -> Matrix3 {
+    let white = xy_to_xyz(temperature_to_xy(kelvin));
+    let adapt = chromatic_adaptation(white, WORKING_SPACE.white_point());
+    WORKING_SPACE.from_xyz() * adapt * WORKING_SPACE.to_xyz()
+}
+
+// Transfer functions applied to linear values on output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    Linear,
+    Srgb,
+    // SMPTE ST 2084 perceptual quantiser, with 1.0 at `REFERENCE_WHITE_NITS`.
+    Pq,
+}
+
+impl Transfer {
+    pub fn encode(&self, v: f64) -> f64 {
+        match self {
+            Transfer::Linear => v.max(0.0),
+            Transfer::Srgb => linear_to_srgb(v.clamp(0.0, 1.0)),
+            Transfer::Pq => {
+                const M1: f64 = 2610.0 / 16384.0;
+                const M2: f64 = 2523.0 / 4096.0 * 128.0;
+                const C1: f64 = 3424.0 / 4096.0;
+                const C2: f64 = 2413.0 / 4096.0 * 32.0;
+                const C3: f64 = 2392.0 / 4096.0 * 32.0;
+                let y = (v * REFERENCE_WHITE_NITS / 10000.0).clamp(0.0, 1.0);
+                let p = y.powf(M1);
+                ((C1 + C2 * p) / (1.0 + C3 * p)).powf(M2)
+            },
+        }
+    }
+}
+
+// How rendered images are stored: the colour space of the pixels and their encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Encoding {
+    pub space: ColorSpace,
+    pub transfer: Transfer,
+}
+
+impl Encoding {
+    pub const SRGB: Encoding = Encoding { space: ColorSpace::LinearSrgb, transfer: Transfer::Srgb };
+
+    // Converts a linear working-space colour to encoded output values.
+    pub fn encode(&self, c: Color) -> Color {
+        let c = WORKING_SPACE.convert(c, self.space);
+        Color::new(self.transfer.encode(c.r()), self.transfer.encode(c.g()), self.transfer.encode(c.b()))
+    }
+
+    // Linear and HDR encodings band badly in eight bits.
+    pub fn bit_depth(&self) -> u8 {
+        if self.transfer == Transfer::Srgb {
+            8
+        } else {
+            16
+        }
+    }
+
+    // Colour primaries and transfer characteristics from ITU-T H.273, for PNG cICP
+    // chunks. ACEScg has no code point.
+    pub fn cicp(&self) -> Option<(u8, u8)> {
+        let primaries = match self.space {
+            ColorSpace::LinearSrgb => 1,
+            ColorSpace::Rec2020 => 9,
+            ColorSpace::DisplayP3 => 12,
+            ColorSpace::AcesCg => return None,
+        };
+        let transfer = match self.transfer {
+            Transfer::Linear => 8,
+            Transfer::Srgb => 13,
+            Transfer::Pq => 16,
+        };
+        Some((primaries, transfer))
+    }
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::SRGB
+    }
+}
+
+const ENCODING_NAMES: [(&str, Encoding); 6] = [
+    ("srgb", Encoding::SRGB),
+    ("linear-srgb", Encoding { space: ColorSpace::LinearSrgb, transfer: Transfer::Linear }),
+    ("display-p3", Encoding { space: ColorSpace::DisplayP3, transfer: Transfer::Srgb }),
+    ("rec2020", Encoding { space: ColorSpace::Rec2020, transfer: Transfer::Srgb }),
+    ("rec2020-pq", Encoding { space: ColorSpace::Rec2020, transfer: Transfer::Pq }),
+    ("acescg", Encoding { space: ColorSpace::AcesCg, transfer: Transfer::Linear }),
+];
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Encoding, String> {
+        ENCODING_NAMES.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, encoding)| *encoding)
+            .ok_or_else(|| format!("unknown colour space `{}`", s))
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match ENCODING_NAMES.iter().find(|(_, encoding)| encoding == self) {
+            Some((name, _)) => write!(f, "{}", name),
+            None => write!(f, "{} ({:?})", self.space, self.transfer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Color, b: Color, epsilon: f64) {
+        assert!((a.r() - b.r()).abs() < epsilon && (a.g() - b.g()).abs() < epsilon && (a.b() - b.b()).abs() < epsilon,
+                "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_srgb_to_xyz() {
+        // The standard sRGB matrix, to the precision it is usually quoted.
+        let m = ColorSpace::LinearSrgb.to_xyz();
+        let expected = [[0.4124, 0.3576, 0.1805], [0.2126, 0.7152, 0.0722], [0.0193, 0.1192, 0.9505]];
+        for (row, expected_row) in m.m.iter().zip(expected) {
+            for (v, e) in row.iter().zip(expected_row) {
+                assert!((v - e).abs() < 1e-4, "{:?}", m);
+            }
+        }
+    }
+
+    #[test]
+    fn test_conversion_round_trip() {
+        let c = Color::new(0.2, 0.5, 0.8);
+        for space in [ColorSpace::DisplayP3, ColorSpace::Rec2020, ColorSpace::AcesCg] {
+            let there = ColorSpace::LinearSrgb.convert(c, space);
+            assert_close(space.convert(there, ColorSpace::LinearSrgb), c, 1e-9);
+            // White stays white, even across white points.
+            assert_close(ColorSpace::LinearSrgb.convert(Color::WHITE, space), Color::WHITE, 1e-3);
+        }
+        // Rec.709 primaries sit inside wider gamuts.
+        let red = ColorSpace::LinearSrgb.convert(Color::new(1.0, 0.0, 0.0), ColorSpace::Rec2020);
+        assert_close(red, Color::new(0.6274, 0.0691, 0.0164), 1e-3);
+    }
+
+    #[test]
+    fn test_white_balance() {
+        // D65 sits on the daylight locus at about 6504 K.
+        let (x, y) = temperature_to_xy(6504.0);
+        assert!((x - 0.3127).abs() < 1e-3 && (y - 0.3290).abs() < 1e-3, "{:?}", (x, y));
+        assert_close(white_balance(6504.0).transform_color(Color::WHITE), Color::WHITE, 1e-2);
+        // Balancing for warm light takes out red, and for cool light takes out blue.
+        let warm = white_balance(3000.0).transform_color(Color::WHITE);
+        assert!(warm.r() < warm.g() && warm.g() < warm.b(), "{:?}", warm);
+        let cool = white_balance(10000.0).transform_color(Color::WHITE);
+        assert!(cool.r() > cool.g() && cool.g() > cool.b(), "{:?}", cool);
+        // Daylight is a little greener than a black body, but close at 4000 K.
+        let (below, above) = (temperature_to_xy(3999.9), temperature_to_xy(4000.0));
+        assert!((below.0 - above.0).abs() < 1e-2 && (below.1 - above.1).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_transfer_functions() {
+        assert!((Transfer::Srgb.encode(2.0) - 1.0).abs() < 1e-9);
+        assert_eq!(Transfer::Linear.encode(2.0), 2.0);
+        assert!(Transfer::Pq.encode(0.0) < 1e-6);
+        // 10000 nits is the top of the PQ range and 100 nits sits near half way.
+        assert!((Transfer::Pq.encode(10000.0 / REFERENCE_WHITE_NITS) - 1.0).abs() < 1e-9);
+        assert!((Transfer::Pq.encode(100.0 / REFERENCE_WHITE_NITS) - 0.5081).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_encoding_parse() {
+        assert_eq!("sRGB".parse::<Encoding>(), Ok(Encoding::SRGB));
+        let pq = "rec2020-pq".parse::<Encoding>().unwrap();
+        assert_eq!(pq.cicp(), Some((9, 16)));
+        assert_eq!(pq.bit_depth(), 16);
+        assert_eq!(pq.to_string(), "rec2020-pq");
+        assert!("adobe-rgb".parse::<Encoding>().is_err());
+    }
+}
@@ -1,3 +1,27 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::aov::Aov;
+use crate::camera::{Projection, StereoLayout};
+use crate::colorspace::{Encoding, TEMPERATURE_RANGE};
+use crate::filter::Filter;
+use crate::lens::Aperture;
+use crate::render::Region;
+use crate::service::Limits;
+use crate::sky::Sky;
+use crate::toml::{self, Value};
+use crate::tonemap::{CameraExposure, ToneMap};
+
+const DEFAULT_WORKER_LISTEN: &str = "127.0.0.1:7878";
+const DEFAULT_SERVE_LISTEN: &str = "127.0.0.1:8080";
+const MAX_SIZE: u32 = 16384;
+
+// Settings for rendering an image, from the options of `render` and `bench`.
 #[derive(Debug)]
 pub struct Config {
     pub width: u32,
@@ -5,7 +29,56 @@ pub struct Config {
     pub samples: u16,
     pub max_depth: u16,
     pub output: String,
-    pub help: bool,
+    pub scene: Option<String>,
+    pub filter: Filter,
+    pub spectral: bool,
+    // Overrides the scene camera's projection.
+    pub projection: Option<Projection>,
+    // A lens prescription to take camera rays through, and how to set it up.
+    pub lens: Option<String>,
+    pub focus: Option<f64>,
+    pub aperture: Aperture,
+    pub sensor_width: f64,
+    // The sun's elevation and azimuth in degrees, lighting the scene with daylight.
+    pub sun: Option<(f64, f64)>,
+    pub turbidity: f64,
+    pub ground_albedo: f64,
+    pub sun_diameter: f64,
+    pub threads: Option<usize>,
+    pub exposure: Option<f64>,
+    pub tone_map: Option<ToneMap>,
+    // Physical camera settings, each left to the default if the others are given.
+    pub iso: Option<f64>,
+    pub shutter: Option<f64>,
+    pub f_number: Option<f64>,
+    // The colour temperature in kelvin to show as white.
+    pub white_balance: Option<f64>,
+    pub encoding: Encoding,
+    pub denoise: bool,
+    pub raw_output: Option<String>,
+    pub aovs: Vec<Aov>,
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: u64,
+    pub resume: bool,
+    // Addresses of workers to render on.
+    pub workers: Vec<String>,
+    pub crop: Option<Crop>,
+    // Whether to render a stereo pair, each eye at the image size, laid out together.
+    pub stereo: Option<StereoLayout>,
+    // The distance between the eyes, in scene units.
+    pub ipd: f64,
+    // The distance the eyes converge at, or None to look in parallel.
+    pub convergence: Option<f64>,
+    // Keyframes to pose the scene by, for rendering a sequence of frames.
+    pub animation: Option<String>,
+    // The frames `first..=last` to render, with the output as a filename pattern.
+    pub frames: Option<(u32, u32)>,
+    // Whether to render frames whose output already exists.
+    pub overwrite: bool,
+    // Whether to write the whole image when cropping, rather than just the crop.
+    pub full_frame: bool,
+    // Where settings came from, for those not left at their defaults.
+    pub sources: Vec<(&'static str, Source)>,
 }
 
 impl Config {
@@ -16,19 +89,72 @@ impl Config {
             samples: s,
             max_depth: md,
             output: out,
-            help: false,
+            scene: None,
+            filter: Filter::default(),
+            spectral: false,
+            projection: None,
+            lens: None,
+            focus: None,
+            aperture: Aperture::CIRCLE,
+            sensor_width: 36.0,
+            sun: None,
+            turbidity: 3.0,
+            ground_albedo: 0.3,
+            sun_diameter: 0.53,
+            threads: None,
+            exposure: None,
+            tone_map: None,
+            iso: None,
+            shutter: None,
+            f_number: None,
+            white_balance: None,
+            encoding: Encoding::SRGB,
+            denoise: false,
+            raw_output: None,
+            aovs: Vec::new(),
+            checkpoint: None,
+            checkpoint_interval: 300,
+            resume: false,
+            workers: Vec::new(),
+            crop: None,
+            full_frame: false,
+            stereo: None,
+            ipd: 0.064,
+            convergence: None,
+            animation: None,
+            frames: None,
+            overwrite: false,
+            sources: Vec::new(),
         }
     }
 
-    pub fn need_help() -> Config {
-        Config {
-            width: 0,
-            height: 0,
-            samples: 0,
-            max_depth: 0,
-            output: String::from(""),
-            help: true,
+    // Whether to render a sequence of frames rather than one image.
+    pub fn is_sequence(&self) -> bool {
+        self.frames.is_some() || self.animation.is_some()
+    }
+
+    // Daylight for the sun's position, if given.
+    pub fn sky(&self) -> Option<Sky> {
+        let (elevation, azimuth) = self.sun?;
+        Some(Sky::new(elevation, azimuth, self.turbidity, self.ground_albedo, self.sun_diameter))
+    }
+
+    // The physical camera's exposure, if any of its settings are given.
+    pub fn camera_exposure(&self) -> Option<CameraExposure> {
+        if self.iso.is_none() && self.shutter.is_none() && self.f_number.is_none() {
+            return None;
         }
+        let default = CameraExposure::DEFAULT;
+        Some(CameraExposure {
+            iso: self.iso.unwrap_or(default.iso),
+            shutter: self.shutter.unwrap_or(default.shutter),
+            f_number: self.f_number.unwrap_or(default.f_number),
+        })
+    }
+
+    // The pixels to render, if cropping.
+    pub fn region(&self) -> Option<Region> {
+        self.crop.and_then(|crop| crop.region(self.width, self.height).ok())
     }
 
     pub fn aspect_ratio(&self) -> Option<f64> {
@@ -36,69 +162,312 @@ impl Config {
             return None;
         }
 
-        return Some(self.width as f64 / self.height as f64);
+        Some(self.width as f64 / self.height as f64)
     }
 
-    pub fn parse_args(args: &Vec<String>) -> Result<Config, &str> {
-        let mut it = args.iter();
-        let mut cfg = Config::default();
+    // Applies an option that sets part of the config, returning false for others.
+    fn apply_option(&mut self, opt: &Opt, value: &str) -> Result<bool, ArgError> {
+        match opt.long {
+            "scene" => self.scene = Some(value.to_string()),
+            "width" => self.width = parse_range(opt, value, 2, MAX_SIZE)?,
+            "height" => self.height = parse_range(opt, value, 2, MAX_SIZE)?,
+            "samples" => self.samples = parse_range(opt, value, 1, u16::MAX)?,
+            "max-depth" => self.max_depth = parse_range(opt, value, 1, 1024)?,
+            "filter" => self.filter = parse_value(opt, value)?,
+            "spectral" => self.spectral = parse_flag(opt, value)?,
+            "projection" => self.projection = Some(parse_value(opt, value)?),
+            "lens" => self.lens = Some(value.to_string()),
+            "focus" => self.focus = Some(parse_distance(opt, value)?),
+            "blades" => self.aperture = parse_value(opt, value)?,
+            "sensor" => self.sensor_width = parse_distance(opt, value)?,
+            "sun" => self.sun = Some(parse_sun(opt, value)?),
+            "turbidity" => self.turbidity = parse_range(opt, value, 2.0, 10.0)?,
+            "ground-albedo" => self.ground_albedo = parse_range(opt, value, 0.0, 1.0)?,
+            "sun-diameter" => self.sun_diameter = parse_range(opt, value, 0.0, 30.0)?,
+            "threads" => self.threads = Some(parse_range(opt, value, 1, 1024)?),
+            "exposure" => self.exposure = Some(parse_value::<f64>(opt, value)
+                .and_then(|ev| check(opt, value, ev.is_finite(), "must be finite").map(|_| ev))?),
+            "tonemap" => self.tone_map = Some(parse_value(opt, value)?),
+            "iso" => self.iso = Some(parse_positive(opt, value)?),
+            "shutter" => self.shutter = Some(parse_shutter(opt, value)?),
+            "f-number" => self.f_number = Some(parse_positive(opt, value)?),
+            "white-balance" => self.white_balance = Some(parse_range(opt, value, TEMPERATURE_RANGE.0,
+                                                                     TEMPERATURE_RANGE.1)?),
+            "colorspace" => self.encoding = parse_value(opt, value)?,
+            "denoise" => self.denoise = parse_flag(opt, value)?,
+            "raw" => self.raw_output = Some(value.to_string()),
+            "aov" => self.aovs = Aov::parse_list(value).map_err(|e| invalid(opt, value, e))?,
+            "checkpoint" => self.checkpoint = Some(value.to_string()),
+            "checkpoint-interval" => self.checkpoint_interval = parse_value(opt, value)?,
+            "resume" => self.resume = parse_flag(opt, value)?,
+            "workers" => {
+                self.workers = value.split(',').filter(|w| !w.is_empty()).map(String::from).collect();
+                check(opt, value, !self.workers.is_empty(), "no addresses given")?;
+            },
+            "crop" => self.crop = Some(parse_value(opt, value)?),
+            "full-frame" => self.full_frame = parse_flag(opt, value)?,
+            "stereo" => self.stereo = Some(parse_value(opt, value)?),
+            "ipd" => self.ipd = parse_distance(opt, value)?,
+            "convergence" => self.convergence = Some(parse_distance(opt, value)?),
+            "animation" => self.animation = Some(value.to_string()),
+            "frames" => self.frames = Some(parse_frames(opt, value)?),
+            "overwrite" => self.overwrite = parse_flag(opt, value)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
 
-        it.next();
-        loop {
-            let token = it.next();
-            if token == None {
-                break;
+    // Applies the settings of one layer that the command takes. Given only one of the
+    // width and height, the other keeps the aspect ratio of the layers below, 16:9 by
+    // default.
+    fn apply_layer(&mut self, spec: &CommandSpec, settings: &[Setting]) -> Result<(), ArgError> {
+        let (width, height) = (self.width as u64, self.height as u64);
+        let mut size = (None, None);
+        let taken = |setting: &&Setting| spec.options.iter().any(|g| g.iter().any(|o| o.long == setting.opt.long));
+        for setting in settings.iter().filter(taken) {
+            let applied = self.apply_option(setting.opt, &setting.value).map_err(|e| match &setting.source {
+                Source::CommandLine => e,
+                source => ArgError::Layer(source.clone(), Box::new(e)),
+            })?;
+            if applied {
+                self.set_source(setting.opt.long, &setting.source);
+                match setting.opt.long {
+                    "width" => size.0 = Some(&setting.source),
+                    "height" => size.1 = Some(&setting.source),
+                    _ => (),
+                }
             }
+        }
+        match size {
+            (Some(source), None) => {
+                self.height = (self.width as u64 * height / width).clamp(2, MAX_SIZE as u64) as u32;
+                self.set_source("height", source);
+            },
+            (None, Some(source)) => {
+                self.width = (self.height as u64 * width / height).clamp(2, MAX_SIZE as u64) as u32;
+                self.set_source("width", source);
+            },
+            _ => (),
+        }
+        Ok(())
+    }
 
-            if let Some(arg) = token {
-                if *arg == String::from("-w") {
-                    if let Some(token) = it.next() {
-                        match token.parse::<u32>() {
-                            Ok(w) => cfg.width = w,
-                            Err(_) => return Err("invalid width"),
-                        }
-                    } else {
-                        return Err("no width provided");
-                    }
-                } else if *arg == String::from("-h") {
-                    if let Some(token) = it.next() {
-                        match token.parse::<u32>() {
-                            Ok(h) => cfg.height = h,
-                            Err(_) => return Err("invalid height"),
-                        }
-                    } else {
-                        return Err("no height provided");
-                    }
-                } else if *arg == String::from("-s") {
-                    if let Some(token) = it.next() {
-                        match token.parse::<u16>() {
-                            Ok(s) => cfg.samples = s,
-                            Err(_) => return Err("invalid number of samples"),
-                        }
-                    } else {
-                        return Err("no number of samples provided");
-                    }
-                } else if *arg == String::from("-d") {
-                    if let Some(token) = it.next() {
-                        match token.parse::<u16>() {
-                            Ok(md) => cfg.max_depth = md,
-                            Err(_) => return Err("invalid maximum depth"),
-                        }
-                    } else {
-                        return Err("no maximum depth provided");
-                    }
-                } else if *arg == String::from("--help") {
-                    return Ok(Config::need_help());
-                } else {
-                    cfg.output = arg.to_string();
-                }
+    fn set_source(&mut self, long: &'static str, source: &Source) {
+        self.sources.retain(|(key, _)| *key != long);
+        self.sources.push((long, source.clone()));
+    }
+
+    // The value of a setting, as a settings file would give it, or None if unset.
+    fn setting(&self, long: &str) -> Option<Value> {
+        let string = |s: &str| Some(Value::String(s.to_string()));
+        let list = |items: Vec<String>| match items.is_empty() {
+            true => None,
+            false => Some(Value::Array(items.into_iter().map(Value::String).collect())),
+        };
+        match long {
+            "scene" => self.scene.as_deref().and_then(string),
+            "width" => Some(Value::Integer(self.width as i64)),
+            "height" => Some(Value::Integer(self.height as i64)),
+            "samples" => Some(Value::Integer(self.samples as i64)),
+            "max-depth" => Some(Value::Integer(self.max_depth as i64)),
+            "filter" => string(&self.filter.to_string()),
+            "spectral" => Some(Value::Boolean(self.spectral)),
+            "threads" => self.threads.map(|n| Value::Integer(n as i64)),
+            "exposure" => self.exposure.map(Value::Float),
+            "tonemap" => self.tone_map.and_then(|t| string(&t.to_string())),
+            "iso" => self.iso.map(Value::Float),
+            "shutter" => self.shutter.map(Value::Float),
+            "f-number" => self.f_number.map(Value::Float),
+            "white-balance" => self.white_balance.map(Value::Float),
+            "projection" => self.projection.and_then(|p| string(&p.to_string())),
+            "lens" => self.lens.as_deref().and_then(string),
+            "focus" => self.focus.map(Value::Float),
+            "blades" => string(&self.aperture.to_string()),
+            "sensor" => Some(Value::Float(self.sensor_width)),
+            "sun" => self.sun.and_then(|(elevation, azimuth)| string(&format!("{},{}", elevation, azimuth))),
+            "turbidity" => Some(Value::Float(self.turbidity)),
+            "ground-albedo" => Some(Value::Float(self.ground_albedo)),
+            "sun-diameter" => Some(Value::Float(self.sun_diameter)),
+            "colorspace" => string(&self.encoding.to_string()),
+            "denoise" => Some(Value::Boolean(self.denoise)),
+            "raw" => self.raw_output.as_deref().and_then(string),
+            "aov" => list(self.aovs.iter().map(|a| a.to_string()).collect()),
+            "checkpoint" => self.checkpoint.as_deref().and_then(string),
+            "checkpoint-interval" => Some(Value::Integer(self.checkpoint_interval as i64)),
+            "workers" => list(self.workers.clone()),
+            "crop" => self.crop.and_then(|c| string(&c.to_string())),
+            "full-frame" => Some(Value::Boolean(self.full_frame)),
+            "stereo" => self.stereo.and_then(|s| string(&s.to_string())),
+            "ipd" => Some(Value::Float(self.ipd)),
+            "convergence" => self.convergence.map(Value::Float),
+            "animation" => self.animation.as_deref().and_then(string),
+            "frames" => self.frames.and_then(|(first, last)| string(&format!("{}-{}", first, last))),
+            "overwrite" => Some(Value::Boolean(self.overwrite)),
+            _ => None,
+        }
+    }
+
+    // The settings a command uses as a settings file, noting where each came from.
+    fn describe(&self, spec: &CommandSpec) -> String {
+        let mut text = format!("# Settings for `raytracer {}` and where they came from.\n", spec.name);
+        for opt in spec.options.iter().flat_map(|g| g.iter()).filter(|o| is_layered(o)) {
+            let source = self.sources.iter().find(|(key, _)| *key == opt.long).map_or(&Source::Default, |(_, s)| s);
+            match self.setting(opt.long) {
+                Some(value) => text.push_str(&format!("{:<39} # {}\n", format!("{} = {}", opt.long, value), source)),
+                None => text.push_str(&format!("# {} is unset\n", opt.long)),
             }
         }
+        text
+    }
+
+    // Resolves the settings of a command from every layer, lowest precedence first:
+    // the defaults, settings files, environment variables, a preset and the command
+    // line.
+    fn resolve(mut self, spec: &CommandSpec, args: &Args, env: &Environment) -> Result<Config, ArgError> {
+        let given = |long: &str| args.options.iter().rev().find(|(o, _)| o.long == long).map(|(_, v)| v.as_str());
+        let files = match given("config").or(env.var(&env_name("config"))) {
+            Some(file) => match read_settings_file(Path::new(file))? {
+                Some(settings) => vec![settings],
+                None => return Err(ArgError::SettingsFile { file: file.to_string(), reason: String::from("not found") }),
+            },
+            None => env.files.iter().filter_map(|f| read_settings_file(f).transpose()).collect::<Result<_, _>>()?,
+        };
+
+        let mut preset = None;
+        let mut presets: Vec<(String, Vec<Setting>)> = PRESETS.iter().map(|(name, settings)| {
+            let settings = settings.iter().map(|(long, value)| Setting {
+                opt: find_layered(long).unwrap(),
+                value: value.to_string(),
+                source: Source::Preset(name.to_string()),
+            });
+            (name.to_string(), settings.collect())
+        }).collect();
+        for file in &files {
+            self.apply_layer(spec, &file.settings)?;
+            preset = file.preset.clone().or(preset);
+            for (name, settings) in &file.presets {
+                presets.retain(|(n, _)| n != name);
+                presets.push((name.clone(), settings.clone()));
+            }
+        }
+
+        let vars: Vec<Setting> = LAYERED.iter().flat_map(|g| g.iter()).filter(|o| is_layered(o)).filter_map(|opt| {
+            let name = env_name(opt.long);
+            env.var(&name).map(|value| Setting { opt, value: value.to_string(), source: Source::Env(name) })
+        }).collect();
+        self.apply_layer(spec, &vars)?;
+
+        let preset = match given("preset") {
+            Some(name) => Some((name, Source::CommandLine)),
+            None => {
+                let name = env_name("preset");
+                match env.var(&name) {
+                    Some(value) => Some((value, Source::Env(name))),
+                    None => preset.as_ref().map(|(name, source)| (name.as_str(), source.clone())),
+                }
+            },
+        };
+        if let Some((name, source)) = preset {
+            let settings = match presets.iter().find(|(n, _)| n == name) {
+                Some((_, settings)) => settings,
+                None => {
+                    let e = invalid(find_layered("preset").unwrap(), name, "unknown preset");
+                    return Err(match source {
+                        Source::CommandLine => e,
+                        source => ArgError::Layer(source, Box::new(e)),
+                    });
+                },
+            };
+            self.apply_layer(spec, settings)?;
+        }
+
+        let options: Vec<Setting> = args.options.iter()
+            .filter(|(opt, _)| !matches!(opt.long, "config" | "preset" | "print-config"))
+            .map(|(opt, value)| Setting { opt, value: value.clone(), source: Source::CommandLine })
+            .collect();
+        self.apply_layer(spec, &options)?;
+        Ok(self)
+    }
+
+    fn from_render_args(args: Args, env: &Environment) -> Result<Command, ArgError> {
+        let spec = &COMMANDS[0];
+        let mut cfg = Config::default().resolve(spec, &args, env)?;
+        if args.options.iter().any(|(o, _)| o.long == "print-config") {
+            return Ok(Command::PrintConfig(cfg.describe(spec)));
+        }
+        cfg.output = args.positional(&["OUTPUT"])?.remove(0);
 
-        if cfg.output.is_empty() {
-            return Err("no output file specified");
+        if cfg.resume && cfg.checkpoint.is_none() {
+            return Err(ArgError::Requires("--resume", "--checkpoint"));
+        }
+        if cfg.checkpoint.is_some() && cfg.is_sequence() {
+            return Err(ArgError::Conflict("--checkpoint", if cfg.frames.is_some() { "--frames" } else { "--animation" }));
+        }
+        if let Some(crop) = cfg.crop {
+            crop.region(cfg.width, cfg.height).map_err(|e| invalid(find_layered("crop").unwrap(), &crop.to_string(), e))?;
+        } else if cfg.full_frame {
+            return Err(ArgError::Requires("--full-frame", "--crop"));
+        }
+        if cfg.lens.is_some() {
+            if cfg.projection.is_some() {
+                return Err(ArgError::Conflict("--lens", "--projection"));
+            }
+        } else if cfg.focus.is_some() {
+            return Err(ArgError::Requires("--focus", "--lens"));
+        }
+        if cfg.stereo.is_some() {
+            if cfg.checkpoint.is_some() {
+                return Err(ArgError::Conflict("--stereo", "--checkpoint"));
+            }
+            if cfg.crop.is_some() {
+                return Err(ArgError::Conflict("--stereo", "--crop"));
+            }
+            // Offsetting both eyes to the side of the view gives the views behind and
+            // beside it no parallax or the wrong way round. Only equirectangular
+            // panoramas move the eyes with the direction.
+            match cfg.projection {
+                Some(p @ Projection::Cubemap) => {
+                    return Err(invalid(find_layered("projection").unwrap(), &p.to_string(), "cube maps cannot be stereo"));
+                },
+                Some(p @ Projection::Fisheye { fov, .. }) if fov > 180.0 => {
+                    return Err(invalid(find_layered("projection").unwrap(), &p.to_string(),
+                                       "fisheyes over 180° cannot be stereo"));
+                },
+                _ => (),
+            }
+        } else if cfg.convergence.is_some() {
+            return Err(ArgError::Requires("--convergence", "--stereo"));
+        }
+        if !cfg.workers.is_empty() {
+            if cfg.checkpoint.is_some() {
+                return Err(ArgError::Conflict("--workers", "--checkpoint"));
+            }
+            if cfg.denoise {
+                return Err(ArgError::Conflict("--workers", "--denoise"));
+            }
+            if !cfg.aovs.is_empty() {
+                return Err(ArgError::Conflict("--workers", "--aov"));
+            }
+            if cfg.crop.is_some() {
+                return Err(ArgError::Conflict("--workers", "--crop"));
+            }
+            if cfg.projection.is_some() {
+                return Err(ArgError::Conflict("--workers", "--projection"));
+            }
+            if cfg.stereo.is_some() {
+                return Err(ArgError::Conflict("--workers", "--stereo"));
+            }
+            if cfg.lens.is_some() {
+                return Err(ArgError::Conflict("--workers", "--lens"));
+            }
+            if cfg.sun.is_some() {
+                return Err(ArgError::Conflict("--workers", "--sun"));
+            }
+            if cfg.is_sequence() {
+                return Err(ArgError::Conflict("--workers", if cfg.frames.is_some() { "--frames" } else { "--animation" }));
+            }
         }
-        return Ok(cfg);
+        Ok(Command::Render(cfg))
     }
 }
 
@@ -108,12 +477,798 @@ impl Default for Config {
     }
 }
 
+// A window of the image, `x0..x1` across and `y0..y1` down from the top left, in
+// pixels or as fractions of the image size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Crop {
+    Pixels([u32; 4]),
+    Fractions([f64; 4]),
+}
+
+impl Crop {
+    // The pixels of the crop, with rows from the bottom up like the film.
+    pub fn region(&self, width: u32, height: u32) -> Result<Region, String> {
+        let [x0, y0, x1, y1] = match *self {
+            Crop::Pixels(p) => p,
+            Crop::Fractions([x0, y0, x1, y1]) => [
+                (x0 * width as f64).floor() as u32,
+                (y0 * height as f64).floor() as u32,
+                (x1 * width as f64).ceil() as u32,
+                (y1 * height as f64).ceil() as u32,
+            ],
+        };
+        if x0 >= x1 || y0 >= y1 {
+            return Err(String::from("the crop is empty"));
+        }
+        if x1 > width || y1 > height {
+            return Err(format!("the crop goes outside the {} x {} image", width, height));
+        }
+        Ok(Region { x0, y0: height - y1, x1, y1: height - y0 })
+    }
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+        if parts.len() != 4 {
+            return Err(String::from("expected X0,Y0,X1,Y1"));
+        }
+        if let Ok(pixels) = parts.iter().map(|p| p.parse::<u32>()).collect::<Result<Vec<_>, _>>() {
+            return Ok(Crop::Pixels([pixels[0], pixels[1], pixels[2], pixels[3]]));
+        }
+        let fractions = parts.iter().map(|p| p.parse::<f64>()).collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("invalid crop `{}`", s))?;
+        if fractions.iter().any(|f| !(0.0..=1.0).contains(f)) {
+            return Err(String::from("fractions must be from 0 to 1"));
+        }
+        Ok(Crop::Fractions([fractions[0], fractions[1], fractions[2], fractions[3]]))
+    }
+}
+
+impl fmt::Display for Crop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Crop::Pixels([x0, y0, x1, y1]) => write!(f, "{},{},{},{}", x0, y0, x1, y1),
+            Crop::Fractions([x0, y0, x1, y1]) => write!(f, "{:?},{:?},{:?},{:?}", x0, y0, x1, y1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffOptions {
+    pub a: String,
+    pub b: String,
+    pub output: Option<String>,
+    // Largest difference in a channel, from 0 to 1, for pixels to count as the same.
+    pub threshold: f64,
+}
+
+// What the program has been asked to do.
+#[derive(Debug)]
+pub enum Command {
+    Render(Config),
+    // Describe a scene.
+    Info { scene: String },
+    // Time renders of a scene without writing them.
+    Bench { config: Config, runs: u32 },
+    // Compare two images.
+    Diff(DiffOptions),
+    // Render tiles for a coordinator.
+    Worker { listen: String },
+    // Render jobs submitted over HTTP.
+    Serve { listen: String, limits: Limits },
+    // Help on a command, or on the program.
+    Help(Option<&'static str>),
+    Version,
+    // Settings resolved for `render` or `bench`, as the text to print.
+    PrintConfig(String),
+}
+
+impl Command {
+    // Parses the whole command line, program name included, along with settings
+    // from the environment. Without a command, `render` is assumed.
+    pub fn parse(args: &[String]) -> Result<Command, ArgError> {
+        Command::parse_in(args, &Environment::current())
+    }
+
+    pub fn parse_in(args: &[String], env: &Environment) -> Result<Command, ArgError> {
+        let args = args.get(1..).unwrap_or(&[]);
+        let (spec, rest) = match args.first().map(String::as_str) {
+            None => return Ok(Command::Help(None)),
+            Some("-h") | Some("--help") => return Ok(Command::Help(None)),
+            Some("-V") | Some("--version") => return Ok(Command::Version),
+            Some("help") => {
+                return match args.get(1) {
+                    None => Ok(Command::Help(None)),
+                    Some(name) => Ok(Command::Help(Some(find_command(name)?.name))),
+                };
+            },
+            Some(name) => match COMMANDS.iter().find(|c| c.name == name) {
+                Some(spec) => (spec, &args[1..]),
+                None => (&COMMANDS[0], args),
+            },
+        };
+
+        let parsed = scan(spec, rest)?;
+        if parsed.help {
+            return Ok(Command::Help(Some(spec.name)));
+        }
+        match spec.name {
+            "render" => Config::from_render_args(parsed, env),
+            "info" => Ok(Command::Info { scene: parsed.positional(&["SCENE"])?.remove(0) }),
+            "bench" => {
+                let config = Config { width: 640, height: 360, samples: 16, ..Config::default() };
+                let config = config.resolve(spec, &parsed, env)?;
+                if parsed.options.iter().any(|(o, _)| o.long == "print-config") {
+                    return Ok(Command::PrintConfig(config.describe(spec)));
+                }
+                let mut runs = 3;
+                for (opt, value) in parsed.options.iter().filter(|(o, _)| o.long == "runs") {
+                    runs = parse_range(opt, value, 1, 100)?;
+                }
+                parsed.positional(&[])?;
+                Ok(Command::Bench { config, runs })
+            },
+            "diff" => {
+                let mut files = parsed.positional(&["A", "B"])?.into_iter();
+                let mut diff = DiffOptions {
+                    a: files.next().unwrap(),
+                    b: files.next().unwrap(),
+                    output: None,
+                    threshold: 0.0,
+                };
+                for (opt, value) in &parsed.options {
+                    match opt.long {
+                        "output" => diff.output = Some(value.to_string()),
+                        _ => diff.threshold = parse_range(opt, value, 0.0, 1.0)?,
+                    }
+                }
+                Ok(Command::Diff(diff))
+            },
+            "worker" | "serve" => {
+                let mut listen = String::from(if spec.name == "worker" { DEFAULT_WORKER_LISTEN } else { DEFAULT_SERVE_LISTEN });
+                let mut limits = Limits::default();
+                for (opt, value) in &parsed.options {
+                    match opt.long {
+                        "listen" => listen = value.to_string(),
+                        "queue" => limits.queue = parse_range(opt, value, 1, 10000)?,
+                        _ => limits.running = parse_range(opt, value, 1, 1024)?,
+                    }
+                }
+                parsed.positional(&[])?;
+                match spec.name {
+                    "worker" => Ok(Command::Worker { listen }),
+                    _ => Ok(Command::Serve { listen, limits }),
+                }
+            },
+            _ => unreachable!("unhandled command {}", spec.name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgError {
+    UnknownCommand(String),
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue { option: String, value: String, reason: String },
+    MissingArgument(&'static str),
+    UnexpectedArgument(String),
+    // An option that needs another.
+    Requires(&'static str, &'static str),
+    // Options that cannot be used together.
+    Conflict(&'static str, &'static str),
+    SettingsFile { file: String, reason: String },
+    // An error in a setting from somewhere other than the command line.
+    Layer(Source, Box<ArgError>),
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::UnknownCommand(name) => write!(f, "unknown command `{}`", name),
+            ArgError::UnknownOption(option) => write!(f, "unknown option `{}`", option),
+            ArgError::MissingValue(option) => write!(f, "option `{}` needs a value", option),
+            ArgError::InvalidValue { option, value, reason } => {
+                write!(f, "invalid value `{}` for `{}`: {}", value, option, reason)
+            },
+            ArgError::MissingArgument(name) => write!(f, "missing {} argument", name),
+            ArgError::UnexpectedArgument(arg) => write!(f, "unexpected argument `{}`", arg),
+            ArgError::Requires(a, b) => write!(f, "`{}` needs `{}`", a, b),
+            ArgError::Conflict(a, b) => write!(f, "`{}` cannot be used with `{}`", a, b),
+            ArgError::SettingsFile { file, reason } => write!(f, "settings file {}: {}", file, reason),
+            ArgError::Layer(source, e) => write!(f, "{}: {}", source, e),
+        }
+    }
+}
+
+impl Error for ArgError {}
+
+// A command-line option, which takes a value if it has a value name.
+struct Opt {
+    long: &'static str,
+    short: Option<char>,
+    value: Option<&'static str>,
+    help: &'static str,
+}
+
+const fn opt(long: &'static str, short: Option<char>, value: Option<&'static str>, help: &'static str) -> Opt {
+    Opt { long, short, value, help }
+}
+
+const HELP: Opt = opt("help", Some('h'), None, "Print help for the command");
+
+const SCENE_OPTIONS: &[Opt] = &[
+    opt("scene", Some('i'), Some("FILE"), "Scene file to render: .gltf, .glb, .ply or .stl. The built-in scene \
+        by default"),
+    opt("width", Some('w'), Some("PIXELS"), "Image width, from 2 to 16384. Given only one of the width and \
+        height, the other follows a 16:9 aspect ratio"),
+    opt("height", Some('H'), Some("PIXELS"), "Image height, from 2 to 16384"),
+    opt("samples", Some('s'), Some("N"), "Samples per pixel, from 1 to 65535"),
+    opt("max-depth", Some('d'), Some("N"), "Longest path in bounces, from 1 to 1024. Default 32"),
+    opt("filter", None, Some("FILTER"), "Pixel reconstruction filter: box, tent, gaussian, mitchell or lanczos, \
+        optionally with :RADIUS in pixels. Default box:0.5"),
+    opt("spectral", None, None, "Trace wavelengths instead of RGB, for dispersion"),
+    opt("projection", None, Some("NAME"), "Camera projection: perspective, orthographic, \
+        fisheye[:equidistant|:equisolid][:FOV], equirectangular or cubemap. Panoramas want a 2:1 image and cube \
+        maps a 3:2 grid of faces. The scene camera's by default"),
+    opt("lens", None, Some("FILE"), "Take camera rays through the lens described in FILE, a line of radius, \
+        thickness, index of refraction and aperture in millimetres for each surface from the front, in place of \
+        the camera's projection"),
+    opt("focus", None, Some("DISTANCE"), "Distance from the film to focus the lens at. Infinity by default"),
+    opt("blades", None, Some("N[:DEGREES]"), "Shape the lens's aperture stop as a polygon of N blades, turned \
+        by DEGREES, for the shape of out-of-focus highlights. 0 for a circle, the default"),
+    opt("sensor", None, Some("MILLIMETRES"), "Width of the film behind the lens. Default 36"),
+    opt("sun", None, Some("ELEVATION,AZIMUTH"), "Light the scene with a clear daylight sky and the sun at \
+        ELEVATION degrees above the horizon and AZIMUTH degrees clockwise from north, which is -z, with east \
+        along +x. Light is in real-world units, so exposure follows --iso, --shutter and --f-number, by \
+        default ISO 100, 1/100 s and f/16"),
+    opt("turbidity", None, Some("T"), "Haziness of the sky, from 2 for clear air to 10. Default 3"),
+    opt("ground-albedo", None, Some("ALBEDO"), "Reflectance of the ground below the horizon, from 0 to 1. \
+        Default 0.3"),
+    opt("sun-diameter", None, Some("DEGREES"), "Angular size of the sun, which softens shadows, from 0 to 30. \
+        Default 0.53"),
+    opt("threads", Some('j'), Some("N"), "Threads to render with. One per CPU by default"),
+    opt("config", None, Some("FILE"), "Read settings from FILE instead of the usual settings files"),
+    opt("preset", None, Some("NAME"), "Apply a preset: preview, final or one from a settings file"),
+    opt("print-config", None, None, "Print the resolved settings and where each came from, then exit"),
+];
+
+const RENDER_OPTIONS: &[Opt] = &[
+    opt("exposure", None, Some("EV"), "Exposure adjustment in stops"),
+    opt("tonemap", None, Some("OP"), "Tone mapping: clamp, reinhard, reinhard-extended[:WHITE], hable or aces"),
+    opt("iso", None, Some("SPEED"), "Film speed of a physical camera, for scenes lit in real-world units. \
+        Giving any of --iso, --shutter and --f-number exposes by all three, defaulting to ISO 100, 1/100 s and \
+        f/16"),
+    opt("shutter", None, Some("SECONDS"), "Shutter time of a physical camera, such as 0.004 or 1/250"),
+    opt("f-number", None, Some("N"), "Aperture of a physical camera as an f-number, such as 2.8"),
+    opt("white-balance", None, Some("KELVIN"), "Colour temperature of light to show as white, from 1667 to \
+        25000. Daylight is around 6500"),
+    opt("colorspace", None, Some("SPACE"), "Output colour space: srgb, linear-srgb, display-p3, rec2020, \
+        rec2020-pq or acescg. Tagged in PNG output, while PPM output must be srgb"),
+    opt("denoise", None, None, "Filter the image guided by albedo, normal and depth"),
+    opt("raw", None, Some("FILE"), "Also write the image before denoising to FILE"),
+    opt("aov", None, Some("PASSES"), "Also write render passes, a comma-separated list or `all`: depth, \
+        position, normal, albedo, object_id, material_id, direct_diffuse, direct_specular, indirect_diffuse, \
+        indirect_specular, emission and sample_count. They become layers of .exr output and separate files \
+        otherwise"),
+    opt("checkpoint", None, Some("FILE"), "Save progress to FILE as the render goes, and at the end"),
+    opt("checkpoint-interval", None, Some("SECONDS"), "Time between checkpoints. Default 300"),
+    opt("resume", None, None, "Continue the render saved in the checkpoint file, up to the number of samples \
+        asked for"),
+    opt("crop", None, Some("X0,Y0,X1,Y1"), "Render only the pixels from (X0, Y0) up to (X1, Y1) from the top \
+        left, given in pixels or, if any has a decimal point, as fractions of the image size. The crop is written \
+        on its own, and composites back into the whole image"),
+    opt("full-frame", None, None, "With --crop, write the whole image, leaving the pixels outside the crop \
+        black"),
+    opt("stereo", None, Some("LAYOUT"), "Render a pair of images for the left and right eyes, each of the \
+        image size, side-by-side or top-bottom with the left eye on top. Equirectangular panoramas are \
+        omnidirectional stereo, and cube maps and fisheyes over 180° cannot be stereo"),
+    opt("ipd", None, Some("DISTANCE"), "Distance between the eyes in scene units. Default 0.064"),
+    opt("convergence", None, Some("DISTANCE"), "Distance at which the eyes' views meet, appearing level with \
+        the screen. The eyes look in parallel by default"),
+    opt("animation", None, Some("FILE"), "Pose the scene for each frame by the keyframes in FILE, which \
+        animate the camera, object transforms and material values. The camera may also orbit, \
+        dolly along a path through points, or track an object"),
+    opt("frames", None, Some("FIRST-LAST"), "Render these frames, or the animation's range by \
+        default. The output names the files: a run of # becomes the frame number, as in out.####.png, and \
+        otherwise it goes before the extension"),
+    opt("overwrite", None, None, "Render frames whose output already exists, rather than skipping them"),
+    opt("workers", None, Some("ADDRESSES"), "Render on the comma-separated list of workers, given as HOST:PORT, \
+        instead of locally. Scenes must not refer to other files"),
+    HELP,
+];
+
+const BENCH_OPTIONS: &[Opt] = &[
+    opt("runs", None, Some("N"), "Renders to time, from 1 to 100. Default 3"),
+    HELP,
+];
+
+const DIFF_OPTIONS: &[Opt] = &[
+    opt("output", Some('o'), Some("FILE"), "Write the absolute differences to FILE"),
+    opt("threshold", None, Some("T"), "Largest difference in a channel, from 0 to 1, for pixels to count as \
+        the same. Default 0"),
+    HELP,
+];
+
+const WORKER_OPTIONS: &[Opt] = &[
+    opt("listen", None, Some("ADDRESS"), "Address to listen on for a coordinator, such as 0.0.0.0:7878 \
+        to accept coordinators on other hosts. Default 127.0.0.1:7878"),
+    HELP,
+];
+
+const SERVE_OPTIONS: &[Opt] = &[
+    opt("listen", None, Some("ADDRESS"), "Address for the HTTP API. Default 127.0.0.1:8080"),
+    opt("queue", None, Some("JOBS"), "How many jobs may wait, from 1 to 10000. Default 16"),
+    opt("jobs", None, Some("JOBS"), "How many jobs render at once, from 1 to 1024. Default 1"),
+    HELP,
+];
+
+const SETTINGS_NOTES: &str = "\
+SETTINGS:
+    Settings come from, lowest precedence first: the defaults; settings files
+    $XDG_CONFIG_HOME/raytracer/config.toml and raytracer.toml, or the file given
+    by --config or RAYTRACER_CONFIG; environment variables named after options,
+    such as RAYTRACER_MAX_DEPTH; the preset given by --preset, RAYTRACER_PRESET
+    or `preset` in a settings file; and the options given here. Settings files
+    are TOML, setting options by their long names, as in `samples = 256` or
+    `aov = [\"depth\", \"normal\"]`, and may define presets in tables such as
+    [preset.draft].
+";
+
+// Options that settings files, environment variables and presets may set.
+const LAYERED: &[&[Opt]] = &[SCENE_OPTIONS, RENDER_OPTIONS];
+
+// Built-in presets, which settings files may replace.
+const PRESETS: &[(&str, &[(&str, &str)])] = &[
+    ("preview", &[("width", "640"), ("height", "360"), ("samples", "16"), ("max-depth", "8")]),
+    ("final", &[("samples", "1024"), ("max-depth", "64"), ("filter", "mitchell")]),
+];
+
+fn is_layered(opt: &Opt) -> bool {
+    !matches!(opt.long, "help" | "resume" | "config" | "preset" | "print-config")
+}
+
+fn find_layered(long: &str) -> Option<&'static Opt> {
+    LAYERED.iter().flat_map(|g| g.iter()).find(|o| o.long == long)
+}
+
+// `max-depth` becomes `RAYTRACER_MAX_DEPTH`.
+fn env_name(long: &str) -> String {
+    format!("RAYTRACER_{}", long.to_uppercase().replace('-', "_"))
+}
+
+// Where a setting came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File { file: String, line: usize },
+    Env(String),
+    Preset(String),
+    CommandLine,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File { file, line } => write!(f, "{}:{}", file, line),
+            Source::Env(name) => write!(f, "{}", name),
+            Source::Preset(name) => write!(f, "preset {}", name),
+            Source::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+// Where settings come from besides the command line.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    pub vars: Vec<(String, String)>,
+    // Settings files to read if they exist, lowest precedence first.
+    pub files: Vec<PathBuf>,
+}
+
+impl Environment {
+    pub fn current() -> Environment {
+        let vars = env::vars().filter(|(name, _)| name.starts_with("RAYTRACER_")).collect();
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+        let mut files: Vec<PathBuf> = config_home.into_iter().map(|dir| dir.join("raytracer").join("config.toml")).collect();
+        files.push(PathBuf::from("raytracer.toml"));
+        Environment { vars, files }
+    }
+
+    fn var(&self, name: &str) -> Option<&str> {
+        self.vars.iter().rev().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+}
+
+// An option and its value in one layer of settings.
+#[derive(Clone)]
+struct Setting {
+    opt: &'static Opt,
+    value: String,
+    source: Source,
+}
+
+struct SettingsFile {
+    settings: Vec<Setting>,
+    preset: Option<(String, Source)>,
+    presets: Vec<(String, Vec<Setting>)>,
+}
+
+// Reads a settings file, or returns None if there is none.
+fn read_settings_file(path: &Path) -> Result<Option<SettingsFile>, ArgError> {
+    let file = path.display().to_string();
+    let error = |reason: String| ArgError::SettingsFile { file: file.clone(), reason };
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(error(e.to_string())),
+    };
+    let tables = toml::parse(&text).map_err(|e| error(e.to_string()))?;
+
+    let mut settings_file = SettingsFile { settings: Vec::new(), preset: None, presets: Vec::new() };
+    for table in tables {
+        let preset = match table.name.strip_prefix("preset.") {
+            Some(name) => Some(name.to_string()),
+            None if table.name.is_empty() => None,
+            None => return Err(error(format!("unknown table [{}]", table.name))),
+        };
+        let mut settings = Vec::new();
+        for entry in table.entries {
+            let source = Source::File { file: file.clone(), line: entry.line };
+            let value = match entry.value {
+                Value::Array(values) => values.iter().map(setting_value).collect::<Vec<_>>().join(","),
+                value => setting_value(&value),
+            };
+            if entry.key == "preset" && preset.is_none() {
+                settings_file.preset = Some((value, source));
+                continue;
+            }
+            match find_layered(&entry.key).filter(|o| is_layered(o)) {
+                Some(opt) => settings.push(Setting {
+                    opt,
+                    value,
+                    source: preset.as_ref().map_or(source, |name| Source::Preset(name.clone())),
+                }),
+                None => return Err(ArgError::Layer(source, Box::new(ArgError::UnknownOption(entry.key)))),
+            }
+        }
+        match preset {
+            Some(name) => settings_file.presets.push((name, settings)),
+            None => settings_file.settings = settings,
+        }
+    }
+    Ok(Some(settings_file))
+}
+
+fn setting_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+struct CommandSpec {
+    name: &'static str,
+    usage: &'static str,
+    about: &'static str,
+    options: &'static [&'static [Opt]],
+    notes: &'static str,
+}
+
+const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "render",
+        usage: "[OPTIONS] OUTPUT",
+        about: "Render a scene to OUTPUT, a .png, .ppm or .exr file. This is the default command.",
+        options: &[SCENE_OPTIONS, RENDER_OPTIONS],
+        notes: SETTINGS_NOTES,
+    },
+    CommandSpec {
+        name: "info",
+        usage: "SCENE",
+        about: "Describe the contents of a scene file.",
+        options: &[&[HELP]],
+        notes: "",
+    },
+    CommandSpec {
+        name: "bench",
+        usage: "[OPTIONS]",
+        about: "Time renders of a scene without writing them. Renders are 640 x 360 with 16 samples unless \
+                asked otherwise.",
+        options: &[SCENE_OPTIONS, BENCH_OPTIONS],
+        notes: SETTINGS_NOTES,
+    },
+    CommandSpec {
+        name: "diff",
+        usage: "[OPTIONS] A B",
+        about: "Compare two PNG or PPM images of the same size, exiting with status 1 if they differ.",
+        options: &[DIFF_OPTIONS],
+        notes: "",
+    },
+    CommandSpec {
+        name: "worker",
+        usage: "[OPTIONS]",
+        about: "Render tiles for coordinators started with `render --workers`.",
+        options: &[WORKER_OPTIONS],
+        notes: "",
+    },
+    CommandSpec {
+        name: "serve",
+        usage: "[OPTIONS]",
+        about: "Render jobs submitted over a local HTTP API.",
+        options: &[SERVE_OPTIONS],
+        notes: "\
+HTTP API:
+    POST /jobs?width=W&height=H&samples=S&depth=D&filter=F&spectral=1&format=EXT
+        Queue a job rendering the scene file in the request body, which needs
+        its format given as gltf, glb, ply or stl, or the built-in scene if the
+        body is empty
+    GET /jobs/ID
+        Job status and progress as JSON
+    GET /jobs/ID/image
+        The image rendered so far as PNG
+    DELETE /jobs/ID
+        Cancel the job, or forget it once finished
+",
+    },
+];
+
+fn find_command(name: &str) -> Result<&'static CommandSpec, ArgError> {
+    COMMANDS.iter().find(|c| c.name == name).ok_or_else(|| ArgError::UnknownCommand(name.to_string()))
+}
+
+// Options with their values, in the order given, and the other arguments.
+struct Args {
+    options: Vec<(&'static Opt, String)>,
+    positional: Vec<String>,
+    help: bool,
+}
+
+impl Args {
+    // The positional arguments, which must be exactly those named.
+    fn positional(&self, names: &[&'static str]) -> Result<Vec<String>, ArgError> {
+        if let Some(extra) = self.positional.get(names.len()) {
+            return Err(ArgError::UnexpectedArgument(extra.clone()));
+        }
+        if let Some(missing) = names.get(self.positional.len()) {
+            return Err(ArgError::MissingArgument(missing));
+        }
+        Ok(self.positional.clone())
+    }
+}
+
+// Accepts `--name VALUE`, `--name=VALUE` and `-n VALUE`, and `--flag` or
+// `--flag=false` for options without values. Everything after `--` is positional.
+fn scan(spec: &CommandSpec, args: &[String]) -> Result<Args, ArgError> {
+    let mut parsed = Args { options: Vec::new(), positional: Vec::new(), help: false };
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        if arg == "--" {
+            parsed.positional.extend(it.by_ref().cloned());
+            break;
+        }
+        let (opt, inline) = if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (long, None),
+            };
+            (spec.options.iter().flat_map(|g| g.iter()).find(|o| o.long == name), inline)
+        } else if arg.len() == 2 && arg.starts_with('-') {
+            let short = arg.chars().nth(1);
+            (spec.options.iter().flat_map(|g| g.iter()).find(|o| o.short.is_some() && o.short == short), None)
+        } else if arg.starts_with('-') && arg.len() > 1 && arg.parse::<f64>().is_err() {
+            return Err(ArgError::UnknownOption(arg.clone()));
+        } else {
+            parsed.positional.push(arg.clone());
+            continue;
+        };
+
+        let opt = opt.ok_or_else(|| ArgError::UnknownOption(arg.split('=').next().unwrap_or(arg).to_string()))?;
+        if opt.long == "help" {
+            parsed.help = true;
+            continue;
+        }
+        let value = match (opt.value, inline) {
+            (Some(_), Some(value)) => value.to_string(),
+            (Some(_), None) => it.next().ok_or_else(|| ArgError::MissingValue(format!("--{}", opt.long)))?.clone(),
+            (None, Some(value)) => value.to_string(),
+            (None, None) => String::from("true"),
+        };
+        parsed.options.push((opt, value));
+    }
+    Ok(parsed)
+}
+
+fn invalid<E: fmt::Display>(opt: &Opt, value: &str, reason: E) -> ArgError {
+    ArgError::InvalidValue { option: format!("--{}", opt.long), value: value.to_string(), reason: reason.to_string() }
+}
+
+fn check(opt: &Opt, value: &str, ok: bool, reason: &str) -> Result<(), ArgError> {
+    if ok { Ok(()) } else { Err(invalid(opt, value, reason)) }
+}
+
+fn parse_flag(opt: &Opt, value: &str) -> Result<bool, ArgError> {
+    match value {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(invalid(opt, value, "must be true or false")),
+    }
+}
+
+// A distance in scene units.
+fn parse_distance(opt: &Opt, value: &str) -> Result<f64, ArgError> {
+    let distance = parse_value::<f64>(opt, value)?;
+    check(opt, value, distance > 0.0 && distance.is_finite(), "must be a positive distance")?;
+    Ok(distance)
+}
+
+fn parse_positive(opt: &Opt, value: &str) -> Result<f64, ArgError> {
+    let v = parse_value::<f64>(opt, value)?;
+    check(opt, value, v > 0.0 && v.is_finite(), "must be positive")?;
+    Ok(v)
+}
+
+// The sun's position as `ELEVATION,AZIMUTH` in degrees.
+fn parse_sun(opt: &Opt, value: &str) -> Result<(f64, f64), ArgError> {
+    let (elevation, azimuth) = value.split_once(',').ok_or_else(|| invalid(opt, value, "expected ELEVATION,AZIMUTH"))?;
+    let elevation = parse_value::<f64>(opt, elevation.trim())?;
+    let azimuth = parse_value::<f64>(opt, azimuth.trim())?;
+    check(opt, value, (0.0..=90.0).contains(&elevation), "the elevation must be from 0 to 90")?;
+    check(opt, value, azimuth.is_finite(), "the azimuth must be finite")?;
+    Ok((elevation, azimuth))
+}
+
+// A time in seconds, or a fraction of a second as `1/N`.
+fn parse_shutter(opt: &Opt, value: &str) -> Result<f64, ArgError> {
+    let seconds = match value.split_once('/') {
+        Some((numerator, denominator)) => parse_value::<f64>(opt, numerator.trim())?
+            / parse_value::<f64>(opt, denominator.trim())?,
+        None => parse_value::<f64>(opt, value)?,
+    };
+    check(opt, value, seconds > 0.0 && seconds.is_finite(), "must be a positive time")?;
+    Ok(seconds)
+}
+
+// A frame number, or a range of them as `FIRST-LAST`.
+fn parse_frames(opt: &Opt, value: &str) -> Result<(u32, u32), ArgError> {
+    let (first, last) = value.split_once('-').unwrap_or((value, value));
+    let first = parse_value::<u32>(opt, first.trim())?;
+    let last = parse_value::<u32>(opt, last.trim())?;
+    check(opt, value, first <= last, "the range is empty")?;
+    Ok((first, last))
+}
+
+fn parse_value<T: FromStr>(opt: &Opt, value: &str) -> Result<T, ArgError>
+where
+    T::Err: fmt::Display,
+{
+    value.parse::<T>().map_err(|e| invalid(opt, value, e))
+}
+
+fn parse_range<T>(opt: &Opt, value: &str, min: T, max: T) -> Result<T, ArgError>
+where
+    T: FromStr + PartialOrd + fmt::Display,
+    T::Err: fmt::Display,
+{
+    let v = parse_value::<T>(opt, value)?;
+    if v < min || v > max {
+        return Err(invalid(opt, value, format!("must be from {} to {}", min, max)));
+    }
+    Ok(v)
+}
+
+// Help for a command, or for the program as a whole, generated from the option
+// tables.
+pub fn help(command: Option<&str>) -> String {
+    let mut text = String::new();
+    let spec = match command.map(find_command) {
+        Some(Ok(spec)) => spec,
+        _ => {
+            text.push_str("Render scenes with the raytracer.\n\nUSAGE:\n    raytracer [COMMAND] [OPTIONS] [ARGS]\n\n\
+                           COMMANDS:\n");
+            for spec in COMMANDS {
+                push_entry(&mut text, spec.name, spec.about);
+            }
+            push_entry(&mut text, "help", "Print help for a command.");
+            text.push_str("\nOPTIONS:\n");
+            push_entry(&mut text, "-h, --help", "Print this help");
+            push_entry(&mut text, "-V, --version", "Print the version");
+            text.push_str("\nRun `raytracer help COMMAND` for the options of a command.\n");
+            return text;
+        },
+    };
+
+    push_wrapped(&mut text, 0, 0, spec.about);
+    text.push_str(&format!("\nUSAGE:\n    raytracer {} {}\n\nOPTIONS:\n", spec.name, spec.usage));
+    for opt in spec.options.iter().flat_map(|g| g.iter()) {
+        let short = opt.short.map_or(String::from("    "), |s| format!("-{}, ", s));
+        let value = opt.value.map_or(String::new(), |v| format!(" <{}>", v));
+        push_entry(&mut text, &format!("{}--{}{}", short, opt.long, value), opt.help);
+    }
+    if !spec.notes.is_empty() {
+        text.push('\n');
+        text.push_str(spec.notes);
+    }
+    text
+}
+
+// An indented term with its description wrapped beside it, or below it if the term
+// is too long.
+fn push_entry(text: &mut String, term: &str, description: &str) {
+    const INDENT: usize = 4;
+    const COLUMN: usize = 32;
+    text.push_str(&" ".repeat(INDENT));
+    text.push_str(term);
+    let mut column = INDENT + term.len();
+    if column + 2 > COLUMN {
+        text.push('\n');
+        column = 0;
+    }
+    push_wrapped(text, column, COLUMN, description);
+}
+
+// Words wrapped to 80 columns and indented, starting at the given column of the
+// current line.
+fn push_wrapped(text: &mut String, mut column: usize, indent: usize, words: &str) {
+    const WIDTH: usize = 80;
+    let mut first = true;
+    for word in words.split_whitespace() {
+        if !first && column + 1 + word.len() > WIDTH {
+            text.push('\n');
+            column = 0;
+            first = true;
+        }
+        if first {
+            text.push_str(&" ".repeat(indent.saturating_sub(column)));
+            column = column.max(indent);
+        } else {
+            text.push(' ');
+            column += 1;
+        }
+        text.push_str(word);
+        column += word.len();
+        first = false;
+    }
+    text.push('\n');
+}
+
 #[cfg(test)]
 use float_cmp::assert_approx_eq;
 
 #[cfg(test)]
 mod tests  {
     use super::*;
+    use crate::camera::FisheyeMapping;
+
+    fn parse(line: &str) -> Result<Command, ArgError> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        Command::parse_in(&args, &Environment::default())
+    }
+
+    fn parse_render(line: &str) -> Result<Config, ArgError> {
+        match parse(line)? {
+            Command::Render(cfg) => Ok(cfg),
+            command => panic!("`{}` parsed as {:?}", line, command),
+        }
+    }
+
+    fn invalid_value(line: &str) -> String {
+        invalid_value_in(parse(line))
+    }
+
+    fn invalid_value_in(result: Result<Command, ArgError>) -> String {
+        match result {
+            Err(ArgError::InvalidValue { option, .. }) => option,
+            result => panic!("expected an invalid value, got {:?}", result.map(|_| ())),
+        }
+    }
 
     #[test]
     fn test_aspect_ratio() {
@@ -125,71 +1280,402 @@ mod tests  {
             panic!("None returned for valid aspect ratio");
         }
 
-        let cfg = Config::need_help();
+        let cfg = Config::new(1920, 0, 64, 32, String::new());
         assert_eq!(cfg.aspect_ratio(), None);
     }
 
     #[test]
     fn test_only_output() {
-        let args: Vec<String> = vec![String::from("argparse"), String::from("output.ppm")];
-        match Config::parse_args(&args) {
+        match parse_render("argparse output.ppm") {
             Ok(cfg) => assert_eq!(cfg.output, String::from("output.ppm")),
             Err(e) => panic!("Error {} parsing no-option args", e),
         }
+        let cfg = parse_render("argparse render output.ppm").expect("render command rejected");
+        assert_eq!(cfg.output, String::from("output.ppm"));
     }
 
     #[test]
     fn test_no_output() {
-        let args: Vec<String> = vec![String::from("argparse")];
-        println!("{:?}", Config::parse_args(&args));
-        match Config::parse_args(&args) {
+        match parse("argparse render") {
             Ok(_) => panic!("Valid Config from invalid args"),
-            Err(e) => assert_eq!(e, "no output file specified"),
+            Err(e) => assert_eq!(e, ArgError::MissingArgument("OUTPUT")),
         }
+        assert_eq!(parse("argparse a.png b.png").unwrap_err(), ArgError::UnexpectedArgument(String::from("b.png")));
     }
 
     #[test]
     fn test_valid_width_arg() {
-        let args: Vec<String> = vec![String::from("argparse"),
-                                     String::from("-w"), String::from("1680"),
-                                     String::from("output.ppm")];
-        match Config::parse_args(&args) {
+        match parse_render("argparse -w 1680 output.ppm") {
             Ok(cfg) => {
                 assert_eq!(cfg.width, 1680);
+                // The height follows from 16:9.
+                assert_eq!(cfg.height, 945);
                 assert_eq!(cfg.output, String::from("output.ppm"));
             },
             Err(_) => panic!("can't create config from `argparse -w 1680 output.ppm`"),
         }
+        let cfg = parse_render("argparse --height=720 output.ppm").unwrap();
+        assert_eq!((cfg.width, cfg.height), (1280, 720));
     }
 
     #[test]
     fn test_invalid_width_arg() {
-        let args: Vec<String> = vec![String::from("argparse"),
-                                     String::from("-w"), String::from("x1680"),
-                                     String::from("output.ppm")];
-        match Config::parse_args(&args) {
-            Ok(_) => panic!("valid config from `argparse -w x1680 output.ppm"),
-            Err(s) => assert_eq!(s, "invalid width"),
-        }
+        assert_eq!(invalid_value("argparse -w x1680 output.ppm"), "--width");
+        assert_eq!(invalid_value("argparse --width 0 output.ppm"), "--width");
+        assert_eq!(invalid_value("argparse -H 1 output.ppm"), "--height");
+        assert_eq!(invalid_value("argparse -s 0 output.ppm"), "--samples");
+        assert_eq!(parse("argparse -w").unwrap_err(), ArgError::MissingValue(String::from("--width")));
     }
 
     #[test]
     fn test_valid_width_no_output_arg() {
-        let args: Vec<String> = vec![String::from("argparse"),
-                                     String::from("-w"), String::from("1680")];
-        match Config::parse_args(&args) {
+        match parse("argparse -w 1680") {
             Ok(_) => panic!("valid Config without output file"),
-            Err(e) => assert_eq!(e, "no output file specified"),
+            Err(e) => assert_eq!(e, ArgError::MissingArgument("OUTPUT")),
         }
     }
 
+    #[test]
+    fn test_unknown_args() {
+        assert_eq!(parse("argparse -m 4 out.png").unwrap_err(), ArgError::UnknownOption(String::from("-m")));
+        assert_eq!(parse("argparse --colour=red out.png").unwrap_err(),
+                   ArgError::UnknownOption(String::from("--colour")));
+        assert_eq!(parse("argparse help rendr").unwrap_err(), ArgError::UnknownCommand(String::from("rendr")));
+        assert_eq!(invalid_value("argparse --denoise=yes out.png"), "--denoise");
+    }
+
+    #[test]
+    fn test_help_args() {
+        assert!(matches!(parse("argparse"), Ok(Command::Help(None))));
+        assert!(matches!(parse("argparse -h"), Ok(Command::Help(None))));
+        assert!(matches!(parse("argparse -w 640 -h"), Ok(Command::Help(Some("render")))));
+        assert!(matches!(parse("argparse help diff"), Ok(Command::Help(Some("diff")))));
+        assert!(matches!(parse("argparse --version"), Ok(Command::Version)));
+
+        let text = help(Some("render"));
+        assert!(text.contains("-H, --height <PIXELS>"));
+        assert!(text.contains("-d, --max-depth <N>"));
+        assert!(text.lines().all(|l| l.len() <= 80), "{}", text);
+        assert!(help(None).contains("bench"));
+    }
+
+    #[test]
+    fn test_scene_arg() {
+        match parse_render("argparse -i scene.glb output.ppm") {
+            Ok(cfg) => {
+                assert_eq!(cfg.scene, Some(String::from("scene.glb")));
+                assert_eq!(cfg.output, String::from("output.ppm"));
+            },
+            Err(e) => panic!("error {} from valid arguments", e),
+        }
+    }
+
+    #[test]
+    fn test_filter_arg() {
+        let cfg = parse_render("argparse --filter lanczos:2 output.ppm").expect("valid arguments rejected");
+        assert_eq!(cfg.filter, Filter::Lanczos { radius: 2.0 });
+        assert_eq!(invalid_value("argparse --filter mitchell:-1 output.ppm"), "--filter");
+    }
+
+    #[test]
+    fn test_projection_args() {
+        let cfg = parse_render("argparse --projection fisheye:equisolid:200 output.ppm").unwrap();
+        assert_eq!(cfg.projection, Some(Projection::Fisheye { mapping: FisheyeMapping::Equisolid, fov: 200.0 }));
+        assert_eq!(parse_render("argparse output.ppm").unwrap().projection, None);
+        assert_eq!(invalid_value("argparse --projection fisheye:0 output.ppm"), "--projection");
+        assert_eq!(parse("argparse --projection cubemap --workers h:1 out.png").unwrap_err(),
+                   ArgError::Conflict("--workers", "--projection"));
+    }
+
+    #[test]
+    fn test_lens_args() {
+        let cfg = parse_render("argparse --lens dgauss.dat --focus 2.5 --blades 7:10 --sensor 24 out.png")
+            .expect("valid arguments rejected");
+        assert_eq!(cfg.lens, Some(String::from("dgauss.dat")));
+        assert_eq!(cfg.focus, Some(2.5));
+        assert_eq!(cfg.aperture, Aperture { blades: 7, rotation: 10.0 });
+        assert_eq!(cfg.sensor_width, 24.0);
+
+        assert_eq!(invalid_value("argparse --lens a.dat --blades 2 out.png"), "--blades");
+        assert_eq!(parse("argparse --focus 3 out.png").unwrap_err(), ArgError::Requires("--focus", "--lens"));
+        assert_eq!(parse("argparse --lens a.dat --projection fisheye out.png").unwrap_err(),
+                   ArgError::Conflict("--lens", "--projection"));
+    }
+
+    #[test]
+    fn test_sky_args() {
+        let cfg = parse_render("argparse --sun 30,-45 --turbidity 5 --ground-albedo 0.1 out.png")
+            .expect("valid arguments rejected");
+        assert_eq!(cfg.sun, Some((30.0, -45.0)));
+        assert_eq!((cfg.turbidity, cfg.ground_albedo, cfg.sun_diameter), (5.0, 0.1, 0.53));
+        let sky = cfg.sky().expect("no sky");
+        assert!(sky.sun.x < 0.0 && sky.sun.z < 0.0);
+        assert!(parse_render("argparse out.png").unwrap().sky().is_none());
+
+        assert_eq!(invalid_value("argparse --sun 30 out.png"), "--sun");
+        assert_eq!(invalid_value("argparse --sun 100,0 out.png"), "--sun");
+        assert_eq!(invalid_value("argparse --sun 30,0 --turbidity 1 out.png"), "--turbidity");
+        assert_eq!(invalid_value("argparse --sun 30,0 --ground-albedo 2 out.png"), "--ground-albedo");
+        assert_eq!(parse("argparse --sun 30,0 --workers h:1 out.png").unwrap_err(),
+                   ArgError::Conflict("--workers", "--sun"));
+    }
+
+    #[test]
+    fn test_tone_map_args() {
+        match parse_render("argparse --exposure -1.5 --tonemap aces output.ppm") {
+            Ok(cfg) => {
+                assert_eq!(cfg.exposure, Some(-1.5));
+                assert_eq!(cfg.tone_map, Some(ToneMap::Aces));
+            },
+            Err(e) => panic!("error {} from valid arguments", e),
+        }
+        assert_eq!(invalid_value("argparse --tonemap drago output.ppm"), "--tonemap");
+        assert_eq!(invalid_value("argparse --exposure inf output.ppm"), "--exposure");
+    }
+
+    #[test]
+    fn test_colorspace_arg() {
+        let cfg = parse_render("argparse --colorspace display-p3 output.png").expect("valid arguments rejected");
+        assert_eq!(cfg.encoding.to_string(), "display-p3");
+        assert_eq!(Config::default().encoding, Encoding::SRGB);
+    }
+
+    #[test]
+    fn test_denoise_args() {
+        let cfg = parse_render("argparse --denoise --raw noisy.png output.png").expect("valid arguments rejected");
+        assert!(cfg.denoise);
+        assert_eq!(cfg.raw_output, Some(String::from("noisy.png")));
+        assert_eq!(cfg.output, String::from("output.png"));
+
+        assert_eq!(parse("argparse --raw").unwrap_err(), ArgError::MissingValue(String::from("--raw")));
+    }
+
+    #[test]
+    fn test_aov_arg() {
+        let cfg = parse_render("argparse --aov depth,albedo output.exr").expect("valid arguments rejected");
+        assert_eq!(cfg.aovs, vec![Aov::Depth, Aov::Albedo]);
+        assert_eq!(invalid_value("argparse --aov depth,velocity output.exr"), "--aov");
+    }
+
+    #[test]
+    fn test_checkpoint_args() {
+        let cfg = parse_render("argparse --checkpoint render.ckpt --checkpoint-interval 60 --resume output.png")
+            .expect("valid arguments rejected");
+        assert_eq!(cfg.checkpoint, Some(String::from("render.ckpt")));
+        assert_eq!(cfg.checkpoint_interval, 60);
+        assert!(cfg.resume);
+
+        assert_eq!(parse("argparse --resume output.png").unwrap_err(), ArgError::Requires("--resume", "--checkpoint"));
+    }
+
+    #[test]
+    fn test_distributed_args() {
+        let cfg = parse_render("argparse --workers 10.0.0.2:7878,localhost:7879 output.png")
+            .expect("valid arguments rejected");
+        assert_eq!(cfg.workers, vec![String::from("10.0.0.2:7878"), String::from("localhost:7879")]);
+
+        assert_eq!(parse("argparse --workers a:1 --denoise output.png").unwrap_err(),
+                   ArgError::Conflict("--workers", "--denoise"));
+
+        match parse("argparse worker") {
+            Ok(Command::Worker { listen }) => assert_eq!(listen, DEFAULT_WORKER_LISTEN),
+            result => panic!("worker mode gave {:?}", result),
+        }
+        match parse("argparse worker --listen 127.0.0.1:9000") {
+            Ok(Command::Worker { listen }) => assert_eq!(listen, "127.0.0.1:9000"),
+            result => panic!("worker mode gave {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_serve_args() {
+        match parse("argparse serve --queue 4 --jobs 2") {
+            Ok(Command::Serve { listen, limits }) => {
+                assert_eq!(listen, DEFAULT_SERVE_LISTEN);
+                assert_eq!(limits, Limits { queue: 4, running: 2 });
+            },
+            result => panic!("serve mode gave {:?}", result),
+        }
+        assert_eq!(invalid_value("argparse serve --jobs 0"), "--jobs");
+        assert_eq!(parse("argparse worker --queue 4").unwrap_err(), ArgError::UnknownOption(String::from("--queue")));
+    }
+
+    #[test]
+    fn test_other_commands() {
+        assert!(matches!(parse("argparse info scene.glb"), Ok(Command::Info { scene }) if scene == "scene.glb"));
+        assert_eq!(parse("argparse info").unwrap_err(), ArgError::MissingArgument("SCENE"));
+
+        match parse("argparse bench -i scene.glb --runs 5 -w 320") {
+            Ok(Command::Bench { config, runs }) => {
+                assert_eq!(runs, 5);
+                assert_eq!((config.width, config.height, config.samples), (320, 180, 16));
+                assert_eq!(config.scene, Some(String::from("scene.glb")));
+            },
+            result => panic!("bench gave {:?}", result),
+        }
+
+        match parse("argparse diff a.png b.png -o d.png --threshold 0.01") {
+            Ok(Command::Diff(diff)) => assert_eq!(diff, DiffOptions {
+                a: String::from("a.png"),
+                b: String::from("b.png"),
+                output: Some(String::from("d.png")),
+                threshold: 0.01,
+            }),
+            result => panic!("diff gave {:?}", result),
+        }
+        assert_eq!(invalid_value("argparse diff a.png b.png --threshold 2"), "--threshold");
+    }
+
+    #[test]
+    fn test_layers() {
+        let dir = std::env::temp_dir().join(format!("raytracer-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("config.toml");
+        let project = dir.join("raytracer.toml");
+        fs::write(&user, "samples = 8\ndenoise = true\n\n[preset.draft]\nsamples = 2\n").unwrap();
+        fs::write(&project, "width = 800\nheight = 800\nmax-depth = 4\naov = [\"depth\"]\n").unwrap();
+        let mut env = Environment {
+            vars: vec![(String::from("RAYTRACER_MAX_DEPTH"), String::from("6"))],
+            files: vec![user.clone(), project.clone(), dir.join("missing.toml")],
+        };
+        let parse_in = |line: &str, env: &Environment| {
+            let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+            Command::parse_in(&args, env)
+        };
+
+        match parse_in("argparse -w 400 out.png", &env) {
+            Ok(Command::Render(cfg)) => {
+                // The height keeps the square aspect ratio from the settings file.
+                assert_eq!((cfg.width, cfg.height, cfg.samples, cfg.max_depth), (400, 400, 8, 6));
+                assert!(cfg.denoise);
+                assert_eq!(cfg.aovs, vec![Aov::Depth]);
+                assert!(cfg.sources.contains(&("max-depth", Source::Env(String::from("RAYTRACER_MAX_DEPTH")))));
+                assert!(cfg.sources.contains(&("height", Source::CommandLine)));
+            },
+            result => panic!("layered settings gave {:?}", result.map(|_| ())),
+        }
+
+        match parse_in("argparse --preset draft --denoise=false --print-config", &env) {
+            Ok(Command::PrintConfig(text)) => {
+                assert!(text.contains("samples = 2"), "{}", text);
+                assert!(text.contains("# preset draft"), "{}", text);
+                assert!(text.contains("denoise = false"), "{}", text);
+                let aov = format!("{}:4", project.display());
+                assert!(text.lines().any(|l| l.starts_with("aov = [\"depth\"]") && l.ends_with(&aov)), "{}", text);
+                assert!(text.contains("# exposure is unset"), "{}", text);
+            },
+            result => panic!("--print-config gave {:?}", result.map(|_| ())),
+        }
+        match parse_in("argparse --preset preview out.png", &env) {
+            Ok(Command::Render(cfg)) => assert_eq!((cfg.width, cfg.height, cfg.samples), (640, 360, 16)),
+            result => panic!("preview preset gave {:?}", result.map(|_| ())),
+        }
+        assert_eq!(invalid_value_in(parse_in("argparse --preset huge out.png", &env)), "--preset");
+
+        env.vars.push((String::from("RAYTRACER_SAMPLES"), String::from("0")));
+        match parse_in("argparse out.png", &env) {
+            Err(ArgError::Layer(Source::Env(name), _)) => assert_eq!(name, "RAYTRACER_SAMPLES"),
+            result => panic!("invalid variable gave {:?}", result.map(|_| ())),
+        }
+        env.vars.clear();
+
+        // Sizes kept to an extreme aspect ratio stay in range.
+        env.vars.push((String::from("RAYTRACER_WIDTH"), String::from("2")));
+        env.vars.push((String::from("RAYTRACER_HEIGHT"), String::from("16384")));
+        match parse_in("argparse --width 16384 out.png", &env) {
+            Ok(Command::Render(cfg)) => assert_eq!((cfg.width, cfg.height), (16384, 16384)),
+            result => panic!("derived height gave {:?}", result.map(|_| ())),
+        }
+        env.vars.clear();
+
+        fs::write(&project, "width = 800\ncolour = \"red\"\n").unwrap();
+        match parse_in("argparse out.png", &env) {
+            Err(ArgError::Layer(Source::File { line, .. }, e)) => {
+                assert_eq!(line, 2);
+                assert_eq!(*e, ArgError::UnknownOption(String::from("colour")));
+            },
+            result => panic!("unknown key gave {:?}", result.map(|_| ())),
+        }
+        assert!(matches!(parse_in("argparse --config missing.toml out.png", &env), Err(ArgError::SettingsFile { .. })));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_crop_args() {
+        let cfg = parse_render("argparse -w 100 -H 50 --crop 10,0,30,20 out.png").expect("valid arguments rejected");
+        assert_eq!(cfg.crop, Some(Crop::Pixels([10, 0, 30, 20])));
+        // Rows count up from the bottom of the image.
+        assert_eq!(cfg.region(), Some(Region { x0: 10, y0: 30, x1: 30, y1: 50 }));
+        assert!(!cfg.full_frame);
+
+        let cfg = parse_render("argparse -w 100 -H 50 --crop 0.5,0.5,1.0,1 --full-frame out.png")
+            .expect("valid arguments rejected");
+        assert_eq!(cfg.region(), Some(Region { x0: 50, y0: 0, x1: 100, y1: 25 }));
+        assert!(cfg.full_frame);
+
+        assert_eq!(invalid_value("argparse -w 100 -H 50 --crop 10,0,130,20 out.png"), "--crop");
+        assert_eq!(invalid_value("argparse --crop 10,10,10,20 out.png"), "--crop");
+        assert_eq!(invalid_value("argparse --crop 0.5,0.5,1.5,1 out.png"), "--crop");
+        assert_eq!(invalid_value("argparse --crop 1,2,3 out.png"), "--crop");
+        assert_eq!(parse("argparse --full-frame out.png").unwrap_err(), ArgError::Requires("--full-frame", "--crop"));
+    }
+
+    #[test]
+    fn test_stereo_args() {
+        let cfg = parse_render("argparse --stereo top-bottom --ipd 0.07 --convergence 3 out.png")
+            .expect("valid arguments rejected");
+        assert_eq!(cfg.stereo, Some(StereoLayout::TopBottom));
+        assert_eq!((cfg.ipd, cfg.convergence), (0.07, Some(3.0)));
+        assert_eq!(parse_render("argparse --stereo sbs out.png").unwrap().ipd, 0.064);
+
+        assert_eq!(invalid_value("argparse --stereo anaglyph out.png"), "--stereo");
+        assert_eq!(invalid_value("argparse --stereo sbs --ipd -1 out.png"), "--ipd");
+        assert_eq!(parse("argparse --convergence 2 out.png").unwrap_err(), ArgError::Requires("--convergence", "--stereo"));
+        assert!(parse_render("argparse --stereo sbs --projection equirectangular -w 64 -H 32 out.png").is_ok());
+        assert!(parse_render("argparse --stereo sbs --projection fisheye:180 out.png").is_ok());
+        assert_eq!(invalid_value("argparse --stereo sbs --projection cubemap out.png"), "--projection");
+        assert_eq!(invalid_value("argparse --stereo sbs --projection fisheye:200 out.png"), "--projection");
+        assert_eq!(parse("argparse --stereo sbs --crop 0,0,8,8 out.png").unwrap_err(),
+                   ArgError::Conflict("--stereo", "--crop"));
+    }
+
+    #[test]
+    fn test_camera_exposure_args() {
+        let cfg = parse_render("argparse --iso 400 --shutter 1/250 --white-balance 3200 out.png")
+            .expect("valid arguments rejected");
+        let camera = cfg.camera_exposure().expect("no camera exposure");
+        assert_eq!((camera.iso, camera.shutter, camera.f_number), (400.0, 0.004, 16.0));
+        assert_eq!(cfg.white_balance, Some(3200.0));
+        assert_eq!(parse_render("argparse --f-number 2.8 out.png").unwrap().camera_exposure().unwrap().shutter, 0.01);
+        assert_eq!(parse_render("argparse out.png").unwrap().camera_exposure(), None);
+
+        assert_eq!(invalid_value("argparse --shutter 1/0 out.png"), "--shutter");
+        assert_eq!(invalid_value("argparse --shutter -0.5 out.png"), "--shutter");
+        assert_eq!(invalid_value("argparse --iso 0 out.png"), "--iso");
+        assert_eq!(invalid_value("argparse --white-balance 1000 out.png"), "--white-balance");
+    }
+
+    #[test]
+    fn test_animation_args() {
+        let cfg = parse_render("argparse --animation spin.toml --frames 1-240 out.####.png")
+            .expect("valid arguments rejected");
+        assert_eq!(cfg.animation, Some(String::from("spin.toml")));
+        assert_eq!(cfg.frames, Some((1, 240)));
+        assert!(cfg.is_sequence() && !cfg.overwrite);
+        assert_eq!(parse_render("argparse --frames 12 --overwrite out.png").unwrap().frames, Some((12, 12)));
+
+        assert_eq!(invalid_value("argparse --frames 10-1 out.png"), "--frames");
+        assert_eq!(invalid_value("argparse --frames a-b out.png"), "--frames");
+        assert_eq!(parse("argparse --frames 1-2 --checkpoint c.ckpt out.png").unwrap_err(),
+                   ArgError::Conflict("--checkpoint", "--frames"));
+        assert_eq!(parse("argparse --animation a.toml --workers h:1 out.png").unwrap_err(),
+                   ArgError::Conflict("--workers", "--animation"));
+    }
+
     #[test]
     fn test_all() {
-        let args: Vec<String> = "argparse -w 640 -h 480 -s 64 -d 16 output.ppm"
-            .split_whitespace()
-            .map(|s| String::from(s))
-            .collect();
-        let cfg = Config::parse_args(&args);
+        let cfg = parse_render("argparse -w 640 -H 480 -s 64 -d 16 output.ppm");
         match cfg {
             Ok(c) => {
                 assert_eq!(c.width, 640);
@@ -0,0 +1,206 @@
+use crate::color::Color;
+use crate::vec::Vector;
+
+// First-hit surface properties of a pixel, averaged over its samples, that guide the
+// denoiser. Pixels whose rays escape have white albedo, a zero normal and infinite depth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Features {
+    pub albedo: Color,
+    pub normal: Vector,
+    pub depth: f64,
+}
+
+impl Features {
+    pub const MISS: Features = Features { albedo: Color::WHITE, normal: Vector::ORIGIN, depth: f64::INFINITY };
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features::MISS
+    }
+}
+
+// Edge-avoiding à-trous wavelet filter (Dammertz et al., "Edge-Avoiding À-Trous Wavelet
+// Transform for fast Global Illumination Filtering"). Each pass blurs with a 5 x 5
+// B-spline kernel whose taps spread twice as far as the last, and neighbours only count
+// where their colour, albedo, normal and depth resemble the centre pixel's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Denoiser {
+    pub iterations: u32,
+    pub sigma_color: f64,
+    pub sigma_albedo: f64,
+    pub normal_power: f64,
+    pub sigma_depth: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            iterations: 5,
+            sigma_color: 0.5,
+            sigma_albedo: 0.1,
+            normal_power: 64.0,
+            sigma_depth: 0.05,
+        }
+    }
+}
+
+impl Denoiser {
+    // Filters linear radiance laid out in rows of `width` pixels. Texture detail is
+    // kept by filtering illumination, the colour divided by albedo, and multiplying
+    // the albedo back in afterwards.
+    pub fn denoise(&self, color: &[Color], features: &[Features], width: usize, height: usize) -> Vec<Color> {
+        assert_eq!(color.len(), width * height, "colour buffer does not match image size");
+        assert_eq!(features.len(), width * height, "feature buffer does not match image size");
+
+        let mut illumination: Vec<Color> = color.iter().zip(features)
+            .map(|(c, f)| demodulate(*c, f.albedo))
+            .collect();
+
+        const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+        for i in 0..self.iterations {
+            let step = 1_isize << i;
+            // Later passes average away noise, so colour edges can be trusted more.
+            let sigma_color = self.sigma_color / (1 << i) as f64;
+            let mut filtered = vec![Color::BLACK; illumination.len()];
+            for y in 0..height {
+                for x in 0..width {
+                    let p = y * width + x;
+                    let (cp, fp) = (illumination[p], &features[p]);
+                    let mut sum = Color::BLACK;
+                    let mut total = 0.0;
+                    for (j, ky) in KERNEL.iter().enumerate() {
+                        let qy = y as isize + (j as isize - 2) * step;
+                        if qy < 0 || qy >= height as isize {
+                            continue;
+                        }
+                        for (k, kx) in KERNEL.iter().enumerate() {
+                            let qx = x as isize + (k as isize - 2) * step;
+                            if qx < 0 || qx >= width as isize {
+                                continue;
+                            }
+                            let q = qy as usize * width + qx as usize;
+                            let cq = illumination[q];
+                            let w = kx * ky
+                                * (-distance_squared(compress(cp), compress(cq)) / (sigma_color * sigma_color)).exp()
+                                * self.feature_weight(fp, &features[q], step as f64);
+                            sum += w * cq;
+                            total += w;
+                        }
+                    }
+                    // The centre tap always has a positive weight.
+                    filtered[p] = sum / total;
+                }
+            }
+            illumination = filtered;
+        }
+
+        illumination.iter().zip(features)
+            .map(|(c, f)| remodulate(*c, f.albedo))
+            .collect()
+    }
+
+    fn feature_weight(&self, p: &Features, q: &Features, step: f64) -> f64 {
+        let albedo = (-distance_squared(p.albedo, q.albedo) / (self.sigma_albedo * self.sigma_albedo)).exp();
+
+        let normal = if p.normal.near_zero() && q.normal.near_zero() {
+            1.0
+        } else if p.normal.near_zero() || q.normal.near_zero() {
+            0.0
+        } else {
+            let cos = p.normal.normalize() * q.normal.normalize();
+            cos.max(0.0).powf(self.normal_power)
+        };
+
+        // Relative depth difference, allowing for the distance between the taps.
+        let depth = if p.depth.is_infinite() && q.depth.is_infinite() {
+            1.0
+        } else {
+            let dz = (p.depth - q.depth).abs() / p.depth.min(q.depth).max(1e-6);
+            (-dz / (self.sigma_depth * step)).exp()
+        };
+
+        albedo * normal * depth
+    }
+}
+
+const ALBEDO_EPSILON: f64 = 1e-3;
+
+fn demodulate(c: Color, albedo: Color) -> Color {
+    Color::new(c.r() / (albedo.r() + ALBEDO_EPSILON),
+               c.g() / (albedo.g() + ALBEDO_EPSILON),
+               c.b() / (albedo.b() + ALBEDO_EPSILON))
+}
+
+fn remodulate(c: Color, albedo: Color) -> Color {
+    Color::new(c.r() * (albedo.r() + ALBEDO_EPSILON),
+               c.g() * (albedo.g() + ALBEDO_EPSILON),
+               c.b() * (albedo.b() + ALBEDO_EPSILON))
+}
+
+// Compares HDR colours on a compressed scale, so that bright fireflies do not stop
+// the filter from averaging them away.
+fn compress(c: Color) -> Color {
+    Color::new(c.r() / (1.0 + c.r()), c.g() / (1.0 + c.g()), c.b() / (1.0 + c.b()))
+}
+
+fn distance_squared(a: Color, b: Color) -> f64 {
+    (a.r() - b.r()).powi(2) + (a.g() - b.g()).powi(2) + (a.b() - b.b()).powi(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    fn surface(normal: Vector) -> Features {
+        Features { albedo: Color::new(0.5, 0.5, 0.5), normal, depth: 2.0 }
+    }
+
+    fn mean_error(image: &[Color], expected: &[Color]) -> f64 {
+        image.iter().zip(expected).map(|(a, b)| (a.g() - b.g()).abs()).sum::<f64>() / image.len() as f64
+    }
+
+    #[test]
+    fn test_denoise_reduces_noise() {
+        let (width, height) = (32, 32);
+        let mut rng = StdRng::seed_from_u64(7);
+        let clean = vec![Color::new(0.4, 0.4, 0.4); width * height];
+        let noisy: Vec<Color> = clean.iter().map(|c| *c * (0.5 + rng.gen::<f64>())).collect();
+        let features = vec![surface(Vector::new(0.0, 0.0, 1.0)); width * height];
+
+        let denoised = Denoiser::default().denoise(&noisy, &features, width, height);
+        assert!(mean_error(&denoised, &clean) < 0.25 * mean_error(&noisy, &clean));
+    }
+
+    #[test]
+    fn test_denoise_keeps_edges() {
+        // Two surfaces meeting at a crease, lit differently, must not bleed together.
+        let (width, height) = (16, 8);
+        let mut color = Vec::new();
+        let mut features = Vec::new();
+        for _ in 0..height {
+            for x in 0..width {
+                if x < width / 2 {
+                    color.push(Color::new(0.1, 0.1, 0.1));
+                    features.push(surface(Vector::new(0.0, 0.0, 1.0)));
+                } else {
+                    color.push(Color::new(0.9, 0.9, 0.9));
+                    features.push(surface(Vector::new(1.0, 0.0, 0.0)));
+                }
+            }
+        }
+
+        let denoised = Denoiser::default().denoise(&color, &features, width, height);
+        assert!(mean_error(&denoised, &color) < 1e-6);
+    }
+
+    #[test]
+    fn test_denoise_background() {
+        let features = vec![Features::MISS; 9];
+        let color = vec![Color::new(0.5, 0.7, 1.0); 9];
+        let denoised = Denoiser::default().denoise(&color, &features, 3, 3);
+        assert!(mean_error(&denoised, &color) < 1e-9);
+    }
+}
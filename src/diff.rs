@@ -0,0 +1,67 @@
+use crate::color::Color;
+use crate::image::ImagePpm;
+
+// How two images of the same size differ, comparing stored values from 0 to 1.
+pub struct Difference {
+    pub rmse: f64,
+    // Peak signal-to-noise ratio in decibels, infinite for identical images.
+    pub psnr: f64,
+    pub max: f64,
+    // Pixels with a channel differing by more than the threshold.
+    pub differing: usize,
+    // Absolute differences per channel.
+    pub image: ImagePpm,
+}
+
+pub fn compare(a: &ImagePpm, b: &ImagePpm, threshold: f64) -> Result<Difference, String> {
+    if (a.width, a.height) != (b.width, b.height) {
+        return Err(format!("images are {} x {} and {} x {}", a.width, a.height, b.width, b.height));
+    }
+
+    let mut image = ImagePpm::new(a.width, a.height);
+    let mut squares = 0.0;
+    let mut max: f64 = 0.0;
+    let mut differing = 0;
+    for y in 0..a.height {
+        for x in 0..a.width {
+            let d = a.get_pixel(x, y) - b.get_pixel(x, y);
+            let d = Color::new(d.r().abs(), d.g().abs(), d.b().abs());
+            let largest = d.r().max(d.g()).max(d.b());
+            squares += d.r() * d.r() + d.g() * d.g() + d.b() * d.b();
+            max = max.max(largest);
+            if largest > threshold {
+                differing += 1;
+            }
+            image.set_pixel(x, y, d);
+        }
+    }
+
+    let rmse = (squares / (3 * a.width * a.height) as f64).sqrt();
+    let psnr = -20.0 * rmse.log10();
+    Ok(Difference { rmse, psnr, max, differing, image })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_cmp::assert_approx_eq;
+
+    #[test]
+    fn test_compare() {
+        let mut a = ImagePpm::new(2, 2);
+        let b = ImagePpm::new(2, 2);
+        let same = compare(&a, &b, 0.0).unwrap();
+        assert_eq!((same.rmse, same.max, same.differing), (0.0, 0.0, 0));
+        assert_eq!(same.psnr, f64::INFINITY);
+
+        a.set_pixel(1, 0, Color::new(0.5, 0.0, 0.0));
+        a.set_pixel(0, 1, Color::new(0.0, 0.01, 0.0));
+        let diff = compare(&a, &b, 0.1).unwrap();
+        assert_eq!(diff.differing, 1);
+        assert_eq!(diff.max, 0.5);
+        assert_approx_eq!(f64, diff.rmse, ((0.25 + 0.0001) / 12.0f64).sqrt());
+        assert_eq!(diff.image.get_pixel(1, 0), Color::new(0.5, 0.0, 0.0));
+
+        assert!(compare(&a, &ImagePpm::new(2, 3), 0.0).is_err());
+    }
+}
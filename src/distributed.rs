@@ -0,0 +1,500 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::checkpoint::{invalid, Decoder, Encoder};
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::render::{self, RenderError, Settings};
+use crate::scene::Scene;
+
+const MAGIC: &[u8; 4] = b"RTDR";
+const VERSION: u32 = 1;
+
+// The largest message either side accepts, which bounds what a peer can make the other
+// allocate. Scenes travel in a single message, so this is also the largest scene.
+const MAX_MESSAGE: usize = 256 << 20;
+
+// The largest image side a worker renders, as on the command line, and the most pixels
+// a tile may cover, so that a coordinator cannot make a worker allocate gigabytes.
+const MAX_SIZE: u32 = 16384;
+const MAX_TILE_PIXELS: u64 = 1 << 23;
+
+// Workers send heartbeats while they are busy, and a worker that stays silent for
+// much longer than that is taken to be gone.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+const WORKER_TIMEOUT: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Everything a worker needs to render tiles of a frame. An empty scene name stands
+// for the built-in scene.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    pub scene_name: String,
+    pub scene: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub max_depth: u16,
+    pub filter: Filter,
+    pub spectral: bool,
+    pub seed: u64,
+}
+
+impl Job {
+    fn encode(&self, out: &mut Encoder) {
+        out.bytes(MAGIC);
+        out.u32(VERSION);
+        string(out, &self.scene_name);
+        out.u64(self.scene.len() as u64);
+        out.bytes(&self.scene);
+        out.u32(self.width);
+        out.u32(self.height);
+        out.u32(self.samples);
+        out.u32(self.max_depth as u32);
+        string(out, &self.filter.to_string());
+        out.u8(self.spectral as u8);
+        out.u64(self.seed);
+    }
+
+    fn decode(input: &mut Decoder) -> io::Result<Job> {
+        if input.bytes(4)? != MAGIC || input.u32()? != VERSION {
+            return Err(invalid("coordinator speaks a different protocol"));
+        }
+        let scene_name = read_string(input)?;
+        let len = input.u64()? as usize;
+        let scene = input.bytes(len)?.to_vec();
+        let width = input.u32()?;
+        let height = input.u32()?;
+        let samples = input.u32()?;
+        let max_depth = input.u32()? as u16;
+        let filter = read_string(input)?.parse::<Filter>().map_err(|e| invalid(&e))?;
+        let spectral = input.u8()? != 0;
+        let seed = input.u64()?;
+        if !(2..=MAX_SIZE).contains(&width) || !(2..=MAX_SIZE).contains(&height) {
+            return Err(invalid(&format!("image sides must be from 2 to {}", MAX_SIZE)));
+        }
+        Ok(Job { scene_name, scene, width, height, samples, max_depth, filter, spectral, seed })
+    }
+
+    pub fn settings(&self) -> Settings {
+        Settings {
+            width: self.width,
+            height: self.height,
+            samples: self.samples,
+            max_depth: self.max_depth,
+            filter: self.filter,
+            spectral: self.spectral,
+            seed: self.seed,
+            ..Settings::default()
+        }
+    }
+
+    pub fn scene(&self) -> io::Result<Scene> {
+        if self.scene_name.is_empty() {
+            return Ok(Scene::default());
+        }
+        Scene::parse(&self.scene_name, &self.scene).map_err(|e| invalid(&e.to_string()))
+    }
+}
+
+// A band of image rows, `y0..=y1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub id: u32,
+    pub y0: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    // Splits the image into bands of `rows` rows.
+    pub fn split(height: u32, rows: u32) -> Vec<Tile> {
+        let rows = rows.max(1);
+        (0..height.div_ceil(rows))
+            .map(|i| Tile { id: i, y0: i * rows, y1: ((i + 1) * rows).min(height) - 1 })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Job = 1,
+    Tile = 2,
+    Done = 3,
+    Result = 4,
+    Heartbeat = 5,
+    Error = 6,
+}
+
+impl Kind {
+    fn from_u8(v: u8) -> io::Result<Kind> {
+        [Kind::Job, Kind::Tile, Kind::Done, Kind::Result, Kind::Heartbeat, Kind::Error]
+            .into_iter()
+            .find(|k| *k as u8 == v)
+            .ok_or_else(|| invalid("unknown message"))
+    }
+}
+
+// Messages are a kind byte and a length-prefixed payload.
+fn send(stream: &mut TcpStream, kind: Kind, payload: &[u8]) -> io::Result<()> {
+    if payload.len() > MAX_MESSAGE {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "message is too large to send"));
+    }
+    let mut message = Vec::with_capacity(payload.len() + 9);
+    message.push(kind as u8);
+    message.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    message.extend_from_slice(payload);
+    stream.write_all(&message)?;
+    stream.flush()
+}
+
+fn receive(stream: &mut TcpStream) -> io::Result<(Kind, Vec<u8>)> {
+    let mut header = [0; 9];
+    stream.read_exact(&mut header)?;
+    let kind = Kind::from_u8(header[0])?;
+    let len = u64::from_le_bytes(header[1..].try_into().unwrap());
+    if len > MAX_MESSAGE as u64 {
+        return Err(invalid(&format!("message of {} bytes is too large", len)));
+    }
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((kind, payload))
+}
+
+fn string(out: &mut Encoder, s: &str) {
+    out.u32(s.len() as u32);
+    out.bytes(s.as_bytes());
+}
+
+fn read_string(input: &mut Decoder) -> io::Result<String> {
+    let len = input.u32()? as usize;
+    String::from_utf8(input.bytes(len)?.to_vec()).map_err(|_| invalid("string is not UTF-8"))
+}
+
+fn encode_tile(tile: &Tile) -> Vec<u8> {
+    let mut out = Encoder::default();
+    out.u32(tile.id);
+    out.u32(tile.y0);
+    out.u32(tile.y1);
+    out.data
+}
+
+fn decode_tile(input: &mut Decoder) -> io::Result<Tile> {
+    Ok(Tile { id: input.u32()?, y0: input.u32()?, y1: input.u32()? })
+}
+
+// Serves coordinators one at a time, forever.
+pub fn serve(listener: TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream.peer_addr().map_or(String::from("unknown"), |a| a.to_string());
+        match session(stream) {
+            Ok(()) => eprintln!("Finished job from {}", peer),
+            Err(e) => eprintln!("Lost coordinator {}: {}", peer, e),
+        }
+    }
+    Ok(())
+}
+
+fn session(mut stream: TcpStream) -> io::Result<()> {
+    // Heartbeats go out from another thread for as long as the session lasts, so that
+    // slow scene loading and long tiles do not look like a dead worker.
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let (stop, stopped) = channel::<()>();
+    let heartbeat = {
+        let writer = Arc::clone(&writer);
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(HEARTBEAT_INTERVAL) {
+                let mut w = writer.lock().unwrap();
+                if send(&mut w, Kind::Heartbeat, &[]).is_err() {
+                    break;
+                }
+            }
+        })
+    };
+
+    let result = (|| {
+        let (kind, payload) = receive(&mut stream)?;
+        if kind != Kind::Job {
+            return Err(invalid("expected a job"));
+        }
+        let job = Job::decode(&mut Decoder::new(&payload))?;
+        let scene = match job.scene() {
+            Ok(scene) => Arc::new(scene),
+            Err(e) => {
+                send(&mut writer.lock().unwrap(), Kind::Error, e.to_string().as_bytes())?;
+                return Err(e);
+            },
+        };
+        eprintln!("Rendering {} x {} with {} samples", job.width, job.height, job.samples);
+
+        loop {
+            let (kind, payload) = receive(&mut stream)?;
+            match kind {
+                Kind::Tile => {
+                    let tile = decode_tile(&mut Decoder::new(&payload))?;
+                    if tile.y0 > tile.y1 || job.width as u64 * (tile.y1 - tile.y0 + 1) as u64 > MAX_TILE_PIXELS {
+                        let why = format!("tile of rows {} to {} is empty or too large", tile.y0, tile.y1);
+                        send(&mut writer.lock().unwrap(), Kind::Error, why.as_bytes())?;
+                        return Err(invalid(&why));
+                    }
+                    let settings = Settings { rows: Some((tile.y0, tile.y1)), ..job.settings() };
+                    // Nothing cancels a worker's renders.
+                    let film = match render::render(&scene, &settings, &mut ()) {
+                        Ok(frame) | Err(RenderError::Cancelled(frame)) => frame.film,
+                        Err(e) => {
+                            send(&mut writer.lock().unwrap(), Kind::Error, e.to_string().as_bytes())?;
+                            return Err(invalid(&e.to_string()));
+                        },
+                    };
+                    let mut out = Encoder::default();
+                    out.u32(tile.id);
+                    film.encode(&mut out);
+                    send(&mut writer.lock().unwrap(), Kind::Result, &out.data)?;
+                },
+                Kind::Done => return Ok(()),
+                _ => return Err(invalid("unexpected message")),
+            }
+        }
+    })();
+
+    drop(stop);
+    heartbeat.join().expect("heartbeat thread panicked");
+    result
+}
+
+struct Queue {
+    pending: VecDeque<Tile>,
+    // Tiles not yet rendered, including those being worked on.
+    outstanding: usize,
+}
+
+enum Event {
+    Rendered(Tile, Film),
+    Lost(String, io::Error),
+}
+
+// Renders `tiles` on the workers at `addresses`, merging the results into `film` and
+// calling `on_tile` as each arrives. Tiles held by a worker that disconnects or goes
+// quiet go back in the queue for the others; the render only fails if every worker
+// is lost.
+pub fn coordinate<F: FnMut(&Tile)>(addresses: &[String], job: &Job, tiles: Vec<Tile>, film: &mut Film,
+                                   mut on_tile: F) -> io::Result<()> {
+    let total = tiles.len();
+    let queue = Arc::new((Mutex::new(Queue { pending: tiles.into(), outstanding: total }), Condvar::new()));
+    let mut out = Encoder::default();
+    job.encode(&mut out);
+    let job = Arc::new(out.data);
+    let (tx, rx) = channel();
+
+    for address in addresses {
+        let (address, job, queue, tx) = (address.clone(), Arc::clone(&job), Arc::clone(&queue), tx.clone());
+        thread::spawn(move || {
+            let mut holding = None;
+            if let Err(e) = drive_worker(&address, &job, &queue, &tx, &mut holding) {
+                let (lock, ready) = &*queue;
+                if let Some(tile) = holding {
+                    lock.lock().unwrap().pending.push_front(tile);
+                    ready.notify_all();
+                }
+                let _ = tx.send(Event::Lost(address, e));
+            }
+        });
+    }
+    drop(tx);
+
+    let mut done = 0;
+    for event in rx {
+        match event {
+            Event::Rendered(tile, tile_film) => {
+                // Workers are not trusted to send back the rows they were given.
+                if (tile_film.width, tile_film.height) != (film.width, film.height)
+                    || tile_film.rows() != film.tile_rows(tile.y0, tile.y1) {
+                    return Err(invalid(&format!("worker sent the wrong rows for tile {}", tile.id)));
+                }
+                film.merge(&tile_film);
+                done += 1;
+                on_tile(&tile);
+            },
+            Event::Lost(address, e) => eprintln!("\nLost worker {}: {}", address, e),
+        }
+    }
+    if done < total {
+        return Err(io::Error::other(format!("every worker failed with {} of {} tiles left", total - done, total)));
+    }
+    Ok(())
+}
+
+fn drive_worker(address: &str, job: &[u8], queue: &(Mutex<Queue>, Condvar),
+                events: &std::sync::mpsc::Sender<Event>, holding: &mut Option<Tile>) -> io::Result<()> {
+    let addr = address.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| invalid("address does not resolve"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(WORKER_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    send(&mut stream, Kind::Job, job)?;
+
+    let (lock, ready) = queue;
+    loop {
+        let tile = {
+            let mut q = lock.lock().unwrap();
+            while q.pending.is_empty() && q.outstanding > 0 {
+                q = ready.wait(q).unwrap();
+            }
+            match q.pending.pop_front() {
+                Some(tile) => tile,
+                None => {
+                    drop(q);
+                    return send(&mut stream, Kind::Done, &[]);
+                },
+            }
+        };
+        *holding = Some(tile);
+        send(&mut stream, Kind::Tile, &encode_tile(&tile))?;
+
+        let film = loop {
+            let (kind, payload) = receive(&mut stream)?;
+            match kind {
+                Kind::Heartbeat => continue,
+                Kind::Result => {
+                    let mut input = Decoder::new(&payload);
+                    if input.u32()? != tile.id {
+                        return Err(invalid("result for the wrong tile"));
+                    }
+                    break Film::decode(&mut input, Filter::default())?;
+                },
+                Kind::Error => return Err(io::Error::other(String::from_utf8_lossy(&payload).into_owned())),
+                _ => return Err(invalid("unexpected message")),
+            }
+        };
+
+        *holding = None;
+        let mut q = lock.lock().unwrap();
+        q.outstanding -= 1;
+        ready.notify_all();
+        drop(q);
+        if events.send(Event::Rendered(tile, film)).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job() -> Job {
+        Job {
+            scene_name: String::new(),
+            scene: vec![],
+            width: 4,
+            height: 10,
+            samples: 1,
+            max_depth: 4,
+            filter: Filter::default(),
+            spectral: false,
+            seed: 1,
+        }
+    }
+
+    #[test]
+    fn test_tile_split() {
+        let tiles = Tile::split(10, 4);
+        assert_eq!(tiles.iter().map(|t| (t.y0, t.y1)).collect::<Vec<_>>(), vec![(0, 3), (4, 7), (8, 9)]);
+    }
+
+    #[test]
+    fn test_job_round_trip() {
+        let job = Job { scene_name: String::from("a.stl"), scene: vec![1, 2, 3], ..job() };
+        let mut out = Encoder::default();
+        job.encode(&mut out);
+        assert_eq!(Job::decode(&mut Decoder::new(&out.data)).unwrap(), job);
+
+        for (width, height) in [(0, 10), (100_000, 100_000), (16385, 2)] {
+            let mut out = Encoder::default();
+            Job { width, height, ..job.clone() }.encode(&mut out);
+            assert!(Job::decode(&mut Decoder::new(&out.data)).is_err(), "{} x {}", width, height);
+        }
+    }
+
+    #[test]
+    fn test_coordinate_with_dropout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let worker = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || serve(listener));
+
+        // A worker that takes the job and a tile, then drops out.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let flaky = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            receive(&mut stream).unwrap();
+            receive(&mut stream).unwrap();
+        });
+
+        let job = job();
+        let mut film = Film::new(job.width, job.height, job.filter);
+        let mut rendered = Vec::new();
+        let addresses = [flaky, worker, String::from("127.0.0.1:1")];
+        coordinate(&addresses, &job, Tile::split(job.height, 3), &mut film, |t| rendered.push(t.id))
+            .expect("render failed");
+
+        rendered.sort();
+        assert_eq!(rendered, vec![0, 1, 2, 3]);
+        assert_eq!(film.sample_counts().iter().sum::<u32>(), 4 * 10);
+        assert!(film.pixels().iter().any(|c| c.b() > 0.0));
+    }
+
+    #[test]
+    fn test_coordinate_rejects_wrong_rows() {
+        // A worker that answers the first tile with the whole image.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let job = job();
+        let whole = Film::new(job.width, job.height, job.filter);
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            receive(&mut stream).unwrap();
+            let (_, payload) = receive(&mut stream).unwrap();
+            let tile = decode_tile(&mut Decoder::new(&payload)).unwrap();
+            let mut out = Encoder::default();
+            out.u32(tile.id);
+            whole.encode(&mut out);
+            send(&mut stream, Kind::Result, &out.data).unwrap();
+            let _ = receive(&mut stream);
+        });
+
+        let mut film = Film::new(job.width, job.height, job.filter);
+        let e = coordinate(&[address], &job, Tile::split(job.height, 5), &mut film, |_| ())
+            .expect_err("merged a tile with the wrong rows");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_receive_too_large() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = vec![Kind::Job as u8];
+            header.extend_from_slice(&u64::MAX.to_le_bytes());
+            stream.write_all(&header).unwrap();
+        });
+        let mut stream = TcpStream::connect(address).unwrap();
+        let e = receive(&mut stream).expect_err("accepted an oversized message");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_coordinate_without_workers() {
+        let job = job();
+        let mut film = Film::new(job.width, job.height, job.filter);
+        let result = coordinate(&[String::from("127.0.0.1:1")], &job, Tile::split(job.height, 5), &mut film, |_| ());
+        assert!(result.is_err());
+    }
+}
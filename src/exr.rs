@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::colorspace::ColorSpace;
+
+// A named image channel, such as `R` or `depth.Z`, stored as 32-bit floats. Layers
+// are expressed through the usual `layer.channel` naming.
+pub struct Channel {
+    pub name: String,
+    pub data: Vec<f32>,
+}
+
+impl Channel {
+    pub fn new(name: &str, data: Vec<f32>) -> Channel {
+        Channel { name: name.to_string(), data }
+    }
+}
+
+// Writes an uncompressed scanline OpenEXR file. Channel data is laid out like the
+// renderer's images, in rows of `width` pixels from the bottom of the image up.
+pub fn write(filename: &str, width: u32, height: u32, space: ColorSpace, channels: &[Channel]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(filename)?);
+    file.write_all(&encode(width, height, space, channels)?)?;
+    file.flush()
+}
+
+pub fn encode(width: u32, height: u32, space: ColorSpace, channels: &[Channel]) -> io::Result<Vec<u8>> {
+    let num_pixels = width as usize * height as usize;
+    if let Some(c) = channels.iter().find(|c| c.data.len() != num_pixels) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("channel {} has {} values for {} pixels", c.name, c.data.len(), num_pixels)));
+    }
+    // Readers expect channels in alphabetical order.
+    let mut channels: Vec<&Channel> = channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&20000630_i32.to_le_bytes());
+    let long_names = channels.iter().any(|c| c.name.len() > 31);
+    let version: u32 = if long_names { 2 | 0x400 } else { 2 };
+    out.extend_from_slice(&version.to_le_bytes());
+
+    let mut chlist = Vec::new();
+    for c in &channels {
+        chlist.extend_from_slice(c.name.as_bytes());
+        chlist.push(0);
+        // FLOAT pixels, not perceptually linear, three reserved bytes, no subsampling.
+        chlist.extend_from_slice(&2_i32.to_le_bytes());
+        chlist.extend_from_slice(&[0, 0, 0, 0]);
+        chlist.extend_from_slice(&1_i32.to_le_bytes());
+        chlist.extend_from_slice(&1_i32.to_le_bytes());
+    }
+    chlist.push(0);
+    attribute(&mut out, "channels", "chlist", &chlist);
+
+    let chromaticities: Vec<u8> = space.chromaticities().iter()
+        .flat_map(|(x, y)| [*x as f32, *y as f32])
+        .flat_map(f32::to_le_bytes)
+        .collect();
+    attribute(&mut out, "chromaticities", "chromaticities", &chromaticities);
+    // No compression.
+    attribute(&mut out, "compression", "compression", &[0]);
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1].iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    attribute(&mut out, "dataWindow", "box2i", &window);
+    attribute(&mut out, "displayWindow", "box2i", &window);
+    // Increasing y, top row first.
+    attribute(&mut out, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut out, "pixelAspectRatio", "float", &1.0_f32.to_le_bytes());
+    attribute(&mut out, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut out, "screenWindowWidth", "float", &1.0_f32.to_le_bytes());
+    out.push(0);
+
+    // One scanline per block, each preceded by its row number and size.
+    let line_size = 4 * width as usize * channels.len();
+    let table_end = out.len() + 8 * height as usize;
+    for row in 0..height as usize {
+        let offset = (table_end + row * (8 + line_size)) as u64;
+        out.extend_from_slice(&offset.to_le_bytes());
+    }
+    for row in 0..height {
+        out.extend_from_slice(&(row as i32).to_le_bytes());
+        out.extend_from_slice(&(line_size as i32).to_le_bytes());
+        let y = height - 1 - row;
+        let start = (y * width) as usize;
+        for c in &channels {
+            for v in &c.data[start..start + width as usize] {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn attribute(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out.extend_from_slice(kind.as_bytes());
+    out.push(0);
+    out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_f32(bytes: &[u8], at: usize) -> f32 {
+        f32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_exr_layout() {
+        // Two by two pixels; the value is the index in bottom-up row order.
+        let values: Vec<f32> = (0..4).map(|i| i as f32).collect();
+        let channels = [Channel::new("depth.Z", values.clone()), Channel::new("B", vec![0.5; 4])];
+        let bytes = encode(2, 2, ColorSpace::LinearSrgb, &channels).unwrap();
+
+        assert_eq!(&bytes[0..4], &[0x76, 0x2f, 0x31, 0x01]);
+        let b = bytes.windows(2).position(|w| w == b"B\0").unwrap();
+        let depth = bytes.windows(8).position(|w| w == b"depth.Z\0").unwrap();
+        assert!(b < depth);
+
+        // The last block holds the bottom row: y, size, then B and depth.Z values.
+        let line_size = 2 * 2 * 4;
+        let last = bytes.len() - (8 + line_size);
+        let table = bytes.len() - 2 * (8 + line_size) - 16;
+        assert_eq!(u64::from_le_bytes(bytes[table + 8..table + 16].try_into().unwrap()), last as u64);
+        assert_eq!(i32::from_le_bytes(bytes[last..last + 4].try_into().unwrap()), 1);
+        assert_eq!(read_f32(&bytes, last + 8), 0.5);
+        assert_eq!(read_f32(&bytes, last + 16), 0.0);
+        assert_eq!(read_f32(&bytes, last + 20), 1.0);
+
+        assert!(encode(3, 2, ColorSpace::LinearSrgb, &channels).is_err());
+    }
+}
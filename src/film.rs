@@ -0,0 +1,244 @@
+use std::io;
+
+use crate::checkpoint::{invalid, Decoder, Encoder};
+use crate::color::Color;
+use crate::filter::Filter;
+
+#[derive(Debug, Clone, Copy)]
+struct FilmPixel {
+    sum: Color,
+    weight: f64,
+    // Samples taken within the pixel, wherever they were splatted.
+    samples: u32,
+}
+
+impl FilmPixel {
+    const EMPTY: FilmPixel = FilmPixel { sum: Color::BLACK, weight: 0.0, samples: 0 };
+    // Bytes of an encoded pixel.
+    const ENCODED_SIZE: usize = 4 * 8 + 4;
+}
+
+// Accumulates radiance samples into pixels through a reconstruction filter. Pixel
+// (x, y) is centred on those coordinates, with rows from the bottom up like
+// `ImagePpm`, and a sample contributes to every pixel whose filter covers it.
+//
+// A film may cover only some rows of the image, so that threads can each fill their
+// own and merge the results afterwards.
+#[derive(Debug, Clone)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub filter: Filter,
+    // Rows [y_min, y_min + rows) of the image that this film holds.
+    y_min: u32,
+    rows: u32,
+    pixels: Vec<FilmPixel>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Film {
+        Film::with_rows(width, height, filter, 0, height)
+    }
+
+    fn with_rows(width: u32, height: u32, filter: Filter, y_min: u32, rows: u32) -> Film {
+        let pixels = vec![FilmPixel::EMPTY; width as usize * rows as usize];
+        Film { width, height, filter, y_min, rows, pixels }
+    }
+
+    // An empty film for the pixels of a `width` x `height` image that samples taken in
+    // rows `y0..=y1` can reach, without the rest of the image.
+    pub fn new_tile(width: u32, height: u32, filter: Filter, y0: u32, y1: u32) -> Film {
+        let (y_min, rows) = tile_rows(height, &filter, y0, y1);
+        Film::with_rows(width, height, filter, y_min, rows)
+    }
+
+    // An empty film for the pixels that samples taken in rows `y0..=y1` can reach.
+    pub fn tile(&self, y0: u32, y1: u32) -> Film {
+        Film::new_tile(self.width, self.height, self.filter, y0, y1)
+    }
+
+    // The first row and number of rows of the tile for rows `y0..=y1`.
+    pub fn tile_rows(&self, y0: u32, y1: u32) -> (u32, u32) {
+        tile_rows(self.height, &self.filter, y0, y1)
+    }
+
+    // The first row and number of rows held.
+    pub fn rows(&self) -> (u32, u32) {
+        (self.y_min, self.rows)
+    }
+
+    // Adds a sample at image coordinates (x, y).
+    pub fn add_sample(&mut self, x: f64, y: f64, c: Color) {
+        if let Some(pixel) = self.pixel_mut(x.round(), y.round()) {
+            pixel.samples += 1;
+        }
+
+        let r = self.filter.radius();
+        let x0 = (x - r).ceil().max(0.0) as u32;
+        let x1 = (x + r).floor().min(self.width as f64 - 1.0);
+        let y0 = (y - r).ceil().max(self.y_min as f64) as u32;
+        let y1 = (y + r).floor().min((self.y_min + self.rows) as f64 - 1.0);
+        if x1 < 0.0 || y1 < 0.0 {
+            return;
+        }
+        for py in y0..=y1 as u32 {
+            for px in x0..=x1 as u32 {
+                let w = self.filter.eval(px as f64 - x, py as f64 - y);
+                if w != 0.0 {
+                    let pixel = &mut self.pixels[((py - self.y_min) * self.width + px) as usize];
+                    pixel.sum += w * c;
+                    pixel.weight += w;
+                }
+            }
+        }
+    }
+
+    pub fn merge(&mut self, tile: &Film) {
+        assert_eq!(self.width, tile.width, "merging films of different widths");
+        let offset = ((tile.y_min - self.y_min) * self.width) as usize;
+        for (i, p) in tile.pixels.iter().enumerate() {
+            let pixel = &mut self.pixels[offset + i];
+            pixel.sum += p.sum;
+            pixel.weight += p.weight;
+            pixel.samples += p.samples;
+        }
+    }
+
+    pub fn sample_counts(&self) -> Vec<u32> {
+        self.pixels.iter().map(|p| p.samples).collect()
+    }
+
+    pub fn encode(&self, out: &mut Encoder) {
+        out.u32(self.width);
+        out.u32(self.height);
+        out.u32(self.y_min);
+        out.u32(self.rows);
+        for p in &self.pixels {
+            for v in [p.sum.r(), p.sum.g(), p.sum.b(), p.weight] {
+                out.f64(v);
+            }
+            out.u32(p.samples);
+        }
+    }
+
+    pub fn decode(input: &mut Decoder, filter: Filter) -> io::Result<Film> {
+        let width = input.u32()?;
+        let height = input.u32()?;
+        let y_min = input.u32()?;
+        let rows = input.u32()?;
+        if y_min.checked_add(rows).is_none_or(|end| end > height) {
+            return Err(invalid("film rows are out of range"));
+        }
+        // Check the size against the data before allocating for it.
+        let size = (width as usize).checked_mul(rows as usize).and_then(|n| n.checked_mul(FilmPixel::ENCODED_SIZE));
+        if size.is_none_or(|size| size > input.remaining()) {
+            return Err(invalid("film is truncated"));
+        }
+        let mut film = Film::with_rows(width, height, filter, y_min, rows);
+        for p in film.pixels.iter_mut() {
+            let sum = Color::new(input.f64()?, input.f64()?, input.f64()?);
+            let weight = input.f64()?;
+            let samples = input.u32()?;
+            *p = FilmPixel { sum, weight, samples };
+        }
+        Ok(film)
+    }
+
+    fn pixel_mut(&mut self, x: f64, y: f64) -> Option<&mut FilmPixel> {
+        if x < 0.0 || x >= self.width as f64 || y < self.y_min as f64 || y >= (self.y_min + self.rows) as f64 {
+            return None;
+        }
+        let index = ((y as u32 - self.y_min) * self.width + x as u32) as usize;
+        Some(&mut self.pixels[index])
+    }
+
+    // Filtered pixel values of the rows held. Negative filter lobes can overshoot
+    // below zero, which is clipped.
+    pub fn pixels(&self) -> Vec<Color> {
+        self.pixels.iter()
+            .map(|p| {
+                // Filters with negative lobes can leave a pixel with no weight to speak
+                // of, or less than none, which would blow its colour up or invert it.
+                if p.weight <= 1e-8 {
+                    return Color::BLACK;
+                }
+                let c = p.sum / p.weight;
+                Color::new(c.r().max(0.0), c.g().max(0.0), c.b().max(0.0))
+            })
+            .collect()
+    }
+}
+
+// The rows of an image `height` high that `filter` spreads samples in rows `y0..=y1`
+// over, as the first row and the number of rows.
+fn tile_rows(height: u32, filter: &Filter, y0: u32, y1: u32) -> (u32, u32) {
+    let reach = filter.radius().ceil() as u32;
+    let y_min = y0.saturating_sub(reach);
+    let y_max = (y1 + reach).min(height - 1);
+    (y_min, y_max - y_min + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_film_box() {
+        // The default box filter averages the samples landing in each pixel.
+        let mut film = Film::new(2, 2, Filter::default());
+        film.add_sample(0.2, 0.1, Color::WHITE);
+        film.add_sample(-0.3, 0.2, Color::BLACK);
+        film.add_sample(1.1, 0.9, Color::new(0.5, 0.5, 0.5));
+        let pixels = film.pixels();
+        assert_eq!(pixels[0], Color::new(0.5, 0.5, 0.5));
+        assert_eq!(pixels[3], Color::new(0.5, 0.5, 0.5));
+        assert_eq!(pixels[1], Color::BLACK);
+        assert_eq!(film.sample_counts(), vec![2, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_film_splat() {
+        let mut film = Film::new(5, 5, Filter::Tent { radius: 1.5 });
+        film.add_sample(2.0, 2.0, Color::WHITE);
+        let pixels = film.pixels();
+        // Only pixels within the radius are touched, and a constant image stays constant.
+        assert_eq!(pixels[2 * 5 + 1], Color::WHITE);
+        assert_eq!(pixels[2 * 5 + 3], Color::WHITE);
+        assert_eq!(pixels[2 * 5], Color::BLACK);
+    }
+
+    #[test]
+    fn test_film_negative_lobes() {
+        // Pixel 1 sees the white sample past the edge through Lanczos' negative lobe,
+        // outweighing the black sample it sees through the positive one.
+        let mut film = Film::new(4, 1, Filter::Lanczos { radius: 3.0 });
+        film.add_sample(-0.4, 0.0, Color::WHITE);
+        film.add_sample(1.9, 0.0, Color::BLACK);
+        assert!(film.pixels[1].weight < 0.0);
+        let pixels = film.pixels();
+        assert_eq!(pixels[1], Color::BLACK);
+        // Ringing next to it overshoots a little, as it should.
+        assert!(pixels[0].r() > 1.0 && pixels[0].r() < 1.1, "{:?}", pixels[0]);
+    }
+
+    #[test]
+    fn test_film_tiles() {
+        let filter = Filter::Gaussian { radius: 1.5, sigma: 0.5 };
+        let mut whole = Film::new(4, 6, filter);
+        let mut merged = Film::new(4, 6, filter);
+        assert_eq!(Film::new_tile(4, 6, filter, 3, 3).pixels.len(), 4 * 5);
+        for y in 0..6 {
+            let mut tile = merged.tile(y, y);
+            for x in 0..4 {
+                let (sx, sy) = (x as f64 + 0.25, y as f64 - 0.3);
+                let c = Color::new(x as f64, y as f64, 1.0);
+                whole.add_sample(sx, sy, c);
+                tile.add_sample(sx, sy, c);
+            }
+            merged.merge(&tile);
+        }
+        for (a, b) in whole.pixels().iter().zip(merged.pixels()) {
+            assert!((a.r() - b.r()).abs() < 1e-12 && (a.g() - b.g()).abs() < 1e-12);
+        }
+    }
+}
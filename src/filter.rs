@@ -0,0 +1,155 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+// Pixel reconstruction filters. Each is separable and zero beyond `radius` pixels of
+// the pixel centre in x and y.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Box { radius: f64 },
+    Tent { radius: f64 },
+    // Gaussian with standard deviation `sigma`, shifted down to reach zero at the radius.
+    Gaussian { radius: f64, sigma: f64 },
+    // Mitchell-Netravali cubic with B = C = 1/3, stretched over the radius.
+    Mitchell { radius: f64 },
+    // Sinc windowed by a sinc stretched to the radius.
+    Lanczos { radius: f64 },
+}
+
+impl Filter {
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius } | Filter::Tent { radius } | Filter::Gaussian { radius, .. }
+                | Filter::Mitchell { radius } | Filter::Lanczos { radius } => radius,
+        }
+    }
+
+    pub fn eval(&self, dx: f64, dy: f64) -> f64 {
+        self.eval_1d(dx) * self.eval_1d(dy)
+    }
+
+    fn eval_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        let radius = self.radius();
+        if x > radius {
+            return 0.0;
+        }
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { .. } => radius - x,
+            Filter::Gaussian { sigma, .. } => {
+                let g = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                (g(x) - g(radius)).max(0.0)
+            },
+            Filter::Mitchell { .. } => mitchell(2.0 * x / radius, 1.0 / 3.0, 1.0 / 3.0),
+            Filter::Lanczos { .. } => sinc(x) * sinc(x / radius),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Filter::Box { .. } => "box",
+            Filter::Tent { .. } => "tent",
+            Filter::Gaussian { .. } => "gaussian",
+            Filter::Mitchell { .. } => "mitchell",
+            Filter::Lanczos { .. } => "lanczos",
+        }
+    }
+}
+
+// A box over the pixel, which is plain averaging of the samples that land in it.
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    // Accepts `box`, `tent`, `gaussian`, `mitchell` and `lanczos`, each optionally
+    // followed by `:RADIUS`.
+    fn from_str(s: &str) -> Result<Filter, String> {
+        let (name, radius) = match s.split_once(':') {
+            Some((name, r)) => {
+                let radius = r.parse::<f64>().ok().filter(|r| *r > 0.0 && r.is_finite())
+                    .ok_or_else(|| format!("invalid filter radius `{}`", r))?;
+                (name, Some(radius))
+            },
+            None => (s, None),
+        };
+        match name.to_ascii_lowercase().as_str() {
+            "box" => Ok(Filter::Box { radius: radius.unwrap_or(0.5) }),
+            "tent" | "triangle" => Ok(Filter::Tent { radius: radius.unwrap_or(1.0) }),
+            "gaussian" => Ok(Filter::Gaussian { radius: radius.unwrap_or(1.5), sigma: 0.5 }),
+            "mitchell" => Ok(Filter::Mitchell { radius: radius.unwrap_or(2.0) }),
+            "lanczos" => Ok(Filter::Lanczos { radius: radius.unwrap_or(3.0) }),
+            _ => Err(format!("unknown reconstruction filter `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.name(), self.radius())
+    }
+}
+
+fn mitchell(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x.powi(3) + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+    } else if x < 2.0 {
+        ((-b - 6.0 * c) * x.powi(3) + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c)) / 6.0
+    } else {
+        0.0
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_cmp::assert_approx_eq;
+
+    #[test]
+    fn test_filter_support() {
+        for name in ["box", "tent", "gaussian", "mitchell", "lanczos"] {
+            let filter: Filter = name.parse().unwrap();
+            let r = filter.radius();
+            assert!(filter.eval(0.0, 0.0) > 0.0, "{} is zero at the centre", filter);
+            assert_eq!(filter.eval(r + 0.01, 0.0), 0.0);
+            assert_eq!(filter.eval(0.0, -r - 0.01), 0.0);
+            assert_approx_eq!(f64, filter.eval(0.3, -0.2), filter.eval(-0.3, 0.2));
+        }
+    }
+
+    #[test]
+    fn test_filter_shapes() {
+        let tent = Filter::Tent { radius: 2.0 };
+        assert_approx_eq!(f64, tent.eval(1.0, 0.0), 2.0);
+        let gaussian = Filter::Gaussian { radius: 1.5, sigma: 0.5 };
+        assert!(gaussian.eval(1.5, 0.0).abs() < 1e-12);
+        // The Mitchell filter and Lanczos have negative lobes.
+        assert!(Filter::Mitchell { radius: 2.0 }.eval(1.5, 0.0) < 0.0);
+        assert!(Filter::Lanczos { radius: 3.0 }.eval(1.5, 0.0) < 0.0);
+        assert_approx_eq!(f64, Filter::Lanczos { radius: 3.0 }.eval(1.0, 0.0), 0.0, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_filter_parse() {
+        assert_eq!("box".parse::<Filter>(), Ok(Filter::default()));
+        assert_eq!("Mitchell:1.5".parse::<Filter>(), Ok(Filter::Mitchell { radius: 1.5 }));
+        assert!("lanczos:0".parse::<Filter>().is_err());
+        assert!("sinc".parse::<Filter>().is_err());
+        let tent = Filter::Tent { radius: 1.25 };
+        assert_eq!(tent.to_string().parse::<Filter>(), Ok(tent));
+    }
+}
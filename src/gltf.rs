@@ -0,0 +1,479 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use ::gltf::buffer::Data as BufferData;
+use ::gltf::camera::Projection;
+use ::gltf::image::{Data as ImageData, Format};
+use ::gltf::khr_lights_punctual::Kind;
+use ::gltf::mesh::Mode;
+use ::gltf::texture::{MagFilter, WrappingMode};
+use ::gltf::json::Value;
+use ::gltf::Document;
+
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::light::Light;
+use crate::material::{AlphaMode, Material};
+use crate::matrix::Matrix4;
+use crate::mesh::Mesh;
+use crate::scene::{Object, Scene, SceneError};
+use crate::spectrum::Dispersion;
+use crate::texture::{Texture, WrapMode};
+use crate::tonemap::ToneMap;
+use crate::vec::Vector;
+
+const DEFAULT_ASPECT_RATIO: f64 = 16.0 / 9.0;
+
+// Loads the default scene of a .gltf or .glb file. Node transforms are baked into the
+// mesh vertices and the first camera found in the node hierarchy becomes the scene camera.
+pub fn load(filename: &str) -> Result<Scene, SceneError> {
+    let (document, buffers, images) = ::gltf::import(filename)?;
+    Importer::new(&document, &buffers, &images).import()
+}
+
+// Loads a self-contained glTF (binary, or JSON with embedded data URIs) from memory.
+pub fn parse(data: &[u8]) -> Result<Scene, SceneError> {
+    let (document, buffers, images) = ::gltf::import_slice(data)?;
+    Importer::new(&document, &buffers, &images).import()
+}
+
+struct Importer<'a> {
+    document: &'a Document,
+    buffers: &'a [BufferData],
+    images: &'a [ImageData],
+    textures: HashMap<(usize, bool), Arc<Texture>>,
+    default_material: usize,
+    objects: Vec<Object>,
+    lights: Vec<Light>,
+    camera: Option<Camera>,
+    warnings: Vec<String>,
+    // Nodes already imported. The crate does not check that the hierarchy is a tree.
+    visited: HashSet<usize>,
+}
+
+impl<'a> Importer<'a> {
+    fn new(document: &'a Document, buffers: &'a [BufferData], images: &'a [ImageData]) -> Importer<'a> {
+        Importer {
+            document,
+            buffers,
+            images,
+            textures: HashMap::new(),
+            default_material: document.materials().len(),
+            objects: vec![],
+            lights: vec![],
+            camera: None,
+            warnings: vec![],
+            visited: HashSet::new(),
+        }
+    }
+
+    fn import(mut self) -> Result<Scene, SceneError> {
+        let mut materials: Vec<Material> = self.document.materials()
+            .map(|m| self.material(&m))
+            .collect();
+        materials.push(Material::default());
+
+        let scene = self.document.default_scene()
+            .or_else(|| self.document.scenes().next())
+            .ok_or_else(|| SceneError::Invalid(String::from("glTF file contains no scenes")))?;
+        for node in scene.nodes() {
+            self.visit(&node, &Matrix4::IDENTITY, &mut vec![])?;
+        }
+        let (exposure, tone_map) = display_settings(&scene)?;
+        let mut result = Scene::new(self.objects, materials, self.lights, self.camera);
+        result.exposure = exposure;
+        result.tone_map = tone_map;
+        result.warnings = self.warnings;
+        Ok(result)
+    }
+
+    // Imports `node` and its descendants below the nodes in `ancestors`.
+    fn visit(&mut self, node: &::gltf::Node, parent: &Matrix4, ancestors: &mut Vec<usize>) -> Result<(), SceneError> {
+        if ancestors.contains(&node.index()) {
+            return Err(SceneError::Invalid(format!("node {} is its own ancestor", node.index())));
+        }
+        if !self.visited.insert(node.index()) {
+            return Err(SceneError::Invalid(format!("node {} has more than one parent", node.index())));
+        }
+        let world = *parent * Matrix4::from_columns(node.transform().matrix());
+        let name = node.name().unwrap_or("");
+
+        if let Some(mesh) = node.mesh() {
+            let mesh_name = mesh.name().unwrap_or(name);
+            for primitive in mesh.primitives() {
+                self.primitive(&primitive, mesh_name, &world)?;
+            }
+        }
+        if let Some(camera) = node.camera() {
+            if self.camera.is_none() {
+                self.camera = Some(camera_from_node(&camera, &world));
+            }
+        }
+        if let Some(light) = node.light() {
+            self.lights.push(light_from_node(&light, &world));
+        }
+
+        ancestors.push(node.index());
+        for child in node.children() {
+            self.visit(&child, &world, ancestors)?;
+        }
+        ancestors.pop();
+        Ok(())
+    }
+
+    fn primitive(&mut self, primitive: &::gltf::Primitive, name: &str, world: &Matrix4) -> Result<(), SceneError> {
+        if primitive.mode() != Mode::Triangles {
+            self.warnings.push(format!("skipped {:?} primitive in mesh `{}`", primitive.mode(), name));
+            return Ok(());
+        }
+
+        let buffers = self.buffers;
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data[..]));
+        let positions: Vec<Vector> = match reader.read_positions() {
+            Some(positions) => positions.map(|p| world.transform_point(&vector(p))).collect(),
+            None => return Err(SceneError::Invalid(format!("primitive in mesh `{}` has no positions", name))),
+        };
+
+        let normal_matrix = world.inverse().map(|m| m.transpose()).unwrap_or(*world);
+        let normals = match reader.read_normals() {
+            Some(normals) => normals.map(|n| normal_matrix.transform_vector(&vector(n)).normalize()).collect(),
+            None => vec![],
+        };
+        let colors = match reader.read_colors(0) {
+            Some(colors) => colors.into_rgb_f32()
+                .map(|[r, g, b]| Color::new(r as f64, g as f64, b as f64))
+                .collect(),
+            None => vec![],
+        };
+        let uvs = match reader.read_tex_coords(0) {
+            Some(uvs) => uvs.into_f32().map(|[u, v]| (u as f64, v as f64)).collect(),
+            None => vec![],
+        };
+        let indices: Vec<usize> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+            None => (0..positions.len()).collect(),
+        };
+        if !indices.len().is_multiple_of(3) {
+            return Err(SceneError::Invalid(format!("primitive in mesh `{}` has {} vertex indices, which is not \
+                                                    a whole number of triangles", name, indices.len())));
+        }
+
+        // Mirroring transforms flip the winding, which would turn the mesh inside out.
+        let mirrored = world.determinant3() < 0.0;
+        // They also flip the handedness of the tangent frame.
+        let handedness = if mirrored { -1.0 } else { 1.0 };
+        let tangents = match reader.read_tangents() {
+            Some(tangents) => tangents
+                .map(|[x, y, z, w]| {
+                    let t = world.transform_vector(&vector([x, y, z])).normalize();
+                    [t.x, t.y, t.z, handedness * w as f64]
+                })
+                .collect(),
+            None => vec![],
+        };
+        let triangles = indices.chunks_exact(3)
+            .map(|t| if mirrored { [t[0], t[2], t[1]] } else { [t[0], t[1], t[2]] })
+            .collect();
+
+        let mut mesh = Mesh::new(positions, normals, colors, uvs, triangles)?;
+        if !tangents.is_empty() {
+            mesh = mesh.with_tangents(tangents)?;
+        }
+        let material = primitive.material().index().unwrap_or(self.default_material);
+        self.objects.push(Object::new(name, Box::new(mesh), material));
+        Ok(())
+    }
+
+    fn material(&mut self, m: &::gltf::Material) -> Material {
+        let pbr = m.pbr_metallic_roughness();
+        let [r, g, b, a] = pbr.base_color_factor();
+        let [er, eg, eb] = m.emissive_factor();
+        let emissive_strength = m.emissive_strength().unwrap_or(1.0) as f64;
+        let ior = m.ior().unwrap_or(1.5) as f64;
+        // KHR_materials_specular scales the reflectance implied by the IOR, whereas our
+        // specular parameter maps [0, 1] to a reflectance of [0, 8%].
+        let specular_factor = m.specular().map_or(1.0, |s| {
+            let [sr, sg, sb] = s.specular_color_factor();
+            (s.specular_factor() * sr.max(sg).max(sb)) as f64
+        });
+        let f0 = ((ior - 1.0) / (ior + 1.0)).powi(2);
+        // KHR_materials_dispersion, which the gltf crate does not know, gives 20 over the
+        // Abbe number.
+        let dispersion = m.extension_value("KHR_materials_dispersion")
+            .and_then(|e| e.get("dispersion"))
+            .and_then(Value::as_f64)
+            .filter(|&d| d > 0.0)
+            .map(|d| Dispersion::from_abbe(ior, 20.0 / d));
+
+        Material {
+            name: m.name().unwrap_or("").to_string(),
+            base_color: Color::new(r as f64, g as f64, b as f64),
+            alpha: a as f64,
+            alpha_mode: match m.alpha_mode() {
+                ::gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                ::gltf::material::AlphaMode::Mask => AlphaMode::Mask(m.alpha_cutoff().unwrap_or(0.5) as f64),
+                ::gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+            },
+            base_color_texture: pbr.base_color_texture().map(|t| self.texture(&t.texture(), true)),
+            metallic: pbr.metallic_factor() as f64,
+            roughness: pbr.roughness_factor() as f64,
+            metallic_roughness_texture: pbr.metallic_roughness_texture().map(|t| self.texture(&t.texture(), false)),
+            normal_texture: m.normal_texture().map(|t| self.texture(&t.texture(), false)),
+            normal_scale: m.normal_texture().map_or(1.0, |t| t.scale() as f64),
+            specular: (specular_factor * f0 / 0.08).min(1.0),
+            transmission: m.transmission().map_or(0.0, |t| t.transmission_factor() as f64),
+            ior,
+            dispersion,
+            emissive: emissive_strength * Color::new(er as f64, eg as f64, eb as f64),
+            emissive_texture: m.emissive_texture().map(|t| self.texture(&t.texture(), true)),
+            double_sided: m.double_sided(),
+            ..Material::default()
+        }
+    }
+
+    fn texture(&mut self, texture: &::gltf::Texture, srgb: bool) -> Arc<Texture> {
+        let images = self.images;
+        self.textures.entry((texture.index(), srgb))
+            .or_insert_with(|| {
+                let mut tex = convert_image(&images[texture.source().index()], srgb);
+                let sampler = texture.sampler();
+                tex.wrap_s = wrap_mode(sampler.wrap_s());
+                tex.wrap_t = wrap_mode(sampler.wrap_t());
+                tex.nearest = sampler.mag_filter() == Some(MagFilter::Nearest);
+                Arc::new(tex)
+            })
+            .clone()
+    }
+}
+
+fn vector(v: [f32; 3]) -> Vector {
+    Vector::new(v[0] as f64, v[1] as f64, v[2] as f64)
+}
+
+// Exposure in stops and tone mapping operator from the scene's extras, for example
+// `"extras": { "exposure": 1.0, "toneMapping": "aces" }`.
+fn display_settings(scene: &::gltf::Scene) -> Result<(Option<f64>, Option<ToneMap>), SceneError> {
+    let Some(raw) = scene.extras() else {
+        return Ok((None, None));
+    };
+    let extras: Value = ::gltf::json::deserialize::from_str(raw.get())
+        .map_err(|e| SceneError::Invalid(format!("scene extras: {}", e)))?;
+    let exposure = match extras.get("exposure") {
+        Some(v) => Some(v.as_f64().ok_or_else(|| SceneError::Invalid(format!("invalid exposure {}", v)))?),
+        None => None,
+    };
+    let tone_map = match extras.get("toneMapping") {
+        Some(v) => {
+            let name = v.as_str().ok_or_else(|| SceneError::Invalid(format!("invalid tone mapping {}", v)))?;
+            Some(name.parse::<ToneMap>().map_err(SceneError::Invalid)?)
+        },
+        None => None,
+    };
+    Ok((exposure, tone_map))
+}
+
+fn camera_from_node(camera: &::gltf::Camera, world: &Matrix4) -> Camera {
+    // glTF cameras look down their local -z axis with +y up.
+    let origin = world.transform_point(&Vector::ORIGIN);
+    let forward = world.transform_vector(&Vector::new(0.0, 0.0, -1.0));
+    let up = world.transform_vector(&Vector::new(0.0, 1.0, 0.0));
+    match camera.projection() {
+        Projection::Perspective(p) => {
+            let aspect_ratio = p.aspect_ratio().map_or(DEFAULT_ASPECT_RATIO, |a| a as f64);
+            Camera::from_frame(origin, forward, up, p.yfov().to_degrees() as f64, aspect_ratio)
+        },
+        // The magnifications are half the size of the view.
+        Projection::Orthographic(o) => {
+            Camera::orthographic(origin, forward, up, 2.0 * o.ymag() as f64, (o.xmag() / o.ymag()) as f64)
+        },
+    }
+}
+
+fn light_from_node(light: &::gltf::khr_lights_punctual::Light, world: &Matrix4) -> Light {
+    let [r, g, b] = light.color();
+    let intensity = light.intensity() as f64 * Color::new(r as f64, g as f64, b as f64);
+    let range = light.range().map(|r| r as f64);
+    let position = world.transform_point(&Vector::ORIGIN);
+    let direction = world.transform_vector(&Vector::new(0.0, 0.0, -1.0)).normalize();
+    match light.kind() {
+        Kind::Directional => Light::Directional { direction, intensity },
+        Kind::Point => Light::Point { position, intensity, range },
+        Kind::Spot { inner_cone_angle, outer_cone_angle } => Light::Spot {
+            position,
+            direction,
+            intensity,
+            range,
+            inner_cone_angle: inner_cone_angle as f64,
+            outer_cone_angle: outer_cone_angle as f64,
+        },
+    }
+}
+
+fn wrap_mode(mode: WrappingMode) -> WrapMode {
+    match mode {
+        WrappingMode::Repeat => WrapMode::Repeat,
+        WrappingMode::MirroredRepeat => WrapMode::MirroredRepeat,
+        WrappingMode::ClampToEdge => WrapMode::ClampToEdge,
+    }
+}
+
+fn convert_image(image: &ImageData, srgb: bool) -> Texture {
+    let width = image.width as usize;
+    let height = image.height as usize;
+    let pixels = &image.pixels;
+    let u16s = || -> Vec<f32> {
+        pixels.chunks_exact(2)
+            .map(|b| u16::from_ne_bytes([b[0], b[1]]) as f32 / 65535.0)
+            .collect()
+    };
+    let f32s = || -> Vec<f32> {
+        pixels.chunks_exact(4)
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    };
+    match image.format {
+        Format::R8 => Texture::from_bytes(width, height, 1, pixels, srgb),
+        Format::R8G8 => Texture::from_bytes(width, height, 2, pixels, srgb),
+        Format::R8G8B8 => Texture::from_bytes(width, height, 3, pixels, srgb),
+        Format::R8G8B8A8 => Texture::from_bytes(width, height, 4, pixels, srgb),
+        Format::R16 => Texture::from_floats(width, height, 1, &u16s(), srgb),
+        Format::R16G16 => Texture::from_floats(width, height, 2, &u16s(), srgb),
+        Format::R16G16B16 => Texture::from_floats(width, height, 3, &u16s(), srgb),
+        Format::R16G16B16A16 => Texture::from_floats(width, height, 4, &u16s(), srgb),
+        Format::R32G32B32FLOAT => Texture::from_floats(width, height, 3, &f32s(), false),
+        Format::R32G32B32A32FLOAT => Texture::from_floats(width, height, 4, &f32s(), false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Hittable;
+
+    // One red triangle at z = -1 under a translated parent node, a camera at the
+    // origin and a point light, with the vertex buffer embedded as a data URI.
+    fn triangle_gltf() -> String {
+        let mut bytes = vec![];
+        for c in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            bytes.extend(c.to_le_bytes());
+        }
+        let data = base64(&bytes);
+        format!(r#"{{
+  "asset": {{ "version": "2.0" }},
+  "extensionsUsed": ["KHR_lights_punctual"],
+  "extensions": {{ "KHR_lights_punctual": {{ "lights": [
+    {{ "type": "point", "color": [1.0, 1.0, 1.0], "intensity": 10.0 }}
+  ] }} }},
+  "scene": 0,
+  "scenes": [ {{ "nodes": [0, 2, 3], "extras": {{ "exposure": -1.0, "toneMapping": "hable" }} }} ],
+  "nodes": [
+    {{ "name": "parent", "translation": [-0.25, -0.25, -1.0], "children": [1] }},
+    {{ "name": "triangle", "mesh": 0 }},
+    {{ "name": "camera", "camera": 0 }},
+    {{ "name": "lamp", "translation": [0.0, 2.0, 0.0], "extensions": {{ "KHR_lights_punctual": {{ "light": 0 }} }} }}
+  ],
+  "cameras": [ {{ "type": "perspective", "perspective": {{ "yfov": 0.8, "znear": 0.1, "aspectRatio": 1.5 }} }} ],
+  "meshes": [ {{ "name": "tri", "primitives": [ {{ "attributes": {{ "POSITION": 0 }}, "material": 0 }} ] }} ],
+  "materials": [ {{ "name": "red", "pbrMetallicRoughness": {{ "baseColorFactor": [1.0, 0.0, 0.0, 1.0], "metallicFactor": 0.25 }},
+                   "extensions": {{ "KHR_materials_transmission": {{ "transmissionFactor": 0.5 }},
+                                    "KHR_materials_ior": {{ "ior": 1.33 }},
+                                    "KHR_materials_dispersion": {{ "dispersion": 0.4 }} }} }} ],
+  "accessors": [ {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                   "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] }} ],
+  "bufferViews": [ {{ "buffer": 0, "byteLength": 36 }} ],
+  "buffers": [ {{ "byteLength": 36, "uri": "data:application/octet-stream;base64,{}" }} ]
+}}"#, data)
+    }
+
+    fn base64(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut out = String::new();
+        for chunk in bytes.chunks(3) {
+            let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+            let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+                } else {
+                    out.push('=');
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_parse_gltf() {
+        let scene = parse(triangle_gltf().as_bytes()).expect("valid glTF rejected");
+        assert_eq!(scene.objects().len(), 1);
+        assert_eq!(scene.objects()[0].name, "tri");
+        assert_eq!(scene.lights.len(), 1);
+        assert_eq!(scene.exposure, Some(-1.0));
+        assert_eq!(scene.tone_map, Some(ToneMap::Hable));
+
+        let material = &scene.materials[scene.objects()[0].material];
+        assert_eq!(material.name, "red");
+        assert_eq!(material.base_color, Color::new(1.0, 0.0, 0.0));
+        assert_eq!(material.metallic, 0.25);
+        assert_eq!(material.transmission, 0.5);
+        assert!((material.ior - 1.33).abs() < 1e-6);
+        // An Abbe number of 20 / 0.4.
+        let dispersion = material.dispersion.expect("dispersion not imported");
+        assert!((dispersion.ior(587.56) - material.ior).abs() < 1e-9);
+        assert!(((dispersion.ior(486.13) - dispersion.ior(656.27)) * 50.0 - 0.33).abs() < 1e-6);
+
+        let camera = scene.camera.expect("camera not imported");
+        let hit = scene.hit(&camera.get_ray(0.5, 0.5), 0.001, f64::INFINITY).expect("triangle not hit");
+        assert!((hit.t - 1.0).abs() < 1e-9);
+        assert!(hit.front_face);
+    }
+
+    #[test]
+    fn test_orthographic_camera() {
+        let gltf = triangle_gltf().replace(
+            r#"{ "type": "perspective", "perspective": { "yfov": 0.8, "znear": 0.1, "aspectRatio": 1.5 } }"#,
+            r#"{ "type": "orthographic", "orthographic": { "xmag": 1.0, "ymag": 0.5, "znear": 0.1, "zfar": 10.0 } }"#);
+        let scene = parse(gltf.as_bytes()).expect("valid glTF rejected");
+        let camera = scene.camera.expect("camera not imported");
+        assert_eq!(camera.projection, crate::camera::Projection::Orthographic);
+        assert!((camera.aspect_ratio() - 2.0).abs() < 1e-9);
+
+        let ray = camera.get_ray(0.6, 0.6);
+        assert!((ray.origin - Vector::new(0.2, 0.1, 0.0)).length() < 1e-9);
+        let hit = scene.hit(&ray, 0.001, f64::INFINITY).expect("triangle not hit");
+        assert!((hit.p - Vector::new(0.2, 0.1, -1.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_skipped_primitive() {
+        let gltf = triangle_gltf().replace(r#""material": 0 }"#, r#""material": 0, "mode": 0 }"#);
+        let scene = parse(gltf.as_bytes()).expect("glTF with points rejected");
+        assert!(scene.objects().is_empty());
+        assert_eq!(scene.warnings, vec![String::from("skipped Points primitive in mesh `tri`")]);
+        assert!(parse(triangle_gltf().as_bytes()).unwrap().warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        match parse(b"{ \"asset\": { \"version\": \"2.0\" } }") {
+            Err(SceneError::Invalid(_)) => (),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("scene without scenes accepted"),
+        }
+        assert!(parse(b"not a gltf file").is_err());
+        let partial = triangle_gltf().replace(r#""count": 3,"#, r#""count": 2,"#);
+        assert!(matches!(parse(partial.as_bytes()), Err(SceneError::Invalid(why)) if why.contains("2 vertex indices")));
+
+        // Hierarchies that are not trees.
+        let cycle = r#"{ "asset": { "version": "2.0" }, "scenes": [ { "nodes": [0] } ],
+                         "nodes": [ { "children": [0] } ] }"#;
+        match parse(cycle.as_bytes()) {
+            Err(SceneError::Invalid(why)) => assert_eq!(why, "node 0 is its own ancestor"),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("cyclic nodes accepted"),
+        }
+        let shared = r#"{ "asset": { "version": "2.0" }, "scenes": [ { "nodes": [0] } ],
+                          "nodes": [ { "children": [1, 1] }, {} ] }"#;
+        assert!(matches!(parse(shared.as_bytes()), Err(SceneError::Invalid(_))));
+    }
+}
@@ -1,8 +1,13 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::fmt;
-use std::io::{Write, BufWriter};
+use std::io::{self, Cursor, Write, BufWriter};
+use std::path::Path;
+
+use png::chunk::ChunkType;
+use png::{BitDepth, ColorType, ScaledFloat, SourceChromaticities, SrgbRenderingIntent, Transformations};
 
 use crate::color::Color;
+use crate::colorspace::{Encoding, Transfer};
 
 
 pub struct ImagePpm {
@@ -13,7 +18,7 @@ pub struct ImagePpm {
 
 impl ImagePpm {
     pub fn new(w: u32, h: u32) -> ImagePpm {
-        let num_pixels = (w * h) as usize;
+        let num_pixels = w as usize * h as usize;
         ImagePpm {
             width: w,
             height: h,
@@ -40,10 +45,7 @@ impl ImagePpm {
     }
 
     pub fn write(&self, filename: &str) -> Result<(), std::io::Error> {
-        let file = match File::create(filename) {
-            Ok(f) => f,
-            Err(why) => return Err(why),
-        };
+        let file = File::create(filename)?;
         let mut file = BufWriter::new(file);
 
         file.write_all(format!("P6\n{} {}\n255\n", self.width, self.height).as_bytes())?;
@@ -57,6 +59,192 @@ impl ImagePpm {
     }
 }
 
+impl ImagePpm {
+    // Writes already encoded pixels, as PNG or PPM by the file name's extension.
+    pub fn save(&self, filename: &str, encoding: &Encoding) -> Result<(), io::Error> {
+        if ImagePpm::check_save(filename, encoding)? {
+            self.write_png(filename, encoding)
+        } else {
+            self.write(filename)
+        }
+    }
+
+    // Whether `save` can write `filename` with `encoding`, and writes it as PNG rather
+    // than PPM. PPM files are untagged 8-bit images, which readers take to be sRGB.
+    pub fn check_save(filename: &str, encoding: &Encoding) -> Result<bool, io::Error> {
+        let extension = Path::new(filename).extension().and_then(|e| e.to_str()).unwrap_or("");
+        if extension.eq_ignore_ascii_case("png") {
+            Ok(true)
+        } else if !extension.eq_ignore_ascii_case("ppm") {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a .png or .ppm file", filename)))
+        } else if *encoding != Encoding::SRGB {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, format!("PPM files cannot hold {} images", encoding)))
+        } else {
+            Ok(false)
+        }
+    }
+
+    // Writes a PNG tagged with the colour space and transfer function of `encoding`.
+    pub fn write_png(&self, filename: &str, encoding: &Encoding) -> Result<(), io::Error> {
+        self.encode_png(BufWriter::new(File::create(filename)?), encoding)
+    }
+
+    pub fn encode_png<W: Write>(&self, out: W, encoding: &Encoding) -> Result<(), io::Error> {
+        let mut encoder = png::Encoder::new(out, self.width, self.height);
+        encoder.set_color(ColorType::Rgb);
+
+        if *encoding == Encoding::SRGB {
+            encoder.set_source_srgb(SrgbRenderingIntent::Perceptual);
+        } else {
+            let [r, g, b, w] = encoding.space.chromaticities()
+                .map(|(x, y)| (x as f32, y as f32));
+            encoder.set_source_chromaticities(SourceChromaticities::new(w, r, g, b));
+            match encoding.transfer {
+                Transfer::Linear => encoder.set_source_gamma(ScaledFloat::new(1.0)),
+                Transfer::Srgb => encoder.set_source_gamma(ScaledFloat::new(1.0 / 2.2)),
+                Transfer::Pq => (),
+            }
+        }
+
+        let mut data = Vec::with_capacity(self.width as usize * self.height as usize * 6);
+        if encoding.bit_depth() == 16 {
+            encoder.set_depth(BitDepth::Sixteen);
+            for y in (0..self.height).rev() {
+                for x in 0..self.width {
+                    let c = self.get_pixel(x, y);
+                    for v in [c.r(), c.g(), c.b()] {
+                        let v = (v.clamp(0.0, 1.0) * 65535.0).round() as u16;
+                        data.extend_from_slice(&v.to_be_bytes());
+                    }
+                }
+            }
+        } else {
+            encoder.set_depth(BitDepth::Eight);
+            for y in (0..self.height).rev() {
+                for x in 0..self.width {
+                    data.extend_from_slice(&self.get_pixel(x, y).as_bytes());
+                }
+            }
+        }
+
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        if let Some((primaries, transfer)) = encoding.cicp() {
+            // Full-range RGB; the chunk must precede the image data.
+            writer.write_chunk(ChunkType(*b"cICP"), &[primaries, transfer, 0, 1]).map_err(io::Error::other)?;
+        }
+        writer.write_image_data(&data).map_err(io::Error::other)?;
+        writer.finish().map_err(io::Error::other)
+    }
+}
+
+impl ImagePpm {
+    // Reads a PNG or PPM image with its values as stored, from 0 to 1, whatever the
+    // file's colour space.
+    pub fn read(filename: &str) -> Result<ImagePpm, io::Error> {
+        let data = fs::read(filename)?;
+        if data.starts_with(b"\x89PNG") {
+            ImagePpm::read_png(&data)
+        } else {
+            ImagePpm::read_ppm(&data)
+        }
+    }
+
+    fn read_png(data: &[u8]) -> Result<ImagePpm, io::Error> {
+        let mut decoder = png::Decoder::new(Cursor::new(data));
+        decoder.set_transformations(Transformations::EXPAND);
+        let mut reader = decoder.read_info().map_err(io::Error::other)?;
+        let size = reader.output_buffer_size().ok_or_else(|| io::Error::other("PNG is too large"))?;
+        let mut buf = vec![0; size];
+        let info = reader.next_frame(&mut buf).map_err(io::Error::other)?;
+
+        let channels = info.color_type.samples();
+        let sixteen = info.bit_depth == BitDepth::Sixteen;
+        let mut img = ImagePpm::new(info.width, info.height);
+        for (row, line) in buf.chunks(info.line_size).take(info.height as usize).enumerate() {
+            for x in 0..info.width as usize {
+                let value = |c: usize| {
+                    let i = x * channels + c;
+                    if sixteen {
+                        u16::from_be_bytes([line[2 * i], line[2 * i + 1]]) as f64 / 65535.0
+                    } else {
+                        line[i] as f64 / 255.0
+                    }
+                };
+                // Grey images have one channel, or two with alpha.
+                let c = if channels < 3 {
+                    Color::new(value(0), value(0), value(0))
+                } else {
+                    Color::new(value(0), value(1), value(2))
+                };
+                img.set_pixel(x as u32, info.height - 1 - row as u32, c);
+            }
+        }
+        Ok(img)
+    }
+
+    // Binary (P6) and plain (P3) PPM.
+    fn read_ppm(data: &[u8]) -> Result<ImagePpm, io::Error> {
+        let invalid = |why: &str| io::Error::new(io::ErrorKind::InvalidData, why.to_string());
+        let mut pos = 0;
+        let mut token = || {
+            // Tokens are separated by whitespace, and comments run to the end of the line.
+            loop {
+                match data.get(pos) {
+                    Some(b'#') => while data.get(pos).is_some_and(|b| *b != b'\n') { pos += 1 },
+                    Some(b) if b.is_ascii_whitespace() => pos += 1,
+                    _ => break,
+                }
+            }
+            let start = pos;
+            while data.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+                pos += 1;
+            }
+            std::str::from_utf8(&data[start..pos]).unwrap_or("").to_string()
+        };
+
+        let magic = token();
+        if magic != "P6" && magic != "P3" {
+            return Err(invalid("not a PNG or PPM image"));
+        }
+        let mut number = || token().parse::<u32>().map_err(|_| invalid("malformed PPM header"));
+        let (width, height, max) = (number()?, number()?, number()?);
+        if width == 0 || height == 0 || max == 0 || max > 65535 {
+            return Err(invalid("malformed PPM header"));
+        }
+
+        // Every value takes at least a byte, so a header claiming more than the data
+        // holds is caught before allocating for it.
+        let count = (width as usize).checked_mul(height as usize).and_then(|n| n.checked_mul(3))
+            .filter(|&count| count <= data.len())
+            .ok_or_else(|| invalid("PPM image is truncated"))?;
+        let values: Vec<f64> = if magic == "P6" {
+            // A single whitespace character separates the header from the pixels.
+            let pixels = data.get(pos + 1..).unwrap_or(&[]);
+            let bytes = if max < 256 { 1 } else { 2 };
+            if pixels.len() / bytes < count {
+                return Err(invalid("PPM image is truncated"));
+            }
+            (0..count)
+                .map(|i| match bytes {
+                    1 => pixels[i] as f64,
+                    _ => u16::from_be_bytes([pixels[2 * i], pixels[2 * i + 1]]) as f64,
+                } / max as f64)
+                .collect()
+        } else {
+            (0..count)
+                .map(|_| number().map(|v| v as f64 / max as f64))
+                .collect::<Result<_, _>>()?
+        };
+
+        let mut img = ImagePpm::new(width, height);
+        for (i, c) in values.chunks(3).enumerate() {
+            let (x, row) = (i as u32 % width, i as u32 / width);
+            img.set_pixel(x, height - 1 - row, Color::new(c[0], c[1], c[2]));
+        }
+        Ok(img)
+    }
+}
+
 impl fmt::Display for ImagePpm {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut pixel = 0;
@@ -99,4 +287,59 @@ mod tests {
         let img = ImagePpm::new(4, 4);
         img.get_pixel(0, 4);
     }
+
+    #[test]
+    fn test_image_write_png() {
+        let mut img = ImagePpm::new(2, 2);
+        img.set_pixel(1, 0, Color::WHITE);
+        let filename = std::env::temp_dir().join(format!("raytracer-test-{}.png", std::process::id()));
+        let filename = filename.to_str().unwrap();
+        let encoding: Encoding = "rec2020-pq".parse().unwrap();
+        img.save(filename, &encoding).expect("could not write PNG");
+
+        let bytes = std::fs::read(filename).unwrap();
+        std::fs::remove_file(filename).unwrap();
+        assert_eq!(&bytes[1..4], b"PNG");
+        let cicp = bytes.windows(4).position(|w| w == b"cICP").expect("no cICP chunk");
+        assert_eq!(&bytes[cicp + 4..cicp + 8], &[9, 16, 0, 1]);
+        assert!(cicp < bytes.windows(4).position(|w| w == b"IDAT").unwrap());
+    }
+
+    #[test]
+    fn test_image_check_save() {
+        let p3: Encoding = "display-p3".parse().unwrap();
+        assert!(ImagePpm::check_save("out.PNG", &p3).unwrap());
+        assert!(!ImagePpm::check_save("out.ppm", &Encoding::SRGB).unwrap());
+        for (filename, encoding) in [("out.ppm", p3), ("out.jpg", Encoding::SRGB), ("out", Encoding::SRGB)] {
+            let e = ImagePpm::check_save(filename, &encoding).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{}", filename);
+        }
+    }
+
+    #[test]
+    fn test_image_read() {
+        let mut img = ImagePpm::new(3, 2);
+        img.set_pixel(0, 0, Color::WHITE);
+        img.set_pixel(2, 1, Color::new(1.0, 0.0, 0.2));
+        for extension in ["png", "ppm"] {
+            let filename = std::env::temp_dir().join(format!("raytracer-read-{}.{}", std::process::id(), extension));
+            let filename = filename.to_str().unwrap();
+            img.save(filename, &Encoding::SRGB).expect("could not write image");
+            let read = ImagePpm::read(filename);
+            std::fs::remove_file(filename).unwrap();
+
+            let read = read.expect("could not read image");
+            assert_eq!((read.width, read.height), (3, 2));
+            assert_eq!(read.get_pixel(0, 0), Color::WHITE);
+            assert_eq!(read.get_pixel(2, 1).as_bytes(), [255, 0, 51]);
+            assert_eq!(read.get_pixel(1, 1), Color::BLACK);
+        }
+
+        // Headers claiming more pixels than there are, or more than fit in memory.
+        let headers = ["P6\n4294967295 4294967295\n255\n", "P6\n100000 100000\n255\n\0\0\0", "P3\n2 2\n255\n1 2 3"];
+        for header in headers {
+            let e = ImagePpm::read_ppm(header.as_bytes()).err().expect("read a truncated image");
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+    }
 }
@@ -0,0 +1,408 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+use crate::camera::Camera;
+use crate::ray::Ray;
+use crate::vec::Vector;
+
+// Prescriptions are in millimetres and scenes in metres.
+const MILLIMETRES_PER_UNIT: f64 = 1000.0;
+// Bands of film radii, from the centre to the corners, to find the exit pupil for,
+// and the grid of points across the rear surface to find it with.
+const PUPIL_BANDS: usize = 64;
+const PUPIL_GRID: usize = 48;
+
+// One surface of a lens. The radius of curvature is positive for surfaces bulging
+// towards the front and zero for flat ones, the thickness is the distance to the
+// next surface, and the index of refraction is that of the glass behind the surface,
+// or 0 for air. The aperture is the surface's diameter. The aperture stop is a flat
+// surface with air on either side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Element {
+    pub radius: f64,
+    pub thickness: f64,
+    pub ior: f64,
+    pub aperture: f64,
+}
+
+impl Element {
+    fn ior(&self) -> f64 {
+        if self.ior == 0.0 { 1.0 } else { self.ior }
+    }
+}
+
+// Reads a prescription: a line of radius, thickness, index of refraction and
+// aperture for each surface from the front of the lens to the back, in millimetres,
+// with `#` starting comments.
+pub fn parse_prescription(text: &str) -> Result<Vec<Element>, String> {
+    let mut elements = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |reason: &str| format!("line {}: {}", i + 1, reason);
+        let values = line.split_whitespace()
+            .map(|v| v.parse::<f64>().ok().filter(|v| v.is_finite()))
+            .collect::<Option<Vec<f64>>>()
+            .ok_or_else(|| error("expected numbers"))?;
+        let [radius, thickness, ior, aperture] = values[..] else {
+            return Err(error("expected radius, thickness, index of refraction and aperture"));
+        };
+        if thickness < 0.0 || aperture <= 0.0 || (ior != 0.0 && ior < 1.0) {
+            return Err(error("thicknesses and apertures must be positive, and indices of refraction at least 1"));
+        }
+        elements.push(Element { radius, thickness, ior, aperture });
+    }
+    if elements.is_empty() {
+        return Err(String::from("no lens surfaces"));
+    }
+    Ok(elements)
+}
+
+// The shape of the aperture stop: a circle, or a polygon of three or more blades
+// with a corner `rotation` degrees from the x axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aperture {
+    pub blades: u32,
+    pub rotation: f64,
+}
+
+impl Aperture {
+    pub const CIRCLE: Aperture = Aperture { blades: 0, rotation: 0.0 };
+
+    // Whether a point is inside the aperture of a radius.
+    fn contains(&self, x: f64, y: f64, radius: f64) -> bool {
+        let r = x.hypot(y);
+        if r > radius {
+            return false;
+        }
+        if self.blades < 3 {
+            return true;
+        }
+        // Distance from the centre along the normal of the nearest blade.
+        let sector = 2.0 * PI / self.blades as f64;
+        let angle = (y.atan2(x) - self.rotation.to_radians()).rem_euclid(sector) - sector / 2.0;
+        r * angle.cos() <= radius * (sector / 2.0).cos()
+    }
+}
+
+impl Default for Aperture {
+    fn default() -> Self {
+        Aperture::CIRCLE
+    }
+}
+
+impl FromStr for Aperture {
+    type Err = String;
+
+    // Accepts a number of blades, 0 for a circle, optionally followed by `:DEGREES`.
+    fn from_str(s: &str) -> Result<Aperture, String> {
+        let (blades, rotation) = s.split_once(':').unwrap_or((s, "0"));
+        let blades = blades.parse::<u32>().ok().filter(|b| *b == 0 || (3..=32).contains(b))
+            .ok_or_else(|| format!("invalid number of blades `{}`, expected 0 or 3 to 32", blades))?;
+        let rotation = rotation.parse::<f64>().ok().filter(|r| r.is_finite())
+            .ok_or_else(|| format!("invalid blade rotation `{}`", rotation))?;
+        Ok(Aperture { blades, rotation })
+    }
+}
+
+impl fmt::Display for Aperture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.blades, self.rotation)
+    }
+}
+
+// A camera lens made of spherical surfaces, traced from the film out into the scene.
+// Lens space has the film at z = 0 and the lens in front of it along +z. Rays are
+// aimed at the exit pupil, the part of the rear surface that light from a point of
+// the film gets through, so that few are wasted on the lens housing.
+#[derive(Debug, Clone)]
+pub struct Lens {
+    elements: Vec<Element>,
+    stop: Option<usize>,
+    // Where each surface crosses the axis.
+    vertices: Vec<f64>,
+    sensor_width: f64,
+    aperture: Aperture,
+    // Bounds on the rear surface of where light gets through from each band of film
+    // radii, as [x0, y0, x1, y1] for film points on the x axis, or None where none does.
+    pupils: Vec<Option<[f64; 4]>>,
+    film_radius: f64,
+    // How much of the rear surface the centre of the film sees through, to weigh
+    // rays by against it.
+    central_area: f64,
+}
+
+impl Lens {
+    // A lens focused at `focus` scene units from the film, or at infinity, in front of
+    // a sensor `sensor_width` millimetres across and as tall as `aspect_ratio` makes it.
+    pub fn new(elements: Vec<Element>, sensor_width: f64, aspect_ratio: f64, focus: Option<f64>,
+               aperture: Aperture) -> Result<Lens, String> {
+        let air = |i: usize| i == 0 || elements[i - 1].ior() == 1.0;
+        let stop = (0..elements.len()).find(|&i| elements[i].radius == 0.0 && elements[i].ior() == 1.0 && air(i));
+        if stop.is_none() && aperture.blades >= 3 {
+            return Err(String::from("the lens has no flat stop in air for the aperture blades to shape"));
+        }
+        let mut lens = Lens {
+            elements,
+            stop,
+            vertices: Vec::new(),
+            sensor_width,
+            aperture,
+            pupils: Vec::new(),
+            film_radius: sensor_width / 2.0 * (1.0 + 1.0 / (aspect_ratio * aspect_ratio)).sqrt(),
+            central_area: 0.0,
+        };
+        lens.place(0.0);
+        // The film moves back from the rear surface as the focus comes closer, which
+        // moves the subject in turn, so settle on where it ends up.
+        let mut film_distance = 0.0;
+        for _ in 0..8 {
+            film_distance = lens.image_distance(focus.map(|f| f * MILLIMETRES_PER_UNIT - film_distance))
+                .ok_or_else(|| match focus {
+                    Some(f) => format!("the lens cannot focus at {}", f),
+                    None => String::from("the lens cannot focus at infinity"),
+                })?;
+        }
+        lens.place(film_distance);
+        lens.find_pupils();
+        if lens.central_area == 0.0 {
+            return Err(String::from("no light gets through the lens to the centre of the film"));
+        }
+        Ok(lens)
+    }
+
+    pub fn load(filename: &str, sensor_width: f64, aspect_ratio: f64, focus: Option<f64>, aperture: Aperture)
+                -> Result<Lens, String> {
+        let text = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let elements = parse_prescription(&text).map_err(|e| format!("{}: {}", filename, e))?;
+        Lens::new(elements, sensor_width, aspect_ratio, focus, aperture)
+    }
+
+    // Puts the rear surface `film_distance` in front of the film.
+    fn place(&mut self, film_distance: f64) {
+        let mut z = film_distance;
+        self.vertices = vec![0.0; self.elements.len()];
+        for i in (0..self.elements.len()).rev() {
+            if i + 1 < self.elements.len() {
+                z += self.elements[i].thickness;
+            }
+            self.vertices[i] = z;
+        }
+    }
+
+    fn film_distance(&self) -> f64 {
+        self.vertices[self.vertices.len() - 1]
+    }
+
+    // How far behind the rear surface the lens brings light from a point on the axis,
+    // `distance` in front of the rear surface or at infinity, to a focus.
+    fn image_distance(&self, distance: Option<f64>) -> Option<f64> {
+        let rear = self.film_distance();
+        let height = 0.01 * self.elements.iter().map(|e| e.aperture).fold(f64::INFINITY, f64::min);
+        let front = Vector::new(height, 0.0, self.vertices[0]);
+        let (origin, direction) = match distance {
+            Some(d) if d > self.vertices[0] - rear => {
+                let object = Vector::new(0.0, 0.0, rear + d);
+                (object, front - object)
+            },
+            Some(_) => return None,
+            None => (front + Vector::new(0.0, 0.0, 1.0), Vector::new(0.0, 0.0, -1.0)),
+        };
+        let (p, d) = self.trace(origin, direction, false)?;
+        if p.x * d.x >= 0.0 {
+            return None;
+        }
+        let crossing = p.z - p.x * d.z / d.x;
+        (crossing < rear).then_some(rear - crossing)
+    }
+
+    // Follows a ray through the surfaces, towards the front or the back as it points,
+    // to where it leaves the lens, or None if it hits the housing or reflects inside.
+    // The stop takes the shape of the blades if `blades` is set.
+    fn trace(&self, mut origin: Vector, mut direction: Vector, blades: bool) -> Option<(Vector, Vector)> {
+        let forwards = direction.z > 0.0;
+        let n = self.elements.len();
+        for k in 0..n {
+            let i = if forwards { n - 1 - k } else { k };
+            let element = &self.elements[i];
+            let (t, normal) = intersect(element, self.vertices[i], origin, direction)?;
+            origin = origin + t * direction;
+            let aperture = if blades && self.stop == Some(i) { self.aperture } else { Aperture::CIRCLE };
+            if !aperture.contains(origin.x, origin.y, element.aperture / 2.0) {
+                return None;
+            }
+            let ahead = if i > 0 { self.elements[i - 1].ior() } else { 1.0 };
+            let (from, to) = if forwards { (element.ior(), ahead) } else { (ahead, element.ior()) };
+            direction = refract(-direction.normalize(), normal, from / to)?;
+        }
+        Some((origin, direction))
+    }
+
+    // Finds the bounds of the exit pupil for each band of film radii, by tracing from
+    // either edge of the band to a grid of points over the rear surface.
+    fn find_pupils(&mut self) {
+        let rear = &self.elements[self.elements.len() - 1];
+        let (z, half) = (self.film_distance(), rear.aperture / 2.0);
+        let cell = 2.0 * half / PUPIL_GRID as f64;
+        let grid: Vec<(f64, f64)> = (0..PUPIL_GRID * PUPIL_GRID)
+            .map(|i| (-half + ((i % PUPIL_GRID) as f64 + 0.5) * cell, -half + ((i / PUPIL_GRID) as f64 + 0.5) * cell))
+            .collect();
+        let passes = |r: f64, (x, y): (f64, f64)| {
+            let film = Vector::new(r, 0.0, 0.0);
+            self.trace(film, Vector::new(x, y, z) - film, false).is_some()
+        };
+        let central_area = grid.iter().filter(|&&p| passes(0.0, p)).count() as f64 * cell * cell;
+        let pupils = (0..PUPIL_BANDS)
+            .map(|band| {
+                let radii = [band, band + 1].map(|b| b as f64 / PUPIL_BANDS as f64 * self.film_radius);
+                grid.iter()
+                    .filter(|&&p| radii.iter().any(|&r| passes(r, p)))
+                    .map(|&(x, y)| [x - cell, y - cell, x + cell, y + cell])
+                    .reduce(|a, b| [a[0].min(b[0]), a[1].min(b[1]), a[2].max(b[2]), a[3].max(b[3])])
+            })
+            .collect();
+        self.central_area = central_area;
+        self.pupils = pupils;
+    }
+
+    // The ray into the scene from a point of the image, through a point of the exit
+    // pupil picked by `sample` from the unit square, and the light it brings relative
+    // to the centre of the image. None for rays that the lens stops.
+    pub fn get_ray(&self, camera: &Camera, u: f64, v: f64, sample: (f64, f64)) -> Option<(Ray, f64)> {
+        // The image is upside down on the film.
+        let film = Vector::new((0.5 - u) * self.sensor_width, (0.5 - v) * self.sensor_width / camera.aspect_ratio(), 0.0);
+        let r = film.x.hypot(film.y);
+        let band = ((r / self.film_radius * PUPIL_BANDS as f64) as usize).min(PUPIL_BANDS - 1);
+        let [x0, y0, x1, y1] = self.pupils[band]?;
+        let (x, y) = (x0 + sample.0 * (x1 - x0), y0 + sample.1 * (y1 - y0));
+        let (sin, cos) = film.y.atan2(film.x).sin_cos();
+        let pupil = Vector::new(cos * x - sin * y, sin * x + cos * y, self.film_distance());
+        let direction = (pupil - film).normalize();
+        let (origin, out) = self.trace(film, direction, true)?;
+        let weight = direction.z.powi(4) * (x1 - x0) * (y1 - y0) / self.central_area;
+
+        let (right, up, forward) = camera.basis();
+        let to_scene = |v: Vector| v.x * right + v.y * up + v.z * forward;
+        Some((Ray::new(camera.origin + to_scene(origin) / MILLIMETRES_PER_UNIT, to_scene(out)), weight))
+    }
+}
+
+// Where a ray meets a surface, and the surface normal facing back along the ray.
+fn intersect(element: &Element, vertex: f64, origin: Vector, direction: Vector) -> Option<(f64, Vector)> {
+    if element.radius == 0.0 {
+        let t = (vertex - origin.z) / direction.z;
+        return (t > 0.0).then(|| (t, Vector::new(0.0, 0.0, -direction.z.signum())));
+    }
+    let center = Vector::new(0.0, 0.0, vertex - element.radius);
+    let oc = origin - center;
+    let (a, b, c) = (direction * direction, oc * direction, oc * oc - element.radius * element.radius);
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    // Of the two crossings of the sphere, the one near the vertex is the surface.
+    let root = discriminant.sqrt();
+    let nearer = (direction.z > 0.0) != (element.radius > 0.0);
+    let t = if nearer { (-b - root) / a } else { (-b + root) / a };
+    if t <= 0.0 {
+        return None;
+    }
+    let normal = (origin + t * direction - center).normalize();
+    Some((t, if normal * direction > 0.0 { -normal } else { normal }))
+}
+
+// Refracts the direction `wi` arrives from through a surface with the normal `n` on
+// its side, for `eta` the ratio of the indices of refraction before and after.
+fn refract(wi: Vector, n: Vector, eta: f64) -> Option<Vector> {
+    let cos_i = wi * n;
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-eta * wi + (eta * cos_i - cos_t) * n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A plano-convex singlet of focal length 100 mm behind a stop.
+    const SINGLET: &str = "\
+# radius  thickness  ior  aperture
+0         2          0    20        # stop
+50        5          1.5  30
+0         0          0    30
+";
+
+    #[test]
+    fn test_parse_prescription() {
+        let elements = parse_prescription(SINGLET).expect("valid prescription rejected");
+        assert_eq!(elements.len(), 3);
+        assert_eq!(elements[1], Element { radius: 50.0, thickness: 5.0, ior: 1.5, aperture: 30.0 });
+        assert!(parse_prescription("50 5 1.5\n").is_err());
+        assert!(parse_prescription("50 5 glass 30\n").is_err());
+        assert!(parse_prescription("50 5 0.5 30\n").is_err());
+        assert!(parse_prescription("# nothing\n").is_err());
+
+        let lens = Lens::new(elements, 36.0, 1.5, None, "6".parse().unwrap()).unwrap();
+        assert_eq!(lens.stop, Some(0));
+        // Without a stop there is nothing for blades to shape.
+        let unstopped: String = SINGLET.lines().filter(|l| !l.ends_with("# stop")).map(|l| format!("{}\n", l)).collect();
+        let unstopped = parse_prescription(&unstopped).unwrap();
+        assert!(Lens::new(unstopped.clone(), 36.0, 1.5, None, "6".parse().unwrap()).is_err());
+        assert_eq!(Lens::new(unstopped, 36.0, 1.5, None, Aperture::CIRCLE).unwrap().stop, None);
+    }
+
+    #[test]
+    fn test_focus() {
+        let elements = parse_prescription(SINGLET).unwrap();
+        // The back focal length of a plano-convex lens is f - t / n.
+        let lens = Lens::new(elements.clone(), 36.0, 1.5, None, Aperture::CIRCLE).unwrap();
+        assert!((lens.film_distance() - (100.0 - 5.0 / 1.5)).abs() < 0.01);
+        // Closer subjects focus further back.
+        let near = Lens::new(elements, 36.0, 1.5, Some(1.0), Aperture::CIRCLE).unwrap();
+        assert!(near.film_distance() > lens.film_distance() + 5.0);
+    }
+
+    #[test]
+    fn test_get_ray() {
+        let lens = Lens::new(parse_prescription(SINGLET).unwrap(), 36.0, 1.5, None, Aperture::CIRCLE).unwrap();
+        let camera = Camera::new().with_aspect_ratio(1.5);
+
+        // Rays from the centre of the film leave in parallel along the view.
+        let mut rays = 0;
+        for sample in [(0.5, 0.5), (0.2, 0.7), (0.6, 0.4)] {
+            if let Some((ray, weight)) = lens.get_ray(&camera, 0.5, 0.5, sample) {
+                assert!((ray.direction.normalize() - Vector::new(0.0, 0.0, -1.0)).length() < 1e-3);
+                assert!(weight > 0.5 && weight < 2.0);
+                rays += 1;
+            }
+        }
+        assert!(rays > 0, "the lens stopped every ray from the centre");
+        // The image is the right way up, and corners get less light.
+        let light = |u: f64, v: f64| {
+            let samples = (0..256).map(|i| ((i % 16) as f64 / 16.0 + 0.03, (i / 16) as f64 / 16.0 + 0.03));
+            samples.filter_map(|s| lens.get_ray(&camera, u, v, s)).map(|(_, weight)| weight).sum::<f64>() / 256.0
+        };
+        assert!((light(0.5, 0.5) - 1.0).abs() < 0.1);
+        assert!(light(0.95, 0.95) < 0.96 * light(0.5, 0.5));
+        let (ray, _) = lens.get_ray(&camera, 0.95, 0.95, (0.5, 0.5)).expect("no light reaches the corner");
+        assert!(ray.direction.x > 0.0 && ray.direction.y > 0.0);
+    }
+
+    #[test]
+    fn test_aperture() {
+        let hexagon: Aperture = "6:0".parse().unwrap();
+        assert!(hexagon.contains(0.99, 0.0, 1.0));
+        assert!(!hexagon.contains(0.0, 0.95, 1.0));
+        assert!(Aperture::CIRCLE.contains(0.0, 0.95, 1.0));
+        assert_eq!("5".parse(), Ok(Aperture { blades: 5, rotation: 0.0 }));
+        assert!("2".parse::<Aperture>().is_err());
+        assert!("6:x".parse::<Aperture>().is_err());
+    }
+}
@@ -1,7 +1,36 @@
+pub mod animation;
+pub mod aov;
+pub mod bsdf;
+pub mod bvh;
 pub mod camera;
+pub mod checkpoint;
 pub mod color;
+pub mod colorspace;
 pub mod config;
+pub mod denoise;
+pub mod diff;
+pub mod distributed;
+pub mod exr;
+pub mod film;
+pub mod filter;
+pub mod gltf;
 pub mod image;
+pub mod lens;
+pub mod light;
+pub mod material;
+pub mod matrix;
+pub mod mesh;
+pub mod ply;
 pub mod ray;
+pub mod render;
+pub mod scene;
+pub mod service;
+pub mod sky;
 pub mod sphere;
+pub mod spectrum;
+pub mod stl;
+pub mod texture;
+pub mod toml;
+pub mod tonemap;
+pub mod transform;
 pub mod vec;
@@ -0,0 +1,158 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+use crate::bsdf::Frame;
+use crate::color::Color;
+use crate::vec::Vector;
+
+// Punctual lights following KHR_lights_punctual. Intensity is the light colour scaled by
+// its strength; directions point the way the light travels.
+#[derive(Debug, Clone, Copy)]
+pub enum Light {
+    Point {
+        position: Vector,
+        intensity: Color,
+        range: Option<f64>,
+    },
+    Spot {
+        position: Vector,
+        direction: Vector,
+        intensity: Color,
+        range: Option<f64>,
+        inner_cone_angle: f64,
+        outer_cone_angle: f64,
+    },
+    Directional {
+        direction: Vector,
+        intensity: Color,
+    },
+    // A directional light from a disc across `angular_diameter` radians, for soft shadows.
+    Sun {
+        direction: Vector,
+        intensity: Color,
+        angular_diameter: f64,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    // Unit vector from the shaded point towards the light.
+    pub direction: Vector,
+    pub distance: f64,
+    pub radiance: Color,
+}
+
+impl Light {
+    pub fn sample(&self, p: &Vector) -> Option<LightSample> {
+        match *self {
+            Light::Point { position, intensity, range } => {
+                let (direction, distance) = towards(p, &position)?;
+                let falloff = attenuation(distance, range);
+                Some(LightSample { direction, distance, radiance: falloff * intensity })
+            },
+            Light::Spot { position, direction: spot, intensity, range, inner_cone_angle, outer_cone_angle } => {
+                let (direction, distance) = towards(p, &position)?;
+                let cos_outer = outer_cone_angle.cos();
+                let scale = 1.0 / (inner_cone_angle.cos() - cos_outer).max(0.001);
+                let cd = spot.normalize() * -direction;
+                let cone = ((cd - cos_outer) * scale).clamp(0.0, 1.0);
+                let falloff = cone * cone * attenuation(distance, range);
+                if falloff <= 0.0 {
+                    return None;
+                }
+                Some(LightSample { direction, distance, radiance: falloff * intensity })
+            },
+            Light::Directional { direction, intensity } => {
+                Some(LightSample {
+                    direction: -direction.normalize(),
+                    distance: f64::INFINITY,
+                    radiance: intensity,
+                })
+            },
+            Light::Sun { direction, intensity, angular_diameter } => {
+                // Uniformly over the cone the disc fills.
+                let mut rng = rand::thread_rng();
+                let cos_theta = 1.0 - rng.gen::<f64>() * (1.0 - (0.5 * angular_diameter).cos());
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * rng.gen::<f64>();
+                let local = Vector::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                Some(LightSample {
+                    direction: Frame::from_normal(&-direction.normalize()).to_world(&local),
+                    distance: f64::INFINITY,
+                    radiance: intensity,
+                })
+            },
+        }
+    }
+}
+
+fn towards(p: &Vector, position: &Vector) -> Option<(Vector, f64)> {
+    let to_light = *position - *p;
+    let distance = to_light.length();
+    if distance <= 0.0 {
+        return None;
+    }
+    Some((to_light / distance, distance))
+}
+
+// Inverse-square falloff with the smooth windowing recommended by KHR_lights_punctual.
+fn attenuation(distance: f64, range: Option<f64>) -> f64 {
+    let window = match range {
+        Some(r) if r > 0.0 => (1.0 - (distance / r).powi(4)).clamp(0.0, 1.0),
+        _ => 1.0,
+    };
+    window / (distance * distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_point_light() {
+        let light = Light::Point { position: Vector::new(0.0, 2.0, 0.0), intensity: Color::WHITE, range: None };
+        let s = light.sample(&Vector::ORIGIN).expect("no light sample");
+        assert_eq!(s.direction, Vector::new(0.0, 1.0, 0.0));
+        assert_eq!(s.distance, 2.0);
+        assert_eq!(s.radiance, Color::new(0.25, 0.25, 0.25));
+    }
+
+    #[test]
+    fn test_spot_light_cone() {
+        let light = Light::Spot {
+            position: Vector::new(0.0, 1.0, 0.0),
+            direction: Vector::new(0.0, -1.0, 0.0),
+            intensity: Color::WHITE,
+            range: None,
+            inner_cone_angle: 0.1,
+            outer_cone_angle: 0.2,
+        };
+        let s = light.sample(&Vector::ORIGIN).expect("point inside cone not lit");
+        assert_eq!(s.radiance, Color::WHITE);
+        assert!(light.sample(&Vector::new(1.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn test_directional_light() {
+        let light = Light::Directional { direction: Vector::new(0.0, -2.0, 0.0), intensity: Color::WHITE };
+        let s = light.sample(&Vector::ORIGIN).expect("no light sample");
+        assert_eq!(s.direction, Vector::new(0.0, 1.0, 0.0));
+        assert_eq!(s.distance, f64::INFINITY);
+    }
+
+    #[test]
+    fn test_sun_light_disc() {
+        let diameter = 0.1;
+        let light = Light::Sun {
+            direction: Vector::new(0.0, -1.0, 0.0),
+            intensity: Color::WHITE,
+            angular_diameter: diameter,
+        };
+        for _ in 0..100 {
+            let s = light.sample(&Vector::ORIGIN).expect("no light sample");
+            assert!((s.direction.length() - 1.0).abs() < 1e-9);
+            assert!(s.direction.y >= (0.5 * diameter).cos() - 1e-12, "{:?}", s.direction);
+        }
+    }
+}
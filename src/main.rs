@@ -1,174 +1,556 @@
-use raytracer::config::Config;
+use raytracer::config::{self, Command, Config, DiffOptions};
 use std::env;
+use std::path::Path;
+use std::net::TcpListener;
 use std::process;
 use std::sync::Arc;
-use std::sync::mpsc::channel;
-use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
 use std::time::Instant;
-use threadpool::ThreadPool;
 
-use raytracer::camera::Camera;
+use raytracer::animation::{frame_filename, Animation};
+use raytracer::aov::{Aov, AovBuffer};
+use raytracer::camera::{Camera, Eye, Projection};
+use raytracer::checkpoint::{self, Checkpoint};
 use raytracer::color::Color;
+use raytracer::colorspace::{white_balance, Encoding, WORKING_SPACE};
+use raytracer::denoise::Denoiser;
+use raytracer::diff;
+use raytracer::distributed::{self, Job, Tile};
+use raytracer::exr::{self, Channel};
 use raytracer::image::ImagePpm;
-use raytracer::ray::{Hittable, Ray, HitRecord};
-use raytracer::sphere::Sphere;
-use raytracer::vec::Vector;
+use raytracer::lens::Lens;
+use raytracer::matrix::Matrix3;
+use raytracer::ray::Hittable;
+use raytracer::render::{self, Frame, Observer, Progress, RenderError, Settings};
+use raytracer::scene::Scene;
+use raytracer::service::{self, Limits};
+use raytracer::tonemap::{exposure_scale, CameraExposure, ToneMap};
 
-fn ray_color(r: Ray, world: &[Sphere], depth: u16) -> Color {
-    if depth == 0 {
-        return Color::BLACK;
+fn listen(address: &str) -> TcpListener {
+    match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Error listening on {}: {}", address, e);
+            process::exit(1);
+        },
     }
+}
 
-    if let Some(hit) = hit_world(world, &r, 0.001, f64::INFINITY) {
-        let target = hit.p + Vector::random_in_hemisphere(&hit.n);
-        // let target = hit.p + hit.n + Vector::random_unit_vector();
-        return 0.5 * ray_color(Ray::new(hit.p, target - hit.p), world, depth - 1)
+fn run_worker(address: &str) {
+    let listener = listen(address);
+    eprintln!("Waiting for work on {}", address);
+    if let Err(e) = distributed::serve(listener) {
+        eprintln!("Error accepting connections: {}", e);
+        process::exit(1);
     }
+}
 
-    let unit_dir = r.direction.normalize();
-    let t = 0.5 * (unit_dir.y + 1.0);
-    Color::lerp(Color::WHITE, Color::BACKGROUND, t)
+fn run_service(address: &str, limits: Limits) {
+    let listener = listen(address);
+    eprintln!("Serving render jobs on http://{}", address);
+    if let Err(e) = service::serve(listener, limits) {
+        eprintln!("Error accepting connections: {}", e);
+        process::exit(1);
+    }
 }
 
-fn hit_world(world: &[Sphere], r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-    let mut closest = t_max;
-    let mut hit_record = None;
-    for sphere in world {
-        if let Some(hit) = sphere.hit(r, t_min, closest) {
-            closest = hit.t;
-            hit_record = Some(hit);
+// Writes the beauty image and any passes: as layers of one scene-linear file for
+// OpenEXR output, and otherwise as display images named after the output file.
+fn write_output(filename: &str, pixels: &[Color], aovs: &[Aov], buffer: &AovBuffer,
+                display: &Display) {
+    let start = Instant::now();
+    eprint!("Writing image to {}...", filename);
+    let result = if has_extension(filename, "exr") {
+        let linear: Vec<Color> = pixels.iter().map(|c| display.linear(*c)).collect();
+        let mut channels = vec![
+            Channel::new("R", linear.iter().map(|c| c.r() as f32).collect()),
+            Channel::new("G", linear.iter().map(|c| c.g() as f32).collect()),
+            Channel::new("B", linear.iter().map(|c| c.b() as f32).collect()),
+        ];
+        for aov in aovs {
+            let mut layer = buffer.channels(*aov);
+            if aov.is_lighting() {
+                if let [r, g, b] = &mut layer[..] {
+                    for i in 0..r.data.len() {
+                        let c = display.expose(Color::new(r.data[i] as f64, g.data[i] as f64, b.data[i] as f64));
+                        (r.data[i], g.data[i], b.data[i]) = (c.r() as f32, c.g() as f32, c.b() as f32);
+                    }
+                }
+            }
+            channels.extend(layer);
+        }
+        exr::write(filename, buffer.width, buffer.height, display.encoding.space, &channels)
+    } else {
+        let mut img = ImagePpm::new(buffer.width, buffer.height);
+        for y in 0..buffer.height {
+            for x in 0..buffer.width {
+                img.set_pixel(x, y, display.encode(pixels[(y * buffer.width + x) as usize]));
+            }
         }
+        img.save(filename, &display.encoding).and_then(|_| {
+            aovs.iter().try_for_each(|aov| {
+                let (image, encoding) = if aov.is_lighting() {
+                    (buffer.image(*aov, |c| display.encode(c)), display.encoding)
+                } else {
+                    (buffer.image(*aov, |c| c), Encoding::SRGB)
+                };
+                image.save(&aov_filename(filename, *aov), &encoding)
+            })
+        })
+    };
+    match result {
+        Ok(_) => eprintln!("done in {} ms.", start.elapsed().as_millis()),
+        Err(e) => eprintln!("error writing image: {}", e),
+    };
+}
+
+// How radiance becomes output values.
+struct Display {
+    exposure: f64,
+    white_balance: Matrix3,
+    tone_map: ToneMap,
+    encoding: Encoding,
+}
+
+impl Display {
+    // Radiance as the camera records it, before tone mapping.
+    fn expose(&self, c: Color) -> Color {
+        self.white_balance.transform_color(self.exposure * c)
+    }
+
+    fn encode(&self, c: Color) -> Color {
+        self.encoding.encode(self.tone_map.apply(self.expose(c)))
+    }
+
+    fn linear(&self, c: Color) -> Color {
+        WORKING_SPACE.convert(self.expose(c), self.encoding.space)
     }
-    hit_record
 }
 
-fn show_help() {
-    let help_text = "\
-Render a scene with the raytracer.
+fn has_extension(filename: &str, extension: &str) -> bool {
+    Path::new(filename).extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
 
-USAGE:
- \traytracer [OPTIONS] OUTPUT_FILE
+// `out.png` becomes `out.depth.png`.
+fn aov_filename(filename: &str, aov: Aov) -> String {
+    let path = Path::new(filename);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(filename);
+    let name = match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => format!("{}.{}.{}", stem, aov, extension),
+        None => format!("{}.{}", stem, aov),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
 
-OPTIONS:
+// Prints progress and saves checkpoints as the render goes.
+struct Console<'a> {
+    cfg: &'a Config,
+    fingerprint: u64,
+    seed: u64,
+    last_checkpoint: Instant,
+}
 
--w <WIDTH>     Pixel width of the image
--h <HEIGHT>    Pixel height of the image
--s <SAMPLES>   Number of antialiasing samples per pixel
--m <MAXDEPTH>  Maximum depth for reflections
--h             Prints help information
+impl Observer for Console<'_> {
+    fn tile_done(&mut self, progress: &Progress, _: &Frame) {
+        show_progress(progress.tiles_done, progress.tiles_total);
+    }
 
-If only one of the width or height is specified, the default aspect ration of
-16:9 is used.";
+    fn pass_done(&mut self, frame: &Frame) {
+        if let Some(filename) = &self.cfg.checkpoint {
+            let last = frame.samples >= self.cfg.samples as u32;
+            if last || self.last_checkpoint.elapsed().as_secs() >= self.cfg.checkpoint_interval {
+                if let Err(e) = checkpoint::save(filename, self.fingerprint, self.seed, frame) {
+                    eprintln!("error writing checkpoint {}: {}", filename, e);
+                }
+                self.last_checkpoint = Instant::now();
+            }
+        }
+    }
+}
+
+// Fifty dots for the whole render.
+fn show_progress(done: usize, total: usize) {
+    eprint!("{}", ".".repeat(done * 50 / total - (done - 1) * 50 / total));
+}
+
+// Loads the scene named in the config, or the built-in one.
+fn load_scene(filename: Option<&str>) -> Scene {
+    match filename {
+        Some(filename) => match Scene::load(filename) {
+            Ok(scene) => {
+                for warning in &scene.warnings {
+                    eprintln!("Warning: {} in {}", warning, filename);
+                }
+                scene
+            },
+            Err(e) => {
+                eprintln!("Error loading scene {}: {}", filename, e);
+                process::exit(1);
+            },
+        },
+        None => Scene::default(),
+    }
+}
+
+fn run_info(filename: &str) {
+    let scene = load_scene(Some(filename));
+    println!("Scene:      {}", filename);
+    println!("Objects:    {}", scene.objects().len());
+    println!("Materials:  {}", scene.materials.len());
+    println!("Lights:     {}", scene.lights.len());
+    println!("Camera:     {}", if scene.camera.is_some() { "yes" } else { "no, the default is used" });
+    if !scene.objects().is_empty() {
+        let bounds = scene.bounding_box();
+        println!("Bounds:     ({:.3}, {:.3}, {:.3}) to ({:.3}, {:.3}, {:.3})",
+                 bounds.min.x, bounds.min.y, bounds.min.z, bounds.max.x, bounds.max.y, bounds.max.z);
+    }
+    if let Some(ev) = scene.exposure {
+        println!("Exposure:   {} EV", ev);
+    }
+    if let Some(tone_map) = scene.tone_map {
+        println!("Tone map:   {}", tone_map);
+    }
+}
+
+// The scene camera fitted to the image, with any projection asked for. Orthographic
+// views keep whatever is in the middle at the size it was in perspective.
+fn fit_camera(cfg: &Config, scene: &Scene) -> Camera {
+    let camera = scene.camera.unwrap_or_default().with_aspect_ratio(cfg.aspect_ratio().unwrap_or(16.0 / 9.0));
+    match cfg.projection {
+        Some(Projection::Orthographic) if camera.projection != Projection::Orthographic => {
+            // The centre ray is one unit long, so it hits at that distance.
+            let distance = scene.hit(&camera.get_ray(0.5, 0.5), 0.001, f64::INFINITY).map_or(1.0, |hit| hit.t);
+            camera.with_projection(Projection::Orthographic).with_view_height(camera.vertical.length() * distance)
+        },
+        Some(projection) => camera.with_projection(projection),
+        None => camera,
+    }
+}
 
-    eprintln!("{}", help_text);
+// The lens named in the config, set up for the image.
+fn load_lens(cfg: &Config) -> Option<Arc<Lens>> {
+    let filename = cfg.lens.as_deref()?;
+    let aspect_ratio = cfg.aspect_ratio().unwrap_or(16.0 / 9.0);
+    match Lens::load(filename, cfg.sensor_width, aspect_ratio, cfg.focus, cfg.aperture) {
+        Ok(lens) => Some(Arc::new(lens)),
+        Err(e) => {
+            eprintln!("Error loading lens {}: {}", filename, e);
+            process::exit(1);
+        },
+    }
+}
+
+// The frame of a finished render. Nothing cancels renders from the command line, and
+// settings the config let through but the renderer rejects end the program.
+fn finish(result: Result<Frame, RenderError>) -> Frame {
+    match result {
+        Ok(frame) | Err(RenderError::Cancelled(frame)) => frame,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        },
+    }
+}
+
+fn run_bench(cfg: &Config, runs: u32) {
+    let mut scene = load_scene(cfg.scene.as_deref());
+    if let Some(sky) = cfg.sky() {
+        scene.set_sky(sky);
+    }
+    let camera = fit_camera(cfg, &scene);
+    let world = Arc::new(scene);
+    let settings = Settings {
+        width: cfg.width,
+        height: cfg.height,
+        samples: cfg.samples as u32,
+        max_depth: cfg.max_depth,
+        filter: cfg.filter,
+        spectral: cfg.spectral,
+        camera: Some(camera),
+        lens: load_lens(cfg),
+        seed: 0,
+        threads: cfg.threads.unwrap_or_else(num_cpus::get),
+        ..Settings::default()
+    };
+
+    println!("Rendering {} x {} with {} samples on {} threads", cfg.width, cfg.height, cfg.samples, settings.threads);
+    let total = (cfg.width * cfg.height) as f64 * cfg.samples as f64;
+    let mut times = Vec::new();
+    for run in 1..=runs {
+        let start = Instant::now();
+        finish(render::render(&world, &settings, &mut ()));
+        let seconds = start.elapsed().as_secs_f64();
+        println!("Run {}: {:.3} s, {:.2} Msamples/s", run, seconds, total / seconds / 1e6);
+        times.push(seconds);
+    }
+    let best = times.iter().cloned().fold(f64::INFINITY, f64::min);
+    let mean = times.iter().sum::<f64>() / times.len() as f64;
+    println!("Best {:.3} s, mean {:.3} s, {:.2} Msamples/s at best", best, mean, total / best / 1e6);
+}
+
+fn run_diff(options: &DiffOptions) {
+    let read = |filename: &str| match ImagePpm::read(filename) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", filename, e);
+            process::exit(2);
+        },
+    };
+    let (a, b) = (read(&options.a), read(&options.b));
+    let difference = match diff::compare(&a, &b, options.threshold) {
+        Ok(difference) => difference,
+        Err(e) => {
+            eprintln!("Error comparing images: {}", e);
+            process::exit(2);
+        },
+    };
+
+    println!("RMSE:       {:.6}", difference.rmse);
+    println!("PSNR:       {:.2} dB", difference.psnr);
+    println!("Largest:    {:.6}", difference.max);
+    println!("Differing:  {} of {} pixels", difference.differing, a.width * a.height);
+    if let Some(filename) = &options.output {
+        if let Err(e) = difference.image.save(filename, &Encoding::SRGB) {
+            eprintln!("Error writing {}: {}", filename, e);
+            process::exit(2);
+        }
+    }
+    if difference.differing > 0 {
+        process::exit(1);
+    }
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let cfg = match Config::parse_args(&args) {
-        Ok(c) => c,
+    let cfg = match Command::parse(&args) {
+        Ok(Command::Render(cfg)) => cfg,
+        Ok(Command::Info { scene }) => return run_info(&scene),
+        Ok(Command::Bench { config, runs }) => return run_bench(&config, runs),
+        Ok(Command::Diff(options)) => return run_diff(&options),
+        Ok(Command::Worker { listen }) => return run_worker(&listen),
+        Ok(Command::Serve { listen, limits }) => return run_service(&listen, limits),
+        Ok(Command::Help(command)) => return print!("{}", config::help(command)),
+        Ok(Command::Version) => return println!("raytracer {}", env!("CARGO_PKG_VERSION")),
+        Ok(Command::PrintConfig(text)) => return print!("{}", text),
         Err(e) => {
-            eprintln!("Error parsing arguments: {}", e);
-            Config::need_help()
+            eprintln!("Error: {}", e);
+            eprintln!("Run `raytracer --help` for usage.");
+            process::exit(2);
         },
     };
 
-    if cfg.help {
-        show_help();
-        process::exit(1);
+    // Find out about outputs that cannot be written before rendering them.
+    for filename in [Some(&cfg.output), cfg.raw_output.as_ref()].into_iter().flatten() {
+        if !has_extension(filename, "exr") {
+            if let Err(e) = ImagePpm::check_save(filename, &cfg.encoding) {
+                eprintln!("Error: {}", e);
+                process::exit(2);
+            }
+        }
     }
 
-    // Random number generator
-    let dist = Uniform::new(-0.5, 0.5);
+    let animation = cfg.animation.as_deref().map(|filename| match Animation::load(filename) {
+        Ok(animation) => animation,
+        Err(e) => {
+            eprintln!("Error loading animation {}: {}", filename, e);
+            process::exit(1);
+        },
+    });
+    let frames = cfg.frames.or_else(|| animation.as_ref().and_then(Animation::frames));
+    let Some((first, last)) = frames else {
+        let scene = load_scene(cfg.scene.as_deref());
+        return render_image(&cfg, scene, &cfg.output, cfg.raw_output.as_deref());
+    };
+
+    // Each frame is its own render, from a freshly loaded scene posed for that frame.
+    for f in first..=last {
+        let output = frame_filename(&cfg.output, f);
+        if !cfg.overwrite && Path::new(&output).exists() {
+            eprintln!("Skipping frame {}, {} already exists", f, output);
+            continue;
+        }
+        let mut scene = load_scene(cfg.scene.as_deref());
+        if let Some(animation) = &animation {
+            scene = match animation.apply(scene, f as f64) {
+                Ok(scene) => scene,
+                Err(e) => {
+                    eprintln!("Error animating frame {}: {}", f, e);
+                    process::exit(1);
+                },
+            };
+        }
+        eprintln!("Frame {} of {}-{}", f, first, last);
+        let raw = cfg.raw_output.as_deref().map(|raw| frame_filename(raw, f));
+        render_image(&cfg, scene, &output, raw.as_deref());
+    }
+}
+
+// Renders one image of the scene and writes it to `output`, and the unprocessed
+// image to `raw_output` if given.
+fn render_image(cfg: &Config, mut scene: Scene, output: &str, raw_output: Option<&str>) {
+    if let Some(sky) = cfg.sky() {
+        scene.set_sky(sky);
+    }
 
-    // World
-    let world  = Arc::new(vec![
-        Sphere::new(Vector::new(0.0, 0.0, -1.0), 0.5),
-        Sphere::new(Vector::new(0.0, -100.5, -1.0), 100.0),
-    ]);
+    // Display. Daylight is in real-world units, which want a physical camera.
+    let camera_exposure = cfg.camera_exposure().or(scene.sky.map(|_| CameraExposure::DEFAULT));
+    let display = Display {
+        exposure: exposure_scale(cfg.exposure.or(scene.exposure).unwrap_or(0.0))
+            * camera_exposure.map_or(1.0, |camera| camera.scale()),
+        white_balance: cfg.white_balance.map_or(Matrix3::IDENTITY, white_balance),
+        tone_map: cfg.tone_map.or(scene.tone_map).unwrap_or_default(),
+        encoding: cfg.encoding,
+    };
 
     // Camera
-    let camera = Camera::new();
+    let camera = fit_camera(cfg, &scene);
+    let world = Arc::new(scene);
 
-    // Render
-    let pool = ThreadPool::new(num_cpus::get());
-    let (tx, rx) = channel();
+    // Render in passes over the whole image when checkpointing, so that there is
+    // something to save along the way.
+    let mut settings = Settings {
+        width: cfg.width,
+        height: cfg.height,
+        samples: cfg.samples as u32,
+        max_depth: cfg.max_depth,
+        filter: cfg.filter,
+        spectral: cfg.spectral,
+        record_paths: cfg.denoise || !cfg.aovs.is_empty(),
+        camera: Some(camera),
+        lens: load_lens(cfg),
+        seed: rand::thread_rng().gen(),
+        passes: if cfg.checkpoint.is_some() { 16 } else { 1 },
+        region: cfg.region(),
+        threads: cfg.threads.unwrap_or_else(num_cpus::get),
+        ..Settings::default()
+    };
 
-    eprint!("Rendering {} x {}", cfg.width, cfg.height);
-    let start = Instant::now();
-    let mut img = ImagePpm::new(cfg.width, cfg.height);
-
-    for y in 0..cfg.height {
-        let tx = tx.clone();
-        let w = Arc::clone(&world);
-        pool.execute(move || {
-            let mut rng = rand::thread_rng();
-            for x in 0..cfg.width {
-                let mut c = Color::BLACK;
-                for _ in 0..cfg.samples {
-                    let u = ((x as f64) + dist.sample(&mut rng)) / (cfg.width - 1) as f64;
-                    let v = ((y as f64) + dist.sample(&mut rng)) / (cfg.height - 1) as f64;
-                    let r = camera.get_ray(u, v);
-
-                    c += ray_color(r, &w, cfg.max_depth);
-                }
-                c /= cfg.samples as f64;
-                tx.send((x, y, c.gamma_correct())).expect("Could not set pixel data");
-            }
-        });
+    // Everything that decides the value of a sample, for matching up checkpoints. The
+    // display settings only apply afterwards, so they may change between runs.
+    let scene_bytes = cfg.scene.as_ref().map_or(Ok(Vec::new()), std::fs::read).unwrap_or_default();
+    let sky = cfg.sun.map(|sun| (sun, cfg.turbidity, cfg.ground_albedo, cfg.sun_diameter));
+    let description = format!("{}x{} depth {} filter {} spectral {} paths {} region {:?} projection {} lens {:?} \
+                               sky {:?}",
+                              cfg.width, cfg.height, cfg.max_depth, cfg.filter, cfg.spectral, settings.record_paths,
+                              settings.region, camera.projection, settings.lens, sky);
+    let fingerprint = checkpoint::fingerprint(&[&scene_bytes, description.as_bytes()]);
+
+    let mut frame = match (&cfg.checkpoint, cfg.resume) {
+        (Some(filename), true) => match Checkpoint::load(filename, cfg.filter) {
+            Ok(c) if c.fingerprint != fingerprint => {
+                eprintln!("Checkpoint {} is from a different scene or settings", filename);
+                process::exit(1);
+            },
+            Ok(c) => {
+                eprintln!("Resuming from {} with {} of {} samples", filename, c.frame.samples, cfg.samples);
+                settings.seed = c.seed;
+                c.frame
+            },
+            Err(e) => {
+                eprintln!("Error loading checkpoint {}: {}", filename, e);
+                process::exit(1);
+            },
+        },
+        _ => Frame::new(&settings),
+    };
+
+    match (settings.region, cfg.stereo) {
+        (Some(r), _) => eprint!("Rendering {} x {} of {} x {}", r.width(), r.height(), cfg.width, cfg.height),
+        (None, Some(_)) => eprint!("Rendering {} x {} for each eye", cfg.width, cfg.height),
+        (None, None) => eprint!("Rendering {} x {}", cfg.width, cfg.height),
     }
-    drop(tx);
+    let start = Instant::now();
 
-    let progress_period = cfg.width * cfg.height / 50;
-    let mut num_pixels = 0;
-    for (x, y, pixel) in rx.iter() {
-        img.set_pixel(x, y, pixel);
-        num_pixels += 1;
-        if num_pixels % progress_period == 0 {
-            eprint!(".");
+    // Workers take every sample between them, leaving none to render here.
+    if !cfg.workers.is_empty() {
+        let job = Job {
+            scene_name: cfg.scene.as_deref()
+                .and_then(|f| Path::new(f).file_name()?.to_str())
+                .unwrap_or_default()
+                .to_string(),
+            scene: scene_bytes,
+            width: settings.width,
+            height: settings.height,
+            samples: settings.samples,
+            max_depth: settings.max_depth,
+            filter: settings.filter,
+            spectral: settings.spectral,
+            seed: settings.seed,
+        };
+        let tiles = Tile::split(cfg.height, 16);
+        let total = tiles.len();
+        let mut done = 0;
+        let result = distributed::coordinate(&cfg.workers, &job, tiles, &mut frame.film, |_| {
+            done += 1;
+            show_progress(done, total);
+        });
+        if let Err(e) = result {
+            eprintln!("\nError rendering on workers: {}", e);
+            process::exit(1);
         }
+        frame.samples = job.samples;
     }
 
+    let mut console = Console { cfg, fingerprint, seed: settings.seed, last_checkpoint: Instant::now() };
+    // Nothing cancels the render. Each eye of a stereo pair is a render of its own, and is
+    // denoised on its own so that the filter does not blend the eyes across the seam.
+    let mut eyes: Vec<(Vec<Color>, AovBuffer)> = match cfg.stereo {
+        Some(_) => [Eye::Left, Eye::Right].into_iter().map(|eye| {
+            let camera = camera.eye(eye, cfg.ipd, cfg.convergence);
+            let settings = Settings { camera: Some(camera), ..settings.clone() };
+            let frame = finish(render::render(&world, &settings, &mut console));
+            (frame.film.pixels(), frame.aovs.unwrap_or_else(|| AovBuffer::new(cfg.width, cfg.height)))
+        }).collect(),
+        None => {
+            let frame = finish(render::resume(&world, &settings, frame, &mut console));
+            vec![(frame.film.pixels(), frame.aovs.unwrap_or_else(|| AovBuffer::new(cfg.width, cfg.height)))]
+        },
+    };
     eprintln!("rendering done in {} ms.", start.elapsed().as_millis());
 
-    let start = Instant::now();
-    eprint!("Writing image to {}...", cfg.output);
-    match img.write(&cfg.output) {
-        Ok(_) => eprintln!("done in {} ms.", start.elapsed().as_millis()),
-        Err(e) => eprintln!("error writing image: {}", e),
+    // Samples around a crop leave some light outside it, which is cleared from a
+    // whole image. Passes only have pixels inside.
+    for (pixels, buffer) in &mut eyes {
+        match settings.region {
+            Some(region) if cfg.full_frame => {
+                for (i, c) in pixels.iter_mut().enumerate() {
+                    if !region.contains(i as u32 % cfg.width, i as u32 / cfg.width) {
+                        *c = Color::BLACK;
+                    }
+                }
+            },
+            Some(region) => {
+                *pixels = region.crop(pixels, cfg.width);
+                *buffer = buffer.crop(&region);
+            },
+            None => (),
+        }
+    }
+
+    let combine = |eyes: &[(Vec<Color>, AovBuffer)]| match (cfg.stereo, eyes) {
+        (Some(layout), [left, right]) => layout.combine(&left.0, &right.0, left.1.width),
+        _ => eyes[0].0.clone(),
+    };
+    let buffer = match (cfg.stereo, &eyes[..]) {
+        (Some(layout), [left, right]) => AovBuffer::stereo(&left.1, &right.1, layout),
+        _ => eyes[0].1.clone(),
     };
-}
 
-#[cfg(test)]
-mod tests {
-    use raytracer::camera::Camera;
-    use super::*;
+    if let Some(raw) = raw_output {
+        write_output(raw, &combine(&eyes), &[], &buffer, &display);
+    }
 
-    #[test]
-    fn test_hit_world() {
-        let camera = Camera::new();
-        let world = vec![
-            Sphere::new(Vector::new(0.0, 0.0, -1.0), 0.5),
-            Sphere::new(Vector::new(0.0, 0.0, -2.0), 0.5),
-        ];
-        let expected = HitRecord {
-            p: Vector::new(0.0, 0.0, -0.5),
-            n: Vector::new(0.0, 0.0, 1.0),
-            t: 0.5,
-            front_face: true,
-        };
-        let ray = camera.get_ray(0.5, 0.5);
-        let hit_record = hit_world(&world, &ray, 0.0, f64::INFINITY).expect("no hit record returned");
-        assert!(hit_record.p == expected.p);
-        assert!(hit_record.n == expected.n);
-        assert!(hit_record.t == 0.5);
-        assert!(hit_record.front_face);
-
-        let ray = camera.get_ray(0.0, 0.0);
-        let hit_record = hit_world(&world, &camera.get_ray(0.0, 0.0), 0.0, f64::INFINITY);
-        match hit_record {
-            Some(_hit) => panic!("ray {:?} should not have hit", ray),
-            None => (),
+    if cfg.denoise {
+        let start = Instant::now();
+        eprint!("Denoising...");
+        for (pixels, buffer) in &mut eyes {
+            *pixels = Denoiser::default().denoise(pixels, &buffer.features(), buffer.width as usize,
+                                                  buffer.height as usize);
         }
+        eprintln!("done in {} ms.", start.elapsed().as_millis());
     }
+
+    write_output(output, &combine(&eyes), &cfg.aovs, &buffer, &display);
 }
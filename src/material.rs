@@ -0,0 +1,344 @@
+use std::sync::Arc;
+use rand::Rng;
+
+use crate::bsdf::{roughness_to_alpha, Bsdf, Frame};
+use crate::color::Color;
+use crate::ray::HitRecord;
+use crate::spectrum::{Dispersion, Wavelengths};
+use crate::texture::Texture;
+use crate::vec::Vector;
+
+// How the alpha of a material affects visibility, following glTF: masked surfaces are
+// cut out where alpha falls below the cutoff, blended ones let a ray pass straight
+// through with probability 1 - alpha.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    Mask(f64),
+    Blend,
+}
+
+// Principled metallic-roughness material. The core parameters match glTF 2.0, with
+// Disney-style extras on top: `specular` scales the dielectric reflectance (0.5 gives
+// the usual 4%), `sheen` adds a grazing-angle rim tinted towards the base colour by
+// `sheen_tint`, and `transmission` blends the diffuse base into refraction with `ior`.
+// Texture channels follow the glTF layout: roughness in green and metalness in blue
+// of the metallic-roughness map.
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: String,
+    pub base_color: Color,
+    pub alpha: f64,
+    pub alpha_mode: AlphaMode,
+    pub base_color_texture: Option<Arc<Texture>>,
+    pub metallic: f64,
+    pub roughness: f64,
+    pub metallic_roughness_texture: Option<Arc<Texture>>,
+    pub normal_texture: Option<Arc<Texture>>,
+    pub normal_scale: f64,
+    pub bump_texture: Option<Arc<Texture>>,
+    pub bump_scale: f64,
+    pub specular: f64,
+    pub sheen: f64,
+    pub sheen_tint: f64,
+    pub clearcoat: f64,
+    pub clearcoat_roughness: f64,
+    pub transmission: f64,
+    pub ior: f64,
+    pub dispersion: Option<Dispersion>,
+    pub emissive: Color,
+    pub emissive_texture: Option<Arc<Texture>>,
+    pub double_sided: bool,
+}
+
+impl Material {
+    pub fn diffuse(base_color: Color) -> Material {
+        Material {
+            name: String::new(),
+            base_color,
+            alpha: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            base_color_texture: None,
+            metallic: 0.0,
+            roughness: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            bump_texture: None,
+            bump_scale: 1.0,
+            specular: 0.5,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            transmission: 0.0,
+            ior: 1.5,
+            dispersion: None,
+            emissive: Color::BLACK,
+            emissive_texture: None,
+            double_sided: false,
+        }
+    }
+
+    pub fn metal(base_color: Color, roughness: f64) -> Material {
+        Material {
+            metallic: 1.0,
+            roughness,
+            ..Material::diffuse(base_color)
+        }
+    }
+
+    pub fn glass(ior: f64, roughness: f64) -> Material {
+        Material {
+            roughness,
+            transmission: 1.0,
+            ior,
+            ..Material::diffuse(Color::WHITE)
+        }
+    }
+
+    pub fn base_color_at(&self, hit: &HitRecord) -> Color {
+        let mut c = self.base_color;
+        if let Some(tex) = &self.base_color_texture {
+            c = c * tex.color(hit.u, hit.v);
+        }
+        if let Some(vertex_color) = hit.color {
+            c = c * vertex_color;
+        }
+        c
+    }
+
+    pub fn alpha_at(&self, hit: &HitRecord) -> f64 {
+        match &self.base_color_texture {
+            Some(tex) => self.alpha * tex.alpha(hit.u, hit.v),
+            None => self.alpha,
+        }
+    }
+
+    // Whether rays pass through the surface at this hit instead of interacting with it.
+    pub fn is_cut_out(&self, hit: &HitRecord) -> bool {
+        match self.alpha_mode {
+            AlphaMode::Opaque => false,
+            AlphaMode::Mask(cutoff) => self.alpha_at(hit) < cutoff,
+            AlphaMode::Blend => {
+                let alpha = self.alpha_at(hit);
+                alpha < 1.0 && rand::thread_rng().gen::<f64>() >= alpha
+            },
+        }
+    }
+
+    pub fn metallic_roughness_at(&self, hit: &HitRecord) -> (f64, f64) {
+        match &self.metallic_roughness_texture {
+            Some(tex) => {
+                let [_, g, b, _] = tex.sample(hit.u, hit.v);
+                (self.metallic * b as f64, self.roughness * g as f64)
+            },
+            None => (self.metallic, self.roughness),
+        }
+    }
+
+    pub fn emitted(&self, hit: &HitRecord) -> Color {
+        match &self.emissive_texture {
+            Some(tex) => self.emissive * tex.color(hit.u, hit.v),
+            None => self.emissive,
+        }
+    }
+
+    // Shading normal perturbed by the tangent-space normal map and the greyscale bump
+    // map. Bump heights are in units of the texel spacing, scaled by `bump_scale`.
+    pub fn shading_normal(&self, hit: &HitRecord) -> Vector {
+        if self.normal_texture.is_none() && self.bump_texture.is_none() {
+            return hit.n;
+        }
+
+        // Surface slopes along the tangent and bitangent.
+        let (mut dx, mut dy) = (0.0, 0.0);
+        if let Some(tex) = &self.normal_texture {
+            let [r, g, b, _] = tex.sample(hit.u, hit.v);
+            let z = (2.0 * b as f64 - 1.0).max(1e-3);
+            dx = self.normal_scale * (2.0 * r as f64 - 1.0) / z;
+            dy = self.normal_scale * (2.0 * g as f64 - 1.0) / z;
+        }
+        if let Some(tex) = &self.bump_texture {
+            let height = |u: f64, v: f64| tex.sample(u, v)[0] as f64;
+            let h = height(hit.u, hit.v);
+            let dh_du = height(hit.u + 1.0 / tex.width() as f64, hit.v) - h;
+            let dh_dv = height(hit.u, hit.v + 1.0 / tex.height() as f64) - h;
+            // The bitangent points towards decreasing v.
+            dx -= self.bump_scale * dh_du;
+            dy += self.bump_scale * dh_dv;
+        }
+
+        let n = (dx * hit.tangent + dy * hit.bitangent + hit.n).normalize();
+        // A normal facing into the surface would make every direction invalid.
+        if n * hit.ng <= 0.0 {
+            hit.n
+        } else {
+            n
+        }
+    }
+
+    // The BSDF at a hit point, with all textures and vertex colours resolved.
+    pub fn bsdf(&self, hit: &HitRecord) -> Bsdf {
+        let base_color = self.base_color_at(hit);
+        let (metallic, roughness) = self.metallic_roughness_at(hit);
+        Bsdf {
+            frame: Frame::from_normal(&self.shading_normal(hit)),
+            base_color,
+            metallic: metallic.clamp(0.0, 1.0),
+            alpha: roughness_to_alpha(roughness.clamp(0.0, 1.0)),
+            specular_f0: 0.08 * self.specular.clamp(0.0, 1.0),
+            sheen: self.sheen * Color::lerp(Color::WHITE, tint(base_color), self.sheen_tint.clamp(0.0, 1.0)),
+            clearcoat: self.clearcoat.clamp(0.0, 1.0),
+            clearcoat_alpha: roughness_to_alpha(self.clearcoat_roughness.clamp(0.0, 1.0)),
+            transmission: self.transmission.clamp(0.0, 1.0),
+            eta: if hit.front_face { self.ior } else { 1.0 / self.ior },
+        }
+    }
+
+    // Whether refraction through this material separates wavelengths.
+    pub fn is_dispersive(&self) -> bool {
+        self.dispersion.is_some() && self.transmission > 0.0
+    }
+
+    // The BSDF for a spectral path, with colours uplifted to the path's wavelengths and
+    // the refractive index taken at the hero wavelength for dispersive materials.
+    pub fn spectral_bsdf(&self, hit: &HitRecord, wavelengths: &Wavelengths) -> Bsdf {
+        let mut bsdf = self.bsdf(hit);
+        bsdf.base_color = wavelengths.uplift(bsdf.base_color);
+        bsdf.sheen = wavelengths.uplift(bsdf.sheen);
+        if let Some(dispersion) = self.dispersion {
+            let ior = dispersion.ior(wavelengths.hero());
+            bsdf.eta = if hit.front_face { ior } else { 1.0 / ior };
+        }
+        bsdf
+    }
+}
+
+// Base colour normalised to unit luminance, so tinting changes hue but not brightness.
+fn tint(c: Color) -> Color {
+    let luminance = c.luminance();
+    if luminance > 0.0 {
+        c / luminance
+    } else {
+        Color::WHITE
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::diffuse(Color::new(0.5, 0.5, 0.5))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn hit() -> HitRecord {
+        HitRecord::new(Vector::ORIGIN, Vector::new(0.0, 1.0, 0.0), 1.0, true)
+    }
+
+    #[test]
+    fn test_material_sample_diffuse() {
+        let material = Material { specular: 0.0, ..Material::diffuse(Color::new(0.2, 0.4, 0.6)) };
+        let bsdf = material.bsdf(&hit());
+        let wo = Vector::new(0.0, 1.0, 0.0);
+        for _ in 0..100 {
+            let s = bsdf.sample(&wo).expect("diffuse absorbed ray");
+            assert!(s.wi * hit().n >= 0.0);
+            assert!((s.weight.b() - 0.6).abs() < 0.01, "weight {:?}", s.weight);
+        }
+    }
+
+    #[test]
+    fn test_material_sample_mirror() {
+        let material = Material::metal(Color::WHITE, 0.0);
+        let bsdf = material.bsdf(&hit());
+        let wo = Vector::new(-1.0, 1.0, 0.0).normalize();
+        let expected = Vector::new(1.0, 1.0, 0.0).normalize();
+        // GGX keeps long tails at its smallest roughness, so a few samples stray from the
+        // mirror direction.
+        let near = (0..100)
+            .filter(|_| (bsdf.sample(&wo).expect("mirror absorbed ray").wi - expected).length() < 0.01)
+            .count();
+        assert!(near >= 80, "{} of 100 samples near the mirror direction", near);
+    }
+
+    #[test]
+    fn test_material_eval() {
+        let material = Material { specular: 0.0, ..Material::diffuse(Color::WHITE) };
+        let bsdf = material.bsdf(&hit());
+        let up = Vector::new(0.0, 1.0, 0.0);
+        let c = bsdf.eval(&up, &up);
+        assert!((c.r() - 1.0 / PI).abs() < 1e-12);
+        assert!(bsdf.eval(&up, &Vector::new(0.0, -1.0, 0.0)).is_black());
+        assert!(Material::metal(Color::WHITE, 0.5).bsdf(&hit()).eval(&up, &up).b() > 0.0);
+    }
+
+    #[test]
+    fn test_material_glass_eta() {
+        let glass = Material::glass(1.5, 0.0);
+        assert_eq!(glass.bsdf(&hit()).eta, 1.5);
+        let inside = HitRecord::new(Vector::ORIGIN, Vector::new(0.0, 1.0, 0.0), 1.0, false);
+        assert_eq!(glass.bsdf(&inside).eta, 1.0 / 1.5);
+    }
+
+    #[test]
+    fn test_material_normal_map() {
+        let flat = Texture::new(1, 1, vec![[0.5, 0.5, 1.0, 1.0]]);
+        let tilted = Texture::new(1, 1, vec![[1.0, 0.5, 1.0, 1.0]]);
+        let material = Material { normal_texture: Some(Arc::new(flat)), ..Material::default() };
+        assert!((material.shading_normal(&hit()) - hit().n).length() < 1e-6);
+
+        // A tangent-space (1, 0, 1) normal tilts the normal half way towards the tangent.
+        let material = Material { normal_texture: Some(Arc::new(tilted)), ..Material::default() };
+        let n = material.shading_normal(&hit());
+        let expected = (hit().tangent + hit().n).normalize();
+        assert!((n - expected).length() < 1e-6, "{:?}", n);
+    }
+
+    #[test]
+    fn test_material_bump_map() {
+        // Height rising along u tilts the normal away from the tangent.
+        let ramp = Texture::new(4, 1, vec![[0.0; 4], [0.25; 4], [0.5; 4], [0.75; 4]]);
+        let material = Material { bump_texture: Some(Arc::new(ramp)), ..Material::default() };
+        let mut h = hit();
+        h.u = 0.3;
+        let n = material.shading_normal(&h);
+        assert!(n * h.tangent < 0.0);
+        assert!((n * h.bitangent).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_material_alpha_cutout() {
+        let leaf = Texture::new(2, 1, vec![[1.0, 1.0, 1.0, 1.0], [1.0, 1.0, 1.0, 0.0]]);
+        let material = Material {
+            alpha_mode: AlphaMode::Mask(0.5),
+            base_color_texture: Some(Arc::new(leaf)),
+            ..Material::default()
+        };
+        let mut h = hit();
+        h.u = 0.25;
+        assert!(!material.is_cut_out(&h));
+        h.u = 0.75;
+        assert!(material.is_cut_out(&h));
+        assert!(!Material { alpha_mode: AlphaMode::Opaque, ..material }.is_cut_out(&h));
+
+        let glass = Material { alpha: 0.25, alpha_mode: AlphaMode::Blend, ..Material::default() };
+        let passed = (0..10000).filter(|_| glass.is_cut_out(&hit())).count();
+        assert!((7000..8000).contains(&passed), "{} of 10000 rays passed", passed);
+    }
+
+    #[test]
+    fn test_material_spectral_bsdf() {
+        let prism = Material { dispersion: Some(Dispersion::BK7), ..Material::glass(1.5, 0.0) };
+        assert!(prism.is_dispersive());
+        assert!(!Material::default().is_dispersive());
+
+        let blue = prism.spectral_bsdf(&hit(), &Wavelengths::sample(0.2));
+        let red = prism.spectral_bsdf(&hit(), &Wavelengths::sample(0.6));
+        assert!(blue.eta > red.eta);
+    }
+}
@@ -0,0 +1,281 @@
+use std::ops::Mul;
+
+use crate::color::Color;
+use crate::vec::Vector;
+
+// Row-major 4x4 matrix for affine transforms of points, directions and normals.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub const IDENTITY: Matrix4 = Matrix4 {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    pub fn new(m: [[f64; 4]; 4]) -> Matrix4 {
+        Matrix4 { m }
+    }
+
+    // Builds a matrix from column-major storage, as used by glTF.
+    pub fn from_columns(cols: [[f32; 4]; 4]) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (c, col) in cols.iter().enumerate() {
+            for (r, value) in col.iter().enumerate() {
+                m[r][c] = *value as f64;
+            }
+        }
+        Matrix4 { m }
+    }
+
+    pub fn translation(t: Vector) -> Matrix4 {
+        let mut m = Matrix4::IDENTITY;
+        m.m[0][3] = t.x;
+        m.m[1][3] = t.y;
+        m.m[2][3] = t.z;
+        m
+    }
+
+    pub fn scale(s: Vector) -> Matrix4 {
+        let mut m = Matrix4::IDENTITY;
+        m.m[0][0] = s.x;
+        m.m[1][1] = s.y;
+        m.m[2][2] = s.z;
+        m
+    }
+
+    // Rotation from a unit quaternion given as [x, y, z, w].
+    pub fn rotation(q: [f64; 4]) -> Matrix4 {
+        let [x, y, z, w] = q;
+        Matrix4 {
+            m: [
+                [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - z * w), 2.0 * (x * z + y * w), 0.0],
+                [2.0 * (x * y + z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - x * w), 0.0],
+                [2.0 * (x * z - y * w), 2.0 * (y * z + x * w), 1.0 - 2.0 * (x * x + y * y), 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn from_trs(t: Vector, r: [f64; 4], s: Vector) -> Matrix4 {
+        Matrix4::translation(t) * Matrix4::rotation(r) * Matrix4::scale(s)
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (r, row) in m.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value = self.m[c][r];
+            }
+        }
+        Matrix4 { m }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting. Returns None for singular matrices.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inv = Matrix4::IDENTITY.m;
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let d = a[col][col];
+            for c in 0..4 {
+                a[col][c] /= d;
+                inv[col][c] /= d;
+            }
+            for r in 0..4 {
+                if r != col {
+                    let f = a[r][col];
+                    for c in 0..4 {
+                        a[r][c] -= f * a[col][c];
+                        inv[r][c] -= f * inv[col][c];
+                    }
+                }
+            }
+        }
+        Some(Matrix4 { m: inv })
+    }
+
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn transform_point(&self, p: &Vector) -> Vector {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w != 1.0 && w != 0.0 {
+            Vector::new(x / w, y / w, z / w)
+        } else {
+            Vector::new(x, y, z)
+        }
+    }
+
+    pub fn transform_vector(&self, v: &Vector) -> Vector {
+        let m = &self.m;
+        Vector::new(m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+                    m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+                    m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z)
+    }
+
+    // Normals transform by the inverse transpose; the result is not normalized.
+    pub fn transform_normal(&self, n: &Vector) -> Vector {
+        match self.inverse() {
+            Some(inv) => inv.transpose().transform_vector(n),
+            None => *n,
+        }
+    }
+}
+
+impl Default for Matrix4 {
+    fn default() -> Self {
+        Matrix4::IDENTITY
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (r, row) in m.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[r][k] * other.m[k][c]).sum();
+            }
+        }
+        Matrix4 { m }
+    }
+}
+
+// Row-major 3x3 matrix, used for linear colour transforms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix3 {
+    pub m: [[f64; 3]; 3],
+}
+
+impl Matrix3 {
+    pub const IDENTITY: Matrix3 = Matrix3 {
+        m: [
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+        ],
+    };
+
+    pub fn new(m: [[f64; 3]; 3]) -> Matrix3 {
+        Matrix3 { m }
+    }
+
+    pub fn diagonal(d: [f64; 3]) -> Matrix3 {
+        let mut m = Matrix3::IDENTITY;
+        for (i, v) in d.iter().enumerate() {
+            m.m[i][i] = *v;
+        }
+        m
+    }
+
+    pub fn inverse(&self) -> Option<Matrix3> {
+        let m = &self.m;
+        let cofactor = |r: usize, c: usize| {
+            let (r0, r1) = ((r + 1) % 3, (r + 2) % 3);
+            let (c0, c1) = ((c + 1) % 3, (c + 2) % 3);
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let det = m[0][0] * cofactor(0, 0) + m[0][1] * cofactor(0, 1) + m[0][2] * cofactor(0, 2);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        Some(Matrix3 { m: std::array::from_fn(|i| std::array::from_fn(|j| cofactor(j, i) / det)) })
+    }
+
+    pub fn apply(&self, v: [f64; 3]) -> [f64; 3] {
+        std::array::from_fn(|i| self.m[i][0] * v[0] + self.m[i][1] * v[1] + self.m[i][2] * v[2])
+    }
+
+    pub fn transform_color(&self, c: Color) -> Color {
+        let [r, g, b] = self.apply([c.r(), c.g(), c.b()]);
+        Color::new(r, g, b)
+    }
+}
+
+impl Mul for Matrix3 {
+    type Output = Matrix3;
+
+    fn mul(self, other: Matrix3) -> Matrix3 {
+        Matrix3 { m: std::array::from_fn(|i| std::array::from_fn(|j| (0..3).map(|k| self.m[i][k] * other.m[k][j]).sum())) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_cmp::assert_approx_eq;
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    #[test]
+    fn test_matrix_trs() {
+        // 90 degrees about +y maps +x to -z.
+        let q = [0.0, FRAC_1_SQRT_2, 0.0, FRAC_1_SQRT_2];
+        let m = Matrix4::from_trs(Vector::new(1.0, 2.0, 3.0), q, Vector::new(2.0, 2.0, 2.0));
+        let p = m.transform_point(&Vector::new(1.0, 0.0, 0.0));
+        assert_approx_eq!(f64, p.x, 1.0, epsilon = 1e-12);
+        assert_approx_eq!(f64, p.y, 2.0, epsilon = 1e-12);
+        assert_approx_eq!(f64, p.z, 1.0, epsilon = 1e-12);
+
+        let v = m.transform_vector(&Vector::new(0.0, 1.0, 0.0));
+        assert_eq!(v, Vector::new(0.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn test_matrix_inverse() {
+        let m = Matrix4::from_trs(Vector::new(1.0, -2.0, 0.5),
+                                  [0.0, 0.0, FRAC_1_SQRT_2, FRAC_1_SQRT_2],
+                                  Vector::new(1.0, 3.0, 0.5));
+        let product = m * m.inverse().expect("invertible matrix reported singular");
+        for r in 0..4 {
+            for c in 0..4 {
+                let expected = if r == c { 1.0 } else { 0.0 };
+                assert_approx_eq!(f64, product.m[r][c], expected, epsilon = 1e-12);
+            }
+        }
+        assert!(Matrix4::scale(Vector::new(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    #[test]
+    fn test_matrix_transform_normal() {
+        // Non-uniform scale must keep normals perpendicular to the surface.
+        let m = Matrix4::scale(Vector::new(2.0, 1.0, 1.0));
+        let tangent = m.transform_vector(&Vector::new(1.0, -1.0, 0.0));
+        let normal = m.transform_normal(&Vector::new(1.0, 1.0, 0.0));
+        assert_approx_eq!(f64, tangent * normal, 0.0, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_matrix3_inverse() {
+        let m = Matrix3::new([[2.0, 0.0, 1.0], [1.0, 3.0, 0.0], [0.0, 1.0, 4.0]]);
+        let product = m * m.inverse().expect("matrix is invertible");
+        for i in 0..3 {
+            for j in 0..3 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((product.m[i][j] - expected).abs() < 1e-12);
+            }
+        }
+        assert!(Matrix3::diagonal([1.0, 0.0, 1.0]).inverse().is_none());
+    }
+}
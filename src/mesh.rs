@@ -0,0 +1,276 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::bsdf::Frame;
+use crate::bvh::{Aabb, Bvh};
+use crate::color::Color;
+use crate::ray::{Ray, Hittable, HitRecord};
+use crate::vec::Vector;
+
+#[derive(Debug)]
+pub enum MeshError {
+    Io(io::Error),
+    Truncated,
+    Malformed(String),
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::Io(e) => write!(f, "I/O error: {}", e),
+            MeshError::Truncated => write!(f, "file is truncated"),
+            MeshError::Malformed(why) => write!(f, "malformed file: {}", why),
+        }
+    }
+}
+
+impl Error for MeshError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MeshError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MeshError {
+    fn from(e: io::Error) -> MeshError {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            MeshError::Truncated
+        } else {
+            MeshError::Io(e)
+        }
+    }
+}
+
+// Indexed triangle mesh. Normals, colours, texture coordinates and tangents are
+// optional per-vertex attributes; each is either empty or has one entry per position.
+// Tangents carry the bitangent handedness in w, as in glTF. Without them the tangent
+// frame is derived from the texture coordinates of each triangle.
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    positions: Vec<Vector>,
+    normals: Vec<Vector>,
+    colors: Vec<Color>,
+    uvs: Vec<(f64, f64)>,
+    tangents: Vec<[f64; 4]>,
+    triangles: Vec<[usize; 3]>,
+    bvh: Bvh,
+}
+
+impl Mesh {
+    pub fn new(positions: Vec<Vector>,
+               normals: Vec<Vector>,
+               colors: Vec<Color>,
+               uvs: Vec<(f64, f64)>,
+               triangles: Vec<[usize; 3]>) -> Result<Mesh, MeshError> {
+        let n = positions.len();
+        if !normals.is_empty() && normals.len() != n {
+            return Err(MeshError::Malformed(format!("{} normals for {} vertices", normals.len(), n)));
+        }
+        if !colors.is_empty() && colors.len() != n {
+            return Err(MeshError::Malformed(format!("{} colours for {} vertices", colors.len(), n)));
+        }
+        if !uvs.is_empty() && uvs.len() != n {
+            return Err(MeshError::Malformed(format!("{} texture coordinates for {} vertices", uvs.len(), n)));
+        }
+        if let Some(tri) = triangles.iter().find(|tri| tri.iter().any(|&i| i >= n)) {
+            return Err(MeshError::Malformed(
+                format!("triangle {:?} references a vertex out of range ({} vertices)", tri, n)));
+        }
+
+        let bounds: Vec<Aabb> = triangles.iter()
+            .map(|[a, b, c]| Aabb::from_points(&[positions[*a], positions[*b], positions[*c]]))
+            .collect();
+        let bvh = Bvh::new(&bounds);
+        Ok(Mesh { positions, normals, colors, uvs, tangents: vec![], triangles, bvh, })
+    }
+
+    pub fn with_tangents(mut self, tangents: Vec<[f64; 4]>) -> Result<Mesh, MeshError> {
+        if tangents.len() != self.positions.len() {
+            return Err(MeshError::Malformed(
+                format!("{} tangents for {} vertices", tangents.len(), self.positions.len())));
+        }
+        self.tangents = tangents;
+        Ok(self)
+    }
+
+    pub fn positions(&self) -> &[Vector] {
+        &self.positions
+    }
+
+    pub fn normals(&self) -> &[Vector] {
+        &self.normals
+    }
+
+    pub fn colors(&self) -> &[Color] {
+        &self.colors
+    }
+
+    pub fn uvs(&self) -> &[(f64, f64)] {
+        &self.uvs
+    }
+
+    pub fn tangents(&self) -> &[[f64; 4]] {
+        &self.tangents
+    }
+
+    pub fn triangles(&self) -> &[[usize; 3]] {
+        &self.triangles
+    }
+
+    fn hit_triangle(&self, index: usize, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let [i0, i1, i2] = self.triangles[index];
+        let p0 = self.positions[i0];
+        let e1 = self.positions[i1] - p0;
+        let e2 = self.positions[i2] - p0;
+
+        // Möller-Trumbore intersection.
+        let pvec = r.direction.cross(&e2);
+        let det = e1 * pvec;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.origin - p0;
+        let b1 = (tvec * pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(&e1);
+        let b2 = (r.direction * qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = (e2 * qvec) * inv_det;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+
+        let b0 = 1.0 - b1 - b2;
+        let geometric_normal = e1.cross(&e2).normalize();
+        let mut hit = HitRecord::from_ray(*r, geometric_normal, t);
+        let normal = if self.normals.is_empty() {
+            geometric_normal
+        } else {
+            (b0 * self.normals[i0] + b1 * self.normals[i1] + b2 * self.normals[i2]).normalize()
+        };
+        if !self.uvs.is_empty() {
+            let (u0, v0) = self.uvs[i0];
+            let (u1, v1) = self.uvs[i1];
+            let (u2, v2) = self.uvs[i2];
+            hit.u = b0 * u0 + b1 * u1 + b2 * u2;
+            hit.v = b0 * v0 + b1 * v1 + b2 * v2;
+        } else {
+            hit.u = b1;
+            hit.v = b2;
+        }
+        let (tangent, bitangent) = self.tangent_frame(index, [b0, b1, b2], &normal);
+        hit.set_shading(normal, tangent, bitangent);
+        if !self.colors.is_empty() {
+            hit.color = Some(b0 * self.colors[i0] + b1 * self.colors[i1] + b2 * self.colors[i2]);
+        }
+        Some(hit)
+    }
+
+    // Unit tangent and bitangent at a point of a triangle, orthogonal to `normal`.
+    fn tangent_frame(&self, index: usize, barycentrics: [f64; 3], normal: &Vector) -> (Vector, Vector) {
+        let [i0, i1, i2] = self.triangles[index];
+        let [b0, b1, b2] = barycentrics;
+        let (tangent, handedness) = if !self.tangents.is_empty() {
+            let t = |i: usize| Vector::new(self.tangents[i][0], self.tangents[i][1], self.tangents[i][2]);
+            (b0 * t(i0) + b1 * t(i1) + b2 * t(i2), self.tangents[i0][3].signum())
+        } else if !self.uvs.is_empty() {
+            let e1 = self.positions[i1] - self.positions[i0];
+            let e2 = self.positions[i2] - self.positions[i0];
+            let (u0, v0) = self.uvs[i0];
+            let (du1, dv1) = (self.uvs[i1].0 - u0, self.uvs[i1].1 - v0);
+            let (du2, dv2) = (self.uvs[i2].0 - u0, self.uvs[i2].1 - v0);
+            let det = du1 * dv2 - du2 * dv1;
+            if det.abs() < 1e-12 {
+                (Vector::ORIGIN, 1.0)
+            } else {
+                let dpdu = (dv2 * e1 - dv1 * e2) / det;
+                let dpdv = (du1 * e2 - du2 * e1) / det;
+                // The bitangent points towards decreasing v, so flip it when the
+                // texture is mirrored.
+                let handedness = if normal.cross(&dpdu) * dpdv <= 0.0 { 1.0 } else { -1.0 };
+                (dpdu, handedness)
+            }
+        } else {
+            (Vector::ORIGIN, 1.0)
+        };
+
+        let tangent = tangent - (tangent * *normal) * *normal;
+        if tangent.near_zero() {
+            let frame = Frame::from_normal(normal);
+            return (frame.s, frame.t);
+        }
+        let tangent = tangent.normalize();
+        (tangent, handedness * normal.cross(&tangent))
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.bvh.hit(r, t_min, t_max, |i, closest| self.hit_triangle(i, r, t_min, closest))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad() -> Mesh {
+        let positions = vec![
+            Vector::new(-1.0, -1.0, -2.0),
+            Vector::new(1.0, -1.0, -2.0),
+            Vector::new(1.0, 1.0, -2.0),
+            Vector::new(-1.0, 1.0, -2.0),
+        ];
+        let colors = vec![Color::WHITE, Color::WHITE, Color::BLACK, Color::BLACK];
+        let uvs = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        Mesh::new(positions, vec![], colors, uvs, vec![[0, 1, 2], [0, 2, 3]]).unwrap()
+    }
+
+    #[test]
+    fn test_mesh_hit() {
+        let mesh = quad();
+        let ray = Ray::new(Vector::ORIGIN, Vector::new(0.0, 0.0, -1.0));
+        let hit = mesh.hit(&ray, 0.0, f64::INFINITY).expect("no hit");
+        assert_eq!(hit.t, 2.0);
+        assert_eq!(hit.n, Vector::new(0.0, 0.0, 1.0));
+        assert!(hit.front_face);
+        assert!((hit.u - 0.5).abs() < 1e-12 && (hit.v - 0.5).abs() < 1e-12);
+        assert_eq!(hit.color, Some(Color::new(0.5, 0.5, 0.5)));
+        // The texture's v axis runs up the quad, so the bitangent points down it.
+        assert_eq!(hit.tangent, Vector::new(1.0, 0.0, 0.0));
+        assert_eq!(hit.bitangent, Vector::new(0.0, -1.0, 0.0));
+
+        let ray = Ray::new(Vector::new(2.0, 0.0, 0.0), Vector::new(0.0, 0.0, -1.0));
+        assert!(mesh.hit(&ray, 0.0, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_mesh_hit_back_face() {
+        let mesh = quad();
+        let ray = Ray::new(Vector::new(0.2, 0.3, -4.0), Vector::new(0.0, 0.0, 1.0));
+        let hit = mesh.hit(&ray, 0.0, f64::INFINITY).expect("no hit");
+        assert!(!hit.front_face);
+        assert_eq!(hit.n, Vector::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn test_mesh_index_out_of_range() {
+        let positions = vec![Vector::ORIGIN; 3];
+        match Mesh::new(positions, vec![], vec![], vec![], vec![[0, 1, 3]]) {
+            Err(MeshError::Malformed(_)) => (),
+            other => panic!("expected malformed mesh error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,410 @@
+use std::fs;
+use std::str;
+
+use crate::color::Color;
+use crate::mesh::{Mesh, MeshError};
+use crate::vec::Vector;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<ScalarType, MeshError> {
+        match name {
+            "char" | "int8" => Ok(ScalarType::Int8),
+            "uchar" | "uint8" => Ok(ScalarType::UInt8),
+            "short" | "int16" => Ok(ScalarType::Int16),
+            "ushort" | "uint16" => Ok(ScalarType::UInt16),
+            "int" | "int32" => Ok(ScalarType::Int32),
+            "uint" | "uint32" => Ok(ScalarType::UInt32),
+            "float" | "float32" => Ok(ScalarType::Float32),
+            "double" | "float64" => Ok(ScalarType::Float64),
+            _ => Err(MeshError::Malformed(format!("unknown PLY property type `{}`", name))),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+
+    // Scale that maps the type's range onto [0, 1] when used as a colour channel.
+    fn color_scale(&self) -> f64 {
+        match self {
+            ScalarType::UInt8 => 1.0 / 255.0,
+            ScalarType::UInt16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PropertyKind {
+    Scalar(ScalarType),
+    List(ScalarType, ScalarType),
+}
+
+#[derive(Debug, Clone)]
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn property(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|p| names.contains(&p.name.as_str()))
+    }
+}
+
+#[derive(Debug)]
+struct Header {
+    encoding: Encoding,
+    elements: Vec<Element>,
+    body_offset: usize,
+}
+
+pub fn load(filename: &str) -> Result<Mesh, MeshError> {
+    parse(&fs::read(filename)?)
+}
+
+pub fn parse(data: &[u8]) -> Result<Mesh, MeshError> {
+    let header = parse_header(data)?;
+    let mut reader = match header.encoding {
+        Encoding::Ascii => {
+            let body = str::from_utf8(&data[header.body_offset..])
+                .map_err(|_| MeshError::Malformed(String::from("ASCII PLY body is not valid text")))?;
+            ValueReader::Ascii(body.split_ascii_whitespace())
+        },
+        encoding => ValueReader::Binary {
+            data: &data[header.body_offset..],
+            pos: 0,
+            big_endian: encoding == Encoding::BinaryBigEndian,
+        },
+    };
+
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut colors = vec![];
+    let mut uvs = vec![];
+    let mut triangles = vec![];
+    let mut values: Vec<Vec<f64>> = vec![];
+
+    for element in &header.elements {
+        let xyz = [element.property(&["x"]), element.property(&["y"]), element.property(&["z"])];
+        let nxyz = [element.property(&["nx"]), element.property(&["ny"]), element.property(&["nz"])];
+        let rgb = [
+            element.property(&["red", "diffuse_red", "r"]),
+            element.property(&["green", "diffuse_green", "g"]),
+            element.property(&["blue", "diffuse_blue", "b"]),
+        ];
+        let uv = [
+            element.property(&["u", "s", "texture_u", "texture_s"]),
+            element.property(&["v", "t", "texture_v", "texture_t"]),
+        ];
+        let face_indices = element.property(&["vertex_indices", "vertex_index"]);
+
+        let position = match xyz {
+            [Some(x), Some(y), Some(z)] => Some([x, y, z]),
+            _ if element.name == "vertex" => {
+                return Err(MeshError::Malformed(String::from("vertex element has no x, y, z properties")));
+            },
+            _ => None,
+        };
+        if element.name == "face" && face_indices.is_none() {
+            return Err(MeshError::Malformed(String::from("face element has no vertex_indices property")));
+        }
+
+        for _ in 0..element.count {
+            values.clear();
+            for property in &element.properties {
+                values.push(reader.read_property(&property.kind)?);
+            }
+
+            // The value of a vertex property, or the first of a list.
+            let value = |p: usize| values[p].first().copied().ok_or_else(|| {
+                MeshError::Malformed(format!("vertex property `{}` is an empty list", element.properties[p].name))
+            });
+            if let (Some([x, y, z]), "vertex") = (position, element.name.as_str()) {
+                positions.push(Vector::new(value(x)?, value(y)?, value(z)?));
+                if let [Some(nx), Some(ny), Some(nz)] = nxyz {
+                    normals.push(Vector::new(value(nx)?, value(ny)?, value(nz)?));
+                }
+                if let [Some(r), Some(g), Some(b)] = rgb {
+                    let channel = |p: usize| match element.properties[p].kind {
+                        PropertyKind::Scalar(t) => value(p).map(|v| v * t.color_scale()),
+                        PropertyKind::List(..) => Ok(0.0),
+                    };
+                    colors.push(Color::new(channel(r)?, channel(g)?, channel(b)?));
+                }
+                if let [Some(u), Some(v)] = uv {
+                    uvs.push((value(u)?, value(v)?));
+                }
+            } else if let (Some(face_indices), "face") = (face_indices, element.name.as_str()) {
+                let indices = &values[face_indices];
+                if indices.len() < 3 {
+                    return Err(MeshError::Malformed(format!("face with {} vertices", indices.len())));
+                }
+                if indices.iter().any(|&i| i < 0.0) {
+                    return Err(MeshError::Malformed(String::from("negative vertex index in face")));
+                }
+                // Polygons are split into a triangle fan.
+                for k in 1..indices.len() - 1 {
+                    triangles.push([indices[0] as usize, indices[k] as usize, indices[k + 1] as usize]);
+                }
+            }
+        }
+    }
+
+    Mesh::new(positions, normals, colors, uvs, triangles)
+}
+
+fn parse_header(data: &[u8]) -> Result<Header, MeshError> {
+    let mut pos = 0;
+    let mut next_line = || -> Result<&str, MeshError> {
+        let len = data[pos..].iter().position(|&b| b == b'\n').ok_or(MeshError::Truncated)?;
+        let line = str::from_utf8(&data[pos..pos + len])
+            .map_err(|_| MeshError::Malformed(String::from("PLY header is not valid text")))?;
+        pos += len + 1;
+        Ok(line.trim())
+    };
+
+    if next_line()? != "ply" {
+        return Err(MeshError::Malformed(String::from("missing `ply` magic number")));
+    }
+
+    let mut encoding = None;
+    let mut elements: Vec<Element> = vec![];
+    loop {
+        let line = next_line()?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", format, version] => {
+                if *version != "1.0" {
+                    return Err(MeshError::Malformed(format!("unsupported PLY version {}", version)));
+                }
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::BinaryLittleEndian,
+                    "binary_big_endian" => Encoding::BinaryBigEndian,
+                    _ => return Err(MeshError::Malformed(format!("unknown PLY format `{}`", format))),
+                });
+            },
+            ["element", name, count] => {
+                let count = count.parse::<usize>()
+                    .map_err(|_| MeshError::Malformed(format!("invalid element count `{}`", count)))?;
+                elements.push(Element { name: name.to_string(), count, properties: vec![] });
+            },
+            ["property", "list", count_type, item_type, name] => {
+                let kind = PropertyKind::List(ScalarType::parse(count_type)?, ScalarType::parse(item_type)?);
+                add_property(&mut elements, name, kind)?;
+            },
+            ["property", scalar_type, name] => {
+                let kind = PropertyKind::Scalar(ScalarType::parse(scalar_type)?);
+                add_property(&mut elements, name, kind)?;
+            },
+            ["comment", ..] | ["obj_info", ..] | [] => (),
+            ["end_header"] => break,
+            _ => return Err(MeshError::Malformed(format!("unexpected PLY header line `{}`", line))),
+        }
+    }
+
+    match encoding {
+        Some(encoding) => Ok(Header { encoding, elements, body_offset: pos }),
+        None => Err(MeshError::Malformed(String::from("missing PLY format line"))),
+    }
+}
+
+fn add_property(elements: &mut [Element], name: &str, kind: PropertyKind) -> Result<(), MeshError> {
+    match elements.last_mut() {
+        Some(element) => {
+            element.properties.push(Property { name: name.to_string(), kind });
+            Ok(())
+        },
+        None => Err(MeshError::Malformed(format!("property `{}` declared before any element", name))),
+    }
+}
+
+enum ValueReader<'a> {
+    Ascii(str::SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], pos: usize, big_endian: bool },
+}
+
+impl<'a> ValueReader<'a> {
+    fn read_property(&mut self, kind: &PropertyKind) -> Result<Vec<f64>, MeshError> {
+        match kind {
+            PropertyKind::Scalar(t) => Ok(vec![self.read(*t)?]),
+            PropertyKind::List(count_type, item_type) => {
+                let count = self.read(*count_type)?;
+                if count < 0.0 {
+                    return Err(MeshError::Malformed(String::from("negative list length")));
+                }
+                (0..count as usize).map(|_| self.read(*item_type)).collect()
+            },
+        }
+    }
+
+    fn read(&mut self, t: ScalarType) -> Result<f64, MeshError> {
+        match self {
+            ValueReader::Ascii(tokens) => {
+                let token = tokens.next().ok_or(MeshError::Truncated)?;
+                token.parse::<f64>()
+                    .map_err(|_| MeshError::Malformed(format!("invalid number `{}`", token)))
+            },
+            ValueReader::Binary { data, pos, big_endian } => {
+                let size = t.size();
+                if *pos + size > data.len() {
+                    return Err(MeshError::Truncated);
+                }
+                let mut bytes = [0u8; 8];
+                bytes[..size].copy_from_slice(&data[*pos..*pos + size]);
+                *pos += size;
+                if *big_endian {
+                    bytes[..size].reverse();
+                }
+                let value = match t {
+                    ScalarType::Int8 => bytes[0] as i8 as f64,
+                    ScalarType::UInt8 => bytes[0] as f64,
+                    ScalarType::Int16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    ScalarType::UInt16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    ScalarType::Int32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    ScalarType::UInt32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    ScalarType::Float32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    ScalarType::Float64 => f64::from_le_bytes(bytes),
+                };
+                Ok(value)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII_PLY: &str = "\
+ply
+format ascii 1.0
+comment a single coloured quad
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+property float u
+property float v
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 0 0 1 255 0 0 0 0
+1 0 0 0 0 1 0 255 0 1 0
+1 1 0 0 0 1 0 0 255 1 1
+0 1 0 0 0 1 255 255 255 0 1
+4 0 1 2 3
+";
+
+    #[test]
+    fn test_parse_ascii() {
+        let mesh = parse(ASCII_PLY.as_bytes()).expect("valid PLY rejected");
+        assert_eq!(mesh.positions().len(), 4);
+        assert_eq!(mesh.normals()[0], Vector::new(0.0, 0.0, 1.0));
+        assert_eq!(mesh.colors()[1], Color::new(0.0, 1.0, 0.0));
+        assert_eq!(mesh.uvs()[2], (1.0, 1.0));
+        assert_eq!(mesh.triangles(), &[[0, 1, 2], [0, 2, 3]]);
+    }
+
+    fn binary_ply(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian { "binary_big_endian" } else { "binary_little_endian" };
+        let mut data = format!("ply\nformat {} 1.0\nelement vertex 3\nproperty float x\n\
+                                property float y\nproperty float z\nelement face 1\n\
+                                property list uchar uint vertex_indices\nend_header\n", format)
+            .into_bytes();
+        for v in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            for c in v {
+                data.extend(if big_endian { c.to_be_bytes() } else { c.to_le_bytes() });
+            }
+        }
+        data.push(3);
+        for i in 0u32..3 {
+            data.extend(if big_endian { i.to_be_bytes() } else { i.to_le_bytes() });
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_binary() {
+        for big_endian in [false, true] {
+            let mesh = parse(&binary_ply(big_endian)).expect("valid PLY rejected");
+            assert_eq!(mesh.positions()[1], Vector::new(1.0, 0.0, 0.0));
+            assert_eq!(mesh.triangles(), &[[0, 1, 2]]);
+            assert!(mesh.normals().is_empty() && mesh.colors().is_empty() && mesh.uvs().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let data = binary_ply(false);
+        match parse(&data[..data.len() - 2]) {
+            Err(MeshError::Truncated) => (),
+            other => panic!("expected truncated error, got {:?}", other),
+        }
+
+        let text = &ASCII_PLY[..ASCII_PLY.len() - 4];
+        match parse(text.as_bytes()) {
+            Err(MeshError::Truncated) => (),
+            other => panic!("expected truncated error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        match parse(b"plx\nformat ascii 1.0\nend_header\n") {
+            Err(MeshError::Malformed(_)) => (),
+            other => panic!("expected malformed error, got {:?}", other),
+        }
+
+        let empty_list = "ply\nformat ascii 1.0\nelement vertex 1\nproperty list uchar float x\n\
+                          property float y\nproperty float z\nend_header\n0 1 2\n";
+        match parse(empty_list.as_bytes()) {
+            Err(MeshError::Malformed(_)) => (),
+            other => panic!("expected malformed error, got {:?}", other),
+        }
+
+        let bad_index = ASCII_PLY.replace("4 0 1 2 3", "3 0 1 7");
+        match parse(bad_index.as_bytes()) {
+            Err(MeshError::Malformed(_)) => (),
+            other => panic!("expected malformed error, got {:?}", other),
+        }
+    }
+}
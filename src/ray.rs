@@ -1,3 +1,6 @@
+use crate::bsdf::Frame;
+use crate::bvh::Aabb;
+use crate::color::Color;
 use crate::vec::Vector;
 
 #[cfg(test)]
@@ -19,33 +22,85 @@ impl Ray {
     }
 }
 
+// Offset applied along the geometric normal when spawning rays from a surface.
+const RAY_OFFSET: f64 = 1e-6;
+
+// Surface interaction. `n` is the shading normal, which interpolated normals may bend
+// away from the true surface normal `ng`; both face the incoming ray. The tangent and
+// bitangent follow the texture coordinates (bitangent towards decreasing v, as glTF
+// normal maps expect) and are flipped with the normals on back faces.
 #[derive(Debug, Clone, Copy)]
 pub struct HitRecord {
     pub p: Vector,
     pub n: Vector,
+    pub ng: Vector,
+    pub tangent: Vector,
+    pub bitangent: Vector,
     pub t: f64,
     pub front_face: bool,
+    pub u: f64,
+    pub v: f64,
+    pub color: Option<Color>,
+    pub object: usize,
 }
 
 impl HitRecord {
     pub fn new(p: Vector, n: Vector, t: f64, front_face: bool) -> HitRecord {
-        HitRecord { p, n, t, front_face, }
+        let frame = Frame::from_normal(&n);
+        HitRecord {
+            p,
+            n,
+            ng: n,
+            tangent: frame.s,
+            bitangent: frame.t,
+            t,
+            front_face,
+            u: 0.0,
+            v: 0.0,
+            color: None,
+            object: 0,
+        }
     }
 
     pub fn from_ray(r: Ray, outward_normal: Vector, t: f64) -> HitRecord {
         let front_face = r.direction * outward_normal < 0.0;
         let normal = if front_face { outward_normal } else { -outward_normal };
+        let frame = Frame::from_normal(&normal);
         HitRecord {
             p: r.at(t),
             n: normal,
+            ng: normal,
+            tangent: frame.s,
+            bitangent: frame.t,
             t,
             front_face,
+            u: 0.0,
+            v: 0.0,
+            color: None,
+            object: 0,
         }
     }
+
+    // Sets the shading normal and tangent frame from their outward-facing versions.
+    pub fn set_shading(&mut self, normal: Vector, tangent: Vector, bitangent: Vector) {
+        let sign = if self.front_face { 1.0 } else { -1.0 };
+        self.n = sign * normal;
+        self.tangent = sign * tangent;
+        self.bitangent = sign * bitangent;
+    }
+
+    // A ray leaving the surface, nudged off it along the geometric normal on the side
+    // it travels towards so that it cannot hit the surface it starts from.
+    pub fn spawn_ray(&self, direction: Vector) -> Ray {
+        let scale = RAY_OFFSET * (1.0 + self.p.x.abs().max(self.p.y.abs()).max(self.p.z.abs()));
+        let offset = if direction * self.ng >= 0.0 { scale } else { -scale };
+        Ray::new(self.p + offset * self.ng, direction)
+    }
 }
 
 pub trait Hittable {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn bounding_box(&self) -> Aabb;
 }
 
 #[cfg(test)]
@@ -74,4 +129,12 @@ mod tests {
         let r = Ray::new(o, d);
         assert_eq!(r.at(0.5), Vector::new(1.5, 2.0, 2.5));
     }
+
+    #[test]
+    fn test_spawn_ray() {
+        let r = Ray::new(Vector::new(0.0, 1.0, 0.0), Vector::new(0.0, -1.0, 0.0));
+        let hit = HitRecord::from_ray(r, Vector::new(0.0, 1.0, 0.0), 1.0);
+        assert!(hit.spawn_ray(Vector::new(0.0, 1.0, 0.0)).origin.y > 0.0);
+        assert!(hit.spawn_ray(Vector::new(1.0, -1.0, 0.0)).origin.y < 0.0);
+    }
 }
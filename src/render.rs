@@ -0,0 +1,627 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use threadpool::ThreadPool;
+
+use crate::aov::{AovBuffer, AovPixel, PathRecord, SurfaceRecord};
+use crate::bsdf::Bsdf;
+use crate::camera::{Camera, Projection};
+use crate::color::Color;
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::lens::Lens;
+use crate::ray::{HitRecord, Hittable, Ray};
+use crate::scene::Scene;
+use crate::spectrum::Wavelengths;
+use crate::vec::Vector;
+
+// Rows rendered together as one task, and reported on as one tile.
+const TILE_ROWS: u32 = 4;
+
+// What to render and how.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub width: u32,
+    pub height: u32,
+    // Samples per pixel for the finished frame.
+    pub samples: u32,
+    pub max_depth: u16,
+    pub filter: Filter,
+    pub spectral: bool,
+    // Whether to fill in the frame's auxiliary passes as well as the image.
+    pub record_paths: bool,
+    // The scene's camera, fitted to the image, unless given.
+    pub camera: Option<Camera>,
+    // A lens to take camera rays through in place of the camera's projection.
+    pub lens: Option<Arc<Lens>>,
+    pub seed: u64,
+    // How many passes over the image to take the samples in. Observers are told as
+    // each one finishes, when every pixel has the same number of samples.
+    pub passes: u32,
+    // The rows `y0..=y1` to render, all of them by default.
+    pub rows: Option<(u32, u32)>,
+    // Pixels to render, all of them by default. Samples are also taken as far around
+    // the region as the filter reaches, so that its pixels come out as they would in
+    // the whole image, and only its pixels get auxiliary passes.
+    pub region: Option<Region>,
+    pub threads: usize,
+    pub cancel: CancelToken,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            width: 1920,
+            height: 1080,
+            samples: 64,
+            max_depth: 32,
+            filter: Filter::default(),
+            spectral: false,
+            record_paths: false,
+            camera: None,
+            lens: None,
+            seed: 0,
+            passes: 1,
+            rows: None,
+            region: None,
+            threads: num_cpus::get(),
+            cancel: CancelToken::default(),
+        }
+    }
+}
+
+// A render in progress: the image and auxiliary passes so far, which have `samples`
+// samples in every pixel and possibly more in some.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub film: Film,
+    pub aovs: Option<AovBuffer>,
+    pub samples: u32,
+}
+
+impl Frame {
+    pub fn new(settings: &Settings) -> Frame {
+        let film = match settings.rows {
+            Some((y0, y1)) => Film::new_tile(settings.width, settings.height, settings.filter, y0, y1),
+            None => Film::new(settings.width, settings.height, settings.filter),
+        };
+        let aovs = settings.record_paths.then(|| AovBuffer::new(settings.width, settings.height));
+        Frame { film, aovs, samples: 0 }
+    }
+}
+
+// The pixels `x0..x1` across and `y0..y1` up from the bottom of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Region {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        (self.x0..self.x1).contains(&x) && (self.y0..self.y1).contains(&y)
+    }
+
+    // The region's pixels out of those of an image `width` pixels wide.
+    pub fn crop<T: Copy>(&self, pixels: &[T], width: u32) -> Vec<T> {
+        (self.y0..self.y1)
+            .flat_map(|y| &pixels[(y * width + self.x0) as usize..(y * width + self.x1) as usize])
+            .copied()
+            .collect()
+    }
+}
+
+// Shared flag for stopping a render from another thread. Tiles already being
+// rendered finish, and no more are started.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// How far a render has got, counting only the samples taken by this call.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+    pub samples_per_second: f64,
+    pub elapsed: Duration,
+    pub eta: Duration,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        match self.tiles_total {
+            0 => 1.0,
+            n => self.tiles_done as f64 / n as f64,
+        }
+    }
+}
+
+// Hears about a render as it goes. Both methods run on the thread that called
+// `render`, so they hold up the render while they work.
+pub trait Observer {
+    fn tile_done(&mut self, _progress: &Progress, _frame: &Frame) {}
+
+    fn pass_done(&mut self, _frame: &Frame) {}
+}
+
+impl Observer for () {}
+
+#[derive(Debug)]
+pub enum RenderError {
+    // Settings that do not describe a render, saying why.
+    Invalid(String),
+    // A cancelled render, with the frame as it was left.
+    Cancelled(Frame),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Invalid(why) => write!(f, "invalid render settings: {}", why),
+            RenderError::Cancelled(_) => write!(f, "render cancelled"),
+        }
+    }
+}
+
+impl Error for RenderError {}
+
+impl Settings {
+    // Checks that the image has room for the sampler's pixel spacing, and that the rows
+    // and region to render lie inside it.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width < 2 || self.height < 2 {
+            return Err(format!("a {} x {} image is smaller than 2 x 2", self.width, self.height));
+        }
+        if let Some((y0, y1)) = self.rows {
+            if y0 > y1 || y1 >= self.height {
+                return Err(format!("rows {} to {} are not in an image {} high", y0, y1, self.height));
+            }
+        }
+        if let Some(r) = self.region {
+            if r.x0 >= r.x1 || r.y0 >= r.y1 || r.x1 > self.width || r.y1 > self.height {
+                return Err(format!("region {:?} is empty or not in a {} x {} image", r, self.width, self.height));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn render<O: Observer + ?Sized>(scene: &Arc<Scene>, settings: &Settings, observer: &mut O)
+                                    -> Result<Frame, RenderError> {
+    settings.validate().map_err(RenderError::Invalid)?;
+    resume(scene, settings, Frame::new(settings), observer)
+}
+
+// Carries on with a frame from an earlier render with the same settings, taking the
+// samples it is missing. Every tile of every pass has its own random sequences,
+// which depend on the seed and the samples already taken, so a resumed render takes
+// new samples.
+pub fn resume<O: Observer + ?Sized>(scene: &Arc<Scene>, settings: &Settings, mut frame: Frame, observer: &mut O)
+                                    -> Result<Frame, RenderError> {
+    settings.validate().map_err(RenderError::Invalid)?;
+    let film = &frame.film;
+    let rows = settings.rows.map_or((0, settings.height), |(y0, y1)| film.tile_rows(y0, y1));
+    if (film.width, film.height) != (settings.width, settings.height) || film.rows() != rows
+        || frame.aovs.as_ref().is_some_and(|a| (a.width, a.height) != (settings.width, settings.height)) {
+        return Err(RenderError::Invalid(String::from("the frame is for a different image")));
+    }
+    let camera = settings.camera.unwrap_or_else(|| {
+        scene.camera.unwrap_or_default().with_aspect_ratio(settings.width as f64 / settings.height as f64)
+    });
+    let (mut y0, mut y1) = settings.rows.unwrap_or((0, settings.height - 1));
+    let mut columns = (0, settings.width - 1);
+    if let Some(region) = settings.region {
+        let reach = settings.filter.radius().ceil() as u32;
+        y0 = y0.max(region.y0.saturating_sub(reach));
+        y1 = y1.min(region.y1 - 1 + reach);
+        columns = (region.x0.saturating_sub(reach), (region.x1 - 1 + reach).min(settings.width - 1));
+    }
+    let sampler = Sampler { camera, lens: settings.lens.clone(), width: settings.width, height: settings.height, columns,
+                            region: settings.region, max_depth: settings.max_depth, spectral: settings.spectral };
+    let tiles: Vec<(u32, u32)> = (y0..=y1).step_by(TILE_ROWS as usize)
+        .map(|t0| (t0, (t0 + TILE_ROWS - 1).min(y1)))
+        .collect();
+
+    let remaining = settings.samples.saturating_sub(frame.samples);
+    let pass_size = remaining.div_ceil(settings.passes.max(1)).max(1);
+    let passes: Vec<u32> = (0..remaining).step_by(pass_size as usize)
+        .map(|start| pass_size.min(remaining - start))
+        .collect();
+
+    let pool = ThreadPool::new(settings.threads.max(1));
+    let start = Instant::now();
+    let tiles_total = tiles.len() * passes.len();
+    let mut tiles_done = 0;
+    let mut samples_taken = 0;
+
+    for batch in passes {
+        let (tx, rx) = channel();
+        let seed = settings.seed ^ ((frame.samples as u64) << 32);
+        for &(t0, t1) in &tiles {
+            let tx = tx.clone();
+            let scene = Arc::clone(scene);
+            let cancel = settings.cancel.clone();
+            let mut film = frame.film.tile(t0, t1);
+            let width = settings.width as usize;
+            let record_paths = settings.record_paths;
+            let sampler = sampler.clone();
+            pool.execute(move || {
+                if cancel.is_cancelled() {
+                    return;
+                }
+                let mut aovs = vec![AovPixel::default(); if record_paths { width * (t1 - t0 + 1) as usize } else { 0 }];
+                for y in t0..=t1 {
+                    let row = if record_paths { &mut aovs[(y - t0) as usize * width..][..width] } else { &mut [] };
+                    sampler.render_row(&scene, y, batch, seed ^ y as u64, &mut film, row);
+                }
+                tx.send((t0, t1, film, aovs)).expect("Could not send rendered tile");
+            });
+        }
+        drop(tx);
+
+        for (t0, t1, film, aovs) in rx.iter() {
+            frame.film.merge(&film);
+            if let Some(buffer) = &mut frame.aovs {
+                for (i, aov) in aovs.iter().enumerate() {
+                    buffer.add((i % settings.width as usize) as u32, t0 + (i / settings.width as usize) as u32, aov);
+                }
+            }
+            tiles_done += 1;
+            samples_taken += (t1 - t0 + 1) as u64 * (columns.1 - columns.0 + 1) as u64 * batch as u64;
+            let elapsed = start.elapsed();
+            let progress = Progress {
+                tiles_done,
+                tiles_total,
+                samples_per_second: samples_taken as f64 / elapsed.as_secs_f64().max(1e-9),
+                elapsed,
+                eta: elapsed.mul_f64((tiles_total - tiles_done) as f64 / tiles_done as f64),
+            };
+            observer.tile_done(&progress, &frame);
+        }
+        if settings.cancel.is_cancelled() {
+            return Err(RenderError::Cancelled(frame));
+        }
+        frame.samples += batch;
+        observer.pass_done(&frame);
+    }
+    Ok(frame)
+}
+
+// Radiance along a ray. In spectral mode the colour channels hold radiance at the
+// path's wavelengths, and RGB inputs are uplifted to spectra as they are encountered.
+// Camera rays can pass a record to be filled in with what they hit and how their
+// radiance splits up, for the auxiliary render passes.
+fn ray_color(r: Ray, scene: &Scene, depth: u16, wavelengths: &mut Option<Wavelengths>,
+             record: Option<&mut PathRecord>) -> Color {
+    if depth == 0 {
+        return Color::BLACK;
+    }
+
+    if let Some(hit) = scene.hit(&r, 0.001, f64::INFINITY) {
+        let material = scene.material(&hit);
+        let wo = -r.direction.normalize();
+        let (bsdf, single) = match wavelengths {
+            Some(w) => {
+                let bsdf = material.spectral_bsdf(&hit, w);
+                (bsdf, material.is_dispersive() && w.terminate_secondary())
+            },
+            None => (material.bsdf(&hit), false),
+        };
+
+        let (direct_diffuse, direct) = direct_light(scene, &bsdf, &hit, &wo, wavelengths);
+        let (mut indirect_diffuse, mut indirect) = (Color::BLACK, Color::BLACK);
+        if let Some(sample) = bsdf.sample(&wo) {
+            let l = ray_color(hit.spawn_ray(sample.wi), scene, depth - 1, wavelengths, None);
+            indirect_diffuse = sample.diffuse_weight * l;
+            indirect = sample.weight * l;
+        }
+        let emitted = radiance(material.emitted(&hit), wavelengths);
+        let wavelengths = *wavelengths;
+        let finish = |c: Color| match wavelengths {
+            Some(w) if single => w.single(c),
+            _ => c,
+        };
+
+        if let Some(record) = record {
+            record.surface = Some(SurfaceRecord {
+                depth: hit.t * r.direction.length(),
+                position: hit.p,
+                normal: material.shading_normal(&hit),
+                albedo: material.base_color_at(&hit),
+                object: hit.object,
+                material: scene.material_index(&hit),
+            });
+            record.direct_diffuse = finish(direct_diffuse);
+            record.direct_specular = finish(direct - direct_diffuse);
+            record.indirect_diffuse = finish(indirect_diffuse);
+            record.indirect_specular = finish(indirect - indirect_diffuse);
+            record.emission = emitted;
+        }
+        return emitted + finish(direct + indirect);
+    }
+
+    // The sky leaves out the sun's disc, which only lights the scene as a light.
+    let background = match &scene.sky {
+        Some(sky) => sky.radiance(&r.direction),
+        None => {
+            let unit_dir = r.direction.normalize();
+            let t = 0.5 * (unit_dir.y + 1.0);
+            Color::lerp(Color::WHITE, Color::BACKGROUND, t)
+        },
+    };
+    let background = radiance(background, wavelengths);
+    if let Some(record) = record {
+        record.emission = background;
+    }
+    background
+}
+
+// Light arriving straight from the scene's punctual lights, which scattered rays can
+// never hit, as (diffuse, total).
+fn direct_light(scene: &Scene, bsdf: &Bsdf, hit: &HitRecord, wo: &Vector,
+                wavelengths: &Option<Wavelengths>) -> (Color, Color) {
+    let mut diffuse = Color::BLACK;
+    let mut c = Color::BLACK;
+    for light in &scene.lights {
+        if let Some(sample) = light.sample(&hit.p) {
+            let shadow_ray = hit.spawn_ray(sample.direction);
+            if scene.hit(&shadow_ray, 0.001, sample.distance).is_none() {
+                let l = radiance(sample.radiance, wavelengths);
+                let (fd, f) = bsdf.eval_split(wo, &sample.direction);
+                diffuse += l * fd;
+                c += l * f;
+            }
+        }
+    }
+    (diffuse, c)
+}
+
+fn radiance(rgb: Color, wavelengths: &Option<Wavelengths>) -> Color {
+    match wavelengths {
+        Some(w) => w.uplift(rgb),
+        None => rgb,
+    }
+}
+
+// Turns camera samples into film samples.
+#[derive(Clone)]
+struct Sampler {
+    camera: Camera,
+    lens: Option<Arc<Lens>>,
+    width: u32,
+    height: u32,
+    // The columns `x0..=x1` to sample.
+    columns: (u32, u32),
+    // Pixels to record paths for, all of them by default.
+    region: Option<Region>,
+    max_depth: u16,
+    spectral: bool,
+}
+
+// Where a sample at `sx`, `sy` lies in the image, from 0 to 1 each way. Panoramas cover
+// whole pixels, so that the seam of an equirectangular image and the edges of the cube's
+// faces fall between pixels, while other projections put the image's corners at the
+// centres of the corner pixels.
+fn image_uv(projection: &Projection, width: u32, height: u32, sx: f64, sy: f64) -> (f64, f64) {
+    match projection {
+        Projection::Equirectangular | Projection::Cubemap => ((sx + 0.5) / width as f64, (sy + 0.5) / height as f64),
+        _ => (sx / (width - 1) as f64, sy / (height - 1) as f64),
+    }
+}
+
+impl Sampler {
+    // Takes `samples` samples in every pixel of row `y`, recording the paths in `aovs`
+    // unless it is empty.
+    fn render_row(&self, scene: &Scene, y: u32, samples: u32, seed: u64, tile: &mut Film, aovs: &mut [AovPixel]) {
+        let dist = Uniform::new(-0.5, 0.5);
+        let mut rng = StdRng::seed_from_u64(seed);
+        for x in self.columns.0..=self.columns.1 {
+            let record_paths = !aovs.is_empty() && self.region.is_none_or(|r| r.contains(x, y));
+            for _ in 0..samples {
+                let sx = (x as f64) + dist.sample(&mut rng);
+                let sy = (y as f64) + dist.sample(&mut rng);
+                let (u, v) = image_uv(&self.camera.projection, self.width, self.height, sx, sy);
+                // Rays the lens stops bring no light, and the rest bring more or less
+                // as the lens lets it through.
+                let ray = match &self.lens {
+                    Some(lens) => lens.get_ray(&self.camera, u, v, (rng.gen(), rng.gen())),
+                    None => self.camera.covers(u, v).then(|| (self.camera.get_ray(u, v), 1.0)),
+                };
+
+                let mut record = PathRecord::default();
+                let record_ref = if record_paths { Some(&mut record) } else { None };
+                let c = match ray {
+                    None => Color::BLACK,
+                    Some((r, weight)) if self.spectral => {
+                        let mut wavelengths = Some(Wavelengths::sample(rng.gen()));
+                        let l = ray_color(r, scene, self.max_depth, &mut wavelengths, record_ref);
+                        weight * match wavelengths {
+                            Some(w) => {
+                                record = record.map_lighting(|l| w.to_rgb(l));
+                                w.to_rgb(l)
+                            },
+                            None => l,
+                        }
+                    },
+                    Some((r, weight)) => weight * ray_color(r, scene, self.max_depth, &mut None, record_ref),
+                };
+                tile.add_sample(sx, sy, c);
+                if record_paths {
+                    aovs[x as usize].add(&record);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings { width: 8, height: 6, samples: 4, max_depth: 4, passes: 2, threads: 2, ..Settings::default() }
+    }
+
+    #[derive(Default)]
+    struct Counter {
+        tiles: usize,
+        passes: Vec<u32>,
+        last: Option<Progress>,
+    }
+
+    impl Observer for Counter {
+        fn tile_done(&mut self, progress: &Progress, _: &Frame) {
+            self.tiles += 1;
+            self.last = Some(*progress);
+        }
+
+        fn pass_done(&mut self, frame: &Frame) {
+            self.passes.push(frame.samples);
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let scene = Arc::new(Scene::default());
+        let mut counter = Counter::default();
+        let frame = render(&scene, &Settings { record_paths: true, ..settings() }, &mut counter).unwrap();
+        assert_eq!(frame.samples, 4);
+        assert!(frame.film.sample_counts().iter().all(|n| *n == 4));
+        // The top row sees the sky. Pixels lower down can lose every path in the sphere's
+        // contact shadow.
+        let width = settings().width as usize;
+        assert!(frame.film.pixels().iter().rev().take(width).all(|c| c.r() > 0.0));
+        assert_eq!(counter.passes, vec![2, 4]);
+        assert_eq!(counter.tiles, 4);
+        let last = counter.last.unwrap();
+        assert_eq!(last.fraction(), 1.0);
+        assert_eq!(last.eta, Duration::ZERO);
+        assert!(frame.aovs.is_some());
+    }
+
+    #[test]
+    fn test_render_resume() {
+        let scene = Arc::new(Scene::default());
+        let settings = settings();
+        let half = render(&scene, &Settings { samples: 2, ..settings.clone() }, &mut ()).unwrap();
+        let mut counter = Counter::default();
+        let frame = resume(&scene, &settings, half, &mut counter).unwrap();
+        assert_eq!(counter.passes, vec![3, 4]);
+        assert!(frame.film.sample_counts().iter().all(|n| *n == 4));
+
+        // Only the rows asked for are rendered.
+        let band = render(&scene, &Settings { rows: Some((2, 3)), ..settings }, &mut ()).unwrap();
+        assert_eq!(band.film.sample_counts().iter().sum::<u32>(), 8 * 2 * 4);
+    }
+
+    #[test]
+    fn test_render_region() {
+        let scene = Arc::new(Scene::default());
+        let region = Region { x0: 3, y0: 2, x1: 5, y1: 3 };
+        let settings = Settings { region: Some(region), record_paths: true, filter: Filter::Tent { radius: 1.0 },
+                                  ..settings() };
+        let frame = render(&scene, &settings, &mut ()).unwrap();
+        // The region and the pixels its filter reaches are sampled, and no others.
+        let counts = frame.film.sample_counts();
+        for y in 0..6 {
+            for x in 0..8 {
+                let sampled = (2..6).contains(&x) && (1..4).contains(&y);
+                assert_eq!(counts[y * 8 + x] > 0, sampled, "pixel ({}, {})", x, y);
+            }
+        }
+        let aovs = frame.aovs.unwrap().crop(&region);
+        assert_eq!((aovs.width, aovs.height), (2, 1));
+        assert_eq!(region.crop(&counts, 8), vec![4, 4]);
+    }
+
+    #[test]
+    fn test_render_cancel() {
+        struct Canceller(CancelToken);
+        impl Observer for Canceller {
+            fn tile_done(&mut self, _: &Progress, _: &Frame) {
+                self.0.cancel();
+            }
+        }
+
+        let scene = Arc::new(Scene::default());
+        let settings = Settings { height: 40, threads: 1, ..settings() };
+        match render(&scene, &settings, &mut Canceller(settings.cancel.clone())) {
+            Err(RenderError::Cancelled(frame)) => assert_eq!(frame.samples, 0),
+            other => panic!("render was not cancelled: {:?}", other.map(|f| f.samples)),
+        }
+    }
+
+    #[test]
+    fn test_image_uv_cube_faces() {
+        let camera = Camera::new().with_projection(Projection::Cubemap);
+        let (width, height) = (12, 8);
+        // The direction each face looks in, from its centre.
+        let faces: Vec<Vector> = (0..6)
+            .map(|i| camera.get_ray((i % 3) as f64 / 3.0 + 1.0 / 6.0, 0.75 - (i / 3) as f64 / 2.0).direction.normalize())
+            .collect();
+        for y in 0..height {
+            for x in 0..width {
+                let expected = (1 - y / (height / 2)) * 3 + x / (width / 3);
+                // Samples anywhere in a pixel, up to its edges, see its face alone.
+                for (dx, dy) in [(-0.499, -0.499), (0.499, -0.499), (-0.499, 0.499), (0.499, 0.499)] {
+                    let (u, v) = image_uv(&camera.projection, width, height, x as f64 + dx, y as f64 + dy);
+                    let d = camera.get_ray(u, v).direction.normalize();
+                    let face = (0..6).max_by(|&a, &b| (d * faces[a]).total_cmp(&(d * faces[b]))).unwrap();
+                    assert_eq!(face, expected as usize, "pixel {}, {} sample {}, {}", x, y, dx, dy);
+                }
+            }
+        }
+
+        // The panorama's seam falls between the first and last columns.
+        let panorama = Projection::Equirectangular;
+        assert_eq!(image_uv(&panorama, width, height, -0.5, 0.0).0, 0.0);
+        assert_eq!(image_uv(&panorama, width, height, width as f64 - 0.5, 0.0).0, 1.0);
+    }
+
+    #[test]
+    fn test_render_invalid() {
+        let scene = Arc::new(Scene::default());
+        let invalid = [
+            Settings { width: 1, ..settings() },
+            Settings { height: 0, ..settings() },
+            Settings { rows: Some((3, 6)), ..settings() },
+            Settings { rows: Some((3, 2)), ..settings() },
+            Settings { region: Some(Region { x0: 2, y0: 0, x1: 9, y1: 1 }), ..settings() },
+            Settings { region: Some(Region { x0: 2, y0: 1, x1: 2, y1: 3 }), ..settings() },
+        ];
+        for settings in invalid {
+            assert!(matches!(render(&scene, &settings, &mut ()), Err(RenderError::Invalid(_))), "{:?}", settings);
+        }
+
+        // A frame from a render of a different size.
+        let frame = render(&scene, &Settings { samples: 1, ..settings() }, &mut ()).unwrap();
+        let settings = Settings { width: 9, ..settings() };
+        assert!(matches!(resume(&scene, &settings, frame, &mut ()), Err(RenderError::Invalid(_))));
+    }
+}
@@ -0,0 +1,276 @@
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use crate::bvh::{Aabb, Bvh};
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::gltf;
+use crate::light::Light;
+use crate::material::Material;
+use crate::mesh::{Mesh, MeshError};
+use crate::ply;
+use crate::ray::{Ray, Hittable, HitRecord};
+use crate::sky::Sky;
+use crate::sphere::Sphere;
+use crate::stl;
+use crate::tonemap::ToneMap;
+use crate::vec::Vector;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Mesh(MeshError),
+    Gltf(::gltf::Error),
+    UnsupportedFormat(String),
+    Invalid(String),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Mesh(e) => write!(f, "{}", e),
+            SceneError::Gltf(e) => write!(f, "glTF error: {}", e),
+            SceneError::UnsupportedFormat(name) => write!(f, "unsupported scene format: {}", name),
+            SceneError::Invalid(why) => write!(f, "invalid scene: {}", why),
+        }
+    }
+}
+
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneError::Mesh(e) => Some(e),
+            SceneError::Gltf(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<MeshError> for SceneError {
+    fn from(e: MeshError) -> SceneError {
+        SceneError::Mesh(e)
+    }
+}
+
+impl From<::gltf::Error> for SceneError {
+    fn from(e: ::gltf::Error) -> SceneError {
+        SceneError::Gltf(e)
+    }
+}
+
+pub struct Object {
+    pub name: String,
+    pub shape: Box<dyn Hittable + Send + Sync>,
+    pub material: usize,
+}
+
+impl Object {
+    pub fn new(name: &str, shape: Box<dyn Hittable + Send + Sync>, material: usize) -> Object {
+        Object { name: name.to_string(), shape, material, }
+    }
+}
+
+// Everything needed to render a frame: geometry with its materials, lights and an
+// optional camera. Objects sit in a BVH, so they can only be set at construction.
+pub struct Scene {
+    pub camera: Option<Camera>,
+    // Display settings the scene asks for, which the command line can override.
+    pub exposure: Option<f64>,
+    pub tone_map: Option<ToneMap>,
+    // Daylight seen past the objects, or a plain gradient if None.
+    pub sky: Option<Sky>,
+    pub lights: Vec<Light>,
+    pub materials: Vec<Material>,
+    // Parts of the file the loader skipped, for the caller to report.
+    pub warnings: Vec<String>,
+    objects: Vec<Object>,
+    bvh: Bvh,
+}
+
+impl Scene {
+    pub fn new(objects: Vec<Object>, materials: Vec<Material>, lights: Vec<Light>, camera: Option<Camera>) -> Scene {
+        if let Some(o) = objects.iter().find(|o| o.material >= materials.len()) {
+            panic!("Scene object `{}` uses material {} of {}", o.name, o.material, materials.len());
+        }
+        let bounds: Vec<Aabb> = objects.iter().map(|o| o.shape.bounding_box()).collect();
+        let bvh = Bvh::new(&bounds);
+        Scene { camera, exposure: None, tone_map: None, sky: None, lights, materials, warnings: vec![], objects, bvh, }
+    }
+
+    // Loads a scene, choosing the format from the file extension.
+    pub fn load(filename: &str) -> Result<Scene, SceneError> {
+        match extension(filename).as_deref() {
+            Some("gltf") | Some("glb") => gltf::load(filename),
+            Some("ply") => Ok(Scene::from_mesh(ply::load(filename)?)),
+            Some("stl") => Ok(Scene::from_mesh(stl::load(filename)?)),
+            _ => Err(SceneError::UnsupportedFormat(filename.to_string())),
+        }
+    }
+
+    // Parses the contents of a scene file named `filename`. glTF files must be
+    // self-contained, since there is nowhere to look for their external resources.
+    pub fn parse(filename: &str, data: &[u8]) -> Result<Scene, SceneError> {
+        match extension(filename).as_deref() {
+            Some("gltf") | Some("glb") => gltf::parse(data),
+            Some("ply") => Ok(Scene::from_mesh(ply::parse(data)?)),
+            Some("stl") => Ok(Scene::from_mesh(stl::parse(data)?)),
+            _ => Err(SceneError::UnsupportedFormat(filename.to_string())),
+        }
+    }
+
+    // A lone mesh with the default material, framed by a camera looking down -z.
+    pub fn from_mesh(mesh: Mesh) -> Scene {
+        let bounds = mesh.bounding_box();
+        let center = bounds.centroid();
+        let radius = 0.5 * bounds.extent().length();
+        let vfov: f64 = 40.0;
+        let distance = radius / (vfov.to_radians() / 2.0).sin();
+        let camera = Camera::look_at(center + Vector::new(0.0, 0.0, distance), center,
+                                     Vector::new(0.0, 1.0, 0.0), vfov, 16.0 / 9.0);
+        let objects = vec![Object::new("mesh", Box::new(mesh), 0)];
+        Scene::new(objects, vec![Material::default()], vec![], Some(camera))
+    }
+
+    // The scene with its objects replaced by what `f` makes of them, leaving out those
+    // it returns None for.
+    pub fn map_objects<F: FnMut(Object) -> Option<Object>>(self, f: F) -> Scene {
+        let objects = self.objects.into_iter().filter_map(f).collect();
+        let mut scene = Scene::new(objects, self.materials, self.lights, self.camera);
+        scene.exposure = self.exposure;
+        scene.tone_map = self.tone_map;
+        scene.sky = self.sky;
+        scene.warnings = self.warnings;
+        scene
+    }
+
+    // Lights the scene with the sky and its sun.
+    pub fn set_sky(&mut self, sky: Sky) {
+        self.lights.push(sky.sun_light());
+        self.sky = Some(sky);
+    }
+
+    pub fn objects(&self) -> &[Object] {
+        &self.objects
+    }
+
+    pub fn material(&self, hit: &HitRecord) -> &Material {
+        &self.materials[self.material_index(hit)]
+    }
+
+    pub fn material_index(&self, hit: &HitRecord) -> usize {
+        self.objects[hit.object].material
+    }
+}
+
+fn extension(filename: &str) -> Option<String> {
+    Path::new(filename).extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        let objects = vec![
+            Object::new("sphere", Box::new(Sphere::new(Vector::new(0.0, 0.0, -1.0), 0.5)), 0),
+            Object::new("ground", Box::new(Sphere::new(Vector::new(0.0, -100.5, -1.0), 100.0)), 0),
+        ];
+        Scene::new(objects, vec![Material::diffuse(Color::new(0.5, 0.5, 0.5))], vec![], None)
+    }
+}
+
+impl Hittable for Scene {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.bvh.hit(r, t_min, t_max, |i, closest| {
+            let object = &self.objects[i];
+            let material = &self.materials[object.material];
+            // Keep looking past cut-out hits, which the shape reports in order.
+            let mut t_start = t_min;
+            loop {
+                let mut hit = object.shape.hit(r, t_start, closest)?;
+                if !material.is_cut_out(&hit) {
+                    hit.object = i;
+                    return Some(hit);
+                }
+                t_start = hit.t;
+            }
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::material::AlphaMode;
+
+    #[test]
+    fn test_scene_hit() {
+        let camera = Camera::new();
+        let objects = vec![
+            Object::new("near", Box::new(Sphere::new(Vector::new(0.0, 0.0, -1.0), 0.5)), 1),
+            Object::new("far", Box::new(Sphere::new(Vector::new(0.0, 0.0, -2.0), 0.5)), 0),
+        ];
+        let materials = vec![Material::default(), Material::metal(Color::WHITE, 0.0)];
+        let scene = Scene::new(objects, materials, vec![], None);
+
+        let hit = scene.hit(&camera.get_ray(0.5, 0.5), 0.0, f64::INFINITY).expect("no hit record returned");
+        assert!(hit.p == Vector::new(0.0, 0.0, -0.5));
+        assert!(hit.n == Vector::new(0.0, 0.0, 1.0));
+        assert!(hit.t == 0.5);
+        assert!(hit.front_face);
+        assert_eq!(hit.object, 0);
+        assert_eq!(scene.material(&hit).metallic, 1.0);
+
+        let ray = camera.get_ray(0.0, 0.0);
+        if let Some(_hit) = scene.hit(&ray, 0.0, f64::INFINITY) {
+            panic!("ray {:?} should not have hit", ray);
+        }
+    }
+
+    #[test]
+    fn test_scene_hit_cutout() {
+        let camera = Camera::new();
+        let objects = vec![
+            Object::new("cutout", Box::new(Sphere::new(Vector::new(0.0, 0.0, -1.0), 0.5)), 1),
+            Object::new("far", Box::new(Sphere::new(Vector::new(0.0, 0.0, -3.0), 0.5)), 0),
+        ];
+        let cutout = Material { alpha: 0.0, alpha_mode: AlphaMode::Mask(0.5), ..Material::default() };
+        let scene = Scene::new(objects, vec![Material::default(), cutout], vec![], None);
+
+        let hit = scene.hit(&camera.get_ray(0.5, 0.5), 0.0, f64::INFINITY).expect("ray stopped by cut-out sphere");
+        assert_eq!(hit.object, 1);
+        assert_eq!(hit.t, 2.5);
+    }
+
+    #[test]
+    fn test_scene_from_mesh() {
+        let positions = vec![Vector::new(-1.0, 0.0, 0.0), Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0)];
+        let mesh = Mesh::new(positions, vec![], vec![], vec![], vec![[0, 1, 2]]).unwrap();
+        let scene = Scene::from_mesh(mesh);
+        let camera = scene.camera.expect("mesh scene has no camera");
+        let ray = camera.get_ray(0.5, 0.5);
+        assert!(scene.hit(&ray, 0.001, f64::INFINITY).is_some());
+    }
+
+    #[test]
+    fn test_scene_load_unsupported() {
+        match Scene::load("scene.xyz") {
+            Err(SceneError::UnsupportedFormat(_)) => (),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("loaded a scene from an unknown format"),
+        }
+    }
+
+    #[test]
+    fn test_scene_parse() {
+        let stl = b"solid t\nfacet normal 0 0 1\nouter loop\nvertex -1 0 0\nvertex 1 0 0\nvertex 0 1 0\n\
+                    endloop\nendfacet\nendsolid t\n";
+        let scene = Scene::parse("triangle.STL", stl).expect("could not parse STL scene");
+        assert_eq!(scene.objects().len(), 1);
+        assert!(matches!(Scene::parse("scene.obj", stl), Err(SceneError::UnsupportedFormat(_))));
+    }
+}
@@ -0,0 +1,617 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::color::Color;
+use crate::colorspace::Encoding;
+use crate::distributed::Job;
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::image::ImagePpm;
+use crate::render::{self, CancelToken, Frame, Observer, Progress, Settings};
+use crate::scene::Scene;
+use crate::tonemap::{exposure_scale, ToneMap};
+
+const MAX_BODY: usize = 256 << 20;
+// The longest request or header line, and the most headers in a request.
+const MAX_LINE: u64 = 8192;
+const MAX_HEADERS: usize = 100;
+// Connections served at once, each on its own thread. More are turned away.
+const MAX_CONNECTIONS: usize = 64;
+const MAX_SIZE: u32 = 16384;
+// The most pixels in a job, which bounds the memory each running job takes.
+const MAX_PIXELS: u64 = 1 << 23;
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+// How often the image served for a running job is brought up to date.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_millis(500);
+// Finished jobs are forgotten after this long, or sooner once there are more of them
+// than clients are likely to come back for.
+const FINISHED_TTL: Duration = Duration::from_secs(60 * 60);
+const MAX_FINISHED: usize = 64;
+
+// How many jobs may wait, and how many render at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub queue: usize,
+    pub running: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits { queue: 16, running: 1 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Queued,
+    Running,
+    Done,
+    Cancelled,
+}
+
+impl State {
+    fn name(&self) -> &'static str {
+        match self {
+            State::Queued => "queued",
+            State::Running => "running",
+            State::Done => "done",
+            State::Cancelled => "cancelled",
+        }
+    }
+}
+
+struct Status {
+    state: State,
+    progress: Option<Progress>,
+    samples: u32,
+    // The image as of the last snapshot, once the job has started.
+    film: Option<Film>,
+    finished: Option<Instant>,
+    // The scene to render, until the job starts.
+    scene: Option<Scene>,
+}
+
+// The job keeps its settings but not the uploaded scene, which is parsed on submission.
+struct Entry {
+    id: u64,
+    job: Job,
+    // How the scene asks to be shown.
+    exposure: f64,
+    tone_map: ToneMap,
+    cancel: CancelToken,
+    status: Mutex<Status>,
+}
+
+impl Entry {
+    fn to_json(&self) -> String {
+        let status = self.status.lock().unwrap();
+        let (progress, rate, eta) = match (status.state, status.progress) {
+            (State::Done, _) => (1.0, 0.0, 0.0),
+            (_, Some(p)) => (p.fraction(), p.samples_per_second, p.eta.as_secs_f64()),
+            (_, None) => (0.0, 0.0, 0.0),
+        };
+        format!("{{\"id\":{},\"state\":\"{}\",\"progress\":{:.4},\"samples\":{},\"total_samples\":{},\
+                 \"samples_per_second\":{:.0},\"eta_seconds\":{:.1},\"width\":{},\"height\":{}}}",
+                self.id, status.state.name(), progress, status.samples, self.job.samples, rate, eta,
+                self.job.width, self.job.height)
+    }
+
+    // The image as it stands, shown the way the scene asks.
+    fn png(&self) -> io::Result<Vec<u8>> {
+        let pixels = self.status.lock().unwrap().film.as_ref().map(Film::pixels);
+        let mut img = ImagePpm::new(self.job.width, self.job.height);
+        for y in 0..self.job.height {
+            for x in 0..self.job.width {
+                let c = pixels.as_ref().map_or(Color::BLACK, |p| p[(y * self.job.width + x) as usize]);
+                img.set_pixel(x, y, Encoding::SRGB.encode(self.tone_map.apply(self.exposure * c)));
+            }
+        }
+        let mut data = Vec::new();
+        img.encode_png(&mut data, &Encoding::SRGB)?;
+        Ok(data)
+    }
+}
+
+struct Service {
+    limits: Limits,
+    next_id: AtomicU64,
+    jobs: Mutex<BTreeMap<u64, Arc<Entry>>>,
+    queue: Mutex<VecDeque<Arc<Entry>>>,
+    ready: Condvar,
+    connections: AtomicUsize,
+}
+
+impl Service {
+    fn new(limits: Limits) -> Service {
+        Service {
+            limits,
+            next_id: AtomicU64::new(1),
+            jobs: Mutex::new(BTreeMap::new()),
+            queue: Mutex::new(VecDeque::new()),
+            ready: Condvar::new(),
+            connections: AtomicUsize::new(0),
+        }
+    }
+
+    // Forgets jobs that finished more than `ttl` ago, and the oldest finished jobs
+    // beyond the `keep` most recent.
+    fn evict(&self, ttl: Duration, keep: usize) {
+        // Statuses are locked before the job table elsewhere, so the table is not held
+        // while looking at them.
+        let entries: Vec<Arc<Entry>> = self.jobs.lock().unwrap().values().cloned().collect();
+        let mut finished: Vec<(Instant, u64)> = entries.iter()
+            .filter_map(|e| e.status.lock().unwrap().finished.map(|t| (t, e.id)))
+            .collect();
+        finished.sort();
+        let excess = finished.len().saturating_sub(keep);
+        let mut jobs = self.jobs.lock().unwrap();
+        for (i, (t, id)) in finished.into_iter().enumerate() {
+            if i < excess || t.elapsed() > ttl {
+                jobs.remove(&id);
+            }
+        }
+    }
+}
+
+// Serves the render API on `listener` forever. The API is
+//
+//   POST   /jobs?width=W&height=H&samples=S&depth=D&filter=F&spectral=1&format=EXT
+//          with the scene file as the body, or no body for the built-in scene
+//   GET    /jobs/ID        status and progress as JSON
+//   GET    /jobs/ID/image  the image so far as PNG
+//   DELETE /jobs/ID        cancels the job, or forgets it once it has finished
+//
+// Finished jobs are forgotten anyway after an hour, or once 64 newer ones have finished.
+pub fn serve(listener: TcpListener, limits: Limits) -> io::Result<()> {
+    let service = Arc::new(Service::new(limits));
+    let running = limits.running.max(1);
+    for _ in 0..running {
+        let service = Arc::clone(&service);
+        let threads = (num_cpus::get() / running).max(1);
+        thread::spawn(move || run_jobs(&service, threads));
+    }
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        if service.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            service.connections.fetch_sub(1, Ordering::SeqCst);
+            let _ = stream.set_write_timeout(Some(READ_TIMEOUT));
+            let _ = write_response(stream, &Response::error(503, "too many connections"));
+            continue;
+        }
+        let service = Arc::clone(&service);
+        thread::spawn(move || {
+            if let Err(e) = handle(&service, stream) {
+                eprintln!("Error serving request: {}", e);
+            }
+            service.connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+fn run_jobs(service: &Service, threads: usize) {
+    loop {
+        let entry = {
+            let mut queue = service.queue.lock().unwrap();
+            loop {
+                match queue.pop_front() {
+                    Some(entry) => break entry,
+                    None => queue = service.ready.wait(queue).unwrap(),
+                }
+            }
+        };
+        let state = run_job(&entry, threads);
+        let mut status = entry.status.lock().unwrap();
+        status.state = state;
+        status.finished = Some(Instant::now());
+        drop(status);
+        eprintln!("Job {} {}", entry.id, state.name());
+        service.evict(FINISHED_TTL, MAX_FINISHED);
+    }
+}
+
+// Keeps a job's status up to date as it renders.
+struct Reporter<'a> {
+    entry: &'a Entry,
+    last_snapshot: Instant,
+}
+
+impl Observer for Reporter<'_> {
+    fn tile_done(&mut self, progress: &Progress, frame: &Frame) {
+        let mut status = self.entry.status.lock().unwrap();
+        status.progress = Some(*progress);
+        if self.last_snapshot.elapsed() >= SNAPSHOT_INTERVAL {
+            status.film = Some(frame.film.clone());
+            self.last_snapshot = Instant::now();
+        }
+    }
+
+    fn pass_done(&mut self, frame: &Frame) {
+        let mut status = self.entry.status.lock().unwrap();
+        status.samples = frame.samples;
+        status.film = Some(frame.film.clone());
+        self.last_snapshot = Instant::now();
+    }
+}
+
+// Renders in passes over the whole image, so that the image fills in evenly.
+fn run_job(entry: &Entry, threads: usize) -> State {
+    let scene = {
+        let mut status = entry.status.lock().unwrap();
+        // The job may have been cancelled between leaving the queue and getting here.
+        if status.state == State::Cancelled {
+            return State::Cancelled;
+        }
+        status.state = State::Running;
+        status.scene.take()
+    };
+    let Some(scene) = scene else {
+        return State::Cancelled;
+    };
+    let settings = Settings { passes: 16, threads, cancel: entry.cancel.clone(), ..entry.job.settings() };
+    let mut reporter = Reporter { entry, last_snapshot: Instant::now() };
+    match render::render(&Arc::new(scene), &settings, &mut reporter) {
+        Ok(_) => State::Done,
+        Err(_) => State::Cancelled,
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(status: u16, body: String) -> Response {
+        Response { status, content_type: "application/json", body: body.into_bytes() }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response::json(status, format!("{{\"error\":\"{}\"}}", message.replace('\\', "\\\\").replace('"', "\\\"")))
+    }
+}
+
+fn handle(service: &Service, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader) {
+        Ok(request) => route(service, request),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Response::error(400, &e.to_string()),
+        Err(e) => return Err(e),
+    };
+    write_response(stream, &response)
+}
+
+fn route(service: &Service, request: Request) -> Response {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    let entry = |id: &str| id.parse::<u64>().ok().and_then(|id| service.jobs.lock().unwrap().get(&id).cloned());
+    match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["jobs"]) => submit(service, &request),
+        ("GET", ["jobs", id]) => match entry(id) {
+            Some(entry) => Response::json(200, entry.to_json()),
+            None => Response::error(404, "no such job"),
+        },
+        ("GET", ["jobs", id, "image"]) => match entry(id).map(|e| e.png()) {
+            Some(Ok(png)) => Response { status: 200, content_type: "image/png", body: png },
+            Some(Err(e)) => Response::error(500, &e.to_string()),
+            None => Response::error(404, "no such job"),
+        },
+        ("DELETE", ["jobs", id]) => match entry(id) {
+            Some(entry) => cancel(service, &entry),
+            None => Response::error(404, "no such job"),
+        },
+        (_, ["jobs"]) | (_, ["jobs", _]) | (_, ["jobs", _, "image"]) => Response::error(405, "method not allowed"),
+        _ => Response::error(404, "not found"),
+    }
+}
+
+fn submit(service: &Service, request: &Request) -> Response {
+    let mut job = match parse_job(&request.query, &request.body) {
+        Ok(job) => job,
+        Err(why) => return Response::error(400, &why),
+    };
+    let scene = match job.scene() {
+        Ok(scene) => scene,
+        Err(e) => return Response::error(400, &e.to_string()),
+    };
+    // Only the parsed scene is needed from here on.
+    job.scene = Vec::new();
+
+    service.evict(FINISHED_TTL, MAX_FINISHED);
+    let mut queue = service.queue.lock().unwrap();
+    if queue.len() >= service.limits.queue {
+        return Response::error(503, "job queue is full");
+    }
+    let id = service.next_id.fetch_add(1, Ordering::SeqCst);
+    let (exposure, tone_map) = (exposure_scale(scene.exposure.unwrap_or(0.0)), scene.tone_map.unwrap_or_default());
+    let status = Status {
+        state: State::Queued,
+        progress: None,
+        samples: 0,
+        film: None,
+        finished: None,
+        scene: Some(scene),
+    };
+    let cancel = CancelToken::default();
+    let entry = Arc::new(Entry { id, job, exposure, tone_map, cancel, status: Mutex::new(status) });
+    service.jobs.lock().unwrap().insert(id, Arc::clone(&entry));
+    queue.push_back(Arc::clone(&entry));
+    service.ready.notify_one();
+    eprintln!("Job {} queued: {} x {} with {} samples", id, entry.job.width, entry.job.height, entry.job.samples);
+    Response::json(201, entry.to_json())
+}
+
+fn cancel(service: &Service, entry: &Arc<Entry>) -> Response {
+    let mut queue = service.queue.lock().unwrap();
+    let mut status = entry.status.lock().unwrap();
+    match status.state {
+        State::Queued => {
+            queue.retain(|e| e.id != entry.id);
+            status.state = State::Cancelled;
+            status.finished = Some(Instant::now());
+            status.scene = None;
+        },
+        State::Running => entry.cancel.cancel(),
+        State::Done | State::Cancelled => {
+            service.jobs.lock().unwrap().remove(&entry.id);
+        },
+    }
+    drop(status);
+    drop(queue);
+    Response::json(200, entry.to_json())
+}
+
+fn parse_job(query: &[(String, String)], scene: &[u8]) -> Result<Job, String> {
+    let mut job = Job {
+        scene_name: String::new(),
+        scene: scene.to_vec(),
+        width: 640,
+        height: 360,
+        samples: 64,
+        max_depth: 32,
+        filter: Filter::default(),
+        spectral: false,
+        seed: rand::random(),
+    };
+    let size = |v: &str| v.parse::<u32>().ok().filter(|s| (2..=MAX_SIZE).contains(s));
+    for (key, value) in query {
+        match key.as_str() {
+            "width" => job.width = size(value).ok_or(format!("width must be from 2 to {}", MAX_SIZE))?,
+            "height" => job.height = size(value).ok_or(format!("height must be from 2 to {}", MAX_SIZE))?,
+            "samples" => job.samples = value.parse::<u16>().ok().filter(|s| *s > 0)
+                .ok_or("samples must be from 1 to 65535")? as u32,
+            "depth" => job.max_depth = value.parse().map_err(|_| "invalid maximum depth")?,
+            "filter" => job.filter = value.parse()?,
+            "spectral" => job.spectral = matches!(value.as_str(), "1" | "true" | "yes"),
+            "format" => job.scene_name = format!("scene.{}", value),
+            _ => return Err(format!("unknown parameter `{}`", key)),
+        }
+    }
+    if job.width as u64 * job.height as u64 > MAX_PIXELS {
+        return Err(format!("the image must have at most {} pixels", MAX_PIXELS));
+    }
+    if !scene.is_empty() && job.scene_name.is_empty() {
+        return Err(String::from("the scene needs a format: gltf, glb, ply or stl"));
+    }
+    Ok(job)
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+    let bad = |why: &str| io::Error::new(io::ErrorKind::InvalidData, why.to_string());
+    let line = read_line(reader)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => (method, target),
+        _ => return Err(bad("malformed request line")),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query.split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (k, v) = p.split_once('=').unwrap_or((p, ""));
+            Ok((percent_decode(k).ok_or_else(|| bad("malformed query"))?,
+                percent_decode(v).ok_or_else(|| bad("malformed query"))?))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut length = 0;
+    for i in 0.. {
+        if i > MAX_HEADERS {
+            return Err(bad("too many headers"));
+        }
+        let header = read_line(reader)?;
+        if header.is_empty() {
+            return Err(bad("truncated headers"));
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().map_err(|_| bad("invalid content length"))?;
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                return Err(bad("chunked bodies are not supported"));
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(bad("scene is too large"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Request { method: method.to_string(), path: path.to_string(), query, body })
+}
+
+// A line of at most MAX_LINE bytes, ending in a newline unless the stream ends first.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    Read::take(reader, MAX_LINE).read_line(&mut line)?;
+    if line.len() as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line is too long"));
+    }
+    Ok(line)
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut it = s.bytes();
+    while let Some(b) = it.next() {
+        match b {
+            b'%' => {
+                let hex = [it.next()?, it.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            },
+            b'+' => bytes.push(b' '),
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn write_response<W: Write>(mut out: W, response: &Response) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let mut head = String::new();
+    let _ = write!(head, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                   response.status, reason, response.content_type, response.body.len());
+    out.write_all(head.as_bytes())?;
+    out.write_all(&response.body)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Instant;
+
+    fn request(address: &str, method: &str, target: &str) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nHost: test\r\nContent-Length: 0\r\n\r\n", method, target).unwrap();
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+        let split = data.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let status = String::from_utf8_lossy(&data[9..12]).parse().unwrap();
+        (status, data[split + 4..].to_vec())
+    }
+
+    fn state(address: &str, id: u32) -> String {
+        let (_, body) = request(address, "GET", &format!("/jobs/{}", id));
+        let body = String::from_utf8(body).unwrap();
+        let start = body.find("\"state\":\"").unwrap() + 9;
+        body[start..].split('"').next().unwrap().to_string()
+    }
+
+    fn wait_for(address: &str, id: u32, wanted: &str) {
+        let start = Instant::now();
+        while state(address, id) != wanted {
+            assert!(start.elapsed() < Duration::from_secs(10), "job {} never became {}", id, wanted);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_service() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || serve(listener, Limits { queue: 1, running: 1 }));
+
+        // One job runs, one waits and there is no room for a third.
+        let (status, body) = request(&address, "POST", "/jobs?width=64&height=64&samples=10000");
+        assert_eq!(status, 201, "{}", String::from_utf8_lossy(&body));
+        wait_for(&address, 1, "running");
+        assert_eq!(request(&address, "POST", "/jobs?width=8&height=8").0, 201);
+        assert_eq!(request(&address, "POST", "/jobs?width=8&height=8").0, 503);
+
+        assert_eq!(request(&address, "DELETE", "/jobs/2").0, 200);
+        assert_eq!(state(&address, 2), "cancelled");
+        request(&address, "DELETE", "/jobs/1");
+        wait_for(&address, 1, "cancelled");
+
+        assert_eq!(request(&address, "POST", "/jobs?width=4&height=4&samples=2&filter=tent%3A1").0, 201);
+        wait_for(&address, 3, "done");
+        let (status, png) = request(&address, "GET", "/jobs/3/image");
+        assert_eq!(status, 200);
+        assert_eq!(&png[1..4], b"PNG");
+
+        assert_eq!(request(&address, "DELETE", "/jobs/3").0, 200);
+        assert_eq!(request(&address, "GET", "/jobs/3").0, 404);
+        assert_eq!(request(&address, "PUT", "/jobs").0, 405);
+    }
+
+    #[test]
+    fn test_evict() {
+        let service = Service::new(Limits::default());
+        for id in 1..=4 {
+            let job = parse_job(&[], &[]).unwrap();
+            let status = Status { state: State::Done, progress: None, samples: 0, film: None, finished: None,
+                                  scene: None };
+            let entry = Entry { id, job, exposure: 1.0, tone_map: ToneMap::default(), cancel: CancelToken::default(),
+                                status: Mutex::new(status) };
+            service.jobs.lock().unwrap().insert(id, Arc::new(entry));
+        }
+        let finish = |id: u64| service.jobs.lock().unwrap()[&id].status.lock().unwrap().finished = Some(Instant::now());
+        finish(1);
+        finish(2);
+        finish(3);
+        let ids = || service.jobs.lock().unwrap().keys().copied().collect::<Vec<_>>();
+
+        // Unfinished jobs stay, and the oldest finished go first.
+        service.evict(Duration::from_secs(60), 2);
+        assert_eq!(ids(), vec![2, 3, 4]);
+        thread::sleep(Duration::from_millis(5));
+        service.evict(Duration::ZERO, 10);
+        assert_eq!(ids(), vec![4]);
+    }
+
+    #[test]
+    fn test_parse_job() {
+        let query = |q: &str| q.split('&').map(|p| {
+            let (k, v) = p.split_once('=').unwrap();
+            (k.to_string(), v.to_string())
+        }).collect::<Vec<_>>();
+        let job = parse_job(&query("width=320&height=200&samples=8&spectral=1&filter=gaussian"), &[]).unwrap();
+        assert_eq!((job.width, job.height, job.samples, job.spectral), (320, 200, 8, true));
+        assert_eq!(job.filter, "gaussian".parse().unwrap());
+        assert!(parse_job(&query("width=0"), &[]).is_err());
+        assert!(parse_job(&query("width=16384&height=16384"), &[]).is_err());
+        assert!(parse_job(&query("colour=red"), &[]).is_err());
+        assert!(parse_job(&query("samples=4"), b"solid").is_err());
+        assert_eq!(percent_decode("a%3Ab+c"), Some(String::from("a:b c")));
+        assert_eq!(percent_decode("%4"), None);
+    }
+
+    #[test]
+    fn test_read_request_limits() {
+        let request = b"GET /jobs/1 HTTP/1.1\r\nHost: test\r\n\r\n";
+        assert_eq!(read_request(&mut &request[..]).unwrap().path, "/jobs/1");
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE as usize));
+        let e = read_request(&mut long.as_bytes()).err().expect("accepted an overlong line");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let many = format!("GET / HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(MAX_HEADERS + 1));
+        assert!(read_request(&mut many.as_bytes()).is_err());
+    }
+}
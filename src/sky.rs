@@ -0,0 +1,172 @@
+use std::f64::consts::PI;
+
+use crate::color::Color;
+use crate::colorspace::{xy_to_xyz, WORKING_SPACE};
+use crate::light::Light;
+use crate::matrix::Matrix3;
+use crate::vec::Vector;
+
+// Illuminance of sunlight above the atmosphere, in lux.
+const EXTRATERRESTRIAL_ILLUMINANCE: f64 = 128_000.0;
+
+// Wavelengths in micrometres standing in for the red, green and blue channels when
+// attenuating sunlight.
+const WAVELENGTHS: [f64; 3] = [0.68, 0.55, 0.44];
+
+// Preetham, Shirley and Smits' analytic daylight, from "A Practical Analytic Model for
+// Daylight": the luminance and chromaticity of the clear sky for the sun's position
+// and the turbidity of the air, in cd/m², with a sun of lux to match. Below the horizon
+// lies ground that reflects the light falling on it by its albedo.
+#[derive(Debug, Clone, Copy)]
+pub struct Sky {
+    // Unit vector towards the sun.
+    pub sun: Vector,
+    pub turbidity: f64,
+    pub ground_albedo: f64,
+    // Angular diameter of the sun's disc in radians.
+    pub sun_diameter: f64,
+    // Perez distribution coefficients A to E for luminance and the x and y chromaticities.
+    perez: [[f64; 5]; 3],
+    // Luminance and chromaticities at the zenith, divided by the distribution there.
+    zenith: [f64; 3],
+    from_xyz: Matrix3,
+    ground: Color,
+}
+
+impl Sky {
+    // A sky with the sun at `elevation` degrees above the horizon and `azimuth` degrees
+    // clockwise from north, which is -z, with east along +x.
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, ground_albedo: f64, sun_diameter: f64) -> Sky {
+        let (elevation, azimuth) = (elevation.clamp(0.0, 90.0).to_radians(), azimuth.to_radians());
+        let sun = Vector::new(elevation.cos() * azimuth.sin(), elevation.sin(), -elevation.cos() * azimuth.cos());
+        let t = turbidity;
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        let theta = PI / 2.0 - elevation;
+        let (t2, theta2, theta3) = (t * t, theta * theta, theta * theta * theta);
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192) * 1000.0;
+        let x = t2 * (0.00166 * theta3 - 0.00375 * theta2 + 0.00209 * theta)
+            + t * (-0.02903 * theta3 + 0.06377 * theta2 - 0.03202 * theta + 0.00394)
+            + (0.11693 * theta3 - 0.21196 * theta2 + 0.06052 * theta + 0.25886);
+        let y = t2 * (0.00275 * theta3 - 0.00610 * theta2 + 0.00317 * theta)
+            + t * (-0.04214 * theta3 + 0.08970 * theta2 - 0.04153 * theta + 0.00516)
+            + (0.15346 * theta3 - 0.26756 * theta2 + 0.06670 * theta + 0.26688);
+        let zenith = std::array::from_fn(|i| [luminance.max(0.0), x, y][i] / perez_f(&perez[i], 1.0, theta.cos()));
+
+        let mut sky = Sky {
+            sun,
+            turbidity,
+            ground_albedo,
+            sun_diameter: sun_diameter.to_radians(),
+            perez,
+            zenith,
+            from_xyz: WORKING_SPACE.from_xyz(),
+            ground: Color::BLACK,
+        };
+        sky.ground = ground_albedo / PI * (sky.sun_irradiance() * sun.y + sky.irradiance());
+        sky
+    }
+
+    // Radiance arriving from `direction`.
+    pub fn radiance(&self, direction: &Vector) -> Color {
+        let d = direction.normalize();
+        if d.y <= 0.0 {
+            return self.ground;
+        }
+        let cos_gamma = (d * self.sun).clamp(-1.0, 1.0);
+        let [luminance, x, y] = std::array::from_fn(|i| self.zenith[i] * perez_f(&self.perez[i], d.y, cos_gamma));
+        let [r, g, b] = self.from_xyz.apply(xy_to_xyz((x, y)).map(|v| v * luminance));
+        Color::new(r.max(0.0), g.max(0.0), b.max(0.0))
+    }
+
+    // Illuminance from the sun on a surface facing it, after passing through the
+    // atmosphere: Rayleigh scattering and aerosols by Ångström's formula with the
+    // optical air mass of Kasten.
+    pub fn sun_irradiance(&self) -> Color {
+        let zenith = self.sun.y.clamp(0.0, 1.0).acos();
+        let air_mass = 1.0 / (zenith.cos() + 0.15 * (93.885 - zenith.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let [r, g, b] = WAVELENGTHS.map(|l| {
+            let rayleigh = 0.008735 * l.powf(-4.08);
+            let aerosol = beta * l.powf(-1.3);
+            EXTRATERRESTRIAL_ILLUMINANCE * (-(rayleigh + aerosol) * air_mass).exp()
+        });
+        Color::new(r, g, b)
+    }
+
+    // The sun as a light for the scene.
+    pub fn sun_light(&self) -> Light {
+        Light::Sun { direction: -self.sun, intensity: self.sun_irradiance(), angular_diameter: self.sun_diameter }
+    }
+
+    // Illuminance from the sky alone on the ground, by the midpoint rule.
+    fn irradiance(&self) -> Color {
+        const STEPS: usize = 32;
+        let (d_theta, d_phi) = (PI / 2.0 / STEPS as f64, 2.0 * PI / (2 * STEPS) as f64);
+        let mut e = Color::BLACK;
+        for i in 0..STEPS {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..2 * STEPS {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let d = Vector::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                e += (theta.cos() * theta.sin() * d_theta * d_phi) * self.radiance(&d);
+            }
+        }
+        e
+    }
+}
+
+// The Perez sky distribution for a view `cos_theta` from the zenith and `cos_gamma`
+// from the sun.
+fn perez_f(c: &[f64; 5], cos_theta: f64, cos_gamma: f64) -> f64 {
+    let gamma = cos_gamma.acos();
+    (1.0 + c[0] * (c[1] / cos_theta.max(0.01)).exp())
+        * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * cos_gamma * cos_gamma)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sky_radiance() {
+        let sky = Sky::new(30.0, 90.0, 3.0, 0.3, 0.53);
+        assert!((sky.sun - Vector::new(0.75f64.sqrt(), 0.5, 0.0)).length() < 1e-9);
+        // Blue overhead, and brightest around the sun.
+        let up = sky.radiance(&Vector::new(0.0, 1.0, 0.0));
+        assert!(up.b() > up.r(), "{:?}", up);
+        let near_sun = sky.radiance(&(sky.sun + Vector::new(0.0, 0.05, 0.0)));
+        let away = sky.radiance(&Vector::new(-1.0, 0.2, 0.0));
+        assert!(near_sun.luminance() > 2.0 * away.luminance());
+        // Clear skies are thousands of cd/m².
+        assert!(up.luminance() > 1000.0 && up.luminance() < 20000.0, "{:?}", up);
+        // The ground is grey, lit by sun and sky.
+        let ground = sky.radiance(&Vector::new(0.0, -1.0, 0.0));
+        assert!(ground.luminance() > 0.0);
+        let brighter = Sky::new(30.0, 90.0, 3.0, 0.6, 0.53).radiance(&Vector::new(0.0, -1.0, 0.0));
+        assert!((brighter.luminance() / ground.luminance() - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sun_irradiance() {
+        let noon = Sky::new(90.0, 0.0, 3.0, 0.3, 0.53).sun_irradiance();
+        assert!(noon.luminance() > 80_000.0 && noon.luminance() < 110_000.0, "{:?}", noon);
+        // The low sun is dimmer and redder, and haze dims it too.
+        let evening = Sky::new(5.0, 0.0, 3.0, 0.3, 0.53).sun_irradiance();
+        assert!(evening.luminance() < noon.luminance() && evening.b() / evening.r() < noon.b() / noon.r());
+        let hazy = Sky::new(90.0, 0.0, 8.0, 0.3, 0.53).sun_irradiance();
+        assert!(hazy.luminance() < noon.luminance());
+        match Sky::new(45.0, 0.0, 3.0, 0.3, 0.53).sun_light() {
+            Light::Sun { direction, angular_diameter, .. } => {
+                assert!(direction.y < 0.0 && direction.z > 0.0);
+                assert!((angular_diameter - 0.53f64.to_radians()).abs() < 1e-12);
+            },
+            light => panic!("unexpected light {:?}", light),
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::sync::OnceLock;
+
+use crate::color::Color;
+use crate::colorspace::WORKING_SPACE;
+use crate::matrix::Matrix3;
+
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+
+const N_WAVELENGTHS: usize = 3;
+
+// Wavelengths carried by a path with hero-wavelength sampling: a uniformly sampled
+// hero and companions spaced evenly across the visible range. Spectral quantities at
+// these wavelengths are stored in the channels of a Color, hero first, so the rest of
+// the renderer can treat them like RGB.
+#[derive(Debug, Clone, Copy)]
+pub struct Wavelengths {
+    lambda: [f64; N_WAVELENGTHS],
+    single: bool,
+}
+
+impl Wavelengths {
+    pub fn sample(u: f64) -> Wavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + u * range;
+        let mut lambda = [hero; N_WAVELENGTHS];
+        for (i, l) in lambda.iter_mut().enumerate().skip(1) {
+            *l = hero + i as f64 * range / N_WAVELENGTHS as f64;
+            if *l > LAMBDA_MAX {
+                *l -= range;
+            }
+        }
+        Wavelengths { lambda, single: false }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    pub fn lambda(&self) -> [f64; N_WAVELENGTHS] {
+        self.lambda
+    }
+
+    // Drops the companion wavelengths after a wavelength-dependent event such as
+    // dispersion. Returns true the first time, when the caller must apply `single`
+    // to whatever it computes from here on.
+    pub fn terminate_secondary(&mut self) -> bool {
+        let first = !self.single;
+        self.single = true;
+        first
+    }
+
+    // Keeps only the hero wavelength, reweighted so the estimate stays unbiased.
+    pub fn single(&self, c: Color) -> Color {
+        Color::new(N_WAVELENGTHS as f64 * c.r(), 0.0, 0.0)
+    }
+
+    // Values at these wavelengths of the smooth spectrum matching a linear sRGB colour.
+    pub fn uplift(&self, rgb: Color) -> Color {
+        let coeffs = tables().rgb_to_basis.apply([rgb.r(), rgb.g(), rgb.b()]);
+        let value = |l: f64| {
+            let b = basis(l);
+            (coeffs[0] * b[0] + coeffs[1] * b[1] + coeffs[2] * b[2]).max(0.0)
+        };
+        Color::new(value(self.lambda[0]), value(self.lambda[1]), value(self.lambda[2]))
+    }
+
+    // Monte Carlo estimate of the CIE XYZ tristimulus values of a spectrum sampled at
+    // these wavelengths, with Y = 1 for the constant spectrum of value one.
+    pub fn to_xyz(&self, c: Color) -> [f64; 3] {
+        let values = [c.r(), c.g(), c.b()];
+        let scale = (LAMBDA_MAX - LAMBDA_MIN) / (N_WAVELENGTHS as f64 * tables().y_integral);
+        let mut xyz = [0.0; 3];
+        for (l, v) in self.lambda.iter().zip(values) {
+            let cmf = cie_xyz(*l);
+            for i in 0..3 {
+                xyz[i] += v * cmf[i] * scale;
+            }
+        }
+        xyz
+    }
+
+    // Linear working-space RGB, white balanced so that uplifted white comes back as white.
+    pub fn to_rgb(&self, c: Color) -> Color {
+        let [r, g, b] = tables().xyz_to_rgb.apply(self.to_xyz(c));
+        Color::new(r, g, b)
+    }
+}
+
+// Wavelength-dependent refractive index, with wavelengths in micrometres.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dispersion {
+    // n = a + b / λ²
+    Cauchy { a: f64, b: f64 },
+    // n² = 1 + Σ b λ² / (λ² - c)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+
+    // The Cauchy dispersion with index `ior` at the Fraunhofer d line and Abbe number
+    // `abbe`, which is (n_d - 1) / (n_F - n_C).
+    pub fn from_abbe(ior: f64, abbe: f64) -> Dispersion {
+        let [d, f, c] = [587.56f64, 486.13, 656.27].map(|l| (l / 1000.0).powi(-2));
+        let b = (ior - 1.0) / (abbe * (f - c));
+        Dispersion::Cauchy { a: ior - b * d, b }
+    }
+
+    pub fn ior(&self, lambda_nm: f64) -> f64 {
+        let l2 = (lambda_nm / 1000.0).powi(2);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let n2 = 1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>();
+                n2.max(1.0).sqrt()
+            },
+        }
+    }
+}
+
+// Piecewise Gaussian fit to the CIE 1931 colour matching functions from Wyman, Sloan
+// and Shirley, "Simple Analytic Approximations to the CIE XYZ Color Matching Functions".
+pub fn cie_xyz(lambda: f64) -> [f64; 3] {
+    let g = |mu: f64, s1: f64, s2: f64| {
+        let t = (lambda - mu) / if lambda < mu { s1 } else { s2 };
+        (-0.5 * t * t).exp()
+    };
+    [
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    ]
+}
+
+// Smooth red, green and blue bands that sum to one everywhere, so that white uplifts
+// to the constant spectrum and any colour with channels in [0, 1] that uplifts without
+// clamping is a valid reflectance.
+fn basis(lambda: f64) -> [f64; 3] {
+    let smoothstep = |a: f64, b: f64| {
+        let t = ((lambda - a) / (b - a)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    };
+    let blue = 1.0 - smoothstep(475.0, 505.0);
+    let red = smoothstep(565.0, 600.0);
+    [red, 1.0 - red - blue, blue]
+}
+
+struct Tables {
+    y_integral: f64,
+    xyz_to_rgb: Matrix3,
+    rgb_to_basis: Matrix3,
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        // Integrate the basis against the matching functions in 1 nm steps.
+        let mut y_integral = 0.0;
+        let mut basis_xyz = [[0.0; 3]; 3];
+        let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+        for i in 0..steps {
+            let lambda = LAMBDA_MIN + i as f64 + 0.5;
+            let cmf = cie_xyz(lambda);
+            let b = basis(lambda);
+            y_integral += cmf[1];
+            for (j, bj) in b.iter().enumerate() {
+                for k in 0..3 {
+                    basis_xyz[j][k] += bj * cmf[k];
+                }
+            }
+        }
+
+        let xyz_to_linear = WORKING_SPACE.from_xyz();
+        let basis_rgb = basis_xyz.map(|xyz| xyz_to_linear.apply(xyz.map(|v| v / y_integral)));
+        let white: [f64; 3] = std::array::from_fn(|i| basis_rgb.iter().map(|rgb| rgb[i]).sum());
+        let balance = Matrix3::diagonal(white.map(|w| 1.0 / w));
+        let basis_to_rgb = Matrix3::new(std::array::from_fn(|i| std::array::from_fn(|j| basis_rgb[j][i] / white[i])));
+        let rgb_to_basis = basis_to_rgb.inverse().expect("spectral basis is degenerate");
+        Tables { y_integral, xyz_to_rgb: balance * xyz_to_linear, rgb_to_basis }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_cmp::assert_approx_eq;
+
+    // Averages a colour through uplifting and back over evenly spread hero wavelengths.
+    fn round_trip(rgb: Color) -> Color {
+        let n = 2000;
+        let mut sum = Color::BLACK;
+        for i in 0..n {
+            let wavelengths = Wavelengths::sample((i as f64 + 0.5) / n as f64);
+            sum += wavelengths.to_rgb(wavelengths.uplift(rgb));
+        }
+        sum / n as f64
+    }
+
+    #[test]
+    fn test_wavelengths_sample() {
+        let w = Wavelengths::sample(0.9);
+        assert_approx_eq!(f64, w.hero(), LAMBDA_MIN + 0.9 * (LAMBDA_MAX - LAMBDA_MIN));
+        for l in w.lambda() {
+            assert!((LAMBDA_MIN..=LAMBDA_MAX).contains(&l));
+        }
+        assert_eq!(w.single(Color::new(1.0, 2.0, 3.0)), Color::new(3.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_uplift_white() {
+        let w = Wavelengths::sample(0.3);
+        let c = w.uplift(Color::WHITE);
+        for v in [c.r(), c.g(), c.b()] {
+            assert_approx_eq!(f64, v, 1.0, epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_uplift_round_trip() {
+        for rgb in [Color::WHITE, Color::new(0.5, 0.3, 0.2), Color::new(0.2, 0.4, 0.6)] {
+            let back = round_trip(rgb);
+            assert!((back.r() - rgb.r()).abs() < 0.01 && (back.g() - rgb.g()).abs() < 0.01
+                    && (back.b() - rgb.b()).abs() < 0.01, "{:?} came back as {:?}", rgb, back);
+        }
+    }
+
+    #[test]
+    fn test_dispersion() {
+        // BK7 at the helium d line.
+        assert_approx_eq!(f64, Dispersion::BK7.ior(587.56), 1.5168, epsilon = 1e-4);
+        assert!(Dispersion::BK7.ior(450.0) > Dispersion::BK7.ior(650.0));
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.004 };
+        assert_approx_eq!(f64, cauchy.ior(500.0), 1.516, epsilon = 1e-12);
+        let glass = Dispersion::from_abbe(1.5168, 64.17);
+        assert_approx_eq!(f64, glass.ior(587.56), 1.5168, epsilon = 1e-12);
+        assert_approx_eq!(f64, (glass.ior(486.13) - glass.ior(656.27)) * 64.17, 0.5168, epsilon = 1e-12);
+    }
+}
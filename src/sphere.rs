@@ -1,3 +1,6 @@
+use std::f64::consts::PI;
+
+use crate::bvh::Aabb;
 use crate::ray::{Ray, Hittable, HitRecord};
 use crate::vec::Vector;
 
@@ -29,12 +32,27 @@ impl Hittable for Sphere {
                 if t_min < *root && * root < t_max {
                     let p = ray.at(*root);
                     let normal = (p - self.center) / self.radius;
-                    return Some(HitRecord::from_ray(*ray, normal, *root))
+                    let mut hit = HitRecord::from_ray(*ray, normal, *root);
+                    // Longitude around +y from -x, latitude from the north pole.
+                    hit.u = ((-normal.z).atan2(normal.x) + PI) / (2.0 * PI);
+                    hit.v = normal.y.clamp(-1.0, 1.0).acos() / PI;
+                    let mut tangent = Vector::new(normal.z, 0.0, -normal.x);
+                    if tangent.near_zero() {
+                        tangent = Vector::new(1.0, 0.0, 0.0);
+                    }
+                    let tangent = tangent.normalize();
+                    hit.set_shading(normal, tangent, normal.cross(&tangent));
+                    return Some(hit);
                 }
             }
         }
         None
     }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Vector::new(self.radius.abs(), self.radius.abs(), self.radius.abs());
+        Aabb::new(self.center - r, self.center + r)
+    }
 }
 
 #[cfg(test)]
@@ -48,4 +66,21 @@ mod tests {
         let hit = sphere.hit(&ray, 0.0, f64::INFINITY);
         assert_eq!(hit.unwrap().t, 4.0);
     }
+
+    #[test]
+    fn test_sphere_uv() {
+        let sphere = Sphere::new(Vector::ORIGIN, 1.0);
+        let ray = Ray::new(Vector::new(0.0, 5.0, 0.0), Vector::new(0.0, -1.0, 0.0));
+        let hit = sphere.hit(&ray, 0.0, f64::INFINITY).unwrap();
+        assert_eq!(hit.v, 0.0);
+
+        let ray = Ray::new(Vector::new(5.0, 0.0, 0.0), Vector::new(-1.0, 0.0, 0.0));
+        let hit = sphere.hit(&ray, 0.0, f64::INFINITY).unwrap();
+        assert_eq!((hit.u, hit.v), (0.5, 0.5));
+
+        let ray = Ray::new(Vector::new(0.0, 0.0, 5.0), Vector::new(0.0, 0.0, -1.0));
+        let hit = sphere.hit(&ray, 0.0, f64::INFINITY).unwrap();
+        assert_eq!(hit.tangent, Vector::new(1.0, 0.0, 0.0));
+        assert_eq!(hit.bitangent, Vector::new(0.0, 1.0, 0.0));
+    }
 }
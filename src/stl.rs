@@ -0,0 +1,237 @@
+use std::fs;
+use std::str;
+
+use crate::color::Color;
+use crate::mesh::{Mesh, MeshError};
+use crate::vec::Vector;
+
+const HEADER_SIZE: usize = 80;
+const FACET_SIZE: usize = 50;
+
+pub fn load(filename: &str) -> Result<Mesh, MeshError> {
+    parse(&fs::read(filename)?)
+}
+
+pub fn parse(data: &[u8]) -> Result<Mesh, MeshError> {
+    if is_ascii(data) {
+        parse_ascii(data)
+    } else {
+        parse_binary(data)
+    }
+}
+
+// Binary files are allowed to start with "solid" too, so the keyword alone is not
+// enough: an ASCII file must also contain a facet or the end of the solid.
+fn is_ascii(data: &[u8]) -> bool {
+    let start = data.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(data.len());
+    if !data[start..].starts_with(b"solid") {
+        return false;
+    }
+    if data.len() >= HEADER_SIZE + 4 {
+        let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        if data.len() == HEADER_SIZE + 4 + count * FACET_SIZE {
+            return false;
+        }
+    }
+    let head = &data[..data.len().min(1024)];
+    let head = String::from_utf8_lossy(head);
+    head.contains("facet") || head.contains("endsolid")
+}
+
+fn parse_binary(data: &[u8]) -> Result<Mesh, MeshError> {
+    if data.len() < HEADER_SIZE + 4 {
+        return Err(MeshError::Truncated);
+    }
+    let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+    let expected = HEADER_SIZE + 4 + count * FACET_SIZE;
+    if data.len() < expected {
+        return Err(MeshError::Truncated);
+    }
+    if data.len() > expected {
+        return Err(MeshError::Malformed(
+            format!("{} bytes of trailing data after {} facets", data.len() - expected, count)));
+    }
+
+    let read_f32 = |offset: usize| {
+        f32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]) as f64
+    };
+    let read_vector = |offset: usize| Vector::new(read_f32(offset), read_f32(offset + 4), read_f32(offset + 8));
+
+    let mut positions = Vec::with_capacity(3 * count);
+    let mut colors = Vec::with_capacity(3 * count);
+    let mut has_color = false;
+    for i in 0..count {
+        let facet = HEADER_SIZE + 4 + i * FACET_SIZE;
+        // The facet normal at offset 0 is ignored; the winding order is authoritative.
+        for v in 0..3 {
+            positions.push(read_vector(facet + 12 + 12 * v));
+        }
+
+        // VisCAM/SolidView convention: bit 15 marks a valid 15-bit BGR colour.
+        let attribute = u16::from_le_bytes([data[facet + 48], data[facet + 49]]);
+        let color = if attribute & 0x8000 != 0 {
+            has_color = true;
+            let channel = |shift: u16| ((attribute >> shift) & 0x1f) as f64 / 31.0;
+            Color::new(channel(10), channel(5), channel(0))
+        } else {
+            Color::WHITE
+        };
+        colors.extend([color; 3]);
+    }
+
+    if !has_color {
+        colors.clear();
+    }
+    let triangles = (0..count).map(|i| [3 * i, 3 * i + 1, 3 * i + 2]).collect();
+    Mesh::new(positions, vec![], colors, vec![], triangles)
+}
+
+// A line of text by its number, split into tokens.
+type Line<'a> = (usize, Vec<&'a str>);
+
+fn parse_ascii(data: &[u8]) -> Result<Mesh, MeshError> {
+    let text = str::from_utf8(data)
+        .map_err(|_| MeshError::Malformed(String::from("ASCII STL is not valid text")))?;
+    let mut lines = text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split_ascii_whitespace().collect::<Vec<_>>()))
+        .filter(|(_, tokens)| !tokens.is_empty());
+    let mut positions = vec![];
+
+    // Solid names may contain spaces and follow both `solid` and `endsolid`.
+    expect(lines.next(), &["solid"], true)?;
+    loop {
+        let line = lines.next().ok_or(MeshError::Truncated)?;
+        if line.1[0] == "endsolid" {
+            // Another solid may follow.
+            match lines.next() {
+                Some(line) => expect(Some(line), &["solid"], true)?,
+                None => break,
+            };
+            continue;
+        }
+        read_vector(expect(Some(line), &["facet", "normal"], true)?)?;
+        expect(lines.next(), &["outer", "loop"], false)?;
+        for _ in 0..3 {
+            positions.push(read_vector(expect(lines.next(), &["vertex"], true)?)?);
+        }
+        expect(lines.next(), &["endloop"], false)?;
+        expect(lines.next(), &["endfacet"], false)?;
+    }
+
+    let triangles = (0..positions.len() / 3).map(|i| [3 * i, 3 * i + 1, 3 * i + 2]).collect();
+    Mesh::new(positions, vec![], vec![], vec![], triangles)
+}
+
+// Checks that `line` starts with `keywords`, and returns the tokens after them, which
+// are only allowed if `rest` is set.
+fn expect<'a>(line: Option<Line<'a>>, keywords: &[&str], rest: bool) -> Result<Line<'a>, MeshError> {
+    let (number, tokens) = line.ok_or(MeshError::Truncated)?;
+    if !tokens.starts_with(keywords) {
+        return Err(MeshError::Malformed(format!("line {}: expected `{}`, found `{}`",
+                                                number, keywords.join(" "), tokens.join(" "))));
+    }
+    let after = tokens[keywords.len()..].to_vec();
+    if !after.is_empty() && !rest {
+        return Err(MeshError::Malformed(format!("line {}: unexpected `{}` after `{}`",
+                                                number, after.join(" "), keywords.join(" "))));
+    }
+    Ok((number, after))
+}
+
+fn read_vector((number, tokens): Line) -> Result<Vector, MeshError> {
+    let [x, y, z] = tokens.as_slice() else {
+        return Err(MeshError::Malformed(format!("line {}: expected three coordinates", number)));
+    };
+    let parse = |token: &str| token.parse::<f64>()
+        .map_err(|_| MeshError::Malformed(format!("line {}: invalid number `{}`", number, token)));
+    Ok(Vector::new(parse(x)?, parse(y)?, parse(z)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII_STL: &str = "\
+solid triangle pair
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 1 1 0
+    endloop
+  endfacet
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 1 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid triangle pair
+";
+
+    fn binary_stl(color: Option<u16>) -> Vec<u8> {
+        // Binary headers beginning with "solid" must not be mistaken for ASCII.
+        let mut data = b"solid exported by a tool that ignores the spec".to_vec();
+        data.resize(HEADER_SIZE, b' ');
+        data.extend(1u32.to_le_bytes());
+        for c in [0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            data.extend(c.to_le_bytes());
+        }
+        data.extend(color.unwrap_or(0).to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_parse_ascii() {
+        let mesh = parse(ASCII_STL.as_bytes()).expect("valid STL rejected");
+        assert_eq!(mesh.positions().len(), 6);
+        assert_eq!(mesh.positions()[5], Vector::new(0.0, 1.0, 0.0));
+        assert_eq!(mesh.triangles(), &[[0, 1, 2], [3, 4, 5]]);
+    }
+
+    #[test]
+    fn test_parse_binary() {
+        let mesh = parse(&binary_stl(None)).expect("valid STL rejected");
+        assert_eq!(mesh.positions()[2], Vector::new(0.0, 1.0, 0.0));
+        assert!(mesh.colors().is_empty());
+
+        let red = 0x8000 | (31 << 10);
+        let mesh = parse(&binary_stl(Some(red))).expect("valid STL rejected");
+        assert_eq!(mesh.colors()[0], Color::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let data = binary_stl(None);
+        match parse(&data[..data.len() - 10]) {
+            Err(MeshError::Truncated) => (),
+            other => panic!("expected truncated error, got {:?}", other),
+        }
+
+        let text = &ASCII_STL[..ASCII_STL.rfind("endloop").unwrap()];
+        match parse(text.as_bytes()) {
+            Err(MeshError::Truncated) => (),
+            other => panic!("expected truncated error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let text = ASCII_STL.replace("outer loop", "outer lop");
+        match parse(text.as_bytes()) {
+            Err(MeshError::Malformed(_)) => (),
+            other => panic!("expected malformed error, got {:?}", other),
+        }
+
+        // Stray tokens between facets are not skipped.
+        let text = ASCII_STL.replacen("  facet", "  color 1 0 0\n  facet", 2);
+        match parse(text.as_bytes()) {
+            Err(MeshError::Malformed(why)) => assert!(why.starts_with("line 2:"), "{}", why),
+            other => panic!("expected malformed error, got {:?}", other),
+        }
+        let text = ASCII_STL.replace("vertex 1 0 0", "vertex 1 0");
+        assert!(matches!(parse(text.as_bytes()), Err(MeshError::Malformed(_))));
+    }
+}
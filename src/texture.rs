@@ -0,0 +1,166 @@
+use crate::color::{srgb_to_linear, Color};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+// RGBA image texture stored as linear floats. Texture coordinates follow the image
+// convention: (0, 0) is the top-left corner of the first row.
+#[derive(Debug, Clone)]
+pub struct Texture {
+    width: usize,
+    height: usize,
+    data: Vec<[f32; 4]>,
+    pub wrap_s: WrapMode,
+    pub wrap_t: WrapMode,
+    pub nearest: bool,
+}
+
+impl Texture {
+    pub fn new(width: usize, height: usize, data: Vec<[f32; 4]>) -> Texture {
+        if width == 0 || height == 0 || data.len() != width * height {
+            panic!("Texture: {} texels for a {} x {} image", data.len(), width, height);
+        }
+        Texture {
+            width,
+            height,
+            data,
+            wrap_s: WrapMode::Repeat,
+            wrap_t: WrapMode::Repeat,
+            nearest: false,
+        }
+    }
+
+    // Builds a texture from 8-bit samples with `channels` components per texel. Colour
+    // channels of sRGB-encoded images are decoded to linear; alpha is always linear.
+    pub fn from_bytes(width: usize, height: usize, channels: usize, bytes: &[u8], srgb: bool) -> Texture {
+        let values: Vec<f32> = bytes.iter().map(|&b| b as f32 / 255.0).collect();
+        Texture::from_floats(width, height, channels, &values, srgb)
+    }
+
+    pub fn from_floats(width: usize, height: usize, channels: usize, values: &[f32], srgb: bool) -> Texture {
+        let decode = |c: f32| if srgb { srgb_to_linear(c as f64) as f32 } else { c };
+        let data = values.chunks_exact(channels)
+            .map(|texel| match *texel {
+                [l] => [decode(l), decode(l), decode(l), 1.0],
+                [l, a] => [decode(l), decode(l), decode(l), a],
+                [r, g, b] => [decode(r), decode(g), decode(b), 1.0],
+                [r, g, b, a, ..] => [decode(r), decode(g), decode(b), a],
+                [] => unreachable!(),
+            })
+            .collect();
+        Texture::new(width, height, data)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn texel(&self, x: usize, y: usize) -> [f32; 4] {
+        self.data[y * self.width + x]
+    }
+
+    // Bilinearly filtered lookup (or nearest, if requested) honouring the wrap modes.
+    pub fn sample(&self, u: f64, v: f64) -> [f32; 4] {
+        let x = u * self.width as f64;
+        let y = v * self.height as f64;
+        if self.nearest {
+            let x = wrap(x.floor() as i64, self.width, self.wrap_s);
+            let y = wrap(y.floor() as i64, self.height, self.wrap_t);
+            return self.texel(x, y);
+        }
+
+        let x = x - 0.5;
+        let y = y - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = (x - x0) as f32;
+        let fy = (y - y0) as f32;
+        let xs = [wrap(x0 as i64, self.width, self.wrap_s), wrap(x0 as i64 + 1, self.width, self.wrap_s)];
+        let ys = [wrap(y0 as i64, self.height, self.wrap_t), wrap(y0 as i64 + 1, self.height, self.wrap_t)];
+
+        let mut result = [0.0f32; 4];
+        for (j, &ty) in ys.iter().enumerate() {
+            for (i, &tx) in xs.iter().enumerate() {
+                let w = (if i == 0 { 1.0 - fx } else { fx }) * (if j == 0 { 1.0 - fy } else { fy });
+                let texel = self.texel(tx, ty);
+                for k in 0..4 {
+                    result[k] += w * texel[k];
+                }
+            }
+        }
+        result
+    }
+
+    pub fn color(&self, u: f64, v: f64) -> Color {
+        let [r, g, b, _] = self.sample(u, v);
+        Color::new(r as f64, g as f64, b as f64)
+    }
+
+    pub fn alpha(&self, u: f64, v: f64) -> f64 {
+        self.sample(u, v)[3] as f64
+    }
+}
+
+fn wrap(i: i64, size: usize, mode: WrapMode) -> usize {
+    let n = size as i64;
+    let i = match mode {
+        WrapMode::Repeat => i.rem_euclid(n),
+        WrapMode::MirroredRepeat => {
+            let period = i.rem_euclid(2 * n);
+            if period < n { period } else { 2 * n - 1 - period }
+        },
+        WrapMode::ClampToEdge => i.clamp(0, n - 1),
+    };
+    i as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker() -> Texture {
+        // 2 x 2 black and white checkerboard.
+        Texture::from_bytes(2, 2, 1, &[0, 255, 255, 0], false)
+    }
+
+    #[test]
+    fn test_texture_nearest() {
+        let mut tex = checker();
+        tex.nearest = true;
+        assert_eq!(tex.color(0.25, 0.25), Color::BLACK);
+        assert_eq!(tex.color(0.75, 0.25), Color::WHITE);
+        assert_eq!(tex.color(1.25, 0.25), Color::BLACK);
+    }
+
+    #[test]
+    fn test_texture_bilinear() {
+        let tex = checker();
+        let [r, _, _, a] = tex.sample(0.5, 0.5);
+        assert!((r - 0.5).abs() < 1e-6);
+        assert_eq!(a, 1.0);
+    }
+
+    #[test]
+    fn test_texture_wrap() {
+        assert_eq!(wrap(-1, 4, WrapMode::Repeat), 3);
+        assert_eq!(wrap(-1, 4, WrapMode::MirroredRepeat), 0);
+        assert_eq!(wrap(5, 4, WrapMode::MirroredRepeat), 2);
+        assert_eq!(wrap(7, 4, WrapMode::ClampToEdge), 3);
+    }
+
+    #[test]
+    fn test_texture_srgb() {
+        let tex = Texture::from_bytes(1, 1, 4, &[255, 0, 0, 128], true);
+        let [r, g, _, a] = tex.texel(0, 0);
+        assert_eq!((r, g), (1.0, 0.0));
+        assert!((a - 128.0 / 255.0).abs() < 1e-6);
+    }
+}
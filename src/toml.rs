@@ -0,0 +1,289 @@
+use std::error::Error;
+use std::fmt;
+
+// The part of TOML that settings files need: tables, bare or quoted keys, and
+// strings, integers, floats, booleans and single-line arrays of them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            },
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(x) if x.fract() == 0.0 && x.is_finite() => write!(f, "{:.1}", x),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (i, v) in values.iter().enumerate() {
+                    write!(f, "{}{}", if i > 0 { ", " } else { "" }, v)?;
+                }
+                write!(f, "]")
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+// Entries under a `[name]` header, or at the top of the file for the table named "".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub name: String,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TomlError {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for TomlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl Error for TomlError {}
+
+pub fn parse(text: &str) -> Result<Vec<Table>, TomlError> {
+    let mut tables = vec![Table { name: String::new(), entries: Vec::new() }];
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let error = |reason: String| TomlError { line: line_number, reason };
+        let mut cursor = Cursor { rest: line.trim_start() };
+        if cursor.at_end() {
+            continue;
+        }
+
+        if cursor.eat('[') {
+            let mut name = cursor.key().map_err(error)?;
+            while cursor.eat('.') {
+                name.push('.');
+                name.push_str(&cursor.key().map_err(error)?);
+            }
+            if !cursor.eat(']') {
+                return Err(error(String::from("expected `]` after table name")));
+            }
+            if !cursor.at_end() {
+                return Err(error(String::from("unexpected text after table header")));
+            }
+            if tables.iter().any(|t| t.name == name) {
+                return Err(error(format!("table `{}` defined twice", name)));
+            }
+            tables.push(Table { name, entries: Vec::new() });
+            continue;
+        }
+
+        let key = cursor.key().map_err(error)?;
+        if cursor.eat('.') {
+            return Err(error(String::from("dotted keys are not supported")));
+        }
+        if !cursor.eat('=') {
+            return Err(error(format!("expected `=` after `{}`", key)));
+        }
+        let value = cursor.value().map_err(error)?;
+        if !cursor.at_end() {
+            return Err(error(String::from("unexpected text after value")));
+        }
+        let table = tables.last_mut().unwrap();
+        if table.entries.iter().any(|e| e.key == key) {
+            return Err(error(format!("key `{}` defined twice", key)));
+        }
+        table.entries.push(Entry { key, value, line: line_number });
+    }
+    Ok(tables)
+}
+
+struct Cursor<'a> {
+    rest: &'a str,
+}
+
+impl Cursor<'_> {
+    fn skip_space(&mut self) {
+        self.rest = self.rest.trim_start_matches([' ', '\t']);
+    }
+
+    // True at the end of the line or at a comment.
+    fn at_end(&mut self) -> bool {
+        self.skip_space();
+        self.rest.is_empty() || self.rest.starts_with('#')
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_space();
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        self.skip_space();
+        if self.rest.starts_with('"') || self.rest.starts_with('\'') {
+            return self.string();
+        }
+        let len = self.rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(self.rest.len());
+        if len == 0 {
+            return Err(String::from("expected a key"));
+        }
+        let (key, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Ok(key.to_string())
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_space();
+        if self.rest.starts_with('"') || self.rest.starts_with('\'') {
+            return self.string().map(Value::String);
+        }
+        if self.eat('[') {
+            let mut values = Vec::new();
+            while !self.eat(']') {
+                values.push(self.value()?);
+                if !self.eat(',') {
+                    if !self.eat(']') {
+                        return Err(String::from("expected `,` or `]` in array"));
+                    }
+                    break;
+                }
+            }
+            return Ok(Value::Array(values));
+        }
+
+        let len = self.rest.find([' ', '\t', ',', ']', '#']).unwrap_or(self.rest.len());
+        let (token, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        match token {
+            "true" => return Ok(Value::Boolean(true)),
+            "false" => return Ok(Value::Boolean(false)),
+            "" => return Err(String::from("expected a value")),
+            _ => (),
+        }
+        let digits = token.replace('_', "");
+        if let Ok(i) = digits.parse::<i64>() {
+            return Ok(Value::Integer(i));
+        }
+        match digits.as_str() {
+            "inf" | "+inf" => Ok(Value::Float(f64::INFINITY)),
+            "-inf" => Ok(Value::Float(f64::NEG_INFINITY)),
+            "nan" | "+nan" | "-nan" => Ok(Value::Float(f64::NAN)),
+            _ if digits.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+') => {
+                digits.parse::<f64>().map(Value::Float).map_err(|_| format!("invalid value `{}`", token))
+            },
+            _ => Err(format!("invalid value `{}`", token)),
+        }
+    }
+
+    // A basic string with escapes, or a literal string in single quotes.
+    fn string(&mut self) -> Result<String, String> {
+        let quote = self.rest.chars().next().unwrap();
+        let mut chars = self.rest[1..].char_indices();
+        let mut s = String::new();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                _ if c == quote => {
+                    self.rest = &self.rest[1 + i + 1..];
+                    return Ok(s);
+                },
+                '\\' if quote == '"' => match chars.next().map(|(_, c)| c) {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some(c) => return Err(format!("unsupported escape `\\{}`", c)),
+                    None => break,
+                },
+                c => s.push(c),
+            }
+        }
+        Err(String::from("unterminated string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "\
+# Settings
+width = 1_280
+exposure = -0.5   # stops
+filter = \"mitchell:2\"
+path = 'C:\\scenes'
+denoise = true
+aov = [\"depth\", 'albedo',]
+
+[preset.draft]
+samples = 4
+\"max-depth\" = 8
+";
+        let tables = parse(text).expect("valid TOML rejected");
+        assert_eq!(tables.len(), 2);
+        let root = &tables[0];
+        assert_eq!(root.name, "");
+        let values: Vec<(&str, &Value)> = root.entries.iter().map(|e| (e.key.as_str(), &e.value)).collect();
+        assert_eq!(values, vec![
+            ("width", &Value::Integer(1280)),
+            ("exposure", &Value::Float(-0.5)),
+            ("filter", &Value::String(String::from("mitchell:2"))),
+            ("path", &Value::String(String::from("C:\\scenes"))),
+            ("denoise", &Value::Boolean(true)),
+            ("aov", &Value::Array(vec![Value::String(String::from("depth")), Value::String(String::from("albedo"))])),
+        ]);
+        assert_eq!(root.entries[1].line, 3);
+        assert_eq!(tables[1].name, "preset.draft");
+        assert_eq!(tables[1].entries[1].key, "max-depth");
+        assert_eq!(tables[1].entries[1].value, Value::Integer(8));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let line = |text: &str| parse(text).unwrap_err().line;
+        assert_eq!(line("a = 1\nb = \"open\n"), 2);
+        assert_eq!(line("a = 1\na = 2\n"), 2);
+        assert_eq!(line("[t]\n[t]\n"), 2);
+        assert_eq!(line("a = yes"), 1);
+        assert_eq!(line("a.b = 1"), 1);
+        assert_eq!(line("a = 1 2"), 1);
+        assert_eq!(line("a = [1, 2"), 1);
+    }
+
+    #[test]
+    fn test_display() {
+        let value = Value::Array(vec![Value::String(String::from("a \"b\"")), Value::Float(2.0), Value::Integer(3)]);
+        assert_eq!(value.to_string(), "[\"a \\\"b\\\"\", 2.0, 3]");
+        assert_eq!(parse(&format!("v = {}", value)).unwrap()[0].entries[0].value, value);
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::color::Color;
+
+// Operators compressing scene-referred radiance into the [0, 1] display range.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMap {
+    // Hard clip, as the renderer always did.
+    #[default]
+    Clamp,
+    // L / (1 + L) on luminance, keeping hue.
+    Reinhard,
+    // Reinhard with a white point: luminance `white` and above maps to one.
+    ExtendedReinhard { white: f64 },
+    // John Hable's Uncharted 2 filmic curve.
+    Hable,
+    // Stephen Hill's fit of the ACES reference rendering and sRGB output transforms.
+    Aces,
+}
+
+impl ToneMap {
+    pub fn apply(&self, c: Color) -> Color {
+        match *self {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => scale_luminance(c, |l| l / (1.0 + l)),
+            ToneMap::ExtendedReinhard { white } => {
+                let w2 = white * white;
+                scale_luminance(c, |l| (l * (1.0 + l / w2) / (1.0 + l)).min(1.0))
+            },
+            ToneMap::Hable => {
+                const EXPOSURE_BIAS: f64 = 2.0;
+                const WHITE: f64 = 11.2;
+                let scale = 1.0 / hable(WHITE);
+                per_channel(c, |x| (hable(EXPOSURE_BIAS * x) * scale).min(1.0))
+            },
+            ToneMap::Aces => {
+                const INPUT: [[f64; 3]; 3] = [
+                    [0.59719, 0.35458, 0.04823],
+                    [0.07600, 0.90834, 0.01566],
+                    [0.02840, 0.13383, 0.83777],
+                ];
+                const OUTPUT: [[f64; 3]; 3] = [
+                    [1.60475, -0.53108, -0.07367],
+                    [-0.10208, 1.10813, -0.00605],
+                    [-0.00327, -0.07276, 1.07602],
+                ];
+                let v = transform(&INPUT, c);
+                let v = per_channel(v, |x| (x * (x + 0.0245786) - 0.000090537) / (x * (0.983729 * x + 0.4329510) + 0.238081));
+                per_channel(transform(&OUTPUT, v), |x| x.clamp(0.0, 1.0))
+            },
+        }
+    }
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    // Accepts `clamp`, `reinhard`, `reinhard-extended[:WHITE]`, `hable` and `aces`.
+    fn from_str(s: &str) -> Result<ToneMap, String> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        let tone_map = match name.to_ascii_lowercase().as_str() {
+            "clamp" | "none" => ToneMap::Clamp,
+            "reinhard" => ToneMap::Reinhard,
+            "reinhard-extended" => {
+                let white = match arg {
+                    Some(w) => w.parse::<f64>().ok().filter(|w| *w > 0.0)
+                        .ok_or_else(|| format!("invalid white point `{}`", w))?,
+                    None => 4.0,
+                };
+                return Ok(ToneMap::ExtendedReinhard { white });
+            },
+            "hable" | "filmic" => ToneMap::Hable,
+            "aces" => ToneMap::Aces,
+            _ => return Err(format!("unknown tone mapping operator `{}`", s)),
+        };
+        if arg.is_some() {
+            return Err(format!("tone mapping operator `{}` takes no argument", name));
+        }
+        Ok(tone_map)
+    }
+}
+
+impl fmt::Display for ToneMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToneMap::Clamp => write!(f, "clamp"),
+            ToneMap::Reinhard => write!(f, "reinhard"),
+            ToneMap::ExtendedReinhard { white } => write!(f, "reinhard-extended:{}", white),
+            ToneMap::Hable => write!(f, "hable"),
+            ToneMap::Aces => write!(f, "aces"),
+        }
+    }
+}
+
+// Linear scale for an exposure in stops.
+pub fn exposure_scale(ev: f64) -> f64 {
+    ev.exp2()
+}
+
+// A physical camera's exposure settings, for scenes lit in real-world units
+// (luminance in cd/m²). The defaults are the "sunny 16" rule for daylight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraExposure {
+    pub iso: f64,
+    // Shutter time in seconds.
+    pub shutter: f64,
+    pub f_number: f64,
+}
+
+impl CameraExposure {
+    pub const DEFAULT: CameraExposure = CameraExposure { iso: 100.0, shutter: 0.01, f_number: 16.0 };
+
+    // Exposure value of the settings at ISO 100.
+    pub fn ev100(&self) -> f64 {
+        (self.f_number * self.f_number / self.shutter * 100.0 / self.iso).log2()
+    }
+
+    // Linear scale taking luminance to film values, where 1 is the luminance that
+    // saturates the sensor, by the ISO 12232 saturation-based speed with its 78%
+    // headroom (q = 0.65).
+    pub fn scale(&self) -> f64 {
+        1.0 / (1.2 * self.ev100().exp2())
+    }
+}
+
+impl Default for CameraExposure {
+    fn default() -> Self {
+        CameraExposure::DEFAULT
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    const A: f64 = 0.15;
+    const B: f64 = 0.50;
+    const C: f64 = 0.10;
+    const D: f64 = 0.20;
+    const E: f64 = 0.02;
+    const F: f64 = 0.30;
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+fn scale_luminance<F: Fn(f64) -> f64>(c: Color, f: F) -> Color {
+    let l = c.luminance();
+    if l <= 0.0 {
+        return Color::BLACK;
+    }
+    (f(l) / l) * c
+}
+
+fn per_channel<F: Fn(f64) -> f64>(c: Color, f: F) -> Color {
+    Color::new(f(c.r()), f(c.g()), f(c.b()))
+}
+
+fn transform(m: &[[f64; 3]; 3], c: Color) -> Color {
+    let v = [c.r(), c.g(), c.b()];
+    let row = |i: usize| m[i][0] * v[0] + m[i][1] * v[1] + m[i][2] * v[2];
+    Color::new(row(0), row(1), row(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_cmp::assert_approx_eq;
+
+    #[test]
+    fn test_tone_map_range() {
+        let operators = [ToneMap::Reinhard, ToneMap::ExtendedReinhard { white: 4.0 }, ToneMap::Hable, ToneMap::Aces];
+        for op in operators {
+            assert!(op.apply(Color::BLACK).luminance().abs() < 1e-3, "{} lifts black", op);
+            let mut previous = 0.0;
+            for i in 1..100 {
+                let x = 0.1 * i as f64;
+                let y = op.apply(Color::new(x, x, x)).g();
+                assert!(y >= previous - 1e-9 && y <= 1.0 + 1e-9, "{} not monotonic at {}", op, x);
+                previous = y;
+            }
+        }
+    }
+
+    #[test]
+    fn test_extended_reinhard_white() {
+        let op = ToneMap::ExtendedReinhard { white: 4.0 };
+        assert_approx_eq!(f64, op.apply(Color::new(4.0, 4.0, 4.0)).r(), 1.0, epsilon = 1e-9);
+        assert_approx_eq!(f64, ToneMap::Hable.apply(Color::new(5.6, 5.6, 5.6)).r(), 1.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_tone_map_parse() {
+        assert_eq!("aces".parse::<ToneMap>(), Ok(ToneMap::Aces));
+        assert_eq!("Reinhard".parse::<ToneMap>(), Ok(ToneMap::Reinhard));
+        assert_eq!("reinhard-extended:2.5".parse::<ToneMap>(), Ok(ToneMap::ExtendedReinhard { white: 2.5 }));
+        assert!("reinhard-extended:-1".parse::<ToneMap>().is_err());
+        assert!("hable:3".parse::<ToneMap>().is_err());
+        assert!("drago".parse::<ToneMap>().is_err());
+        for op in [ToneMap::Clamp, ToneMap::ExtendedReinhard { white: 2.5 }, ToneMap::Aces] {
+            assert_eq!(op.to_string().parse::<ToneMap>(), Ok(op));
+        }
+    }
+
+    #[test]
+    fn test_exposure_scale() {
+        assert_eq!(exposure_scale(0.0), 1.0);
+        assert_eq!(exposure_scale(2.0), 4.0);
+        assert_eq!(exposure_scale(-1.0), 0.5);
+    }
+
+    #[test]
+    fn test_camera_exposure() {
+        // f/1 for a second at ISO 100 is EV 0.
+        let camera = CameraExposure { iso: 100.0, shutter: 1.0, f_number: 1.0 };
+        assert_approx_eq!(f64, camera.ev100(), 0.0);
+        assert_approx_eq!(f64, camera.scale(), 1.0 / 1.2);
+        // Sunny 16 sees sunlit white paper, around 30000 cd/m², close to saturation.
+        let sunny = CameraExposure::default();
+        assert!((sunny.ev100() - 14.64).abs() < 0.01);
+        assert!((30000.0 * sunny.scale() - 1.0).abs() < 0.05);
+        // Doubling the ISO or the shutter time, or opening up a stop, doubles the scale.
+        for faster in [CameraExposure { iso: 200.0, ..sunny }, CameraExposure { shutter: 0.02, ..sunny },
+                       CameraExposure { f_number: 16.0 / 2f64.sqrt(), ..sunny }] {
+            assert_approx_eq!(f64, faster.scale(), 2.0 * sunny.scale(), epsilon = 1e-12);
+        }
+    }
+}
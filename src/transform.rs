@@ -0,0 +1,74 @@
+use crate::bvh::Aabb;
+use crate::matrix::Matrix4;
+use crate::ray::{HitRecord, Hittable, Ray};
+use crate::vec::Vector;
+
+// A shape moved by an affine transform. Rays are brought into the shape's own space
+// to hit it, which leaves their parameter t unchanged, and hits are taken back out.
+pub struct Transformed {
+    shape: Box<dyn Hittable + Send + Sync>,
+    matrix: Matrix4,
+    inverse: Matrix4,
+    normal_matrix: Matrix4,
+}
+
+impl Transformed {
+    // Returns None for transforms that cannot be undone, such as a zero scale.
+    pub fn new(shape: Box<dyn Hittable + Send + Sync>, matrix: Matrix4) -> Option<Transformed> {
+        let inverse = matrix.inverse()?;
+        Some(Transformed { shape, matrix, inverse, normal_matrix: inverse.transpose() })
+    }
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let local = Ray::new(self.inverse.transform_point(&r.origin), self.inverse.transform_vector(&r.direction));
+        let mut hit = self.shape.hit(&local, t_min, t_max)?;
+        hit.p = r.at(hit.t);
+        hit.n = self.normal_matrix.transform_vector(&hit.n).normalize();
+        hit.ng = self.normal_matrix.transform_vector(&hit.ng).normalize();
+        hit.tangent = self.matrix.transform_vector(&hit.tangent).normalize();
+        hit.bitangent = self.matrix.transform_vector(&hit.bitangent).normalize();
+        Some(hit)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let b = self.shape.bounding_box();
+        let corners: Vec<Vector> = (0..8)
+            .map(|i| Vector::new(
+                if i & 1 == 0 { b.min.x } else { b.max.x },
+                if i & 2 == 0 { b.min.y } else { b.max.y },
+                if i & 4 == 0 { b.min.z } else { b.max.z },
+            ))
+            .map(|p| self.matrix.transform_point(&p))
+            .collect();
+        Aabb::from_points(&corners)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn test_transformed_hit() {
+        let sphere = Box::new(Sphere::new(Vector::ORIGIN, 1.0));
+        let matrix = Matrix4::translation(Vector::new(0.0, 0.0, -5.0)) * Matrix4::scale(Vector::new(2.0, 1.0, 1.0));
+        let shape = Transformed::new(sphere, matrix).expect("invertible transform rejected");
+
+        let hit = shape.hit(&Ray::new(Vector::ORIGIN, Vector::new(0.0, 0.0, -1.0)), 0.0, f64::INFINITY).unwrap();
+        assert!((hit.p - Vector::new(0.0, 0.0, -4.0)).length() < 1e-9);
+        assert!((hit.n - Vector::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        assert!((hit.t - 4.0).abs() < 1e-9);
+
+        // The stretched sphere reaches further along x.
+        let r = Ray::new(Vector::new(1.5, 0.0, 0.0), Vector::new(0.0, 0.0, -1.0));
+        assert!(shape.hit(&r, 0.0, f64::INFINITY).is_some());
+        let b = shape.bounding_box();
+        assert!((b.min - Vector::new(-2.0, -1.0, -6.0)).length() < 1e-9);
+        assert!((b.max - Vector::new(2.0, 1.0, -4.0)).length() < 1e-9);
+
+        assert!(Transformed::new(Box::new(Sphere::new(Vector::ORIGIN, 1.0)), Matrix4::scale(Vector::ORIGIN)).is_none());
+    }
+}
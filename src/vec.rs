@@ -45,6 +45,15 @@ impl Vector {
         *self / self.length()
     }
 
+    pub fn near_zero(&self) -> bool {
+        const S: f64 = 1e-8;
+        self.x.abs() < S && self.y.abs() < S && self.z.abs() < S
+    }
+
+    pub fn reflect(&self, n: &Vector) -> Vector {
+        *self - 2.0 * (*self * *n) * *n
+    }
+
     pub fn random_in_unit_sphere() -> Vector {
         let mut rng = rand::thread_rng();
         let dist = Uniform::new(-1.0, 1.0);
@@ -194,7 +203,7 @@ mod tests  {
     #[test]
     fn test_vec_length() {
         let p = Vector::new(1.0, 2.0, 3.0);
-        assert_approx_eq!(f64, p.length(), (1.0 + 4.0 + 9.0 as f64).sqrt());
+        assert_approx_eq!(f64, p.length(), (1.0 + 4.0 + 9.0f64).sqrt());
     }
 
     #[test]
@@ -217,7 +226,7 @@ mod tests  {
         let p = Vector::new(0.1, 0.2, 0.3);
         let q = Vector::new(0.6, 0.5, 0.4);
         let d = p.distance(&q);
-        let expected = (0.25 + 0.09 + 0.01 as f64).sqrt();
+        let expected = (0.25 + 0.09 + 0.01f64).sqrt();
         assert_approx_eq!(f64, d, expected);
     }
 
@@ -302,6 +311,13 @@ mod tests  {
         assert_approx_eq!(f64, vn.length(), 1.0);
     }
 
+    #[test]
+    fn test_vec_reflect() {
+        let v = Vector::new(1.0, -1.0, 0.0);
+        let n = Vector::new(0.0, 1.0, 0.0);
+        assert_eq!(v.reflect(&n), Vector::new(1.0, 1.0, 0.0));
+    }
+
     #[test]
     fn test_vec_random_in_unit_sphere() {
         for _ in 0..100 {